@@ -0,0 +1,482 @@
+//!
+//! The level of logarithmically spaced frequency bands of a signal, for spectrum visualisers.
+//!
+//! A `Feed` is inserted into a signal pipeline and hands the audio to any number of subscribers,
+//! each of which receives the levels of its bands a few dozen times per second through a
+//! callback. The spectrum is computed on a separate thread, so the pipeline is not held up by the
+//! analysis.
+//!
+
+use super::{Analyser, Spectrum, SpectrumAnalyser};
+use crate::audio::*;
+use crate::filter::stft;
+use sample::{self, Frame, Sample};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::*;
+
+/// The level of bands without any energy, in dBFS.
+pub const FLOOR: f64 = -120.0;
+
+/// The number of frames that a feed buffers before handing them to its subscribers.
+const FEED_BLOCK_SIZE: usize = 256;
+/// The number of blocks that can be queued for the analysis thread of a feed before blocks are
+/// skipped.
+const FEED_QUEUE: usize = 32;
+
+/// The parameters of a spectrum subscription.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    pub num_bands: usize,
+    /// The lower edge of the first band in Hz.
+    pub min_freq: f64,
+    /// The upper edge of the last band in Hz. This is limited to the Nyquist frequency.
+    pub max_freq: f64,
+    /// The number of times per second the levels are updated.
+    pub rate: f64,
+    /// How much of the previous level is retained by each update, from 0.0 for no smoothing up to
+    /// but not including 1.0.
+    pub smoothing: f64,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            num_bands: 32,
+            min_freq: 20.0,
+            max_freq: 20_000.0,
+            rate: 40.0,
+            smoothing: 0.5,
+        }
+    }
+}
+
+impl Params {
+    /// Returns the lower and upper edge in Hz of each band.
+    pub fn band_edges(&self) -> Vec<(f64, f64)> {
+        let ratio = (self.max_freq / self.min_freq).powf(1.0 / self.num_bands as f64);
+        (0..self.num_bands)
+            .map(|i| {
+                let lo = self.min_freq * ratio.powi(i as i32);
+                (lo, lo * ratio)
+            })
+            .collect()
+    }
+}
+
+/// Bands reduces the spectrum of a signal to the levels of its bands and passes them to a
+/// handler.
+pub struct Bands {
+    smoothing: f64,
+    /// The range of bins of each band.
+    bins: Vec<ops::Range<usize>>,
+    /// Scales the magnitude of a bin so a full scale sine measures 1.0.
+    norm: f64,
+    /// The most recent levels in dBFS.
+    levels: Vec<f64>,
+    handler: Arc<Fn(&[f64]) + Send + Sync>,
+}
+
+impl Bands {
+    fn new(
+        params: &Params,
+        sample_rate: u32,
+        window: stft::Window,
+        window_size: usize,
+        handler: Arc<Fn(&[f64]) + Send + Sync>,
+    ) -> Bands {
+        let num_bins = window_size / 2 + 1;
+        let bin_width = f64::from(sample_rate) / window_size as f64;
+        let bins = params
+            .band_edges()
+            .into_iter()
+            .map(|(lo, hi)| {
+                let start = ((lo / bin_width).ceil() as usize).min(num_bins - 1);
+                let end = ((hi / bin_width).ceil() as usize).min(num_bins);
+                // Low bands may be narrower than a single bin, in which case they take the level
+                // of the bin that contains them.
+                if start < end {
+                    start..end
+                } else {
+                    let center =
+                        (((lo * hi).sqrt() / bin_width).round() as usize).min(num_bins - 1);
+                    center..center + 1
+                }
+            })
+            .collect();
+        let sum: f64 = window.coefficients(window_size).iter().sum();
+        Bands {
+            smoothing: params.smoothing.max(0.0).min(0.999),
+            bins,
+            norm: 2.0 / sum,
+            levels: vec![FLOOR; params.num_bands],
+            handler,
+        }
+    }
+
+    /// Returns the most recent levels in dBFS.
+    pub fn levels(&self) -> &[f64] {
+        &self.levels
+    }
+}
+
+impl SpectrumAnalyser for Bands {
+    fn process_block(&mut self, block: &stft::Block) {
+        let num_channels = block.len() as f64;
+        for (level, bins) in self.levels.iter_mut().zip(&self.bins) {
+            // The peak of the band is taken, so a sine measures the same regardless of the width
+            // of the band it falls into.
+            let power = bins
+                .clone()
+                .map(|i| block.iter().map(|ch| ch[i].norm_sqr()).sum::<f64>() / num_channels)
+                .fold(0.0, f64::max);
+            let db = (10.0 * (power * self.norm * self.norm).log10()).max(FLOOR);
+            *level = self.smoothing * *level + (1.0 - self.smoothing) * db;
+        }
+        (self.handler)(&self.levels);
+    }
+}
+
+/// Creates an analyser that yields the band levels of a signal at the rate set by the params.
+pub fn analyser(
+    params: &Params,
+    sample_rate: u32,
+    num_channels: usize,
+    handler: Arc<Fn(&[f64]) + Send + Sync>,
+) -> Spectrum<Bands> {
+    let hop_size = ((f64::from(sample_rate) / params.rate).round() as usize).max(1);
+    // A window of at least 50ms is needed to resolve the lowest bands.
+    let window_size = cmp::max(hop_size, sample_rate as usize / 20).next_power_of_two();
+    let window = stft::Window::Hann;
+    let bands = Bands::new(params, sample_rate, window, window_size, handler);
+    Spectrum::new(bands, num_channels, window, window_size, hop_size)
+}
+
+struct Subscriber {
+    id: u64,
+    analyser: Spectrum<Bands>,
+}
+
+struct Subscribers {
+    list: Vec<Subscriber>,
+    next_id: u64,
+}
+
+enum Message {
+    /// Interleaved samples of a block of frames.
+    Block(Vec<f64>),
+    /// Answered once all preceding blocks have been analysed.
+    Flush(mpsc::Sender<()>),
+}
+
+/// Feed hands the frames of a signal to the spectrum analysers of its subscribers. It can be
+/// cloned to subscribe from other threads.
+///
+/// The frames are copied to an analysis thread, which runs until the feed and all its clones are
+/// dropped. If that thread falls behind, blocks are skipped rather than holding up the signal.
+#[derive(Clone)]
+pub struct Feed {
+    sample_rate: u32,
+    num_channels: usize,
+    subscribers: Arc<Mutex<Subscribers>>,
+    /// The length of the list of subscribers, so it can be checked without locking.
+    num_subscribers: Arc<AtomicUsize>,
+    sender: mpsc::SyncSender<Message>,
+    /// The buffers that are not queued for the analysis thread.
+    pool: Arc<Mutex<Vec<Vec<f64>>>>,
+}
+
+impl Feed {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Feed {
+        Feed::with_queue(sample_rate, num_channels, FEED_QUEUE)
+    }
+
+    fn with_queue(sample_rate: u32, num_channels: usize, queue: usize) -> Feed {
+        let subscribers = Arc::new(Mutex::new(Subscribers {
+            list: Vec::new(),
+            next_id: 0,
+        }));
+        let pool: Vec<_> = (0..queue)
+            .map(|_| Vec::with_capacity(FEED_BLOCK_SIZE * num_channels))
+            .collect();
+        let pool = Arc::new(Mutex::new(pool));
+        let (sender, receiver) = mpsc::sync_channel(queue);
+        let (thread_subscribers, thread_pool) = (subscribers.clone(), pool.clone());
+        thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    Message::Block(buf) => {
+                        let mut subscribers = thread_subscribers.lock().unwrap();
+                        for frame in buf.chunks(num_channels) {
+                            for sub in subscribers.list.iter_mut() {
+                                sub.analyser.process(frame);
+                            }
+                        }
+                        drop(subscribers);
+                        thread_pool.lock().unwrap().push(buf);
+                    }
+                    Message::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Feed {
+            sample_rate,
+            num_channels,
+            subscribers,
+            num_subscribers: Arc::new(AtomicUsize::new(0)),
+            sender,
+            pool,
+        }
+    }
+
+    /// Starts calling the handler with the levels of the bands of the signal. The handler is
+    /// called from the analysis thread of the feed.
+    ///
+    /// The subscription lasts until the returned handle is dropped.
+    pub fn subscribe(
+        &self,
+        params: &Params,
+        handler: Arc<Fn(&[f64]) + Send + Sync>,
+    ) -> Subscription {
+        let analyser = analyser(params, self.sample_rate, self.num_channels, handler);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.list.push(Subscriber { id, analyser });
+        self.num_subscribers.fetch_add(1, Ordering::Relaxed);
+        Subscription {
+            id,
+            subscribers: Arc::downgrade(&self.subscribers),
+            num_subscribers: self.num_subscribers.clone(),
+        }
+    }
+
+    /// Returns true if there is at least one subscriber.
+    pub fn is_subscribed(&self) -> bool {
+        self.num_subscribers.load(Ordering::Relaxed) > 0
+    }
+
+    /// Copies the frames to the analysis thread, which passes them to all subscribers. Nothing is
+    /// copied if there are no subscribers.
+    pub fn process<'a, I>(&self, frames: I)
+    where
+        I: IntoIterator<Item = &'a [f64]>,
+    {
+        if !self.is_subscribed() {
+            return;
+        }
+        let mut buf = match self.pool.lock().unwrap().pop() {
+            Some(buf) => buf,
+            // All buffers are queued, so the analysis thread is behind.
+            None => return,
+        };
+        buf.clear();
+        for frame in frames {
+            buf.extend_from_slice(frame);
+        }
+        if let Err(mpsc::TrySendError::Full(Message::Block(buf))) =
+            self.sender.try_send(Message::Block(buf))
+        {
+            self.pool.lock().unwrap().push(buf);
+        }
+    }
+
+    /// Blocks until all frames that have been passed to `process` have been analysed.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// A handle to a subscription of a `Feed`. Dropping it ends the subscription.
+pub struct Subscription {
+    id: u64,
+    subscribers: Weak<Mutex<Subscribers>>,
+    num_subscribers: Arc<AtomicUsize>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            let id = self.id;
+            subscribers.lock().unwrap().list.retain(|sub| sub.id != id);
+            self.num_subscribers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Fed passes a source through unaltered while handing its frames to a feed in blocks, so they
+/// are not copied to the analysis thread one at a time.
+pub struct Fed<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+{
+    input: S,
+    feed: Feed,
+    /// Interleaved samples of the frames that have not yet been handed to the feed.
+    buf: Vec<f64>,
+}
+
+impl<S> iter::Iterator for Fed<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.input.next()?;
+        for ch in 0..S::Item::n_channels() {
+            self.buf.push(frame.channel(ch).unwrap().to_sample());
+        }
+        if self.buf.len() == FEED_BLOCK_SIZE * S::Item::n_channels() {
+            self.feed.process(self.buf.chunks(S::Item::n_channels()));
+            self.buf.clear();
+        }
+        Some(frame)
+    }
+}
+
+impl<S> Source for Fed<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait IntoFeed: Source + Sized
+where
+    Self::Item: sample::Frame,
+{
+    /// Hands the frames of the source to the subscribers of the feed. The feed must have been
+    /// created for the format of the source.
+    fn feed(self, feed: Feed) -> Fed<Self> {
+        assert_eq!(Self::Item::n_channels(), feed.num_channels);
+        Fed {
+            buf: Vec::with_capacity(FEED_BLOCK_SIZE * feed.num_channels),
+            input: self,
+            feed,
+        }
+    }
+}
+
+impl<T> IntoFeed for T
+where
+    T: Source,
+    T::Item: sample::Frame,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::format;
+
+    /// Returns the index of the loudest band of each update.
+    fn loudest(updates: &[Vec<f64>]) -> Vec<usize> {
+        updates
+            .iter()
+            .map(|levels| {
+                (0..levels.len())
+                    .max_by(|&a, &b| levels[a].partial_cmp(&levels[b]).unwrap())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn band_edges() {
+        let params = Params {
+            num_bands: 3,
+            min_freq: 100.0,
+            max_freq: 100_000.0,
+            ..Params::default()
+        };
+        let edges = params.band_edges();
+        assert_eq!(3, edges.len());
+        assert!((edges[0].0 - 100.0).abs() < 1e-6);
+        assert!((edges[1].0 - 1000.0).abs() < 1e-6);
+        assert!((edges[2].1 - 100_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sine_440hz() {
+        let params = Params::default();
+        let expected = params
+            .band_edges()
+            .iter()
+            .position(|&(lo, hi)| lo <= 440.0 && 440.0 < hi)
+            .unwrap();
+        for file in &[
+            "testdata/10s_440hz_i16.wav",
+            "testdata/10s_440hz_f32.wav",
+            "testdata/10s_440hz_level8_16bit.flac",
+            "testdata/10s_440hz_level8_24bit.flac",
+            "testdata/10s_440hz_320cbr_stereo.mp3",
+        ] {
+            let (audio, _) = format::decode_file(path::Path::new(file)).unwrap();
+            let source = dynam::Source::from(audio);
+            let (sample_rate, num_channels) = (source.sample_rate(), source.num_channels());
+            let updates = Arc::new(Mutex::new(Vec::new()));
+            let u = updates.clone();
+            let mut analyser = analyser(
+                &params,
+                sample_rate,
+                num_channels as usize,
+                Arc::new(move |levels| u.lock().unwrap().push(levels.to_vec())),
+            );
+            super::super::feed_dynam(source, &mut analyser);
+
+            let updates = updates.lock().unwrap();
+            // 10 seconds at 40 updates per second, minus the first window.
+            assert!(
+                updates.len() > 390 && updates.len() <= 400,
+                "{}",
+                updates.len()
+            );
+            assert!(
+                loudest(&updates[10..]).iter().all(|&b| b == expected),
+                "{}",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn feed() {
+        // The queue holds all blocks, so none are skipped however slow the analysis is.
+        let feed = Feed::with_queue(44100, 2, 44100 / FEED_BLOCK_SIZE + 1);
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let u = updates.clone();
+        let subscription = feed.subscribe(
+            &Params::default(),
+            Arc::new(move |levels| u.lock().unwrap().push(levels.to_vec())),
+        );
+        assert!(feed.is_subscribed());
+
+        let signal = || Generator::<[f32; 2]>::new(Waveform::Sine(1000.0), 44100, 44100);
+        let out: Vec<_> = signal().feed(feed.clone()).collect();
+        assert_eq!(44100, out.len());
+        feed.flush();
+        let n = updates.lock().unwrap().len();
+        assert!(n >= 35 && n <= 40, "{}", n);
+        let (lo, hi) = Params::default().band_edges()[loudest(&updates.lock().unwrap())[n - 1]];
+        assert!(lo <= 1000.0 && 1000.0 < hi);
+
+        drop(subscription);
+        assert!(!feed.is_subscribed());
+        signal().feed(feed.clone()).for_each(|_| ());
+        feed.flush();
+        assert_eq!(n, updates.lock().unwrap().len());
+    }
+}
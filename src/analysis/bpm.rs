@@ -0,0 +1,285 @@
+//!
+//! This module estimates the tempo of a signal in beats per minute.
+//!
+//! The onset strength of each window is the rise of the log-compressed magnitude spectrum with
+//! respect to the previous window, summed over all bins. Because beats recur at a fixed interval,
+//! the autocorrelation of the onset strength peaks at lags that are multiples of the beat period.
+//! The lag with the strongest autocorrelation is selected after weighing it by a prior that
+//! favours tempi around 120 BPM, which resolves most confusion between a tempo and its halves and
+//! doubles.
+//!
+
+use super::{Spectrum, SpectrumAnalyser};
+use crate::audio::*;
+use crate::filter::stft;
+use sample;
+use std::*;
+
+/// The range of tempi that can be detected.
+pub const MIN_BPM: f64 = 60.0;
+pub const MAX_BPM: f64 = 200.0;
+
+/// The tempo that is favoured when the onsets are ambiguous.
+const PRIOR_BPM: f64 = 120.0;
+/// The width of the tempo prior in octaves.
+const PRIOR_WIDTH: f64 = 1.0;
+/// The factor applied to magnitudes before they are log-compressed.
+const COMPRESSION: f64 = 100.0;
+/// The number of onset frames on either side over which the local mean is subtracted.
+const MEAN_RADIUS: usize = 8;
+/// The number of multiples of the beat period that are used to refine the estimate.
+const REFINE_MULTIPLES: usize = 4;
+
+/// TempoEstimator accumulates the onset strength of the blocks of a spectrum.
+pub struct TempoEstimator {
+    /// The number of blocks per second.
+    block_rate: f64,
+    /// The time in seconds from the start of a block to the center of its window.
+    block_center: f64,
+    previous: Vec<f64>,
+    current: Vec<f64>,
+    onsets: Vec<f64>,
+}
+
+impl TempoEstimator {
+    /// Creates an estimator for blocks of windows of `window_size` frames that are spaced
+    /// `hop_size` frames apart.
+    pub fn new(sample_rate: u32, window_size: usize, hop_size: usize) -> TempoEstimator {
+        TempoEstimator {
+            block_rate: f64::from(sample_rate) / hop_size as f64,
+            block_center: window_size as f64 / 2.0 / f64::from(sample_rate),
+            previous: Vec::new(),
+            current: Vec::new(),
+            onsets: Vec::new(),
+        }
+    }
+
+    /// Returns the estimated tempo in beats per minute, or None if there were not enough onsets
+    /// to determine it.
+    pub fn bpm(&self) -> Option<f64> {
+        let onsets = detrend(&self.onsets);
+        let min_lag = (self.block_rate * 60.0 / MAX_BPM).floor() as usize;
+        let max_lag = (self.block_rate * 60.0 / MIN_BPM).ceil() as usize;
+        if min_lag < 2 || onsets.len() < 2 * (max_lag + 1) {
+            return None;
+        }
+
+        let score = |lag: usize| {
+            let bpm = self.block_rate * 60.0 / lag as f64;
+            let octaves = (bpm / PRIOR_BPM).log2() / PRIOR_WIDTH;
+            autocorrelation(&onsets, lag) * (-0.5 * octaves * octaves).exp()
+        };
+        let (lag, peak) =
+            (min_lag..=max_lag)
+                .map(|lag| (lag, score(lag)))
+                .fold(
+                    (0, 0.0),
+                    |best, (lag, s)| if s > best.1 { (lag, s) } else { best },
+                );
+        if peak <= 0.0 {
+            return None;
+        }
+
+        // The peak of a single lag is only accurate to a block, so the period is refined by
+        // locating the peaks at multiples of the lag as well.
+        let mut period = 0.0;
+        let mut n = 0;
+        for k in 1..=REFINE_MULTIPLES {
+            let center = k * lag;
+            if center + 1 >= onsets.len() / 2 {
+                break;
+            }
+            let best = (center - 1..=center + 1)
+                .max_by(|&a, &b| {
+                    let (ra, rb) = (autocorrelation(&onsets, a), autocorrelation(&onsets, b));
+                    ra.partial_cmp(&rb).unwrap()
+                })
+                .unwrap();
+            let offset = parabolic_peak(
+                autocorrelation(&onsets, best - 1),
+                autocorrelation(&onsets, best),
+                autocorrelation(&onsets, best + 1),
+            );
+            period += (best as f64 + offset) / k as f64;
+            n += 1;
+        }
+        Some(self.block_rate * 60.0 / (period / n as f64))
+    }
+
+    /// Returns the time in seconds of the first beat of the grid with the specified tempo that
+    /// fits the onsets best.
+    ///
+    /// The onsets are projected onto a circle that is traversed once per beat, so the direction of
+    /// their sum is the phase of the beats.
+    pub fn beat_offset(&self, bpm: f64) -> f64 {
+        let onsets = detrend(&self.onsets);
+        let period = self.block_rate * 60.0 / bpm;
+        let (re, im) = onsets
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (i, onset)| {
+                let phi = 2.0 * f64::consts::PI * i as f64 / period;
+                (re + onset * phi.cos(), im + onset * phi.sin())
+            });
+        let block = im.atan2(re) / (2.0 * f64::consts::PI) * period;
+        let beat_length = 60.0 / bpm;
+        let offset = block / self.block_rate + self.block_center;
+        (offset % beat_length + beat_length) % beat_length
+    }
+}
+
+impl SpectrumAnalyser for TempoEstimator {
+    fn process_block(&mut self, block: &stft::Block) {
+        let num_bins = block[0].len();
+        let scale = 1.0 / (num_bins - 1) as f64;
+        self.current.clear();
+        self.current.resize(num_bins, 0.0);
+        for bins in block {
+            for (c, z) in self.current.iter_mut().zip(bins) {
+                *c += (1.0 + COMPRESSION * z.norm() * scale).ln() / block.len() as f64;
+            }
+        }
+        let flux = if self.previous.is_empty() {
+            0.0
+        } else {
+            self.current
+                .iter()
+                .zip(&self.previous)
+                .map(|(c, p)| (c - p).max(0.0))
+                .sum()
+        };
+        self.onsets.push(flux);
+        mem::swap(&mut self.current, &mut self.previous);
+    }
+}
+
+/// Creates an analyser that estimates the tempo of a signal.
+pub fn analyser(sample_rate: u32, num_channels: usize) -> Spectrum<TempoEstimator> {
+    // Windows of about 40ms spaced 10ms apart.
+    let window_size = (sample_rate as usize / 25).next_power_of_two();
+    let hop_size = window_size / 4;
+    Spectrum::new(
+        TempoEstimator::new(sample_rate, window_size, hop_size),
+        num_channels,
+        stft::Window::Hann,
+        window_size,
+        hop_size,
+    )
+}
+
+/// Estimates the tempo of a complete source.
+pub fn measure<S>(source: S) -> Option<f64>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    use sample::Frame;
+    let mut analyser = analyser(source.sample_rate(), S::Item::n_channels());
+    super::feed(source, &mut analyser);
+    analyser.get_ref().bpm()
+}
+
+/// Like `measure`, but for sources of which the format is only known at runtime.
+pub fn measure_dynam(source: dynam::Source) -> Option<f64> {
+    let mut analyser = analyser(source.sample_rate(), source.num_channels() as usize);
+    super::feed_dynam(source, &mut analyser);
+    analyser.get_ref().bpm()
+}
+
+/// Subtracts the local mean from the onset strength and discards the negative part, which leaves
+/// only the onsets that stand out from their surroundings.
+fn detrend(onsets: &[f64]) -> Vec<f64> {
+    (0..onsets.len())
+        .map(|i| {
+            let start = i.saturating_sub(MEAN_RADIUS);
+            let end = cmp::min(i + MEAN_RADIUS + 1, onsets.len());
+            let mean = onsets[start..end].iter().sum::<f64>() / (end - start) as f64;
+            (onsets[i] - mean).max(0.0)
+        })
+        .collect()
+}
+
+fn autocorrelation(signal: &[f64], lag: usize) -> f64 {
+    let n = signal.len() - lag;
+    let sum: f64 = signal[..n]
+        .iter()
+        .zip(&signal[lag..])
+        .map(|(a, b)| a * b)
+        .sum();
+    sum / n as f64
+}
+
+/// Returns the offset of the vertex of the parabola through three equally spaced points relative
+/// to the center point.
+fn parabolic_peak(left: f64, center: f64, right: f64) -> f64 {
+    let denom = left - 2.0 * center + right;
+    if denom >= 0.0 {
+        return 0.0;
+    }
+    (0.5 * (left - right) / denom).max(-0.5).min(0.5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::IntoSource;
+
+    /// Generates a track of short decaying clicks, the first of which is at the offset in
+    /// seconds.
+    fn clicks(bpm: f64, offset: f64, secs: usize) -> Vec<[f64; 1]> {
+        let period = 44100.0 * 60.0 / bpm;
+        (0..44100 * secs)
+            .map(|i| {
+                let j = i as f64 - offset * 44100.0;
+                if j < 0.0 {
+                    return [0.0];
+                }
+                let t = (j % period) / 44100.0;
+                let s = (t * 1000.0 * 2.0 * f64::consts::PI).sin() * (-t * 200.0).exp();
+                [0.5 * s]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn click_track() {
+        for &expected in &[90.0, 120.0, 128.0, 140.0] {
+            let bpm = measure(clicks(expected, 0.0, 20).into_iter().source(44100)).unwrap();
+            assert!((bpm - expected).abs() < 1.0, "{} != {}", bpm, expected);
+        }
+    }
+
+    #[test]
+    fn silence() {
+        let signal = iter::repeat([0.0f64; 2]).take(44100 * 10);
+        assert_eq!(None, measure(signal.source(44100)));
+    }
+
+    #[test]
+    fn too_short() {
+        assert_eq!(
+            None,
+            measure(clicks(120.0, 0.0, 1).into_iter().source(44100))
+        );
+    }
+
+    #[test]
+    fn beat_offset() {
+        for &(bpm, offset) in &[(90.0, 0.3), (128.0, 0.05), (140.0, 0.2)] {
+            let mut analyser = analyser(44100, 1);
+            crate::analysis::feed(
+                clicks(bpm, offset, 20).into_iter().source(44100),
+                &mut analyser,
+            );
+            let tempo = analyser.get_ref();
+            let estimate = tempo.beat_offset(tempo.bpm().unwrap());
+            assert!(
+                (estimate - offset).abs() < 0.02,
+                "{} != {}",
+                estimate,
+                offset
+            );
+        }
+    }
+}
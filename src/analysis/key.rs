@@ -0,0 +1,376 @@
+//!
+//! This module estimates the musical key of a signal.
+//!
+//! The magnitude spectrum of each window is folded into a chromagram: the energy of each of the 12
+//! pitch classes regardless of octave. The chromagram of the whole signal is then correlated with
+//! the Krumhansl-Kessler key profiles, which describe how well each pitch class fits in a major or
+//! minor key, for each of the 12 possible tonics.
+//!
+
+use super::{Spectrum, SpectrumAnalyser};
+use crate::audio::*;
+use crate::filter::stft;
+use rusqlite as sqlite;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Value, ValueRef};
+use sample;
+use std::*;
+
+/// The frequency range that is taken into account. Lower notes can not be told apart by the
+/// spectrum, higher frequencies mostly consist of overtones and noise.
+const MIN_FREQUENCY: f64 = 100.0;
+const MAX_FREQUENCY: f64 = 5000.0;
+
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// A musical key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// The pitch class of the tonic, starting at 0 for C.
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    pub fn new(tonic: u8, mode: Mode) -> Key {
+        Key {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    /// Returns the position of this key on the Camelot wheel, where keys that are a fifth apart
+    /// are adjacent. Minor keys are denoted by A and major keys by B, e.g. 8A for A minor and 8B
+    /// for C major.
+    pub fn camelot(&self) -> (u8, char) {
+        let (major_tonic, letter) = match self.mode {
+            Mode::Major => (self.tonic, 'B'),
+            Mode::Minor => ((self.tonic + 3) % 12, 'A'),
+        };
+        ((major_tonic * 7 + 7) % 12 + 1, letter)
+    }
+
+    /// Returns the minor key that shares its notes with a major key and vice versa.
+    pub fn relative(&self) -> Key {
+        match self.mode {
+            Mode::Major => Key::new(self.tonic + 9, Mode::Minor),
+            Mode::Minor => Key::new(self.tonic + 3, Mode::Major),
+        }
+    }
+
+    /// Returns the keys that mix harmonically with this key: the key itself, its relative key and
+    /// the keys a fifth above and below.
+    pub fn compatible(&self) -> [Key; 4] {
+        [
+            *self,
+            self.relative(),
+            Key::new(self.tonic + 7, self.mode),
+            Key::new(self.tonic + 5, self.mode),
+        ]
+    }
+}
+
+/// Keys are ordered by their position on the Camelot wheel.
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> cmp::Ordering {
+        self.camelot().cmp(&other.camelot())
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Major => "major",
+            Mode::Minor => "minor",
+        };
+        write!(f, "{} {}", PITCH_CLASSES[self.tonic as usize], mode)
+    }
+}
+
+/// Parses keys written like "C major", "F#m", "Bb" or in Camelot notation like "8A".
+impl str::FromStr for Key {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Key, ParseError> {
+        let s = s.trim();
+        if let Some((i, letter)) = s.char_indices().last() {
+            if let Ok(number) = s[..i].parse::<u8>() {
+                let major_tonic = match number {
+                    1...12 => ((number + 4) * 7) % 12,
+                    _ => return Err(ParseError::Unmatched),
+                };
+                return match letter.to_ascii_uppercase() {
+                    'A' => Ok(Key::new(major_tonic, Mode::Major).relative()),
+                    'B' => Ok(Key::new(major_tonic, Mode::Major)),
+                    _ => Err(ParseError::Unmatched),
+                };
+            }
+        }
+
+        let mut chars = s.chars();
+        let mut tonic = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(ParseError::Unmatched),
+        };
+        let mut rest = chars.as_str();
+        if rest.starts_with('#') {
+            tonic += 1;
+            rest = &rest[1..];
+        } else if rest.starts_with('b') {
+            tonic += 11;
+            rest = &rest[1..];
+        }
+        let mode = match rest.trim().to_lowercase().as_str() {
+            "" | "maj" | "major" => Mode::Major,
+            "m" | "min" | "minor" => Mode::Minor,
+            _ => return Err(ParseError::Unmatched),
+        };
+        Ok(Key::new(tonic, mode))
+    }
+}
+
+impl ToSql for Key {
+    fn to_sql(&self) -> Result<ToSqlOutput, sqlite::Error> {
+        Ok(ToSqlOutput::Owned(Value::Text(self.to_string())))
+    }
+}
+
+impl FromSql for Key {
+    fn column_result(value: ValueRef) -> FromSqlResult<Key> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|err| FromSqlError::Other(Box::from(err)))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    /// The input text was not matched.
+    Unmatched,
+}
+
+/// KeyEstimator accumulates the chromagram of the blocks of a spectrum.
+pub struct KeyEstimator {
+    /// The pitch class of each bin if it lies within the analysed frequency range.
+    classes: Vec<Option<usize>>,
+    chroma: [f64; 12],
+}
+
+impl KeyEstimator {
+    /// Creates an estimator for blocks of windows of `window_size` frames.
+    pub fn new(sample_rate: u32, window_size: usize) -> KeyEstimator {
+        let classes = (0..=window_size / 2)
+            .map(|bin| {
+                let freq = bin as f64 * f64::from(sample_rate) / window_size as f64;
+                if freq < MIN_FREQUENCY || freq > MAX_FREQUENCY {
+                    return None;
+                }
+                let semitones_from_a = (12.0 * (freq / 440.0).log2()).round() as i64;
+                Some(((semitones_from_a + 9) % 12 + 12) as usize % 12)
+            })
+            .collect();
+        KeyEstimator {
+            classes,
+            chroma: [0.0; 12],
+        }
+    }
+
+    /// Returns the energy of each pitch class starting at C.
+    pub fn chroma(&self) -> &[f64; 12] {
+        &self.chroma
+    }
+
+    /// Returns the key that fits best with the chromagram, or None if the signal was silent.
+    pub fn key(&self) -> Option<Key> {
+        if self.chroma.iter().all(|&c| c == 0.0) {
+            return None;
+        }
+        let candidates = [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)];
+        let mut best = (Key::new(0, Mode::Major), f64::NEG_INFINITY);
+        for &(mode, profile) in &candidates {
+            for tonic in 0..12 {
+                let rotated: Vec<f64> = (0..12).map(|i| profile[(i + 12 - tonic) % 12]).collect();
+                let r = correlation(&self.chroma, &rotated);
+                if r > best.1 {
+                    best = (Key::new(tonic as u8, mode), r);
+                }
+            }
+        }
+        Some(best.0)
+    }
+}
+
+impl SpectrumAnalyser for KeyEstimator {
+    fn process_block(&mut self, block: &stft::Block) {
+        for bins in block {
+            for (class, z) in self.classes.iter().zip(bins) {
+                if let Some(class) = *class {
+                    self.chroma[class] += z.norm();
+                }
+            }
+        }
+    }
+}
+
+/// Creates an analyser that estimates the key of a signal.
+pub fn analyser(sample_rate: u32, num_channels: usize) -> Spectrum<KeyEstimator> {
+    // Windows of about 200ms, which resolves semitones down to the lower limit.
+    let window_size = (sample_rate as usize / 6).next_power_of_two();
+    Spectrum::new(
+        KeyEstimator::new(sample_rate, window_size),
+        num_channels,
+        stft::Window::Hann,
+        window_size,
+        window_size / 2,
+    )
+}
+
+/// Estimates the key of a complete source.
+pub fn measure<S>(source: S) -> Option<Key>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    use sample::Frame;
+    let mut analyser = analyser(source.sample_rate(), S::Item::n_channels());
+    super::feed(source, &mut analyser);
+    analyser.get_ref().key()
+}
+
+/// Like `measure`, but for sources of which the format is only known at runtime.
+pub fn measure_dynam(source: dynam::Source) -> Option<Key> {
+    let mut analyser = analyser(source.sample_rate(), source.num_channels() as usize);
+    super::feed_dynam(source, &mut analyser);
+    analyser.get_ref().key()
+}
+
+/// Computes the Pearson correlation coefficient of two series.
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    if var_a == 0.0 || var_b == 0.0 {
+        return 0.0;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::IntoSource;
+
+    /// Generates a chord of sines with the specified frequencies and amplitudes.
+    fn chord(notes: &[(f64, f64)], secs: usize) -> Vec<[f64; 1]> {
+        (0..44100 * secs)
+            .map(|i| {
+                let t = i as f64 / 44100.0;
+                let s: f64 = notes
+                    .iter()
+                    .map(|&(freq, amp)| amp * (t * freq * 2.0 * f64::consts::PI).sin())
+                    .sum();
+                [s * 0.1]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn c_major() {
+        // A C major triad over C, with the rest of the scale softly in the background.
+        let notes = [
+            (261.63, 1.0),
+            (523.25, 1.0),
+            (329.63, 1.0),
+            (392.00, 1.0),
+            (293.66, 0.25),
+            (349.23, 0.25),
+            (440.00, 0.25),
+            (493.88, 0.25),
+        ];
+        let key = measure(chord(&notes, 5).into_iter().source(44100));
+        assert_eq!(Some(Key::new(0, Mode::Major)), key);
+    }
+
+    #[test]
+    fn a_minor() {
+        let notes = [
+            (220.00, 1.0),
+            (440.00, 1.0),
+            (261.63, 1.0),
+            (329.63, 1.0),
+            (246.94, 0.25),
+            (293.66, 0.25),
+            (349.23, 0.25),
+            (392.00, 0.25),
+        ];
+        let key = measure(chord(&notes, 5).into_iter().source(44100));
+        assert_eq!(Some(Key::new(9, Mode::Minor)), key);
+    }
+
+    #[test]
+    fn silence() {
+        let signal = iter::repeat([0.0f64; 2]).take(44100 * 2);
+        assert_eq!(None, measure(signal.source(44100)));
+    }
+
+    #[test]
+    fn camelot() {
+        assert_eq!((8, 'B'), Key::new(0, Mode::Major).camelot());
+        assert_eq!((8, 'A'), Key::new(9, Mode::Minor).camelot());
+        assert_eq!((9, 'B'), Key::new(7, Mode::Major).camelot());
+        assert_eq!((7, 'B'), Key::new(5, Mode::Major).camelot());
+        assert_eq!((1, 'B'), Key::new(11, Mode::Major).camelot());
+        assert_eq!((5, 'A'), Key::new(0, Mode::Minor).camelot());
+        for tonic in 0..12 {
+            for &mode in &[Mode::Major, Mode::Minor] {
+                let key = Key::new(tonic, mode);
+                let (number, letter) = key.camelot();
+                let camelot = format!("{}{}", number, letter);
+                assert_eq!(key, camelot.parse().unwrap());
+                assert_eq!(key, key.to_string().parse().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Key::new(6, Mode::Minor), "F#m".parse().unwrap());
+        assert_eq!(Key::new(10, Mode::Major), "Bb".parse().unwrap());
+        assert_eq!(Key::new(3, Mode::Minor), "eb minor".parse().unwrap());
+        assert!("H major".parse::<Key>().is_err());
+        assert!("13A".parse::<Key>().is_err());
+        assert!("C dorian".parse::<Key>().is_err());
+    }
+}
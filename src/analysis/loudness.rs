@@ -0,0 +1,425 @@
+//!
+//! This module implements the measurement of loudness as specified by ITU-R BS.1770-4 and EBU
+//! R128: the integrated loudness in LUFS and the true peak of a signal.
+//!
+//! The loudness of the gating blocks is kept in a histogram instead of a list, so the
+//! measurements of multiple tracks can be cheaply combined to obtain the loudness of an album.
+//!
+
+use super::Analyser;
+use crate::audio::*;
+use crate::filter::biquad::{Biquad, Coefficients};
+use byteorder::{ByteOrder, LittleEndian};
+use sample;
+use std::*;
+
+/// The loudness that ReplayGain 2.0 normalizes to.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+/// Blocks quieter than this are not taken into account.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks that are this much quieter than the ungated loudness are not taken into account.
+const RELATIVE_GATE: f64 = -10.0;
+/// The resolution of the histogram in LU.
+const BIN_WIDTH: f64 = 0.1;
+/// The histogram spans 100 LU starting at the absolute gate. Louder blocks are put in the last
+/// bin.
+const NUM_BINS: usize = 1000;
+
+/// The factor by which the signal is oversampled to find the true peak.
+const OVERSAMPLING: usize = 4;
+/// The number of input samples used to interpolate a single sample.
+const INTERPOLATION_TAPS: usize = 12;
+
+/// The result of a loudness measurement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loudness {
+    histogram: Vec<u32>,
+    /// The linear true peak of the signal.
+    pub peak: f64,
+}
+
+impl Loudness {
+    /// Returns the integrated loudness in LUFS, or None if the signal is too quiet to be measured.
+    pub fn integrated(&self) -> Option<f64> {
+        let (sum, count) = self.energy_above(ABSOLUTE_GATE);
+        if count == 0 {
+            return None;
+        }
+        let gate = energy_to_loudness(sum / count as f64) + RELATIVE_GATE;
+        let (sum, count) = self.energy_above(gate);
+        Some(energy_to_loudness(sum / count as f64))
+    }
+
+    /// Returns the ReplayGain in dB, the gain that brings the signal to the reference loudness.
+    pub fn gain(&self) -> Option<f64> {
+        self.integrated().map(|l| REFERENCE_LOUDNESS - l)
+    }
+
+    fn energy_above(&self, gate: f64) -> (f64, u64) {
+        self.histogram
+            .iter()
+            .enumerate()
+            .filter(|&(_, &n)| n > 0)
+            .map(|(i, &n)| (bin_loudness(i), n))
+            .filter(|&(l, _)| l >= gate)
+            .fold((0.0, 0), |(sum, count), (l, n)| {
+                (
+                    sum + loudness_to_energy(l) * f64::from(n),
+                    count + u64::from(n),
+                )
+            })
+    }
+
+    /// Combines measurements, as if the signals were measured as a whole.
+    pub fn merge<'a, I>(items: I) -> Loudness
+    where
+        I: IntoIterator<Item = &'a Loudness>,
+    {
+        items.into_iter().fold(
+            Loudness {
+                histogram: vec![0; NUM_BINS],
+                peak: 0.0,
+            },
+            |mut acc, l| {
+                for (a, b) in acc.histogram.iter_mut().zip(l.histogram.iter()) {
+                    *a += *b;
+                }
+                acc.peak = acc.peak.max(l.peak);
+                acc
+            },
+        )
+    }
+
+    /// Encodes the histogram so it can be stored.
+    pub fn histogram_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0; NUM_BINS * 4];
+        LittleEndian::write_u32_into(&self.histogram, &mut buf);
+        buf
+    }
+
+    /// Reconstructs a measurement from its stored histogram and peak.
+    pub fn from_histogram_bytes(buf: &[u8], peak: f64) -> Option<Loudness> {
+        if buf.len() != NUM_BINS * 4 {
+            return None;
+        }
+        let mut histogram = vec![0; NUM_BINS];
+        LittleEndian::read_u32_into(buf, &mut histogram);
+        Some(Loudness { histogram, peak })
+    }
+}
+
+/// Meter measures the loudness and true peak of a signal.
+pub struct Meter {
+    num_channels: usize,
+    /// The K-weighting filters.
+    filters: [Biquad; 2],
+    /// The K-weighted samples of the current frame.
+    weighted: Vec<f64>,
+    /// The number of frames in a 100ms sub-block.
+    sub_block_len: usize,
+    /// The number of frames in the current sub-block.
+    sub_block_pos: usize,
+    /// The sum of the squares of the weighted samples of the current sub-block.
+    sub_block_sum: f64,
+    /// The mean squares of the most recent sub-blocks. Four consecutive sub-blocks make up a
+    /// 400ms gating block, so the blocks overlap by 75%.
+    sub_blocks: [f64; 4],
+    num_sub_blocks: usize,
+    histogram: Vec<u32>,
+
+    /// The most recent samples of each channel, used to interpolate the true peak.
+    history: Vec<collections::VecDeque<f64>>,
+    /// The phases of the interpolation filter that yield the samples in between the original
+    /// samples.
+    interpolation: Vec<Vec<f64>>,
+    peak: f64,
+}
+
+impl Meter {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Meter {
+        Meter {
+            num_channels,
+            filters: k_weighting_filters(sample_rate, num_channels),
+            weighted: vec![0.0; num_channels],
+            sub_block_len: (sample_rate / 10) as usize,
+            sub_block_pos: 0,
+            sub_block_sum: 0.0,
+            sub_blocks: [0.0; 4],
+            num_sub_blocks: 0,
+            histogram: vec![0; NUM_BINS],
+            history: (0..num_channels)
+                .map(|_| iter::repeat(0.0).take(INTERPOLATION_TAPS + 1).collect())
+                .collect(),
+            interpolation: interpolation_filter(),
+            peak: 0.0,
+        }
+    }
+
+    /// Returns the measurement of all frames processed so far. Frames of the last incomplete
+    /// gating block are not included.
+    pub fn loudness(&self) -> Loudness {
+        Loudness {
+            histogram: self.histogram.clone(),
+            peak: self.peak,
+        }
+    }
+
+    fn true_peak(&mut self, ch: usize, s: f64) -> f64 {
+        let history = &mut self.history[ch];
+        history.pop_front();
+        history.push_back(s);
+        self.interpolation
+            .iter()
+            .map(|phase| {
+                phase
+                    .iter()
+                    .zip(history.iter().rev())
+                    .map(|(h, x)| h * x)
+                    .sum::<f64>()
+                    .abs()
+            })
+            .fold(s.abs(), f64::max)
+    }
+}
+
+impl Analyser for Meter {
+    fn process(&mut self, frame: &[f64]) {
+        assert_eq!(self.num_channels, frame.len());
+        for (ch, &s) in frame.iter().enumerate() {
+            let peak = self.true_peak(ch, s);
+            self.peak = self.peak.max(peak);
+        }
+        self.weighted.copy_from_slice(frame);
+        for filter in self.filters.iter_mut() {
+            filter.process(&mut self.weighted);
+        }
+        // The channel weights are 1.0 for all channels but the surround channels, which are not
+        // supported.
+        self.sub_block_sum += self.weighted.iter().map(|y| y * y).sum::<f64>();
+
+        self.sub_block_pos += 1;
+        if self.sub_block_pos < self.sub_block_len {
+            return;
+        }
+        self.sub_blocks[self.num_sub_blocks % 4] = self.sub_block_sum / self.sub_block_len as f64;
+        self.num_sub_blocks += 1;
+        self.sub_block_pos = 0;
+        self.sub_block_sum = 0.0;
+        if self.num_sub_blocks < 4 {
+            return;
+        }
+        let block = energy_to_loudness(self.sub_blocks.iter().sum::<f64>() / 4.0);
+        if block >= ABSOLUTE_GATE {
+            let bin = ((block - ABSOLUTE_GATE) / BIN_WIDTH) as usize;
+            self.histogram[cmp::min(bin, NUM_BINS - 1)] += 1;
+        }
+    }
+}
+
+/// Measures the loudness of a complete source.
+pub fn measure<S>(source: S) -> Loudness
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    use sample::Frame;
+    let mut meter = Meter::new(source.sample_rate(), S::Item::n_channels());
+    super::feed(source, &mut meter);
+    meter.loudness()
+}
+
+/// Like `measure`, but for sources of which the format is only known at runtime.
+pub fn measure_dynam(source: dynam::Source) -> Loudness {
+    let mut meter = Meter::new(source.sample_rate(), source.num_channels() as usize);
+    super::feed_dynam(source, &mut meter);
+    meter.loudness()
+}
+
+/// Converts the mean square of a K-weighted signal to LUFS.
+pub fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.max(1e-20).log10()
+}
+
+fn loudness_to_energy(loudness: f64) -> f64 {
+    10.0f64.powf((loudness + 0.691) / 10.0)
+}
+
+/// Returns the loudness at the center of a bin of the histogram.
+fn bin_loudness(bin: usize) -> f64 {
+    ABSOLUTE_GATE + (bin as f64 + 0.5) * BIN_WIDTH
+}
+
+/// Computes the coefficients of the K-weighting filter for any sample rate. The filter consists
+/// of a high shelf which models the acoustic effect of the head, followed by a high pass.
+///
+/// The analog prototypes are those from which the 48kHz coefficients of BS.1770 are derived.
+pub fn k_weighting(sample_rate: u32) -> [Coefficients; 2] {
+    let fs = f64::from(sample_rate);
+
+    let (f0, gain, q) = (
+        1681.974_450_955_533,
+        3.999_843_853_973_347,
+        0.707_175_236_955_419_6,
+    );
+    let k = (f64::consts::PI * f0 / fs).tan();
+    let vh = 10.0f64.powf(gain / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Coefficients {
+        b0: (vh + vb * k / q + k * k) / a0,
+        b1: 2.0 * (k * k - vh) / a0,
+        b2: (vh - vb * k / q + k * k) / a0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+
+    let (f0, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+    let k = (f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Coefficients {
+        b0: 1.0,
+        b1: -2.0,
+        b2: 1.0,
+        a1: 2.0 * (k * k - 1.0) / a0,
+        a2: (1.0 - k / q + k * k) / a0,
+    };
+    [shelf, highpass]
+}
+
+/// Creates the K-weighting filters for frames of the specified number of channels.
+pub fn k_weighting_filters(sample_rate: u32, num_channels: usize) -> [Biquad; 2] {
+    let [shelf, highpass] = k_weighting(sample_rate);
+    [
+        Biquad::new(shelf, num_channels),
+        Biquad::new(highpass, num_channels),
+    ]
+}
+
+/// Computes a Hann windowed sinc interpolation filter, split into its phases. Each phase is
+/// normalized to unity gain at DC.
+fn interpolation_filter() -> Vec<Vec<f64>> {
+    let len = OVERSAMPLING * INTERPOLATION_TAPS + 1;
+    let center = (len / 2) as f64;
+    let h: Vec<f64> = (0..len)
+        .map(|n| {
+            let x = (n as f64 - center) / OVERSAMPLING as f64;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (f64::consts::PI * x).sin() / (f64::consts::PI * x)
+            };
+            let window = 0.5 * (1.0 - (2.0 * f64::consts::PI * n as f64 / (len - 1) as f64).cos());
+            sinc * window
+        })
+        .collect();
+    (1..OVERSAMPLING)
+        .map(|phase| {
+            let taps: Vec<f64> = h
+                .iter()
+                .skip(phase)
+                .step_by(OVERSAMPLING)
+                .cloned()
+                .collect();
+            let sum: f64 = taps.iter().sum();
+            taps.into_iter().map(|t| t / sum).collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::audio::IntoSource;
+
+    /// Returns a 1kHz stereo sine at 48kHz.
+    fn tone(amplitude: f64, secs: u64) -> Generator<[f64; 2]> {
+        let mut gen = Generator::new(Waveform::Sine(1000.0), 48000, 48000 * secs);
+        gen.amplitude = amplitude;
+        gen
+    }
+
+    #[test]
+    fn k_weighting_48khz() {
+        // The coefficients as published in BS.1770.
+        let [shelf, highpass] = k_weighting(48000);
+        let expected_b = [
+            1.535_124_859_586_97,
+            -2.691_696_189_406_38,
+            1.198_392_810_852_85,
+        ];
+        let expected_a = [-1.690_659_293_182_41, 0.732_480_774_215_85];
+        for (a, b) in [shelf.b0, shelf.b1, shelf.b2].iter().zip(expected_b.iter()) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+        for (a, b) in [shelf.a1, shelf.a2].iter().zip(expected_a.iter()) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+        assert!((highpass.a1 + 1.990_047_454_833_98).abs() < 1e-6);
+        assert!((highpass.a2 - 0.990_072_250_366_21).abs() < 1e-6);
+    }
+
+    #[test]
+    fn integrated() {
+        // A 1kHz stereo sine at -23dBFS measures -23 LUFS.
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let loudness = measure(tone(amplitude, 10));
+        let lufs = loudness.integrated().unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "{}", lufs);
+        assert!((loudness.gain().unwrap() - 5.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn gating() {
+        // Silence and quiet passages do not affect the loudness.
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let signal: Vec<_> = tone(amplitude, 10)
+            .chain(tone(0.0, 10))
+            .chain(tone(amplitude * 0.01, 10))
+            .collect();
+        let lufs = measure(signal.into_iter().source(48000))
+            .integrated()
+            .unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "{}", lufs);
+
+        let silence = measure(Generator::<[f64; 2]>::new(Waveform::Silence, 48000, 48000));
+        assert_eq!(None, silence.integrated());
+    }
+
+    #[test]
+    fn true_peak() {
+        // The samples of a sine at an eighth of the sample rate that is offset by half a sample
+        // never hit the top of the wave. The generator always starts at a phase of zero, so the
+        // offset sine is computed here.
+        let signal: Vec<[f64; 2]> = (0..48000)
+            .map(|i| {
+                let s = (f64::from(i) / 8.0 * 2.0 * f64::consts::PI + f64::consts::PI / 8.0).sin();
+                [s, s]
+            })
+            .collect();
+        let sample_peak = signal.iter().fold(0.0f64, |p, f| p.max(f[0].abs()));
+        assert!(sample_peak < 0.93);
+        let loudness = measure(signal.into_iter().source(48000));
+        assert!((loudness.peak - 1.0).abs() < 0.01, "{}", loudness.peak);
+    }
+
+    #[test]
+    fn merge() {
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let a = measure(tone(amplitude, 5));
+        let b = measure(tone(amplitude * 0.5, 5));
+        let merged = Loudness::merge(&[a.clone(), b.clone()]);
+        let (la, lb) = (a.integrated().unwrap(), b.integrated().unwrap());
+        let lm = merged.integrated().unwrap();
+        assert!(lb < lm && lm < la);
+        assert_eq!(a.peak, merged.peak);
+
+        let bytes = merged.histogram_bytes();
+        assert_eq!(
+            Some(merged.clone()),
+            Loudness::from_histogram_bytes(&bytes, merged.peak)
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//!
+//! Level meters for display purposes: the peak and RMS of each channel and the momentary and
+//! short-term loudness as specified by EBU R128.
+//!
+//! A meter is inserted into a signal pipeline and publishes its readings to a `Snapshot` at a
+//! fixed interval. Readers never block the audio, which only touches a few atomics per update
+//! instead of locking a mutex for every frame.
+//!
+
+use super::loudness;
+use super::Analyser;
+use crate::audio::*;
+use crate::filter::biquad::Biquad;
+use sample::{self, Frame, Sample};
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::*;
+
+/// The duration of a block, which is both the resolution of the sliding windows and the interval
+/// at which the snapshot is updated.
+const BLOCK_DURATION: time::Duration = time::Duration::from_millis(10);
+/// The window over which the peak and RMS are taken in blocks, the integration time of a VU meter.
+const LEVEL_BLOCKS: usize = 30;
+/// The window of the momentary loudness in blocks.
+const MOMENTARY_BLOCKS: usize = 40;
+/// The window of the short-term loudness in blocks.
+const SHORT_TERM_BLOCKS: usize = 300;
+
+/// The readings of a meter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Levels {
+    /// The linear sample peak of each channel over the last 300ms.
+    pub peak: Vec<f64>,
+    /// The linear RMS of each channel over the last 300ms.
+    pub rms: Vec<f64>,
+    /// The loudness in LUFS over the last 400ms.
+    pub momentary: f64,
+    /// The loudness in LUFS over the last 3s.
+    pub short_term: f64,
+}
+
+/// Snapshot holds the most recent readings of a meter. It can be cloned and read from any thread.
+///
+/// The values are stored as atomics and guarded by a sequence number, which is odd while an
+/// update is in progress. A reader retries if the sequence changed while it was reading, so it
+/// always observes a consistent set of readings. There must be only a single writer.
+#[derive(Clone)]
+pub struct Snapshot {
+    num_channels: usize,
+    sequence: Arc<AtomicUsize>,
+    /// The momentary and short-term loudness followed by the peak and RMS of each channel, as
+    /// the bits of 32 bit floats.
+    values: Arc<Vec<AtomicUsize>>,
+}
+
+impl Snapshot {
+    pub fn new(num_channels: usize) -> Snapshot {
+        let silence = loudness::energy_to_loudness(0.0) as f32;
+        let values = (0..2 + num_channels * 2)
+            .map(|i| {
+                let v = if i < 2 { silence } else { 0.0 };
+                AtomicUsize::new(v.to_bits() as usize)
+            })
+            .collect();
+        Snapshot {
+            num_channels,
+            sequence: Arc::new(AtomicUsize::new(0)),
+            values: Arc::new(values),
+        }
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    /// Returns the most recently published readings.
+    pub fn levels(&self) -> Levels {
+        let mut values = vec![0.0; self.values.len()];
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                thread::yield_now();
+                continue;
+            }
+            for (v, a) in values.iter_mut().zip(self.values.iter()) {
+                *v = f64::from(f32::from_bits(a.load(Ordering::Relaxed) as u32));
+            }
+            atomic::fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == before {
+                break;
+            }
+        }
+        Levels {
+            momentary: values[0],
+            short_term: values[1],
+            peak: values[2..].iter().step_by(2).cloned().collect(),
+            rms: values[3..].iter().step_by(2).cloned().collect(),
+        }
+    }
+
+    fn publish(&self, momentary: f64, short_term: f64, peak: &[f64], rms: &[f64]) {
+        let seq = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(seq.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+        let store = |i: usize, v: f64| {
+            self.values[i].store((v as f32).to_bits() as usize, Ordering::Relaxed);
+        };
+        store(0, momentary);
+        store(1, short_term);
+        for (ch, (&p, &r)) in peak.iter().zip(rms).enumerate() {
+            store(2 + ch * 2, p);
+            store(3 + ch * 2, r);
+        }
+        self.sequence.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
+/// LevelMeter measures a signal and publishes its readings to a snapshot.
+pub struct LevelMeter {
+    snapshot: Snapshot,
+    /// The K-weighting filters.
+    filters: [Biquad; 2],
+    /// The K-weighted samples of the current frame.
+    filtered: Vec<f64>,
+    block_len: usize,
+    block_pos: usize,
+    /// The peak and sum of squares of each channel in the current block.
+    block_peak: Vec<f64>,
+    block_squares: Vec<f64>,
+    /// The sum of the squares of the weighted samples of all channels in the current block.
+    block_weighted: f64,
+
+    /// Ring buffers of the values of the most recent blocks, indexed by the block count.
+    peaks: Vec<Vec<f64>>,
+    squares: Vec<Vec<f64>>,
+    weighted: Vec<f64>,
+    num_blocks: usize,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32, snapshot: Snapshot) -> LevelMeter {
+        let num_channels = snapshot.num_channels();
+        LevelMeter {
+            filters: loudness::k_weighting_filters(sample_rate, num_channels),
+            filtered: vec![0.0; num_channels],
+            block_len: frames_of(sample_rate, BLOCK_DURATION).max(1) as usize,
+            block_pos: 0,
+            block_peak: vec![0.0; num_channels],
+            block_squares: vec![0.0; num_channels],
+            block_weighted: 0.0,
+            peaks: vec![vec![0.0; num_channels]; LEVEL_BLOCKS],
+            squares: vec![vec![0.0; num_channels]; LEVEL_BLOCKS],
+            weighted: vec![0.0; SHORT_TERM_BLOCKS],
+            num_blocks: 0,
+            snapshot,
+        }
+    }
+
+    fn end_block(&mut self) {
+        let i = self.num_blocks;
+        self.num_blocks += 1;
+        self.peaks[i % LEVEL_BLOCKS].copy_from_slice(&self.block_peak);
+        self.squares[i % LEVEL_BLOCKS].copy_from_slice(&self.block_squares);
+        self.weighted[i % SHORT_TERM_BLOCKS] = self.block_weighted;
+        self.block_pos = 0;
+        self.block_weighted = 0.0;
+        for (p, s) in self
+            .block_peak
+            .iter_mut()
+            .zip(self.block_squares.iter_mut())
+        {
+            *p = 0.0;
+            *s = 0.0;
+        }
+
+        let num_channels = self.block_peak.len();
+        let level_len = (LEVEL_BLOCKS * self.block_len) as f64;
+        let peak: Vec<f64> = (0..num_channels)
+            .map(|ch| self.peaks.iter().map(|b| b[ch]).fold(0.0, f64::max))
+            .collect();
+        let rms: Vec<f64> = (0..num_channels)
+            .map(|ch| (self.squares.iter().map(|b| b[ch]).sum::<f64>() / level_len).sqrt())
+            .collect();
+        let window = |len: usize| {
+            let sum: f64 = (0..len)
+                .map(|n| self.weighted[(i + SHORT_TERM_BLOCKS - n) % SHORT_TERM_BLOCKS])
+                .sum();
+            loudness::energy_to_loudness(sum / (len * self.block_len) as f64)
+        };
+        self.snapshot.publish(
+            window(MOMENTARY_BLOCKS),
+            window(SHORT_TERM_BLOCKS),
+            &peak,
+            &rms,
+        );
+    }
+}
+
+impl Analyser for LevelMeter {
+    fn process(&mut self, frame: &[f64]) {
+        assert_eq!(self.filtered.len(), frame.len());
+        for (ch, &s) in frame.iter().enumerate() {
+            self.block_peak[ch] = self.block_peak[ch].max(s.abs());
+            self.block_squares[ch] += s * s;
+        }
+        self.filtered.copy_from_slice(frame);
+        for filter in self.filters.iter_mut() {
+            filter.process(&mut self.filtered);
+        }
+        self.block_weighted += self.filtered.iter().map(|y| y * y).sum::<f64>();
+        self.block_pos += 1;
+        if self.block_pos == self.block_len {
+            self.end_block();
+        }
+    }
+}
+
+/// Metered passes a source through unaltered while measuring it.
+pub struct Metered<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+{
+    input: S,
+    meter: LevelMeter,
+    buf: Vec<f64>,
+}
+
+impl<S> iter::Iterator for Metered<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.input.next()?;
+        for (ch, s) in self.buf.iter_mut().enumerate() {
+            *s = frame.channel(ch).unwrap().to_sample();
+        }
+        self.meter.process(&self.buf);
+        Some(frame)
+    }
+}
+
+impl<S> Source for Metered<S>
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait IntoMeter: Source + Sized
+where
+    Self::Item: sample::Frame,
+{
+    /// Measures the source, publishing the readings to the snapshot. The snapshot must have been
+    /// created for the number of channels of the source.
+    fn meter(self, snapshot: Snapshot) -> Metered<Self> {
+        let num_channels = Self::Item::n_channels();
+        assert_eq!(num_channels, snapshot.num_channels());
+        Metered {
+            meter: LevelMeter::new(self.sample_rate(), snapshot),
+            input: self,
+            buf: vec![0.0; num_channels],
+        }
+    }
+}
+
+impl<T> IntoMeter for T
+where
+    T: Source,
+    T::Item: sample::Frame,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+
+    #[test]
+    fn sine() {
+        // A 1kHz stereo sine at -23dBFS measures -23 LUFS.
+        let amplitude = 10.0f64.powf(-23.0 / 20.0);
+        let mut gen = Generator::<[f32; 2]>::new(Waveform::Sine(1000.0), 48000, 48000 * 4);
+        gen.amplitude = amplitude;
+        let snapshot = Snapshot::new(2);
+        let frames: Vec<_> = gen.meter(snapshot.clone()).collect();
+        assert_eq!(48000 * 4, frames.len());
+
+        let levels = snapshot.levels();
+        for ch in 0..2 {
+            assert!((levels.peak[ch] - amplitude).abs() < 1e-3);
+            assert!((levels.rms[ch] - amplitude / 2f64.sqrt()).abs() < 1e-3);
+        }
+        assert!(
+            (levels.momentary + 23.0).abs() < 0.1,
+            "{}",
+            levels.momentary
+        );
+        assert!(
+            (levels.short_term + 23.0).abs() < 0.1,
+            "{}",
+            levels.short_term
+        );
+    }
+
+    #[test]
+    fn sliding_window() {
+        // One second of a loud sine followed by silence. The momentary loudness falls to silence
+        // after 400ms, while the short-term loudness still includes the sine.
+        let snapshot = Snapshot::new(1);
+        let signal = Generator::<[f64; 1]>::new(Waveform::Sine(1000.0), 48000, 48000)
+            .chain(Generator::new(Waveform::Silence, 48000, 24000))
+            .source(48000);
+        signal.meter(snapshot.clone()).for_each(|_| ());
+        let levels = snapshot.levels();
+        assert_eq!(0.0, levels.peak[0]);
+        assert!(levels.momentary < -100.0, "{}", levels.momentary);
+        assert!(levels.short_term > -10.0, "{}", levels.short_term);
+    }
+
+    #[test]
+    fn concurrent_reads() {
+        let snapshot = Snapshot::new(2);
+        let reader = snapshot.clone();
+        let thread = thread::spawn(move || {
+            for _ in 0..10_000 {
+                let levels = reader.levels();
+                // Every update writes the same value to all fields.
+                assert!(levels
+                    .peak
+                    .iter()
+                    .chain(&levels.rms)
+                    .all(|&v| v == levels.peak[0]));
+            }
+        });
+        for i in 0..100_000 {
+            let v = f64::from(i % 1000);
+            snapshot.publish(v, v, &[v, v], &[v, v]);
+        }
+        thread.join().unwrap();
+    }
+}
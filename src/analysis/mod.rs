@@ -0,0 +1,178 @@
+use crate::audio::*;
+use crate::filter::{fft, stft};
+use sample::{self, Frame, Sample};
+use std::*;
+
+pub mod bands;
+pub mod bpm;
+pub mod key;
+pub mod loudness;
+pub mod meter;
+pub mod spectrogram;
+pub mod waveform;
+
+/// Analyser is implemented by types that measure properties of a signal by inspecting it one
+/// frame at a time.
+pub trait Analyser {
+    /// Processes a single frame of floating point samples.
+    fn process(&mut self, frame: &[f64]);
+}
+
+impl<A: Analyser> Analyser for Option<A> {
+    fn process(&mut self, frame: &[f64]) {
+        if let Some(ref mut analyser) = *self {
+            analyser.process(frame);
+        }
+    }
+}
+
+impl<A: Analyser, B: Analyser> Analyser for (A, B) {
+    fn process(&mut self, frame: &[f64]) {
+        self.0.process(frame);
+        self.1.process(frame);
+    }
+}
+
+impl<A: Analyser, B: Analyser, C: Analyser> Analyser for (A, B, C) {
+    fn process(&mut self, frame: &[f64]) {
+        self.0.process(frame);
+        self.1.process(frame);
+        self.2.process(frame);
+    }
+}
+
+impl<A: Analyser, B: Analyser, C: Analyser, D: Analyser> Analyser for (A, B, C, D) {
+    fn process(&mut self, frame: &[f64]) {
+        self.0.process(frame);
+        self.1.process(frame);
+        self.2.process(frame);
+        self.3.process(frame);
+    }
+}
+
+/// SpectrumAnalyser is implemented by types that inspect a signal through its short-time spectrum.
+pub trait SpectrumAnalyser {
+    /// Processes the spectrum of a single window of the signal.
+    fn process_block(&mut self, block: &stft::Block);
+}
+
+/// Spectrum computes the spectra of overlapping windows of the frames it is fed and passes them to
+/// a `SpectrumAnalyser`. It is the push based counterpart of `filter::stft::FromSource`, which
+/// allows multiple analysers with different window sizes to share a single pass over the audio.
+pub struct Spectrum<A> {
+    analyser: A,
+    window_size: usize,
+    hop_size: usize,
+    window: Vec<f64>,
+    fft: fft::RealFft,
+    /// The most recent frames of each channel.
+    frames: Vec<collections::VecDeque<f64>>,
+    /// The number of frames fed since the last block.
+    pending: usize,
+    signal: Vec<f64>,
+    block: stft::Block,
+}
+
+impl<A: SpectrumAnalyser> Spectrum<A> {
+    pub fn new(
+        analyser: A,
+        num_channels: usize,
+        window: stft::Window,
+        window_size: usize,
+        hop_size: usize,
+    ) -> Spectrum<A> {
+        assert!(hop_size > 0 && hop_size <= window_size);
+        Spectrum {
+            analyser,
+            window_size,
+            hop_size,
+            window: window.coefficients(window_size),
+            fft: fft::RealFft::new(window_size),
+            frames: vec![collections::VecDeque::with_capacity(window_size); num_channels],
+            pending: 0,
+            signal: vec![0.0; window_size],
+            block: vec![vec![stft::Complex64::new(0.0, 0.0); window_size / 2 + 1]; num_channels],
+        }
+    }
+
+    pub fn get_ref(&self) -> &A {
+        &self.analyser
+    }
+
+    pub fn into_inner(self) -> A {
+        self.analyser
+    }
+}
+
+impl<A: SpectrumAnalyser> Analyser for Spectrum<A> {
+    fn process(&mut self, frame: &[f64]) {
+        for (frames, &s) in self.frames.iter_mut().zip(frame) {
+            if frames.len() == self.window_size {
+                frames.pop_front();
+            }
+            frames.push_back(s);
+        }
+        self.pending += 1;
+        if self.frames[0].len() < self.window_size || self.pending < self.hop_size {
+            return;
+        }
+        self.pending = 0;
+        for (frames, bins) in self.frames.iter().zip(self.block.iter_mut()) {
+            for ((s, x), w) in self.signal.iter_mut().zip(frames).zip(&self.window) {
+                *s = x * w;
+            }
+            self.fft.process(&self.signal, bins);
+        }
+        self.analyser.process_block(&self.block);
+    }
+}
+
+/// Feeds all frames of a source to the analyser.
+pub fn feed<S, A>(source: S, analyser: &mut A)
+where
+    S: Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
+    A: Analyser,
+{
+    let mut buf = vec![0.0; S::Item::n_channels()];
+    for frame in source {
+        for (ch, s) in buf.iter_mut().enumerate() {
+            *s = frame.channel(ch).unwrap().to_sample();
+        }
+        analyser.process(&buf);
+    }
+}
+
+/// Like `feed`, but for sources of which the format is only known at runtime.
+pub fn feed_dynam<A>(source: dynam::Source, analyser: &mut A)
+where
+    A: Analyser,
+{
+    match source {
+        dynam::Source::MonoI8(s) => feed(s, analyser),
+        dynam::Source::MonoU8(s) => feed(s, analyser),
+        dynam::Source::MonoI16(s) => feed(s, analyser),
+        dynam::Source::MonoU16(s) => feed(s, analyser),
+        dynam::Source::MonoI24(s) => feed(s, analyser),
+        dynam::Source::MonoU24(s) => feed(s, analyser),
+        dynam::Source::MonoI32(s) => feed(s, analyser),
+        dynam::Source::MonoU32(s) => feed(s, analyser),
+        dynam::Source::MonoI64(s) => feed(s, analyser),
+        dynam::Source::MonoU64(s) => feed(s, analyser),
+        dynam::Source::MonoF32(s) => feed(s, analyser),
+        dynam::Source::MonoF64(s) => feed(s, analyser),
+        dynam::Source::StereoI8(s) => feed(s, analyser),
+        dynam::Source::StereoU8(s) => feed(s, analyser),
+        dynam::Source::StereoI16(s) => feed(s, analyser),
+        dynam::Source::StereoU16(s) => feed(s, analyser),
+        dynam::Source::StereoI24(s) => feed(s, analyser),
+        dynam::Source::StereoU24(s) => feed(s, analyser),
+        dynam::Source::StereoI32(s) => feed(s, analyser),
+        dynam::Source::StereoU32(s) => feed(s, analyser),
+        dynam::Source::StereoI64(s) => feed(s, analyser),
+        dynam::Source::StereoU64(s) => feed(s, analyser),
+        dynam::Source::StereoF32(s) => feed(s, analyser),
+        dynam::Source::StereoF64(s) => feed(s, analyser),
+    }
+}
@@ -0,0 +1,406 @@
+//!
+//! Renders the spectrogram of a signal to an image. Time runs from left to right and frequency
+//! from bottom to top.
+//!
+//! Spectrograms make the frequency content of a file visible at a glance, e.g. the hard cutoff
+//! at 16kHz of a lossy transcode that poses as a lossless file.
+//!
+
+use crate::audio::*;
+use crate::filter::stft::{self, IntoStft, Stft};
+use png::{self, HasParameters};
+use std::io::Write;
+use std::*;
+
+/// The frequency axis of a spectrogram.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scale {
+    Linear,
+    /// Each octave takes up the same height.
+    Log,
+}
+
+impl str::FromStr for Scale {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Scale, ParseError> {
+        match s {
+            "lin" | "linear" => Ok(Scale::Linear),
+            "log" => Ok(Scale::Log),
+            _ => Err(ParseError::Unmatched),
+        }
+    }
+}
+
+/// Maps the level of a pixel to a color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colormap {
+    Grey,
+    Magma,
+    Viridis,
+}
+
+impl str::FromStr for Colormap {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Colormap, ParseError> {
+        match s {
+            "grey" | "gray" => Ok(Colormap::Grey),
+            "magma" => Ok(Colormap::Magma),
+            "viridis" => Ok(Colormap::Viridis),
+            _ => Err(ParseError::Unmatched),
+        }
+    }
+}
+
+impl Colormap {
+    /// Returns the color of a level in the range [0, 1]. Levels in between the control points
+    /// of the colormap are interpolated linearly.
+    pub fn color(&self, level: f64) -> [u8; 3] {
+        let points: &[[u8; 3]] = match *self {
+            Colormap::Grey => &[[0, 0, 0], [255, 255, 255]],
+            Colormap::Magma => &[
+                [0, 0, 4],
+                [28, 16, 68],
+                [79, 18, 123],
+                [129, 37, 129],
+                [181, 54, 122],
+                [229, 80, 100],
+                [251, 135, 97],
+                [254, 194, 135],
+                [252, 253, 191],
+            ],
+            Colormap::Viridis => &[
+                [68, 1, 84],
+                [71, 44, 122],
+                [59, 81, 139],
+                [44, 113, 142],
+                [33, 144, 141],
+                [39, 173, 129],
+                [92, 200, 99],
+                [170, 220, 50],
+                [253, 231, 37],
+            ],
+        };
+        let x = level.max(0.0).min(1.0) * (points.len() - 1) as f64;
+        let i = (x as usize).min(points.len() - 2);
+        let t = x - i as f64;
+        let mut color = [0; 3];
+        for (c, (a, b)) in color.iter_mut().zip(points[i].iter().zip(&points[i + 1])) {
+            *c = (f64::from(*a) * (1.0 - t) + f64::from(*b) * t).round() as u8;
+        }
+        color
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    pub window: stft::Window,
+    /// The number of frames of each window, which must be a power of two.
+    pub fft_size: usize,
+    /// The number of frames between the starts of consecutive windows.
+    pub hop_size: usize,
+    /// The levels in dBFS that are mapped to the first and last color of the colormap.
+    pub min_db: f64,
+    pub max_db: f64,
+    pub scale: Scale,
+    /// The lowest frequency shown on a logarithmic axis.
+    pub min_freq: f64,
+    pub colormap: Colormap,
+    /// The maximum width of the image. Windows are combined if there are more of them than
+    /// there are columns.
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            window: stft::Window::Hann,
+            fft_size: 4096,
+            hop_size: 1024,
+            min_db: -120.0,
+            max_db: 0.0,
+            scale: Scale::Linear,
+            min_freq: 20.0,
+            colormap: Colormap::Magma,
+            width: 1920,
+            height: 720,
+        }
+    }
+}
+
+/// The file formats an image can be saved as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Determines the format from the extension of a path, ignoring its case.
+    pub fn from_path(path: &path::Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// An image of 8 bit RGB pixels, stored row by row from the top.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Writes the image as a binary PPM.
+    pub fn write_ppm<W: Write>(&self, mut w: W) -> Result<(), Error> {
+        write!(w, "P6\n{} {}\n255\n", self.width, self.height)?;
+        w.write_all(&self.pixels)?;
+        Ok(())
+    }
+
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), Error> {
+        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        Ok(())
+    }
+
+    /// Writes the image to a file in the format indicated by its extension, either PNG or PPM.
+    pub fn save(&self, path: &path::Path) -> Result<(), Error> {
+        let format = ImageFormat::from_path(path).ok_or(Error::UnsupportedFormat)?;
+        let w = io::BufWriter::new(fs::File::create(path)?);
+        match format {
+            ImageFormat::Png => self.write_png(w),
+            ImageFormat::Ppm => self.write_ppm(w),
+        }
+    }
+}
+
+/// Returns the range of bins that make up each row of the image, from the top.
+fn row_bins(params: &Params, sample_rate: u32) -> Vec<ops::Range<usize>> {
+    let num_bins = params.fft_size / 2 + 1;
+    let nyquist = f64::from(sample_rate) / 2.0;
+    let bin_width = nyquist / (num_bins - 1) as f64;
+    // The frequency at the top edge of the row.
+    let edge = |row: usize| {
+        let t = 1.0 - row as f64 / params.height as f64;
+        match params.scale {
+            Scale::Linear => t * nyquist,
+            Scale::Log => {
+                let min_freq = params.min_freq.max(bin_width).min(nyquist);
+                min_freq * (nyquist / min_freq).powf(t)
+            }
+        }
+    };
+    (0..params.height)
+        .map(|row| {
+            let (lo, hi) = (edge(row + 1), edge(row));
+            let start = ((lo / bin_width).ceil() as usize).min(num_bins - 1);
+            let end = ((hi / bin_width).ceil() as usize).min(num_bins);
+            if start < end {
+                start..end
+            } else {
+                // The row is narrower than a bin.
+                let center = (((lo + hi) / 2.0 / bin_width).round() as usize).min(num_bins - 1);
+                center..center + 1
+            }
+        })
+        .collect()
+}
+
+/// Renders the spectrogram of an STFT. The window and size of the STFT take precedence over
+/// those in the params.
+pub fn render<T: Stft>(mut stft: T, params: &Params) -> Image {
+    let params = Params {
+        window: stft.window(),
+        fft_size: stft.window_size(),
+        hop_size: stft.hop_size(),
+        ..*params
+    };
+    let rows = row_bins(&params, stft.sample_rate());
+    let sum: f64 = params.window.coefficients(params.fft_size).iter().sum();
+    // Scales the power of a bin so a full scale sine measures 0dB.
+    let norm = (2.0 / sum).powi(2) / stft.num_channels() as f64;
+    let range = params.max_db - params.min_db;
+
+    // The levels of each window, quantised to a byte to keep long files in memory.
+    let mut columns: Vec<Vec<u8>> = Vec::new();
+    let mut block = stft.new_block();
+    let mut power = vec![0.0; stft.num_bins()];
+    while stft.next_block(&mut block) {
+        for (i, p) in power.iter_mut().enumerate() {
+            *p = block.iter().map(|ch| ch[i].norm_sqr()).sum::<f64>() * norm;
+        }
+        let column = rows
+            .iter()
+            .map(|bins| {
+                let peak = power[bins.clone()].iter().cloned().fold(0.0, f64::max);
+                let db = 10.0 * peak.max(1e-20).log10();
+                ((db - params.min_db) / range * 255.0).max(0.0).min(255.0) as u8
+            })
+            .collect();
+        columns.push(column);
+    }
+
+    let width = columns.len().min(params.width);
+    let mut pixels = vec![0; width * params.height * 3];
+    for x in 0..width {
+        let span = &columns[x * columns.len() / width..(x + 1) * columns.len() / width];
+        for y in 0..params.height {
+            let level = span.iter().map(|c| c[y]).max().unwrap_or(0);
+            let color = params.colormap.color(f64::from(level) / 255.0);
+            let i = (y * width + x) * 3;
+            pixels[i..i + 3].copy_from_slice(&color);
+        }
+    }
+    Image {
+        width,
+        height: params.height,
+        pixels,
+    }
+}
+
+/// Renders the spectrogram of a source of which the format is only known at runtime.
+pub fn render_dynam(source: dynam::Source, params: &Params) -> Result<Image, Error> {
+    let (window, size, hop) = (params.window, params.fft_size, params.hop_size);
+    Ok(match source {
+        dynam::Source::MonoI8(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoU8(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoI16(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoU16(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoI24(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoU24(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoI32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoU32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoI64(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoU64(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoF32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::MonoF64(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoI8(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoU8(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoI16(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoU16(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoI24(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoU24(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoI32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoU32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoI64(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoU64(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoF32(s) => render(s.stft(window, size, hop)?, params),
+        dynam::Source::StereoF64(s) => render(s.stft(window, size, hop)?, params),
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    /// The input text was not matched.
+    Unmatched,
+}
+
+#[derive(Debug, Error)]
+pub enum Error {
+    IO(io::Error),
+    Png(png::EncodingError),
+    Stft(stft::Error),
+    /// The image format is not supported, use PNG or PPM.
+    UnsupportedFormat,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+
+    /// Returns the row of the brightest pixel in the middle column.
+    fn brightest_row(image: &Image) -> usize {
+        let x = image.width / 2;
+        (0..image.height)
+            .max_by_key(|&y| image.pixels[(y * image.width + x) * 3])
+            .unwrap()
+    }
+
+    fn sine(freq: f64, params: &Params) -> Image {
+        let gen = Generator::<[f32; 1]>::new(Waveform::Sine(freq), 44100, 44100);
+        render(
+            gen.stft(params.window, params.fft_size, params.hop_size)
+                .unwrap(),
+            params,
+        )
+    }
+
+    #[test]
+    fn linear() {
+        let params = Params {
+            colormap: Colormap::Grey,
+            height: 441,
+            ..Params::default()
+        };
+        let image = sine(11025.0, &params);
+        assert_eq!(44100 / 1024 + 1, image.width);
+        assert_eq!(441 * 3 * image.width, image.pixels.len());
+        // Each row spans 50Hz, so 11025Hz is in the row from 11000Hz to 11050Hz.
+        let row = brightest_row(&image);
+        assert_eq!(220, row);
+        // A full scale sine reaches the top of the range.
+        let x = image.width / 2;
+        assert!(image.pixels[(row * image.width + x) * 3] > 250);
+    }
+
+    #[test]
+    fn log() {
+        let params = Params {
+            colormap: Colormap::Grey,
+            scale: Scale::Log,
+            min_freq: 20.0,
+            height: 300,
+            ..Params::default()
+        };
+        // 1kHz lies at log(1000 / 20) / log(22050 / 20) of the height from the bottom.
+        let expected = 300.0 * (1.0 - (50f64).ln() / (1102.5f64).ln());
+        let row = brightest_row(&sine(1000.0, &params)) as f64;
+        assert!((row - expected).abs() <= 3.0, "{} != {}", row, expected);
+    }
+
+    #[test]
+    fn width() {
+        let params = Params {
+            width: 10,
+            ..Params::default()
+        };
+        assert_eq!(10, sine(1000.0, &params).width);
+    }
+
+    #[test]
+    fn colormap() {
+        assert_eq!([0, 0, 0], Colormap::Grey.color(0.0));
+        assert_eq!([128, 128, 128], Colormap::Grey.color(0.5));
+        assert_eq!([253, 231, 37], Colormap::Viridis.color(1.0));
+        assert_eq!([0, 0, 4], Colormap::Magma.color(-1.0));
+    }
+
+    #[test]
+    fn image_format() {
+        let format = |p: &str| ImageFormat::from_path(path::Path::new(p));
+        assert_eq!(Some(ImageFormat::Png), format("out.png"));
+        assert_eq!(Some(ImageFormat::Ppm), format("dir.d/OUT.PPM"));
+        assert_eq!(None, format("out.jpg"));
+        assert_eq!(None, format("png"));
+    }
+
+    #[test]
+    fn ppm() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![1, 2, 3, 4, 5, 6],
+        };
+        let mut buf = Vec::new();
+        image.write_ppm(&mut buf).unwrap();
+        assert_eq!(b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec(), buf);
+    }
+}
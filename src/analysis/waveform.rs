@@ -0,0 +1,405 @@
+//!
+//! Waveform overviews of complete tracks, for scrubbing and for displaying the waveform on a deck.
+//!
+//! An overview summarises a fixed number of frames per point by the minimum, maximum and RMS of
+//! the signal. Optionally, the RMS of the low, mid and high frequencies is kept as well, which
+//! can be used to colour the waveform.
+//!
+
+use super::Analyser;
+use byteorder::{ByteOrder, LittleEndian};
+use std::*;
+
+/// The upper edge of the low band in Hz.
+const LOW_MID_CROSSOVER: f64 = 250.0;
+/// The lower edge of the high band in Hz.
+const MID_HIGH_CROSSOVER: f64 = 4000.0;
+
+/// Set in the header of an encoded overview if it includes the bands.
+const FLAG_BANDS: u8 = 1;
+/// The size of the header of an encoded overview: the flags, the frames per point and the sample
+/// rate.
+const HEADER_SIZE: usize = 13;
+/// The lowest level in dB that is distinguished from silence in an encoded overview.
+const MIN_LEVEL_DB: f32 = -60.0;
+
+/// The summary of a range of frames of all channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+/// The RMS of the frequency bands of a range of frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bands {
+    pub low: f32,
+    pub mid: f32,
+    pub high: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overview {
+    pub sample_rate: u32,
+    /// The number of frames summarised by each point. This is fractional for overviews that have
+    /// been resampled.
+    pub frames_per_point: f64,
+    pub points: Vec<Point>,
+    /// The bands of each point, if they were measured.
+    pub bands: Option<Vec<Bands>>,
+}
+
+impl Overview {
+    /// Reduces the overview to at most the specified number of points. Each new point summarises
+    /// an equal share of the original points.
+    pub fn resample(&self, num_points: usize) -> Overview {
+        let len = self.points.len();
+        if num_points >= len || num_points == 0 {
+            return self.clone();
+        }
+        let range = |i: usize| i * len / num_points..(i + 1) * len / num_points;
+        let rms = |values: &mut iter::Iterator<Item = f32>| {
+            let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v * v, n + 1));
+            (sum / n as f32).sqrt()
+        };
+        let points = (0..num_points)
+            .map(|i| {
+                let points = &self.points[range(i)];
+                Point {
+                    min: points.iter().map(|p| p.min).fold(f32::INFINITY, f32::min),
+                    max: points
+                        .iter()
+                        .map(|p| p.max)
+                        .fold(f32::NEG_INFINITY, f32::max),
+                    rms: rms(&mut points.iter().map(|p| p.rms)),
+                }
+            })
+            .collect();
+        let bands = self.bands.as_ref().map(|bands| {
+            (0..num_points)
+                .map(|i| {
+                    let bands = &bands[range(i)];
+                    Bands {
+                        low: rms(&mut bands.iter().map(|b| b.low)),
+                        mid: rms(&mut bands.iter().map(|b| b.mid)),
+                        high: rms(&mut bands.iter().map(|b| b.high)),
+                    }
+                })
+                .collect()
+        });
+        Overview {
+            sample_rate: self.sample_rate,
+            frames_per_point: self.frames_per_point * len as f64 / num_points as f64,
+            points,
+            bands,
+        }
+    }
+
+    /// Encodes the overview so it can be stored. Every value is quantised to a single byte, so a
+    /// point takes up 3 bytes, or 6 with bands.
+    ///
+    /// The minimum and maximum are stored linearly as signed bytes. The RMS and band levels are
+    /// stored on a dB scale, where -60 dB to 0 dB maps to 0 to 255 and 0 denotes silence, so quiet
+    /// passages keep their detail.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let point_size = if self.bands.is_some() { 6 } else { 3 };
+        let mut buf = vec![0; HEADER_SIZE + self.points.len() * point_size];
+        buf[0] = if self.bands.is_some() { FLAG_BANDS } else { 0 };
+        LittleEndian::write_f64(&mut buf[1..9], self.frames_per_point);
+        LittleEndian::write_u32(&mut buf[9..13], self.sample_rate);
+        for (i, (chunk, p)) in buf[HEADER_SIZE..]
+            .chunks_mut(point_size)
+            .zip(&self.points)
+            .enumerate()
+        {
+            chunk[0] = quantise_signed(p.min);
+            chunk[1] = quantise_signed(p.max);
+            chunk[2] = quantise_level(p.rms);
+            if let Some(ref bands) = self.bands {
+                chunk[3] = quantise_level(bands[i].low);
+                chunk[4] = quantise_level(bands[i].mid);
+                chunk[5] = quantise_level(bands[i].high);
+            }
+        }
+        buf
+    }
+
+    /// Decodes an overview that was encoded by `to_bytes`.
+    pub fn from_bytes(buf: &[u8]) -> Option<Overview> {
+        if buf.len() < HEADER_SIZE {
+            return None;
+        }
+        let has_bands = buf[0] & FLAG_BANDS != 0;
+        let point_size = if has_bands { 6 } else { 3 };
+        let data = &buf[HEADER_SIZE..];
+        if data.len() % point_size != 0 {
+            return None;
+        }
+        let points = data
+            .chunks(point_size)
+            .map(|c| Point {
+                min: dequantise_signed(c[0]),
+                max: dequantise_signed(c[1]),
+                rms: dequantise_level(c[2]),
+            })
+            .collect();
+        let bands = if has_bands {
+            Some(
+                data.chunks(point_size)
+                    .map(|c| Bands {
+                        low: dequantise_level(c[3]),
+                        mid: dequantise_level(c[4]),
+                        high: dequantise_level(c[5]),
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        Some(Overview {
+            frames_per_point: LittleEndian::read_f64(&buf[1..9]),
+            sample_rate: LittleEndian::read_u32(&buf[9..13]),
+            points,
+            bands,
+        })
+    }
+}
+
+fn quantise_signed(v: f32) -> u8 {
+    (v.max(-1.0).min(1.0) * 127.0).round() as i8 as u8
+}
+
+fn dequantise_signed(b: u8) -> f32 {
+    f32::from(b as i8) / 127.0
+}
+
+fn quantise_level(v: f32) -> u8 {
+    if v <= 0.0 {
+        return 0;
+    }
+    let db = (20.0 * v.log10()).max(MIN_LEVEL_DB).min(0.0);
+    ((db - MIN_LEVEL_DB) / -MIN_LEVEL_DB * 255.0).round() as u8
+}
+
+fn dequantise_level(b: u8) -> f32 {
+    if b == 0 {
+        return 0.0;
+    }
+    let db = MIN_LEVEL_DB - f32::from(b) / 255.0 * MIN_LEVEL_DB;
+    10f32.powf(db / 20.0)
+}
+
+/// A first order low pass filter.
+struct OnePole {
+    a: f64,
+    y: f64,
+}
+
+impl OnePole {
+    fn new(sample_rate: u32, cutoff: f64) -> OnePole {
+        OnePole {
+            a: 1.0 - (-2.0 * f64::consts::PI * cutoff / f64::from(sample_rate)).exp(),
+            y: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.y += self.a * (x - self.y);
+        self.y
+    }
+}
+
+/// Scanner computes the overview of a signal.
+pub struct Scanner {
+    sample_rate: u32,
+    frames_per_point: usize,
+    /// The number of frames in the current point.
+    pos: usize,
+    min: f64,
+    max: f64,
+    /// The sum of the mean squares of the frames in the current point.
+    squares: f64,
+    /// The filters that split the signal into the low and high bands and the sums of the squares
+    /// of the low, mid and high band in the current point.
+    crossovers: Option<(OnePole, OnePole, [f64; 3])>,
+    points: Vec<Point>,
+    bands: Vec<Bands>,
+}
+
+impl Scanner {
+    /// Creates a scanner that summarises the specified number of frames per point. Bands are only
+    /// measured if `with_bands` is set.
+    pub fn new(sample_rate: u32, frames_per_point: usize, with_bands: bool) -> Scanner {
+        assert!(frames_per_point > 0);
+        Scanner {
+            sample_rate,
+            frames_per_point,
+            pos: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            squares: 0.0,
+            crossovers: if with_bands {
+                Some((
+                    OnePole::new(sample_rate, LOW_MID_CROSSOVER),
+                    OnePole::new(sample_rate, MID_HIGH_CROSSOVER),
+                    [0.0; 3],
+                ))
+            } else {
+                None
+            },
+            points: Vec::new(),
+            bands: Vec::new(),
+        }
+    }
+
+    /// Returns the overview of all frames processed so far, including any incomplete last point.
+    pub fn overview(mut self) -> Overview {
+        if self.pos > 0 {
+            self.end_point();
+        }
+        let bands = if self.crossovers.is_some() {
+            Some(self.bands)
+        } else {
+            None
+        };
+        Overview {
+            sample_rate: self.sample_rate,
+            frames_per_point: self.frames_per_point as f64,
+            bands,
+            points: self.points,
+        }
+    }
+
+    fn end_point(&mut self) {
+        let n = self.pos as f64;
+        self.points.push(Point {
+            min: self.min as f32,
+            max: self.max as f32,
+            rms: (self.squares / n).sqrt() as f32,
+        });
+        if let Some((_, _, ref mut squares)) = self.crossovers {
+            self.bands.push(Bands {
+                low: (squares[0] / n).sqrt() as f32,
+                mid: (squares[1] / n).sqrt() as f32,
+                high: (squares[2] / n).sqrt() as f32,
+            });
+            *squares = [0.0; 3];
+        }
+        self.pos = 0;
+        self.min = f64::INFINITY;
+        self.max = f64::NEG_INFINITY;
+        self.squares = 0.0;
+    }
+}
+
+impl Analyser for Scanner {
+    fn process(&mut self, frame: &[f64]) {
+        let num_channels = frame.len() as f64;
+        let mut sum = 0.0;
+        for &s in frame {
+            self.min = self.min.min(s);
+            self.max = self.max.max(s);
+            sum += s;
+        }
+        self.pos += 1;
+        self.squares += frame.iter().map(|s| s * s).sum::<f64>() / num_channels;
+        if let Some((ref mut low_pass, ref mut high_pass, ref mut squares)) = self.crossovers {
+            // The bands are measured on the sum of all channels.
+            let mono = sum / num_channels;
+            let low = low_pass.process(mono);
+            let high = mono - high_pass.process(mono);
+            let mid = mono - low - high;
+            squares[0] += low * low;
+            squares[1] += mid * mid;
+            squares[2] += high * high;
+        }
+        if self.pos == self.frames_per_point {
+            self.end_point();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+
+    fn scan(freq: f64, amplitude: f64, len: u64) -> Overview {
+        let mut scanner = Scanner::new(44100, 441, true);
+        let mut gen = Generator::<[f64; 1]>::new(Waveform::Sine(freq), 44100, len);
+        gen.amplitude = amplitude;
+        for [s] in gen {
+            scanner.process(&[s, -s]);
+        }
+        scanner.overview()
+    }
+
+    #[test]
+    fn sine() {
+        let overview = scan(1000.0, 0.5, 44100 + 100);
+        assert_eq!(101, overview.points.len());
+        for p in &overview.points[..100] {
+            assert!((p.min + 0.5).abs() < 1e-3 && (p.max - 0.5).abs() < 1e-3);
+            assert!((p.rms - 0.5 / 2f32.sqrt()).abs() < 1e-3, "{}", p.rms);
+        }
+    }
+
+    #[test]
+    fn bands() {
+        // The channels of the test signal cancel each other out, so use a single channel.
+        let band_of = |freq: f64| {
+            let mut scanner = Scanner::new(44100, 4410, true);
+            for frame in Generator::<[f64; 1]>::new(Waveform::Sine(freq), 44100, 44100) {
+                scanner.process(&frame);
+            }
+            let b = scanner.overview().bands.unwrap()[5];
+            let levels = [b.low, b.mid, b.high];
+            (0..3)
+                .max_by(|&a, &b| levels[a].partial_cmp(&levels[b]).unwrap())
+                .unwrap()
+        };
+        assert_eq!(0, band_of(60.0));
+        assert_eq!(1, band_of(1000.0));
+        assert_eq!(2, band_of(12_000.0));
+        assert!(Scanner::new(44100, 441, false).overview().bands.is_none());
+    }
+
+    #[test]
+    fn resample() {
+        let overview = scan(1000.0, 0.5, 44100);
+        let half = overview.resample(50);
+        assert_eq!(50, half.points.len());
+        assert_eq!(50, half.bands.as_ref().unwrap().len());
+        assert_eq!(882.0, half.frames_per_point);
+        assert!((half.points[10].rms - overview.points[20].rms).abs() < 1e-3);
+        assert_eq!(overview, overview.resample(1000));
+    }
+
+    #[test]
+    fn bytes() {
+        let overview = scan(1000.0, 0.5, 44100);
+        let bytes = overview.to_bytes();
+        assert_eq!(HEADER_SIZE + 100 * 6, bytes.len());
+        let decoded = Overview::from_bytes(&bytes).unwrap();
+        assert_eq!(overview.sample_rate, decoded.sample_rate);
+        assert_eq!(overview.frames_per_point, decoded.frames_per_point);
+        for (a, b) in overview.points.iter().zip(&decoded.points) {
+            assert!((a.min - b.min).abs() < 0.01 && (a.rms - b.rms).abs() < 0.01);
+        }
+        assert_eq!(None, Overview::from_bytes(&bytes[..bytes.len() - 1]));
+    }
+
+    #[test]
+    fn levels() {
+        assert_eq!(0, quantise_level(0.0));
+        assert_eq!(0, quantise_level(1e-4));
+        assert_eq!(255, quantise_level(1.0));
+        assert_eq!(0.0, dequantise_level(0));
+        // Quiet levels keep the same relative precision as loud ones.
+        for &v in &[0.002, 0.01, 0.1, 0.5] {
+            let d = dequantise_level(quantise_level(v));
+            assert!((d / v).log10().abs() * 20.0 < 0.2, "{} {}", v, d);
+        }
+    }
+}
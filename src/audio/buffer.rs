@@ -0,0 +1,362 @@
+use super::*;
+use sample;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::*;
+
+/// The number of frames that the reader thread reads before it adds them to the buffer.
+const CHUNK_SIZE: usize = 256;
+/// The minimum time between two reported underruns. Underruns that follow more quickly are
+/// coalesced with the previous one.
+const UNDERRUN_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// The sizes of a buffer, expressed in time so they are independent of the sample rate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Params {
+    /// The maximum amount of audio that is read ahead.
+    pub capacity: time::Duration,
+    /// The amount of audio that must be buffered before playback starts or resumes after the
+    /// buffer has run empty.
+    pub prebuffer: time::Duration,
+    /// An `Event::Low` is fired once less than this amount of audio is buffered.
+    pub watermark: time::Duration,
+}
+
+impl Default for Params {
+    fn default() -> Params {
+        Params {
+            capacity: time::Duration::from_secs(10),
+            prebuffer: time::Duration::from_secs(2),
+            watermark: time::Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The buffer has run empty and playback is suspended until it has been filled up to the
+    /// prebuffer level. Underruns that follow the previous one within a second are not reported.
+    Underrun,
+    /// Enough audio was buffered to start or resume playback after the start or a reported
+    /// underrun.
+    Ready,
+    /// The number of frames in the buffer fell below the watermark.
+    Low(usize),
+}
+
+struct Ring<F> {
+    frames: VecDeque<F>,
+    /// Set when the input has no more frames.
+    end: bool,
+    /// Set when the consuming side is dropped so the reader thread can exit.
+    closed: bool,
+}
+
+/// Buffer reads a source from a separate thread so a consumer is not affected by an input that
+/// occasionally blocks, such as a network stream.
+///
+/// Silence is produced while the buffer is being filled, so the consumer never blocks. Once the
+/// buffer runs empty, playback is suspended until the prebuffer level has been reached again.
+pub struct Buffer<F>
+where
+    F: sample::Frame,
+{
+    ring: Arc<(Mutex<Ring<F>>, Condvar)>,
+    sample_rate: u32,
+    prebuffer: usize,
+    watermark: usize,
+    /// Whether silence is produced until the prebuffer level is reached.
+    buffering: bool,
+    /// Whether the level is below the watermark.
+    low: bool,
+    /// Whether the underrun of the current buffering period has been reported, in which case
+    /// `Ready` is reported once it ends.
+    underrun_reported: bool,
+    /// The time at which the most recent underrun was reported.
+    last_underrun: Option<time::Instant>,
+    /// The events that were raised while the ring was locked, fired once it has been released.
+    pending: Vec<Event>,
+    event_handler: Arc<Fn(Event) + Send + Sync>,
+}
+
+impl<F> Buffer<F>
+where
+    F: sample::Frame,
+{
+    /// Returns the number of frames that are currently buffered.
+    pub fn len(&self) -> usize {
+        (self.ring.0).lock().unwrap().frames.len()
+    }
+
+    /// Takes the next frame from the ring. Events are added to `pending`, so they can be fired
+    /// after the lock has been released.
+    fn take(&mut self) -> Option<F> {
+        let (ref lock, ref cvar) = *self.ring;
+        let mut ring = lock.lock().unwrap();
+        if ring.end && ring.frames.is_empty() {
+            return None;
+        }
+        if self.buffering {
+            if ring.frames.len() < self.prebuffer && !ring.end {
+                return Some(F::equilibrium());
+            }
+            self.buffering = false;
+            if self.underrun_reported {
+                self.pending.push(Event::Ready);
+            }
+        }
+
+        let frame = match ring.frames.pop_front() {
+            Some(frame) => frame,
+            None => {
+                self.buffering = true;
+                let now = time::Instant::now();
+                self.underrun_reported = self
+                    .last_underrun
+                    .map(|last| now.duration_since(last) >= UNDERRUN_INTERVAL)
+                    .unwrap_or(true);
+                if self.underrun_reported {
+                    self.last_underrun = Some(now);
+                    self.pending.push(Event::Underrun);
+                }
+                return Some(F::equilibrium());
+            }
+        };
+        cvar.notify_one();
+        let level = ring.frames.len();
+        if level < self.watermark && !self.low && !ring.end {
+            self.low = true;
+            self.pending.push(Event::Low(level));
+        } else if level >= self.watermark + CHUNK_SIZE {
+            // The level has to rise well above the watermark before it is reported again, so a
+            // level that hovers around it does not fire an event for every frame.
+            self.low = false;
+        }
+        Some(frame)
+    }
+}
+
+impl<F> iter::Iterator for Buffer<F>
+where
+    F: sample::Frame,
+{
+    type Item = F;
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.take();
+        for event in self.pending.drain(..) {
+            (self.event_handler)(event);
+        }
+        frame
+    }
+}
+
+impl<F> Source for Buffer<F>
+where
+    F: sample::Frame,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl<F> Drop for Buffer<F>
+where
+    F: sample::Frame,
+{
+    fn drop(&mut self) {
+        let (ref lock, ref cvar) = *self.ring;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_one();
+    }
+}
+
+/// Reads the input in chunks until it ends or the buffer is dropped.
+fn fill<S>(mut input: S, ring: &(Mutex<Ring<S::Item>>, Condvar), capacity: usize)
+where
+    S: Source,
+    S::Item: sample::Frame,
+{
+    let (ref lock, ref cvar) = *ring;
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    loop {
+        // The input is read without holding the lock, as it may block.
+        chunk.extend(input.by_ref().take(CHUNK_SIZE));
+        let end = chunk.len() < CHUNK_SIZE;
+
+        let mut ring = lock.lock().unwrap();
+        while !ring.closed && ring.frames.len() + chunk.len() > capacity {
+            ring = cvar.wait(ring).unwrap();
+        }
+        if ring.closed {
+            return;
+        }
+        ring.frames.extend(chunk.drain(..));
+        if end {
+            ring.end = true;
+            return;
+        }
+    }
+}
+
+pub trait IntoBuffer: Source + Sized + Send + 'static
+where
+    Self::Item: sample::Frame + Send,
+{
+    /// Starts reading the source into a buffer from a separate thread.
+    fn buffer(
+        self,
+        params: Params,
+        event_handler: Arc<Fn(Event) + Send + Sync>,
+    ) -> Buffer<Self::Item> {
+        let sample_rate = self.sample_rate();
+        let frames = |duration| frames_of(sample_rate, duration) as usize;
+        let capacity = cmp::max(frames(params.capacity), CHUNK_SIZE);
+        let ring = Arc::new((
+            Mutex::new(Ring {
+                frames: VecDeque::with_capacity(capacity),
+                end: false,
+                closed: false,
+            }),
+            Condvar::new(),
+        ));
+        let ring_input = ring.clone();
+        thread::spawn(move || fill(self, &ring_input, capacity));
+        Buffer {
+            ring,
+            sample_rate,
+            prebuffer: cmp::min(frames(params.prebuffer), capacity),
+            watermark: frames(params.watermark),
+            buffering: true,
+            low: false,
+            underrun_reported: true,
+            last_underrun: None,
+            pending: Vec::with_capacity(2),
+            event_handler,
+        }
+    }
+}
+
+impl<T> IntoBuffer for T
+where
+    T: Source + Send + 'static,
+    T::Item: sample::Frame + Send,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::IntoSource;
+
+    /// Yields the numbers 1 to `length` inclusive, pausing before the frame at `stall`.
+    struct Stalling {
+        position: u64,
+        length: u64,
+        stall: u64,
+    }
+
+    impl iter::Iterator for Stalling {
+        type Item = [f32; 1];
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.position >= self.length {
+                return None;
+            }
+            if self.position == self.stall {
+                thread::sleep(time::Duration::from_millis(100));
+            }
+            self.position += 1;
+            Some([self.position as f32])
+        }
+    }
+
+    impl Source for Stalling {
+        fn sample_rate(&self) -> u32 {
+            1000
+        }
+    }
+
+    fn events() -> (Arc<Fn(Event) + Send + Sync>, Arc<Mutex<Vec<Event>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_handler = events.clone();
+        let handler = Arc::new(move |event: Event| events_handler.lock().unwrap().push(event));
+        (handler, events)
+    }
+
+    #[test]
+    fn passthrough() {
+        let input: Vec<[f32; 1]> = (1..10_000).map(|i| [i as f32]).collect();
+        let (handler, events) = events();
+        let params = Params {
+            capacity: time::Duration::from_millis(500),
+            prebuffer: time::Duration::from_millis(100),
+            watermark: time::Duration::from_millis(0),
+        };
+        let out: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(1000)
+            .buffer(params, handler)
+            .filter(|f| f[0] != 0.0)
+            .collect();
+        assert_eq!(input, out);
+        assert_eq!(Event::Ready, events.lock().unwrap()[0]);
+    }
+
+    #[test]
+    fn underrun() {
+        let input = Stalling {
+            position: 0,
+            length: 1000,
+            stall: 500,
+        };
+        let (handler, events) = events();
+        let params = Params {
+            capacity: time::Duration::from_millis(1000),
+            prebuffer: time::Duration::from_millis(50),
+            watermark: time::Duration::from_millis(20),
+        };
+        let mut buffer = input.buffer(params, handler);
+        let mut out = Vec::new();
+        while let Some(frame) = buffer.next() {
+            if frame[0] != 0.0 {
+                out.push(frame[0] as u64);
+            }
+            // Consume slightly slower than realtime so the buffer is filled between stalls.
+            thread::sleep(time::Duration::from_micros(10));
+        }
+        assert_eq!((1..=1000).collect::<Vec<_>>(), out);
+        let events = events.lock().unwrap();
+        assert!(events.contains(&Event::Underrun));
+        assert!(events.iter().any(|e| match *e {
+            Event::Low(level) => level < 20,
+            _ => false,
+        }));
+        let underrun = events.iter().position(|e| *e == Event::Underrun).unwrap();
+        assert!(events[underrun..].contains(&Event::Ready));
+    }
+
+    #[test]
+    fn coalesce_underruns() {
+        // An input that is slightly slower than realtime underruns after every chunk.
+        let input = (1..=CHUNK_SIZE * 8).map(|i| {
+            thread::sleep(time::Duration::from_micros(20));
+            [i as f32]
+        });
+        let (handler, events) = events();
+        let params = Params {
+            capacity: time::Duration::from_millis(1000),
+            prebuffer: time::Duration::from_millis(10),
+            watermark: time::Duration::from_millis(0),
+        };
+        let out: Vec<_> = input
+            .source(1000)
+            .buffer(params, handler)
+            .filter(|f| f[0] != 0.0)
+            .collect();
+        assert_eq!(CHUNK_SIZE * 8, out.len());
+        let events = events.lock().unwrap();
+        let count = |event| events.iter().filter(|e| **e == event).count();
+        assert!(count(Event::Underrun) <= 2, "{:?}", *events);
+        assert_eq!(count(Event::Underrun) + 1, count(Event::Ready));
+    }
+}
@@ -1,3 +1,4 @@
+use super::segment::IntoSegment;
 use sample::{I24, U24};
 use std::*;
 
@@ -36,7 +37,7 @@ pub enum Source {
 }
 
 impl Source {
-    fn num_channels(&self) -> u32 {
+    pub fn num_channels(&self) -> u32 {
         match self {
             Source::MonoI8(_) => 1,
             Source::MonoU8(_) => 1,
@@ -194,7 +195,7 @@ pub enum Seek {
 }
 
 impl Seek {
-    fn num_channels(&self) -> u32 {
+    pub fn num_channels(&self) -> u32 {
         match self {
             Seek::MonoI8(_) => 1,
             Seek::MonoU8(_) => 1,
@@ -309,6 +310,35 @@ impl Seek {
             Seek::StereoF64(s) => s.sample_rate(),
         }
     }
+    /// Limits the source to the frames in `[start, end)`, see `segment::IntoSegment`.
+    pub fn segment(self, start: u64, end: u64) -> Result<Seek, super::SeekError> {
+        Ok(match self {
+            Seek::MonoI8(s) => Seek::MonoI8(Box::new(s.segment(start, end)?)),
+            Seek::MonoU8(s) => Seek::MonoU8(Box::new(s.segment(start, end)?)),
+            Seek::MonoI16(s) => Seek::MonoI16(Box::new(s.segment(start, end)?)),
+            Seek::MonoU16(s) => Seek::MonoU16(Box::new(s.segment(start, end)?)),
+            Seek::MonoI24(s) => Seek::MonoI24(Box::new(s.segment(start, end)?)),
+            Seek::MonoU24(s) => Seek::MonoU24(Box::new(s.segment(start, end)?)),
+            Seek::MonoI32(s) => Seek::MonoI32(Box::new(s.segment(start, end)?)),
+            Seek::MonoU32(s) => Seek::MonoU32(Box::new(s.segment(start, end)?)),
+            Seek::MonoI64(s) => Seek::MonoI64(Box::new(s.segment(start, end)?)),
+            Seek::MonoU64(s) => Seek::MonoU64(Box::new(s.segment(start, end)?)),
+            Seek::MonoF32(s) => Seek::MonoF32(Box::new(s.segment(start, end)?)),
+            Seek::MonoF64(s) => Seek::MonoF64(Box::new(s.segment(start, end)?)),
+            Seek::StereoI8(s) => Seek::StereoI8(Box::new(s.segment(start, end)?)),
+            Seek::StereoU8(s) => Seek::StereoU8(Box::new(s.segment(start, end)?)),
+            Seek::StereoI16(s) => Seek::StereoI16(Box::new(s.segment(start, end)?)),
+            Seek::StereoU16(s) => Seek::StereoU16(Box::new(s.segment(start, end)?)),
+            Seek::StereoI24(s) => Seek::StereoI24(Box::new(s.segment(start, end)?)),
+            Seek::StereoU24(s) => Seek::StereoU24(Box::new(s.segment(start, end)?)),
+            Seek::StereoI32(s) => Seek::StereoI32(Box::new(s.segment(start, end)?)),
+            Seek::StereoU32(s) => Seek::StereoU32(Box::new(s.segment(start, end)?)),
+            Seek::StereoI64(s) => Seek::StereoI64(Box::new(s.segment(start, end)?)),
+            Seek::StereoU64(s) => Seek::StereoU64(Box::new(s.segment(start, end)?)),
+            Seek::StereoF32(s) => Seek::StereoF32(Box::new(s.segment(start, end)?)),
+            Seek::StereoF64(s) => Seek::StereoF64(Box::new(s.segment(start, end)?)),
+        })
+    }
 }
 
 impl fmt::Debug for Seek {
@@ -0,0 +1,384 @@
+//!
+//! Signal generators for testing and calibration.
+//!
+//! The value of each frame is computed from its position, so generators can be seeked freely and
+//! always yield the same frame at the same position. This includes the noise generators, which
+//! derive their values from a hash of the position.
+//!
+
+use super::*;
+use sample::{self, Sample};
+use std::f64::consts::PI;
+use std::*;
+
+/// The number of rows of the Voss-McCartney algorithm used to generate pink noise. Each row
+/// contributes one octave.
+const PINK_ROWS: u32 = 16;
+
+/// The waveform that is generated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Waveform {
+    /// A sine at the frequency in Hz.
+    Sine(f64),
+    /// A square wave at the frequency in Hz.
+    Square(f64),
+    /// A rising sawtooth wave at the frequency in Hz.
+    Sawtooth(f64),
+    /// Noise with equal power at all frequencies, uncorrelated between channels.
+    WhiteNoise,
+    /// Noise of which the power falls by 3dB per octave, uncorrelated between channels.
+    PinkNoise,
+    /// A sine of which the frequency rises exponentially from the first frequency to the second
+    /// over the length of the generator.
+    Sweep(f64, f64),
+    /// A single frame at full amplitude followed by silence.
+    Impulse,
+    Silence,
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Waveform::Sine(freq) => write!(f, "Sine {}Hz", freq),
+            Waveform::Square(freq) => write!(f, "Square {}Hz", freq),
+            Waveform::Sawtooth(freq) => write!(f, "Sawtooth {}Hz", freq),
+            Waveform::WhiteNoise => write!(f, "White noise"),
+            Waveform::PinkNoise => write!(f, "Pink noise"),
+            Waveform::Sweep(from, to) => write!(f, "Sweep {}Hz-{}Hz", from, to),
+            Waveform::Impulse => write!(f, "Impulse"),
+            Waveform::Silence => write!(f, "Silence"),
+        }
+    }
+}
+
+impl Waveform {
+    /// Returns true if all frequencies of the waveform are positive and finite.
+    pub fn is_valid(&self) -> bool {
+        let valid = |freq: f64| freq > 0.0 && freq.is_finite();
+        match *self {
+            Waveform::Sine(freq) | Waveform::Square(freq) | Waveform::Sawtooth(freq) => valid(freq),
+            Waveform::Sweep(from, to) => valid(from) && valid(to),
+            _ => true,
+        }
+    }
+}
+
+/// Generator produces a waveform of a fixed length. All channels carry the same signal, except
+/// for noise.
+pub struct Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+    pub waveform: Waveform,
+    /// The peak amplitude, where 1.0 is full scale.
+    pub amplitude: f64,
+
+    sample_rate: u32,
+    length: u64,
+    position: u64,
+    _f: marker::PhantomData<F>,
+}
+
+impl<F> Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+    /// Creates a generator at full scale of which the length is specified in frames.
+    ///
+    /// Panics if the waveform has a frequency that is not positive and finite.
+    pub fn new(waveform: Waveform, sample_rate: u32, length: u64) -> Generator<F> {
+        assert!(waveform.is_valid(), "invalid waveform: {:?}", waveform);
+        Generator {
+            waveform,
+            amplitude: 1.0,
+            sample_rate,
+            length,
+            position: 0,
+            _f: marker::PhantomData,
+        }
+    }
+
+    /// Returns the value at the position in the range [-1, 1].
+    fn value(&self, position: u64, channel: usize) -> f64 {
+        let rate = f64::from(self.sample_rate);
+        let phase = |freq: f64| (position as f64 * freq / rate).fract();
+        match self.waveform {
+            Waveform::Sine(freq) => (2.0 * PI * phase(freq)).sin(),
+            Waveform::Square(freq) => {
+                if phase(freq) < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sawtooth(freq) => 2.0 * phase(freq) - 1.0,
+            Waveform::WhiteNoise => noise(&[position, channel as u64]),
+            Waveform::PinkNoise => {
+                // Voss-McCartney: row k holds a random value that changes every 2^k frames. The
+                // sum of all rows and a white noise term approximates a 1/f spectrum.
+                let rows: f64 = (0..PINK_ROWS)
+                    .map(|k| noise(&[position >> k, channel as u64, u64::from(k) + 1]))
+                    .sum();
+                (rows + noise(&[position, channel as u64, 0])) / f64::from(PINK_ROWS + 1)
+            }
+            Waveform::Sweep(from, to) => {
+                let duration = self.length as f64 / rate;
+                let k = (to / from).ln();
+                if duration == 0.0 || k == 0.0 {
+                    return (2.0 * PI * phase(from)).sin();
+                }
+                let t = position as f64 / rate;
+                let cycles = from * duration / k * ((t * k / duration).exp() - 1.0);
+                (2.0 * PI * cycles.fract()).sin()
+            }
+            Waveform::Impulse => {
+                if position == 0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Waveform::Silence => 0.0,
+        }
+    }
+}
+
+impl<F> iter::Iterator for Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+    type Item = F;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.length {
+            return None;
+        }
+        let position = self.position;
+        self.position += 1;
+        Some(F::from_fn(|ch| {
+            (self.amplitude * self.value(position, ch)).to_sample()
+        }))
+    }
+}
+
+impl<F> Source for Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl<F> Seekable for Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+    fn seek(&mut self, position: u64) -> Result<(), SeekError> {
+        if position > self.length {
+            return Err(SeekError::OutofRange {
+                pos: position,
+                size: self.length,
+            });
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn current_position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<F> Seek for Generator<F>
+where
+    F: sample::Frame,
+    F::Sample: sample::FromSample<f64>,
+{
+}
+
+/// Creates a generator of 32 bit floating point frames with the number of channels. Returns an
+/// error if the waveform is invalid or the number of channels is not supported.
+pub fn dynam_generator(
+    waveform: Waveform,
+    amplitude: f64,
+    sample_rate: u32,
+    length: u64,
+    num_channels: u32,
+) -> Result<dynam::Seek, Error> {
+    if !waveform.is_valid() {
+        return Err(Error::Waveform(waveform));
+    }
+    match num_channels {
+        1 => {
+            let mut gen = Generator::<[f32; 1]>::new(waveform, sample_rate, length);
+            gen.amplitude = amplitude;
+            Ok(dynam::Seek::MonoF32(Box::new(gen)))
+        }
+        2 => {
+            let mut gen = Generator::<[f32; 2]>::new(waveform, sample_rate, length);
+            gen.amplitude = amplitude;
+            Ok(dynam::Seek::StereoF32(Box::new(gen)))
+        }
+        _ => Err(Error::NumChannels(num_channels)),
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A frequency of the waveform is not positive and finite.
+    Waveform(Waveform),
+    /// Only mono and stereo generators are supported.
+    NumChannels(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Waveform(ref waveform) => write!(f, "Invalid waveform: {:?}", waveform),
+            Error::NumChannels(n) => write!(f, "Unsupported number of channels: {}", n),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Generator error"
+    }
+}
+
+/// Returns a uniformly distributed value in [-1, 1) that is derived from the keys.
+fn noise(keys: &[u64]) -> f64 {
+    let hash = keys
+        .iter()
+        .fold(0x853c_49e6_748f_ea9b, |h, &k| splitmix64(h ^ k));
+    (hash >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+/// See http://xoshiro.di.unimi.it/splitmix64.c
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(waveform: Waveform, length: u64) -> Vec<f64> {
+        Generator::<[f64; 1]>::new(waveform, 44100, length)
+            .map(|f| f[0])
+            .collect()
+    }
+
+    /// Counts the number of times the signal goes from negative to non-negative.
+    fn rising_edges(signal: &[f64]) -> usize {
+        signal
+            .windows(2)
+            .filter(|w| w[0] < 0.0 && w[1] >= 0.0)
+            .count()
+    }
+
+    #[test]
+    fn valid() {
+        assert!(Waveform::Sweep(20.0, 20_000.0).is_valid());
+        assert!(Waveform::WhiteNoise.is_valid());
+        assert!(!Waveform::Sweep(0.0, 1000.0).is_valid());
+        assert!(!Waveform::Sine(-440.0).is_valid());
+        assert!(!Waveform::Square(f64::NAN).is_valid());
+        assert!(!Waveform::Sawtooth(f64::INFINITY).is_valid());
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid() {
+        Generator::<[f64; 1]>::new(Waveform::Sweep(0.0, 1000.0), 44100, 100);
+    }
+
+    #[test]
+    fn periodic() {
+        for &waveform in &[
+            Waveform::Sine(441.0),
+            Waveform::Square(441.0),
+            Waveform::Sawtooth(441.0),
+        ] {
+            let signal = collect(waveform, 44100);
+            assert_eq!(44100, signal.len());
+            let edges = rising_edges(&signal);
+            assert!(edges == 440 || edges == 441, "{}: {}", waveform, edges);
+            assert!(signal.iter().all(|s| s.abs() <= 1.0));
+        }
+    }
+
+    #[test]
+    fn sweep() {
+        let signal = collect(Waveform::Sweep(100.0, 10_000.0), 44100);
+        // The frequency rises from 100Hz to 158Hz in the first tenth of a second and from 6310Hz
+        // to 10kHz in the last, which makes for about 12.7 and 801 cycles.
+        let first = rising_edges(&signal[..4410]);
+        let last = rising_edges(&signal[44100 - 4410..]);
+        assert!(first >= 11 && first <= 14, "first: {}", first);
+        assert!(last >= 790 && last <= 810, "last: {}", last);
+    }
+
+    #[test]
+    fn noise_is_seekable() {
+        for &waveform in &[Waveform::WhiteNoise, Waveform::PinkNoise] {
+            let mut gen = Generator::<[f64; 2]>::new(waveform, 44100, 1000);
+            let all: Vec<_> = gen.by_ref().collect();
+            gen.seek(500).unwrap();
+            assert_eq!(all[500..].to_vec(), gen.collect::<Vec<_>>());
+            assert!(all.iter().all(|f| f[0].abs() <= 1.0));
+            // The channels are not correlated.
+            assert!(all.iter().any(|f| f[0] != f[1]));
+        }
+    }
+
+    #[test]
+    fn noise_spectrum() {
+        // The power of white noise is mostly at high frequencies, which the difference between
+        // adjacent samples emphasises, while pink noise has most of its power at low frequencies.
+        let ratio = |signal: Vec<f64>| {
+            let power: f64 = signal.iter().map(|s| s * s).sum();
+            let diff: f64 = signal.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+            diff / power
+        };
+        let white = ratio(collect(Waveform::WhiteNoise, 44100));
+        let pink = ratio(collect(Waveform::PinkNoise, 44100));
+        assert!((white - 2.0).abs() < 0.1, "white: {}", white);
+        assert!(pink < 0.5, "pink: {}", pink);
+    }
+
+    #[test]
+    fn impulse_and_silence() {
+        let impulse = collect(Waveform::Impulse, 100);
+        assert_eq!(1.0, impulse[0]);
+        assert!(impulse[1..].iter().all(|&s| s == 0.0));
+        assert!(collect(Waveform::Silence, 100).iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn dynam_channels() {
+        match dynam_generator(Waveform::Sine(1000.0), 0.5, 48000, 480, 2) {
+            Ok(dynam::Seek::StereoF32(s)) => {
+                assert_eq!(48000, s.sample_rate());
+                assert_eq!(480, s.length());
+                let peak = s.map(|f| f[0].abs()).fold(0.0, f32::max);
+                assert!((peak - 0.5).abs() < 1e-3);
+            }
+            _ => panic!("unexpected format"),
+        }
+        assert!(dynam_generator(Waveform::Silence, 1.0, 48000, 480, 6).is_err());
+        assert!(dynam_generator(Waveform::Sine(0.0), 1.0, 48000, 480, 2).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use super::*;
+use crate::filter::PARAM_INTERVAL;
+use sample;
+use std::*;
+
+/// A named position in a track.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cue {
+    pub name: String,
+    /// The position in frames.
+    pub position: u64,
+}
+
+/// A region of a source that is played repeatedly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The position of the first frame of the region.
+    pub start: u64,
+    /// The position of the frame after the last frame of the region.
+    pub end: u64,
+    /// The number of times the region is played again, or None to repeat it indefinitely.
+    pub repeats: Option<u32>,
+}
+
+impl Region {
+    /// Creates a region that is repeated indefinitely. Returns None if the region is empty.
+    pub fn new(start: u64, end: u64) -> Option<Region> {
+        if start < end {
+            Some(Region {
+                start,
+                end,
+                repeats: None,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The end of the region was reached and playback jumped back to its start.
+    Loop(Region),
+    /// The frame at the position of the cue was read.
+    Cue(Cue),
+}
+
+/// Looper repeats a region of a seekable source and reports when cue points are passed.
+///
+/// Because the jump back to the start of the region is done when the last frame of the region has
+/// been read, loops are seamless regardless of any buffering further down the pipeline.
+pub struct Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    pub region: sync::Arc<sync::Mutex<Option<Region>>>,
+    pub cues: sync::Arc<sync::Mutex<Vec<Cue>>>,
+
+    input: S,
+    /// The position of the frame that will be read next.
+    position: u64,
+    /// The position from which the input has been read without seeking. A region is only repeated
+    /// if its end was reached by playing from before it, so seeking past a region escapes it.
+    played_from: u64,
+    current_region: Option<Region>,
+    /// The cue points sorted by position.
+    current_cues: Vec<Cue>,
+    /// The number of frames since the parameters were last checked.
+    counter: usize,
+    event_handler: sync::Arc<Fn(Event) + Send + Sync>,
+}
+
+impl<S> Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    fn wrap(&mut self, region: Region) {
+        if self.input.seek(region.start).is_err() {
+            self.current_region = None;
+            return;
+        }
+        self.position = region.start;
+        self.played_from = region.start;
+        let next = match region.repeats {
+            Some(n) if n <= 1 => None,
+            Some(n) => Some(Region {
+                repeats: Some(n - 1),
+                ..region
+            }),
+            None => Some(region),
+        };
+        {
+            // Leave the region alone if it was changed since it was last checked.
+            let mut shared = self.region.lock().unwrap();
+            if *shared == Some(region) {
+                *shared = next;
+            }
+        }
+        self.current_region = next;
+        (self.event_handler)(Event::Loop(region));
+    }
+
+    fn emit_cues(&self) {
+        let position = self.position;
+        let first = self
+            .current_cues
+            .iter()
+            .position(|cue| cue.position >= position);
+        if let Some(first) = first {
+            for cue in self.current_cues[first..]
+                .iter()
+                .take_while(|cue| cue.position == position)
+            {
+                (self.event_handler)(Event::Cue(cue.clone()));
+            }
+        }
+    }
+}
+
+impl<S> iter::Iterator for Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.counter == 0 {
+            self.current_region = *self.region.lock().unwrap();
+            let cues = self.cues.lock().unwrap();
+            if *cues != self.current_cues {
+                self.current_cues = cues.clone();
+                self.current_cues.sort_by_key(|cue| cue.position);
+            }
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+
+        // The end may already lie behind the position if the region was changed since it was
+        // last checked, in which case playback jumps back immediately.
+        if let Some(region) = self.current_region {
+            if self.position >= region.end && self.played_from < region.end {
+                self.wrap(region);
+            }
+        }
+        let frame = self.input.next()?;
+        if !self.current_cues.is_empty() {
+            self.emit_cues();
+        }
+        self.position += 1;
+        Some(frame)
+    }
+}
+
+impl<S> Source for Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+impl<S> Seekable for Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    fn seek(&mut self, position: u64) -> Result<(), SeekError> {
+        self.input.seek(position)?;
+        self.position = position;
+        self.played_from = position;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 {
+        self.input.length()
+    }
+
+    fn current_position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<S> Seek for Looper<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+}
+
+pub trait IntoLooper: Seek + Sized
+where
+    Self::Item: sample::Frame,
+{
+    /// Repeats the region and reports passed cue points to the event handler. Both may be
+    /// changed while the source is being consumed.
+    fn looper(
+        self,
+        region: sync::Arc<sync::Mutex<Option<Region>>>,
+        cues: sync::Arc<sync::Mutex<Vec<Cue>>>,
+        event_handler: sync::Arc<Fn(Event) + Send + Sync>,
+    ) -> Looper<Self> {
+        let position = self.current_position();
+        Looper {
+            region,
+            cues,
+            input: self,
+            position,
+            played_from: position,
+            current_region: None,
+            current_cues: Vec::new(),
+            counter: 0,
+            event_handler,
+        }
+    }
+}
+
+impl<T> IntoLooper for T
+where
+    T: Seek,
+    T::Item: sample::Frame,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::testing::*;
+
+    fn looper(
+        length: u64,
+        region: Option<Region>,
+        cues: Vec<Cue>,
+    ) -> (Looper<Counter>, sync::Arc<sync::Mutex<Vec<Event>>>) {
+        let events = sync::Arc::new(sync::Mutex::new(Vec::new()));
+        let events_handler = events.clone();
+        let looper = counter(length).looper(
+            sync::Arc::new(sync::Mutex::new(region)),
+            sync::Arc::new(sync::Mutex::new(cues)),
+            sync::Arc::new(move |event: Event| events_handler.lock().unwrap().push(event)),
+        );
+        (looper, events)
+    }
+
+    #[test]
+    fn seamless_loop() {
+        let region = Region::new(10, 20).unwrap();
+        let (looper, events) = looper(100, Some(region), vec![]);
+        let out: Vec<u64> = looper.take(35).map(|f| f[0]).collect();
+        let expected: Vec<u64> = (0..20).chain(10..20).chain(10..15).collect();
+        assert_eq!(expected, out);
+        assert_eq!(
+            vec![Event::Loop(region), Event::Loop(region)],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn repeats() {
+        let region = Region {
+            start: 5,
+            end: 10,
+            repeats: Some(2),
+        };
+        let (looper, events) = looper(20, Some(region), vec![]);
+        let shared_region = looper.region.clone();
+        let out: Vec<u64> = looper.map(|f| f[0]).collect();
+        let expected: Vec<u64> = (0..10).chain(5..10).chain(5..20).collect();
+        assert_eq!(expected, out);
+        assert_eq!(2, events.lock().unwrap().len());
+        assert_eq!(None, *shared_region.lock().unwrap());
+    }
+
+    #[test]
+    fn cues() {
+        let cues = vec![
+            Cue {
+                name: "drop".to_string(),
+                position: 12,
+            },
+            Cue {
+                name: "intro".to_string(),
+                position: 3,
+            },
+            Cue {
+                name: "outro".to_string(),
+                position: 50,
+            },
+        ];
+        let region = Region::new(10, 15).unwrap();
+        let (looper, events) = looper(100, Some(region), cues.clone());
+        looper.take(20).for_each(|_| ());
+        assert_eq!(
+            vec![
+                Event::Cue(cues[1].clone()),
+                Event::Cue(cues[0].clone()),
+                Event::Loop(region),
+                Event::Cue(cues[0].clone()),
+            ],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn region_behind_position() {
+        let (mut looper, events) = looper(100, None, vec![]);
+        looper.by_ref().take(30).for_each(|_| ());
+        let region = Region::new(10, 20).unwrap();
+        *looper.region.lock().unwrap() = Some(region);
+        // The region is picked up at the next parameter check, where playback jumps back at once.
+        let out: Vec<u64> = looper.take(50).map(|f| f[0]).collect();
+        let expected: Vec<u64> = (30..64).chain(10..20).chain(10..16).collect();
+        assert_eq!(expected, out);
+        assert_eq!(
+            vec![Event::Loop(region), Event::Loop(region)],
+            *events.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn seek_past_region() {
+        let region = Region::new(10, 20).unwrap();
+        let (mut looper, events) = looper(30, Some(region), vec![]);
+        looper.next();
+        looper.seek(25).unwrap();
+        let out: Vec<u64> = looper.map(|f| f[0]).collect();
+        assert_eq!((25..30).collect::<Vec<_>>(), out);
+        assert!(events.lock().unwrap().is_empty());
+    }
+}
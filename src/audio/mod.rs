@@ -2,7 +2,14 @@ use sample;
 use std::ops::{Deref, DerefMut};
 use std::*;
 
+pub mod buffer;
 pub mod dynam;
+pub mod gen;
+pub mod looper;
+pub mod record;
+pub mod segment;
+#[cfg(test)]
+mod testing;
 
 pub trait Source: iter::Iterator
 where
@@ -78,6 +85,12 @@ pub fn duration_of(sample_rate: u32, num_samples: u64) -> time::Duration {
     time::Duration::new(secs, nanos)
 }
 
+/// Returns the number of frames that make up the duration at the specified sample rate.
+pub fn frames_of(sample_rate: u32, duration: time::Duration) -> u64 {
+    duration.as_secs() * u64::from(sample_rate)
+        + u64::from(duration.subsec_nanos()) * u64::from(sample_rate) / 1_000_000_000
+}
+
 pub trait Sink<F>
 where
     F: sample::Frame,
@@ -0,0 +1,187 @@
+use super::*;
+use log::*;
+use sample;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::*;
+
+/// Recording writes a source to a sink from a separate thread until the source ends or the
+/// recording is stopped.
+pub struct Recording {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<Result<u64, Box<error::Error + Send>>>>,
+}
+
+impl Recording {
+    /// Stops the recording and waits for the sink to be dropped. Returns the number of frames
+    /// that were written.
+    pub fn stop(mut self) -> Result<u64, Box<error::Error + Send>> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join()
+    }
+
+    /// Waits for the source to end. Returns the number of frames that were written.
+    pub fn wait(mut self) -> Result<u64, Box<error::Error + Send>> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<u64, Box<error::Error + Send>> {
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or(Err(Box::new(Error::Panicked))),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for Recording {
+    /// Stops the recording and waits for the sink to be dropped. Errors are logged.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Err(err) = self.join() {
+            error!("recording failed: {}", err);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The thread writing to the sink panicked.
+    Panicked,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Panicked => write!(f, "The recording thread panicked"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Recording error"
+    }
+}
+
+/// Starts writing the source to the sink from a separate thread.
+pub fn record<S, K>(source: S, mut sink: K) -> Recording
+where
+    S: Source + Send + 'static,
+    S::Item: sample::Frame,
+    K: Sink<S::Item> + Send + 'static,
+{
+    assert_eq!(source.sample_rate(), sink.sample_rate());
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_thread = stop.clone();
+    let thread = thread::spawn(move || {
+        let mut num_frames = 0;
+        for frame in source {
+            if stop_thread.load(Ordering::Relaxed) {
+                break;
+            }
+            sink.write_frame(frame)?;
+            num_frames += 1;
+        }
+        Ok(num_frames)
+    });
+    Recording {
+        stop,
+        thread: Some(thread),
+    }
+}
+
+/// Returns a path in the directory of which the filename consists of the prefix followed by the
+/// current UTC time, e.g. `capture-20180901-123000.wav`.
+pub fn timestamped_path(dir: &Path, prefix: &str, extension: &str) -> PathBuf {
+    dir.join(format!(
+        "{}-{}.{}",
+        prefix,
+        timestamp(time::SystemTime::now()),
+        extension
+    ))
+}
+
+/// Formats the time as `YYYYMMDD-hhmmss` in UTC.
+fn timestamp(t: time::SystemTime) -> String {
+    let secs = t
+        .duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::wave;
+
+    #[test]
+    fn timestamps() {
+        let at = |secs| time::UNIX_EPOCH + time::Duration::from_secs(secs);
+        assert_eq!("19700101-000000", timestamp(at(0)));
+        assert_eq!("20010909-014640", timestamp(at(1_000_000_000)));
+        assert_eq!("20000229-235959", timestamp(at(951_868_799)));
+    }
+
+    #[test]
+    fn record_to_wave() {
+        let input: Vec<[i16; 2]> = (0..10_000).map(|i| [i as i16, -i as i16]).collect();
+        let path = env::temp_dir().join("audio-thing-record-test.wav");
+        let file = io::BufWriter::new(fs::File::create(&path).unwrap());
+        let encoder = wave::encode(file, 44100).unwrap();
+        let recording = record(input.clone().into_iter().source(44100), encoder);
+        assert_eq!(10_000, recording.wait().unwrap());
+
+        let (audio, _) = wave::decode(fs::File::open(&path).unwrap()).unwrap();
+        let decoded: Vec<[i16; 2]> = match audio.into_seek() {
+            Some(dynam::Seek::StereoI16(s)) => s.collect(),
+            _ => panic!("unexpected format"),
+        };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(input, decoded);
+    }
+
+    #[test]
+    fn panicked() {
+        struct Panicking;
+        impl Sink<[i16; 1]> for Panicking {
+            fn sample_rate(&self) -> u32 {
+                44100
+            }
+            fn write_frame(&mut self, _: [i16; 1]) -> Result<(), Box<error::Error + Send>> {
+                panic!("write failed");
+            }
+        }
+        let input = iter::repeat([0i16]).take(10).source(44100);
+        match record(input, Panicking).wait() {
+            Err(err) => assert_eq!("The recording thread panicked", err.to_string()),
+            Ok(_) => panic!("expected an error"),
+        }
+        // Dropping a recording of which the thread panicked does not panic.
+        drop(record(
+            iter::repeat([0i16]).take(10).source(44100),
+            Panicking,
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+use super::*;
+use sample;
+use std::*;
+
+/// Segment exposes the frames `[start, end)` of a seekable source. Positions are relative to the
+/// start of the segment, so the segment behaves like a source of its own.
+pub struct Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    input: S,
+    start: u64,
+    end: u64,
+    /// The position of the frame that will be read next, relative to the start.
+    position: u64,
+}
+
+impl<S> Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    /// Returns the position of the first frame of the segment in the input.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the position after the last frame of the segment in the input.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    pub fn into_inner(self) -> S {
+        self.input
+    }
+}
+
+impl<S> iter::Iterator for Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start + self.position >= self.end {
+            return None;
+        }
+        let frame = self.input.next()?;
+        self.position += 1;
+        Some(frame)
+    }
+}
+
+impl<S> Source for Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+impl<S> Seekable for Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+    /// Seeks to the position relative to the start. Positions past the end of the segment are
+    /// clamped to the end.
+    fn seek(&mut self, position: u64) -> Result<(), SeekError> {
+        let position = position.min(self.length());
+        self.input.seek(self.start + position)?;
+        self.position = position;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 {
+        self.end - self.start
+    }
+
+    fn current_position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl<S> Seek for Segment<S>
+where
+    S: Seek,
+    S::Item: sample::Frame,
+{
+}
+
+pub trait IntoSegment: Seek + Sized
+where
+    Self::Item: sample::Frame,
+{
+    /// Limits the source to the frames in `[start, end)`. The end is clamped to the length of the
+    /// source and the start to the end. The source is seeked to the start of the segment.
+    fn segment(mut self, start: u64, end: u64) -> Result<Segment<Self>, SeekError> {
+        let end = end.min(self.length());
+        let start = start.min(end);
+        self.seek(start)?;
+        Ok(Segment {
+            input: self,
+            start,
+            end,
+            position: 0,
+        })
+    }
+}
+
+impl<T> IntoSegment for T
+where
+    T: Seek,
+    T::Item: sample::Frame,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::testing::*;
+
+    #[test]
+    fn window() {
+        let segment = counter(100).segment(10, 20).unwrap();
+        assert_eq!(10, segment.length());
+        assert_eq!(0, segment.current_position());
+        let out: Vec<u64> = segment.map(|f| f[0]).collect();
+        assert_eq!((10..20).collect::<Vec<_>>(), out);
+    }
+
+    #[test]
+    fn seek() {
+        let mut segment = counter(100).segment(10, 20).unwrap();
+        segment.seek(5).unwrap();
+        assert_eq!(5, segment.current_position());
+        assert_eq!(Some([15]), segment.next());
+        assert_eq!(6, segment.current_position());
+
+        // Seeking past the end is clamped to the end of the segment.
+        segment.seek(50).unwrap();
+        assert_eq!(10, segment.current_position());
+        assert_eq!(None, segment.next());
+    }
+
+    #[test]
+    fn clamped_bounds() {
+        let segment = counter(30).segment(20, 40).unwrap();
+        assert_eq!((20, 30), (segment.start(), segment.end()));
+        assert_eq!(10, segment.count());
+
+        let mut empty = counter(30).segment(40, 50).unwrap();
+        assert_eq!(0, empty.length());
+        assert_eq!(None, empty.next());
+    }
+
+    #[test]
+    fn dynam_segment() {
+        let seek = dynam::Seek::MonoU64(Box::new(counter(100)));
+        match seek.segment(90, 95).unwrap() {
+            dynam::Seek::MonoU64(s) => {
+                assert_eq!(5, s.length());
+                assert_eq!(vec![[90], [91], [92], [93], [94]], s.collect::<Vec<_>>());
+            }
+            _ => panic!("unexpected format"),
+        }
+    }
+}
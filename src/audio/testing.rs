@@ -0,0 +1,59 @@
+//!
+//! Sources for testing the adapters in this module.
+//!
+
+use super::*;
+use std::*;
+
+/// A seekable source that yields its own position.
+pub struct Counter {
+    position: u64,
+    length: u64,
+}
+
+impl iter::Iterator for Counter {
+    type Item = [u64; 1];
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.length {
+            return None;
+        }
+        self.position += 1;
+        Some([self.position - 1])
+    }
+}
+
+impl Source for Counter {
+    fn sample_rate(&self) -> u32 {
+        44100
+    }
+}
+
+impl Seekable for Counter {
+    fn seek(&mut self, position: u64) -> Result<(), SeekError> {
+        if position > self.length {
+            return Err(SeekError::OutofRange {
+                pos: position,
+                size: self.length,
+            });
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn current_position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl Seek for Counter {}
+
+pub fn counter(length: u64) -> Counter {
+    Counter {
+        position: 0,
+        length,
+    }
+}
@@ -0,0 +1,149 @@
+//!
+//! Second order IIR filters, on which the equalizer and the K-weighting of loudness measurements
+//! are built.
+//!
+
+use std::*;
+
+/// The coefficients of a biquad filter, normalized so a0 is 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Coefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl Coefficients {
+    /// Returns the coefficients of a filter that passes the signal unaltered.
+    pub fn identity() -> Coefficients {
+        Coefficients {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        }
+    }
+
+    fn sub(&self, other: &Coefficients) -> Coefficients {
+        Coefficients {
+            b0: self.b0 - other.b0,
+            b1: self.b1 - other.b1,
+            b2: self.b2 - other.b2,
+            a1: self.a1 - other.a1,
+            a2: self.a2 - other.a2,
+        }
+    }
+
+    fn scale(&self, f: f64) -> Coefficients {
+        Coefficients {
+            b0: self.b0 * f,
+            b1: self.b1 * f,
+            b2: self.b2 * f,
+            a1: self.a1 * f,
+            a2: self.a2 * f,
+        }
+    }
+
+    fn add(&self, other: &Coefficients) -> Coefficients {
+        self.sub(&other.scale(-1.0))
+    }
+}
+
+/// A biquad filter in transposed direct form II. It operates on frames of floating point samples,
+/// so it can be embedded in other signal processors.
+///
+/// When the coefficients are changed, they are linearly interpolated from the old to the new
+/// values. Because the set of stable biquad denominators is convex, every intermediate filter is
+/// stable as well.
+pub struct Biquad {
+    coefficients: Coefficients,
+    target: Coefficients,
+    step: Coefficients,
+    remaining: usize,
+    /// The two state variables of each channel.
+    state: Vec<[f64; 2]>,
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients, num_channels: usize) -> Biquad {
+        Biquad {
+            coefficients,
+            target: coefficients,
+            step: Coefficients::identity(),
+            remaining: 0,
+            state: vec![[0.0; 2]; num_channels],
+        }
+    }
+
+    /// Returns the coefficients that the filter is moving to, or has reached.
+    pub fn target(&self) -> Coefficients {
+        self.target
+    }
+
+    /// Returns true if the filter is not moving to new coefficients.
+    pub fn is_settled(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Moves the coefficients to the target over the specified number of frames.
+    pub fn set_target(&mut self, target: Coefficients, ramp_length: usize) {
+        self.target = target;
+        if ramp_length == 0 {
+            self.coefficients = target;
+            self.remaining = 0;
+            return;
+        }
+        self.step = target
+            .sub(&self.coefficients)
+            .scale(1.0 / ramp_length as f64);
+        self.remaining = ramp_length;
+    }
+
+    /// Filters a single frame in place.
+    pub fn process(&mut self, frame: &mut [f64]) {
+        debug_assert_eq!(self.state.len(), frame.len());
+        let c = self.coefficients;
+        for (x, s) in frame.iter_mut().zip(self.state.iter_mut()) {
+            let y = c.b0 * *x + s[0];
+            s[0] = c.b1 * *x - c.a1 * y + s[1];
+            s[1] = c.b2 * *x - c.a2 * y;
+            *x = y;
+        }
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.coefficients = if self.remaining == 0 {
+                self.target
+            } else {
+                self.coefficients.add(&self.step)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ramp() {
+        let mut filter = Biquad::new(Coefficients::identity(), 1);
+        let gain = Coefficients {
+            b0: 2.0,
+            ..Coefficients::identity()
+        };
+        filter.set_target(gain, 4);
+        let output: Vec<f64> = (0..6)
+            .map(|_| {
+                let mut frame = [1.0];
+                filter.process(&mut frame);
+                frame[0]
+            })
+            .collect();
+        assert_eq!(vec![1.0, 1.25, 1.5, 1.75, 2.0, 2.0], output);
+        assert!(filter.is_settled());
+        assert_eq!(gain, filter.target());
+    }
+}
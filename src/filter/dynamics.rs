@@ -0,0 +1,602 @@
+//!
+//! This module implements processors that control the dynamic range of a signal: a feed-forward
+//! compressor, a look-ahead brickwall limiter and a noise gate.
+//!
+//! The channels of a frame are linked: the gain is derived from the loudest channel and applied
+//! to all channels, so the stereo image is retained.
+//!
+
+use crate::audio;
+use crate::filter::gain::{db_to_factor, factor_to_db};
+use crate::filter::PARAM_INTERVAL;
+use sample::{self, Frame, Sample};
+use std::*;
+
+/// Processor is implemented by the dynamics processors, so they can be embedded in other signal
+/// processors or applied to a source using `IntoDynamics`.
+pub trait Processor {
+    type Params: Clone;
+
+    /// Updates the parameters. This may be done at any time without causing discontinuities.
+    fn set_params(&mut self, params: &Self::Params);
+
+    /// Processes a single frame in place.
+    fn process(&mut self, frame: &mut [f64]);
+
+    /// The number of frames by which the output lags behind the input.
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CompressorParams {
+    /// The level in dB above which the signal is compressed.
+    pub threshold: f64,
+    /// The ratio between the input and output level above the threshold.
+    pub ratio: f64,
+    /// The width in dB of the region around the threshold over which the ratio is blended in.
+    pub knee: f64,
+    /// The time in seconds in which the gain reduction follows a rising level.
+    pub attack: f64,
+    /// The time in seconds in which the gain reduction recovers from a falling level.
+    pub release: f64,
+    /// The gain in dB applied after compression.
+    pub makeup: f64,
+}
+
+impl Default for CompressorParams {
+    fn default() -> CompressorParams {
+        CompressorParams {
+            threshold: -18.0,
+            ratio: 4.0,
+            knee: 6.0,
+            attack: 0.01,
+            release: 0.1,
+            makeup: 0.0,
+        }
+    }
+}
+
+/// A feed-forward compressor operating on the peak level of the signal.
+pub struct Compressor {
+    sample_rate: u32,
+    params: CompressorParams,
+    attack: f64,
+    release: f64,
+    makeup: f64,
+    /// The current gain reduction in dB, which is zero or negative.
+    reduction: f64,
+}
+
+impl Compressor {
+    pub fn new(sample_rate: u32, params: &CompressorParams) -> Compressor {
+        let mut comp = Compressor {
+            sample_rate,
+            params: *params,
+            attack: 0.0,
+            release: 0.0,
+            makeup: 1.0,
+            reduction: 0.0,
+        };
+        comp.set_params(params);
+        comp
+    }
+
+    /// Computes the output level in dB of a static input level in dB.
+    fn curve(&self, level: f64) -> f64 {
+        let p = &self.params;
+        let over = level - p.threshold;
+        if 2.0 * over < -p.knee {
+            level
+        } else if 2.0 * over.abs() <= p.knee {
+            let x = over + p.knee / 2.0;
+            level + (1.0 / p.ratio - 1.0) * x * x / (2.0 * p.knee)
+        } else {
+            p.threshold + over / p.ratio
+        }
+    }
+}
+
+impl Processor for Compressor {
+    type Params = CompressorParams;
+
+    fn set_params(&mut self, params: &CompressorParams) {
+        assert!(params.ratio >= 1.0);
+        self.params = *params;
+        self.attack = time_coefficient(params.attack, self.sample_rate);
+        self.release = time_coefficient(params.release, self.sample_rate);
+        self.makeup = db_to_factor(params.makeup);
+    }
+
+    fn process(&mut self, frame: &mut [f64]) {
+        let level = factor_to_db(peak(frame));
+        let target = self.curve(level) - level;
+        let coef = if target < self.reduction {
+            self.attack
+        } else {
+            self.release
+        };
+        self.reduction = coef * self.reduction + (1.0 - coef) * target;
+        let gain = db_to_factor(self.reduction) * self.makeup;
+        for s in frame.iter_mut() {
+            *s *= gain;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LimiterParams {
+    /// The maximum level in dB of the output.
+    pub ceiling: f64,
+    /// The time in seconds in which the gain recovers after a peak.
+    pub release: f64,
+}
+
+impl Default for LimiterParams {
+    fn default() -> LimiterParams {
+        LimiterParams {
+            ceiling: -0.1,
+            release: 0.05,
+        }
+    }
+}
+
+/// A brickwall limiter which guarantees that the output never exceeds the ceiling.
+///
+/// The input is delayed by the look-ahead, so the gain can be reduced gradually before a peak
+/// arrives. The gain is derived from the minimum of the required gain over the look-ahead window,
+/// which is then smoothed by a moving average over the same window. Because every gain in the
+/// average is at most the gain required by the delayed frame, so is the average.
+pub struct Limiter {
+    sample_rate: u32,
+    num_channels: usize,
+    ceiling: f64,
+    release: f64,
+    /// The number of frames of look-ahead.
+    lookahead: usize,
+
+    /// The delayed frames, interleaved.
+    delay: Vec<f64>,
+    /// The smoothed gains of the look-ahead window.
+    gains: Vec<f64>,
+    gains_sum: f64,
+    /// The position in the ring buffers.
+    cursor: usize,
+    /// The required gains that are candidates for the minimum of the window, in increasing order
+    /// of both their index and gain.
+    minimum: collections::VecDeque<(u64, f64)>,
+    envelope: f64,
+    counter: u64,
+}
+
+impl Limiter {
+    pub fn new(
+        sample_rate: u32,
+        num_channels: usize,
+        lookahead: time::Duration,
+        params: &LimiterParams,
+    ) -> Limiter {
+        let secs = lookahead.as_secs() as f64 + f64::from(lookahead.subsec_nanos()) * 1e-9;
+        let lookahead = cmp::max(1, (secs * f64::from(sample_rate)).round() as usize);
+        let mut limiter = Limiter {
+            sample_rate,
+            num_channels,
+            ceiling: 1.0,
+            release: 0.0,
+            lookahead,
+            delay: vec![0.0; lookahead * num_channels],
+            gains: vec![1.0; lookahead],
+            gains_sum: lookahead as f64,
+            cursor: 0,
+            minimum: collections::VecDeque::new(),
+            envelope: 1.0,
+            counter: 0,
+        };
+        limiter.set_params(params);
+        limiter
+    }
+}
+
+impl Processor for Limiter {
+    type Params = LimiterParams;
+
+    fn set_params(&mut self, params: &LimiterParams) {
+        self.ceiling = db_to_factor(params.ceiling);
+        self.release = time_coefficient(params.release, self.sample_rate);
+    }
+
+    fn process(&mut self, frame: &mut [f64]) {
+        assert_eq!(self.num_channels, frame.len());
+        let p = peak(frame);
+        let required = if p > self.ceiling {
+            self.ceiling / p
+        } else {
+            1.0
+        };
+
+        // The window of the minimum spans one frame more than the look-ahead, so it covers both
+        // the delayed frame and every frame in the moving average.
+        let n = self.counter;
+        self.counter += 1;
+        while self.minimum.back().map(|&(_, g)| g >= required) == Some(true) {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((n, required));
+        while self.minimum.front().unwrap().0 + (self.lookahead as u64) < n {
+            self.minimum.pop_front();
+        }
+        let minimum = self.minimum.front().unwrap().1;
+        self.envelope = if minimum < self.envelope {
+            minimum
+        } else {
+            self.release * self.envelope + (1.0 - self.release) * minimum
+        };
+
+        self.gains_sum += self.envelope - self.gains[self.cursor];
+        self.gains[self.cursor] = self.envelope;
+        let gain = self.gains_sum / self.lookahead as f64;
+
+        let delayed = &mut self.delay[self.cursor * self.num_channels..][..self.num_channels];
+        for (s, d) in frame.iter_mut().zip(delayed.iter_mut()) {
+            let out = *d * gain;
+            *d = *s;
+            // Guards against rounding errors and changes of the ceiling.
+            *s = out.max(-self.ceiling).min(self.ceiling);
+        }
+
+        self.cursor = (self.cursor + 1) % self.lookahead;
+        if self.cursor == 0 {
+            // Prevent the accumulation of rounding errors.
+            self.gains_sum = self.gains.iter().sum();
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.lookahead
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GateParams {
+    /// The level in dB below which the gate closes.
+    pub threshold: f64,
+    /// The gain in dB that is applied while the gate is closed.
+    pub range: f64,
+    /// The time in seconds in which the gate opens.
+    pub attack: f64,
+    /// The time in seconds that the gate is kept open after the level has fallen below the
+    /// threshold.
+    pub hold: f64,
+    /// The time in seconds in which the gate closes.
+    pub release: f64,
+}
+
+impl Default for GateParams {
+    fn default() -> GateParams {
+        GateParams {
+            threshold: -50.0,
+            range: -80.0,
+            attack: 0.001,
+            hold: 0.05,
+            release: 0.1,
+        }
+    }
+}
+
+/// A noise gate attenuates the signal while its level is below the threshold.
+pub struct Gate {
+    sample_rate: u32,
+    threshold: f64,
+    range: f64,
+    attack: f64,
+    hold: usize,
+    release: f64,
+    /// The number of frames that the gate will remain open for.
+    remaining: usize,
+    gain: f64,
+}
+
+impl Gate {
+    pub fn new(sample_rate: u32, params: &GateParams) -> Gate {
+        let mut gate = Gate {
+            sample_rate,
+            threshold: 0.0,
+            range: 0.0,
+            attack: 0.0,
+            hold: 0,
+            release: 0.0,
+            remaining: 0,
+            gain: 0.0,
+        };
+        gate.set_params(params);
+        gate.gain = gate.range;
+        gate
+    }
+}
+
+impl Processor for Gate {
+    type Params = GateParams;
+
+    fn set_params(&mut self, params: &GateParams) {
+        self.threshold = db_to_factor(params.threshold);
+        self.range = db_to_factor(params.range);
+        self.attack = time_coefficient(params.attack, self.sample_rate);
+        self.hold = (params.hold * f64::from(self.sample_rate)) as usize;
+        self.release = time_coefficient(params.release, self.sample_rate);
+    }
+
+    fn process(&mut self, frame: &mut [f64]) {
+        if peak(frame) >= self.threshold {
+            self.remaining = self.hold + 1;
+        } else if self.remaining > 0 {
+            self.remaining -= 1;
+        }
+        let (target, coef) = if self.remaining > 0 {
+            (1.0, self.attack)
+        } else {
+            (self.range, self.release)
+        };
+        self.gain = coef * self.gain + (1.0 - coef) * target;
+        for s in frame.iter_mut() {
+            *s *= self.gain;
+        }
+    }
+}
+
+/// Dynamics applies a dynamics processor to a source.
+pub struct Dynamics<S, P>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+    P: Processor,
+{
+    pub params: sync::Arc<sync::Mutex<P::Params>>,
+
+    input: S,
+    processor: P,
+    /// The number of frames since the parameters were last checked.
+    counter: usize,
+    /// The number of frames that remain to be flushed from the processor after the input has
+    /// ended.
+    flush: Option<usize>,
+    /// Scratch space for a single frame.
+    frame: Vec<f64>,
+}
+
+impl<S, P> iter::Iterator for Dynamics<S, P>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+    P: Processor,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.counter == 0 {
+            let params = self.params.lock().unwrap().clone();
+            self.processor.set_params(&params);
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+
+        let next = match self.flush {
+            None => self.input.next(),
+            Some(_) => None,
+        };
+        let frame = match next {
+            Some(frame) => frame,
+            None => {
+                // Feed silence to the processor to retrieve the frames it has delayed.
+                let remaining = self.flush.unwrap_or_else(|| self.processor.latency());
+                if remaining == 0 {
+                    self.flush = Some(0);
+                    return None;
+                }
+                self.flush = Some(remaining - 1);
+                S::Item::equilibrium()
+            }
+        };
+        for (ch, s) in self.frame.iter_mut().enumerate() {
+            *s = frame.channel(ch).unwrap().to_sample();
+        }
+        self.processor.process(&mut self.frame);
+        let out = &self.frame;
+        Some(S::Item::from_fn(|ch| {
+            <S::Item as sample::Frame>::Sample::from_sample(out[ch])
+        }))
+    }
+}
+
+impl<S, P> audio::Source for Dynamics<S, P>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+    P: Processor,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait IntoDynamics: audio::Source + Sized
+where
+    Self::Item: sample::Frame,
+    <Self::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    /// Applies a processor to the signal. The parameters may be changed while the signal is
+    /// being consumed.
+    fn dynamics<P>(
+        self,
+        processor: P,
+        params: sync::Arc<sync::Mutex<P::Params>>,
+    ) -> Dynamics<Self, P>
+    where
+        P: Processor,
+    {
+        Dynamics {
+            params,
+            input: self,
+            processor,
+            counter: 0,
+            flush: None,
+            frame: vec![0.0; Self::Item::n_channels()],
+        }
+    }
+
+    fn compress(
+        self,
+        params: sync::Arc<sync::Mutex<CompressorParams>>,
+    ) -> Dynamics<Self, Compressor> {
+        let comp = Compressor::new(self.sample_rate(), &params.lock().unwrap());
+        self.dynamics(comp, params)
+    }
+
+    /// Limits the signal to the ceiling. The output is delayed by the look-ahead, and the source
+    /// is extended by the look-ahead, so no frames are lost.
+    fn limit(
+        self,
+        lookahead: time::Duration,
+        params: sync::Arc<sync::Mutex<LimiterParams>>,
+    ) -> Dynamics<Self, Limiter> {
+        let limiter = Limiter::new(
+            self.sample_rate(),
+            Self::Item::n_channels(),
+            lookahead,
+            &params.lock().unwrap(),
+        );
+        self.dynamics(limiter, params)
+    }
+
+    fn gate(self, params: sync::Arc<sync::Mutex<GateParams>>) -> Dynamics<Self, Gate> {
+        let gate = Gate::new(self.sample_rate(), &params.lock().unwrap());
+        self.dynamics(gate, params)
+    }
+}
+
+impl<T> IntoDynamics for T
+where
+    T: audio::Source,
+    T::Item: sample::Frame,
+    <T::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+}
+
+/// Returns the coefficient of a one-pole filter that reaches about 63% of a step in the
+/// specified time.
+fn time_coefficient(secs: f64, sample_rate: u32) -> f64 {
+    if secs <= 0.0 {
+        0.0
+    } else {
+        (-1.0 / (secs * f64::from(sample_rate))).exp()
+    }
+}
+
+fn peak(frame: &[f64]) -> f64 {
+    frame.iter().fold(0.0, |p, s| f64::max(p, s.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::audio::IntoSource;
+
+    /// Returns a 440Hz sine of which the right channel is at half the level of the left one.
+    fn tone(amplitude: f64, len: u64) -> Vec<[f64; 2]> {
+        let mut gen = Generator::<[f64; 2]>::new(Waveform::Sine(440.0), 44100, len);
+        gen.amplitude = amplitude;
+        gen.map(|f| [f[0], f[1] * 0.5]).collect()
+    }
+
+    fn peak_of(frames: &[[f64; 2]]) -> f64 {
+        frames.iter().fold(0.0, |p, f| p.max(peak(f)))
+    }
+
+    #[test]
+    fn compressor_curve() {
+        let params = CompressorParams {
+            threshold: -20.0,
+            ratio: 4.0,
+            knee: 0.0,
+            ..CompressorParams::default()
+        };
+        let comp = Compressor::new(44100, &params);
+        assert!((comp.curve(-30.0) + 30.0).abs() < 1e-9);
+        assert!((comp.curve(0.0) + 15.0).abs() < 1e-9);
+
+        let soft = Compressor::new(
+            44100,
+            &CompressorParams {
+                knee: 10.0,
+                ..params
+            },
+        );
+        assert!((soft.curve(-26.0) + 26.0).abs() < 1e-9);
+        assert!(soft.curve(-20.0) < -20.0 && soft.curve(-20.0) > -21.0);
+        assert!((soft.curve(0.0) + 15.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compressor() {
+        let params = sync::Arc::new(sync::Mutex::new(CompressorParams {
+            threshold: -12.0,
+            ratio: 4.0,
+            knee: 0.0,
+            makeup: 3.0,
+            ..CompressorParams::default()
+        }));
+        // A 0dB square wave is reduced to -12 + 12/4 + 3 = -6dB once the compressor has settled.
+        let output: Vec<_> = Generator::<[f64; 2]>::new(Waveform::Square(440.0), 44100, 44100)
+            .compress(params.clone())
+            .collect();
+        assert_eq!(44100, output.len());
+        let level = factor_to_db(peak_of(&output[22050..]));
+        assert!((level + 6.0).abs() < 0.5, "{}", level);
+    }
+
+    #[test]
+    fn limiter() {
+        let params = sync::Arc::new(sync::Mutex::new(LimiterParams::default()));
+        let input = tone(2.0, 44100);
+        let lookahead = time::Duration::from_millis(5);
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .limit(lookahead, params.clone())
+            .collect();
+        let latency = Limiter::new(44100, 2, lookahead, &LimiterParams::default()).latency();
+        assert_eq!(input.len() + latency, output.len());
+        assert!(peak_of(&output) <= db_to_factor(-0.1) + 1e-12);
+        // The output is a scaled copy of the delayed input.
+        for (a, b) in input.iter().zip(output[latency..].iter()) {
+            assert!((a[0] * b[1] - a[1] * b[0]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn limiter_transparent() {
+        let params = sync::Arc::new(sync::Mutex::new(LimiterParams::default()));
+        let input = tone(0.5, 4410);
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .limit(time::Duration::from_millis(1), params)
+            .collect();
+        assert_eq!(&input[..], &output[44..]);
+    }
+
+    #[test]
+    fn gate() {
+        let params = sync::Arc::new(sync::Mutex::new(GateParams::default()));
+        let mut input = tone(0.5, 22050);
+        input.extend(tone(0.001, 44100));
+        let output: Vec<_> = input.into_iter().source(44100).gate(params).collect();
+        assert!(peak_of(&output[11025..22050]) > 0.49);
+        // The gate is closed once the hold and release have elapsed.
+        assert!(factor_to_db(peak_of(&output[55125..])) < -100.0);
+    }
+}
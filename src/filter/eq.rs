@@ -0,0 +1,457 @@
+//!
+//! This module implements a parametric equalizer using biquad filters. The coefficients are
+//! calculated as described in Robert Bristow-Johnson's "Cookbook formulae for audio EQ biquad
+//! filter coefficients".
+//!
+
+use crate::audio;
+use crate::filter::biquad::{Biquad, Coefficients};
+use crate::filter::PARAM_INTERVAL;
+use sample::{self, Frame, Sample};
+use serde::de;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::*;
+use toml;
+
+/// The number of frames over which the coefficients of a filter are moved to their new values.
+const RAMP_LENGTH: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    Notch,
+}
+
+/// A single band of an equalizer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Band {
+    pub kind: Kind,
+    /// The center or corner frequency in Hz.
+    pub frequency: f64,
+    /// The gain in dB. Only used by peaking and shelf filters.
+    pub gain: f64,
+    /// The quality factor, which determines the bandwidth.
+    pub q: f64,
+}
+
+impl Band {
+    pub fn new(kind: Kind, frequency: f64, gain: f64, q: f64) -> Band {
+        Band {
+            kind,
+            frequency,
+            gain,
+            q,
+        }
+    }
+}
+
+/// Returns the builtin presets.
+pub fn presets() -> BTreeMap<String, Vec<Band>> {
+    let q = f64::consts::FRAC_1_SQRT_2;
+    let mut presets = BTreeMap::new();
+    presets.insert("flat".to_string(), vec![]);
+    presets.insert(
+        "bass boost".to_string(),
+        vec![Band::new(Kind::LowShelf, 120.0, 6.0, q)],
+    );
+    presets.insert(
+        "treble boost".to_string(),
+        vec![Band::new(Kind::HighShelf, 6000.0, 6.0, q)],
+    );
+    presets.insert(
+        "loudness".to_string(),
+        vec![
+            Band::new(Kind::LowShelf, 100.0, 5.0, q),
+            Band::new(Kind::HighShelf, 8000.0, 3.0, q),
+        ],
+    );
+    presets.insert(
+        "vocal".to_string(),
+        vec![
+            Band::new(Kind::HighPass, 80.0, 0.0, q),
+            Band::new(Kind::Peaking, 300.0, -2.0, 1.0),
+            Band::new(Kind::Peaking, 3000.0, 3.0, 1.0),
+        ],
+    );
+    presets
+}
+
+/// Parses a TOML list of presets:
+///
+/// ```toml
+/// [[preset]]
+/// name = "warm"
+/// [[preset.band]]
+/// kind = "low-shelf"
+/// frequency = 200.0
+/// gain = 3.0
+/// q = 0.7
+/// ```
+pub fn parse_presets(text: &str) -> Result<BTreeMap<String, Vec<Band>>, toml::de::Error> {
+    let list: PresetList = toml::from_str(text)?;
+    let mut presets = BTreeMap::new();
+    for preset in list.preset {
+        let bands = preset
+            .band
+            .into_iter()
+            .map(|b| {
+                let kind = match b.kind.as_str() {
+                    "peaking" => Kind::Peaking,
+                    "low-shelf" => Kind::LowShelf,
+                    "high-shelf" => Kind::HighShelf,
+                    "low-pass" => Kind::LowPass,
+                    "high-pass" => Kind::HighPass,
+                    "notch" => Kind::Notch,
+                    _ => return Err(de::Error::custom(format!("unknown band: {}", b.kind))),
+                };
+                Ok(Band::new(kind, b.frequency, b.gain, b.q))
+            })
+            .collect::<Result<_, toml::de::Error>>()?;
+        presets.insert(preset.name, bands);
+    }
+    Ok(presets)
+}
+
+/// Formats presets in the format read by `parse_presets`.
+pub fn format_presets(presets: &BTreeMap<String, Vec<Band>>) -> Result<String, toml::ser::Error> {
+    let list = PresetList {
+        preset: presets
+            .iter()
+            .map(|(name, bands)| TomlPreset {
+                name: name.clone(),
+                band: bands
+                    .iter()
+                    .map(|b| TomlBand {
+                        kind: match b.kind {
+                            Kind::Peaking => "peaking",
+                            Kind::LowShelf => "low-shelf",
+                            Kind::HighShelf => "high-shelf",
+                            Kind::LowPass => "low-pass",
+                            Kind::HighPass => "high-pass",
+                            Kind::Notch => "notch",
+                        }
+                        .to_string(),
+                        frequency: b.frequency,
+                        gain: b.gain,
+                        q: b.q,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+    toml::to_string(&list)
+}
+
+#[derive(Deserialize, Serialize)]
+struct PresetList {
+    #[serde(default)]
+    preset: Vec<TomlPreset>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TomlPreset {
+    name: String,
+    #[serde(default)]
+    band: Vec<TomlBand>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct TomlBand {
+    kind: String,
+    frequency: f64,
+    #[serde(default)]
+    gain: f64,
+    q: f64,
+}
+
+/// Calculates the coefficients of the filter of a band.
+fn band_coefficients(band: &Band, sample_rate: u32) -> Coefficients {
+    let nyquist = f64::from(sample_rate) / 2.0;
+    let frequency = band.frequency.max(1.0).min(nyquist * 0.99);
+    let w0 = 2.0 * f64::consts::PI * frequency / f64::from(sample_rate);
+    let (sin, cos) = (w0.sin(), w0.cos());
+    let alpha = sin / (2.0 * band.q.max(1e-3));
+    let a = 10f64.powf(band.gain / 40.0);
+    let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+
+    let (b0, b1, b2, a0, a1, a2) = match band.kind {
+        Kind::Peaking => (
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        ),
+        Kind::LowShelf => (
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+            (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+        ),
+        Kind::HighShelf => (
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+            (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+        ),
+        Kind::LowPass => (
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        ),
+        Kind::HighPass => (
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        ),
+        Kind::Notch => (1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+    };
+    Coefficients {
+        b0: b0 / a0,
+        b1: b1 / a0,
+        b2: b2 / a0,
+        a1: a1 / a0,
+        a2: a2 / a0,
+    }
+}
+
+/// A chain of biquad filters implementing the bands of an equalizer. It operates on frames of
+/// floating point samples, so it can be embedded in other signal processors.
+pub struct Chain {
+    sample_rate: u32,
+    num_channels: usize,
+    bands: Vec<Band>,
+    filters: Vec<Biquad>,
+    /// The number of filters that are being faded out after their bands were removed.
+    num_removed: usize,
+}
+
+impl Chain {
+    pub fn new(sample_rate: u32, num_channels: usize) -> Chain {
+        Chain {
+            sample_rate,
+            num_channels,
+            bands: Vec::new(),
+            filters: Vec::new(),
+            num_removed: 0,
+        }
+    }
+
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    /// Changes the bands of the equalizer. The filters smoothly transition to their new
+    /// response. Added bands are faded in and removed bands are faded out.
+    pub fn set_bands(&mut self, bands: &[Band]) {
+        if self.bands[..] == bands[..] {
+            return;
+        }
+        while self.filters.len() < bands.len() {
+            self.filters
+                .push(Biquad::new(Coefficients::identity(), self.num_channels));
+        }
+        for (i, filter) in self.filters.iter_mut().enumerate() {
+            let target = match bands.get(i) {
+                Some(band) => band_coefficients(band, self.sample_rate),
+                None => Coefficients::identity(),
+            };
+            if target != filter.target() {
+                filter.set_target(target, RAMP_LENGTH);
+            }
+        }
+        self.num_removed = self.filters.len() - bands.len();
+        self.bands = bands.to_vec();
+    }
+
+    /// Filters a single frame in place.
+    pub fn process(&mut self, frame: &mut [f64]) {
+        assert_eq!(self.num_channels, frame.len());
+        for filter in self.filters.iter_mut() {
+            filter.process(frame);
+        }
+        // Filters of removed bands are dropped once they have faded to identity.
+        if self.num_removed > 0 && self.filters.last().map(|f| f.is_settled()) == Some(true) {
+            let len = self.bands.len();
+            self.filters.truncate(len);
+            self.num_removed = 0;
+        }
+    }
+}
+
+pub struct Equalizer<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    pub bands: sync::Arc<sync::Mutex<Vec<Band>>>,
+
+    input: S,
+    chain: Chain,
+    /// The number of frames since the parameters were last checked.
+    counter: usize,
+    /// Scratch space for a single frame.
+    frame: Vec<f64>,
+}
+
+impl<S> iter::Iterator for Equalizer<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.counter == 0 {
+            let bands = self.bands.lock().unwrap();
+            self.chain.set_bands(&bands);
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+
+        let frame = self.input.next()?;
+        if self.chain.bands().is_empty() && self.chain.filters.is_empty() {
+            return Some(frame);
+        }
+        for (ch, s) in self.frame.iter_mut().enumerate() {
+            *s = frame.channel(ch).unwrap().to_sample();
+        }
+        self.chain.process(&mut self.frame);
+        let out = &self.frame;
+        Some(S::Item::from_fn(|ch| {
+            <S::Item as sample::Frame>::Sample::from_sample(out[ch])
+        }))
+    }
+}
+
+impl<S> audio::Source for Equalizer<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait IntoEqualizer: audio::Source + Sized
+where
+    Self::Item: sample::Frame,
+    <Self::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    /// Applies the bands of an equalizer to the signal. The bands may be changed while the signal
+    /// is being consumed.
+    fn equalizer(self, bands: sync::Arc<sync::Mutex<Vec<Band>>>) -> Equalizer<Self> {
+        let num_channels = Self::Item::n_channels();
+        Equalizer {
+            bands,
+            chain: Chain::new(self.sample_rate(), num_channels),
+            input: self,
+            counter: 0,
+            frame: vec![0.0; num_channels],
+        }
+    }
+}
+
+impl<T> IntoEqualizer for T
+where
+    T: audio::Source,
+    T::Item: sample::Frame,
+    <T::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::audio::IntoSource;
+    use crate::filter::gain;
+
+    /// Measures the gain of a filter at a frequency by comparing the peak amplitude of a sine
+    /// after the filter has settled.
+    fn gain_at(bands: Vec<Band>, frequency: f64) -> f64 {
+        let input = Generator::<[f64; 1]>::new(Waveform::Sine(frequency), 44100, 44100);
+        let output: Vec<[f64; 1]> = input
+            .equalizer(sync::Arc::new(sync::Mutex::new(bands)))
+            .collect();
+        let peak = output[22050..]
+            .iter()
+            .fold(0.0f64, |p, f| p.max(f[0].abs()));
+        gain::factor_to_db(peak)
+    }
+
+    #[test]
+    fn flat() {
+        let input: Vec<[f64; 2]> = (0..1000).map(|i| [i as f64, -(i as f64)]).collect();
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .equalizer(sync::Arc::new(sync::Mutex::new(Vec::new())))
+            .collect();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn peaking() {
+        let band = Band::new(Kind::Peaking, 1000.0, 6.0, 1.0);
+        assert!((gain_at(vec![band], 1000.0) - 6.0).abs() < 0.1);
+        assert!(gain_at(vec![band], 10000.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn shelves() {
+        let low = Band::new(Kind::LowShelf, 200.0, -6.0, f64::consts::FRAC_1_SQRT_2);
+        assert!((gain_at(vec![low], 30.0) + 6.0).abs() < 0.3);
+        assert!(gain_at(vec![low], 5000.0).abs() < 0.3);
+        let high = Band::new(Kind::HighShelf, 2000.0, 6.0, f64::consts::FRAC_1_SQRT_2);
+        assert!((gain_at(vec![high], 15000.0) - 6.0).abs() < 0.3);
+        assert!(gain_at(vec![high], 100.0).abs() < 0.3);
+    }
+
+    #[test]
+    fn pass_and_notch() {
+        let q = f64::consts::FRAC_1_SQRT_2;
+        assert!(gain_at(vec![Band::new(Kind::LowPass, 500.0, 0.0, q)], 8000.0) < -40.0);
+        assert!(gain_at(vec![Band::new(Kind::HighPass, 5000.0, 0.0, q)], 200.0) < -40.0);
+        assert!(gain_at(vec![Band::new(Kind::Notch, 1000.0, 0.0, 2.0)], 1000.0) < -30.0);
+    }
+
+    #[test]
+    fn presets_roundtrip() {
+        let text = format_presets(&presets()).unwrap();
+        assert_eq!(presets(), parse_presets(&text).unwrap());
+        let unknown =
+            "[[preset]]\nname = \"x\"\n[[preset.band]]\nkind = \"wah\"\nfrequency = 1.0\nq = 1.0\n";
+        assert!(parse_presets(unknown).is_err());
+    }
+
+    #[test]
+    fn remove_band() {
+        let mut chain = Chain::new(44100, 1);
+        chain.set_bands(&[Band::new(Kind::Peaking, 1000.0, 6.0, 1.0)]);
+        chain.set_bands(&[]);
+        for _ in 0..RAMP_LENGTH {
+            chain.process(&mut [0.0]);
+        }
+        assert!(chain.filters.is_empty());
+    }
+}
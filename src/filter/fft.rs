@@ -0,0 +1,181 @@
+//!
+//! This module implements the Fourier transformation of real signals by packing the even and odd
+//! samples of a signal into a complex signal of half the length, which is then transformed using
+//! a regular complex FFT.
+//!
+
+use rustfft::num_complex::Complex64;
+use rustfft::{FFTplanner, FFT};
+use std::*;
+
+/// RealFft computes the spectrum of a real signal.
+pub struct RealFft {
+    size: usize,
+    fft: sync::Arc<FFT<f64>>,
+    /// `e^(-2πik/N)` for each non-negative frequency bin.
+    twiddles: Vec<Complex64>,
+    buf_in: Vec<Complex64>,
+    buf_out: Vec<Complex64>,
+}
+
+impl RealFft {
+    /// Creates a new transformation for signals of the specified size, which must be even.
+    pub fn new(size: usize) -> RealFft {
+        assert!(size >= 2 && size % 2 == 0);
+        RealFft {
+            size,
+            fft: FFTplanner::new(false).plan_fft(size / 2),
+            twiddles: twiddles(size, -1.0),
+            buf_in: vec![Complex64::new(0.0, 0.0); size / 2],
+            buf_out: vec![Complex64::new(0.0, 0.0); size / 2],
+        }
+    }
+
+    /// Computes the `size / 2 + 1` non-negative frequency bins of the input.
+    pub fn process(&mut self, input: &[f64], output: &mut [Complex64]) {
+        assert_eq!(self.size, input.len());
+        assert_eq!(self.size / 2 + 1, output.len());
+        let m = self.size / 2;
+        for (n, z) in self.buf_in.iter_mut().enumerate() {
+            *z = Complex64::new(input[2 * n], input[2 * n + 1]);
+        }
+        self.fft.process(&mut self.buf_in, &mut self.buf_out);
+
+        // Split the spectrum into the spectra of the even and odd samples and combine them.
+        let z = &self.buf_out;
+        for (k, out) in output.iter_mut().enumerate() {
+            let zk = z[k % m];
+            let zc = z[(m - k) % m].conj();
+            let even = (zk + zc) * 0.5;
+            let odd = (zk - zc) * Complex64::new(0.0, -0.5);
+            *out = even + self.twiddles[k] * odd;
+        }
+    }
+}
+
+/// RealIfft reconstructs a real signal from its non-negative frequency bins.
+pub struct RealIfft {
+    size: usize,
+    fft: sync::Arc<FFT<f64>>,
+    /// `e^(2πik/N)` for each non-negative frequency bin.
+    twiddles: Vec<Complex64>,
+    buf_in: Vec<Complex64>,
+    buf_out: Vec<Complex64>,
+}
+
+impl RealIfft {
+    /// Creates a new transformation for signals of the specified size, which must be even.
+    pub fn new(size: usize) -> RealIfft {
+        assert!(size >= 2 && size % 2 == 0);
+        RealIfft {
+            size,
+            fft: FFTplanner::new(true).plan_fft(size / 2),
+            twiddles: twiddles(size, 1.0),
+            buf_in: vec![Complex64::new(0.0, 0.0); size / 2],
+            buf_out: vec![Complex64::new(0.0, 0.0); size / 2],
+        }
+    }
+
+    /// Computes the signal from `size / 2 + 1` bins. The output is normalized, so transforming a
+    /// signal with `RealFft` and back yields the original signal.
+    pub fn process(&mut self, input: &[Complex64], output: &mut [f64]) {
+        assert_eq!(self.size / 2 + 1, input.len());
+        assert_eq!(self.size, output.len());
+        let m = self.size / 2;
+        for (k, z) in self.buf_in.iter_mut().enumerate() {
+            let xk = input[k];
+            let xc = input[m - k].conj();
+            let even = (xk + xc) * 0.5;
+            let odd = (xk - xc) * 0.5 * self.twiddles[k];
+            *z = even + Complex64::new(-odd.im, odd.re);
+        }
+        self.fft.process(&mut self.buf_in, &mut self.buf_out);
+
+        let scale = 1.0 / m as f64;
+        for (n, z) in self.buf_out.iter().enumerate() {
+            output[2 * n] = z.re * scale;
+            output[2 * n + 1] = z.im * scale;
+        }
+    }
+}
+
+fn twiddles(size: usize, sign: f64) -> Vec<Complex64> {
+    (0..=size / 2)
+        .map(|k| {
+            let phi = sign * 2.0 * f64::consts::PI * k as f64 / size as f64;
+            Complex64::new(phi.cos(), phi.sin())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(size: usize) -> Vec<f64> {
+        (0..size)
+            .map(|n| (n as f64 * 0.3).sin() + (n as f64 * 1.7).cos() * 0.25 + 0.1)
+            .collect()
+    }
+
+    #[test]
+    fn forward() {
+        let size = 32;
+        let input = signal(size);
+        let mut output = vec![Complex64::new(0.0, 0.0); size / 2 + 1];
+        RealFft::new(size).process(&input, &mut output);
+
+        for (k, bin) in output.iter().enumerate() {
+            let expected =
+                input
+                    .iter()
+                    .enumerate()
+                    .fold(Complex64::new(0.0, 0.0), |sum, (n, &x)| {
+                        let phi = -2.0 * f64::consts::PI * (k * n) as f64 / size as f64;
+                        sum + Complex64::new(x * phi.cos(), x * phi.sin())
+                    });
+            assert!(
+                (*bin - expected).norm() < 1e-9,
+                "{}: {} != {}",
+                k,
+                bin,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let size = 64;
+        let input = signal(size);
+        let mut spectrum = vec![Complex64::new(0.0, 0.0); size / 2 + 1];
+        let mut output = vec![0.0; size];
+        RealFft::new(size).process(&input, &mut spectrum);
+        RealIfft::new(size).process(&spectrum, &mut output);
+        for (a, b) in input.iter().zip(output.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod benchmarks {
+    extern crate test;
+    use super::*;
+
+    #[bench]
+    fn forward_2048(b: &mut test::Bencher) {
+        let input: Vec<f64> = (0..2048).map(|n| (n as f64 * 0.1).sin()).collect();
+        let mut output = vec![Complex64::new(0.0, 0.0); 1025];
+        let mut fft = RealFft::new(2048);
+        b.iter(|| fft.process(&input, &mut output));
+    }
+
+    #[bench]
+    fn inverse_2048(b: &mut test::Bencher) {
+        let input = vec![Complex64::new(1.0, 0.0); 1025];
+        let mut output = vec![0.0; 2048];
+        let mut fft = RealIfft::new(2048);
+        b.iter(|| fft.process(&input, &mut output));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::audio;
+use crate::filter::PARAM_INTERVAL;
+use sample::{self, Frame, Sample};
+use std::*;
+
+/// The number of frames over which the gain is moved to a new value.
+const RAMP_LENGTH: usize = 512;
+
+/// Gain multiplies a signal by a factor. Changes of the factor are spread out over a short ramp
+/// to prevent clicks.
+pub struct Gain<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+{
+    /// The linear factor by which the signal is multiplied.
+    pub gain: sync::Arc<sync::Mutex<f64>>,
+
+    input: S,
+    current: f64,
+    target: f64,
+    /// The number of frames until the target is reached.
+    remaining: usize,
+    /// The number of frames since the gain was last checked.
+    counter: usize,
+}
+
+impl<S> iter::Iterator for Gain<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.counter == 0 {
+            let gain = *self.gain.lock().unwrap();
+            if gain != self.target {
+                self.target = gain;
+                self.remaining = RAMP_LENGTH;
+            }
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+
+        let frame = self.input.next()?;
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + (self.target - self.current) / (self.remaining + 1) as f64
+            };
+        }
+        if self.current == 1.0 {
+            return Some(frame);
+        }
+        let gain = self.current;
+        Some(S::Item::from_fn(|ch| {
+            let s: f64 = frame.channel(ch).unwrap().to_sample();
+            <S::Item as sample::Frame>::Sample::from_sample(s * gain)
+        }))
+    }
+}
+
+impl<S> audio::Source for Gain<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait AdjustGain: audio::Source + Sized
+where
+    Self::Item: sample::Frame,
+    <Self::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    /// Multiplies the signal by a linear factor which may be changed while the signal is being
+    /// consumed.
+    fn adjust_gain(self, gain: sync::Arc<sync::Mutex<f64>>) -> Gain<Self> {
+        let initial = *gain.lock().unwrap();
+        Gain {
+            gain,
+            input: self,
+            current: initial,
+            target: initial,
+            remaining: 0,
+            counter: 0,
+        }
+    }
+}
+
+impl<T> AdjustGain for T
+where
+    T: audio::Source,
+    T::Item: sample::Frame,
+    <T::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+}
+
+/// Converts a gain in dB to a linear factor.
+pub fn db_to_factor(db: f64) -> f64 {
+    10.0f64.powf(db / 20.0)
+}
+
+/// Converts a linear factor to a gain in dB. Factors are clamped to -200dB, so silence does not
+/// yield negative infinity.
+pub fn factor_to_db(factor: f64) -> f64 {
+    20.0 * factor.max(1e-10).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::IntoSource;
+
+    #[test]
+    fn unity() {
+        let input: Vec<[i16; 2]> = (0..1000).map(|i| [i as i16, -(i as i16)]).collect();
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .adjust_gain(sync::Arc::new(sync::Mutex::new(1.0)))
+            .collect();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn ramp() {
+        let gain = sync::Arc::new(sync::Mutex::new(0.5));
+        let mut signal = iter::repeat([1.0f64])
+            .source(44100)
+            .adjust_gain(gain.clone());
+        let head: Vec<_> = signal.by_ref().take(PARAM_INTERVAL).collect();
+        assert!(head.iter().all(|f| f[0] == 0.5));
+
+        *gain.lock().unwrap() = 2.0;
+        let ramp: Vec<_> = signal.by_ref().take(RAMP_LENGTH).collect();
+        assert!(ramp.windows(2).all(|w| w[0][0] < w[1][0]));
+        assert!((ramp[RAMP_LENGTH - 1][0] - 2.0).abs() < 1e-9);
+        assert_eq!([2.0], signal.next().unwrap());
+    }
+
+    #[test]
+    fn decibels() {
+        assert!((db_to_factor(-6.0) - 0.501).abs() < 1e-3);
+        assert!((factor_to_db(db_to_factor(3.5)) - 3.5).abs() < 1e-9);
+    }
+}
@@ -1,3 +1,20 @@
+/// The number of frames between checks for changed parameters by filters of which the
+/// parameters are shared with other threads. This keeps the audio thread from locking a mutex
+/// for every frame, while changes still take effect within 1.5ms at 44.1kHz.
+pub const PARAM_INTERVAL: usize = 64;
+
+pub mod biquad;
+pub mod dynamics;
+pub use self::dynamics::IntoDynamics;
+pub mod eq;
+pub use self::eq::IntoEqualizer;
+pub mod fft;
+pub mod gain;
+pub use self::gain::AdjustGain;
+pub mod pitch;
+pub use self::pitch::AdjustPitch;
+pub mod resample;
+pub use self::resample::IntoResample;
 pub mod stft;
 pub use self::stft::{IntoStft, Stft};
 pub mod tempo;
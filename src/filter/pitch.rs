@@ -0,0 +1,325 @@
+use crate::audio;
+use crate::filter::resample::{IntoResample, Resample};
+use crate::filter::stft::{self, IntoStft, Stft};
+use crate::filter::tempo::{AdjustTempo, PhaseVocoder};
+use crate::filter::PARAM_INTERVAL;
+use sample;
+use std::*;
+
+/// PitchShift changes the pitch and tempo of a signal independently.
+///
+/// The signal is first stretched by a phase vocoder by the inverse of the pitch factor, which is
+/// then undone by resampling. This moves all frequencies by the pitch factor while the duration
+/// only depends on the tempo.
+///
+/// While neither the tempo or pitch are altered, the input is passed through directly.
+pub struct PitchShift<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    pub tempo: sync::Arc<sync::Mutex<f64>>,
+    pub pitch: sync::Arc<sync::Mutex<f64>>,
+
+    /// The stretch ratio of the phase vocoder, derived from the tempo and pitch.
+    ratio: sync::Arc<sync::Mutex<f64>>,
+    input: Resample<stft::Inverse<PhaseVocoder<stft::FromSource<S>>, S::Item>>,
+
+    /// Whether the STFT is currently bypassed.
+    bypass: bool,
+    /// Frames that have been read by the STFT but were not yet yielded when it was bypassed.
+    replay: collections::VecDeque<S::Item>,
+    /// The number of frames to discard after the STFT has been resumed.
+    skip: usize,
+    /// The number of frames since the tempo and pitch were last checked.
+    counter: usize,
+}
+
+impl<S> PitchShift<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    fn analysis(&mut self) -> &mut stft::FromSource<S> {
+        self.input.get_mut().get_mut().get_mut()
+    }
+
+    fn enable_bypass(&mut self) {
+        // Yield the frames that the resampler and the STFT have read ahead, followed by the
+        // frames that are still in the window. The vocoder holds the block after the one it has
+        // last reconstructed, so none of the window has been yielded yet.
+        self.replay.extend(self.input.lookahead().iter().cloned());
+        let inverse = self.input.get_mut();
+        while inverse.buffered() > 0 {
+            self.replay.extend(inverse.next());
+        }
+        let frames: Vec<_> = self.analysis().frames().iter().cloned().collect();
+        self.replay.extend(frames);
+        self.bypass = true;
+    }
+
+    fn disable_bypass(&mut self) {
+        self.replay.clear();
+        self.input.get_mut().reset();
+        self.input.reset();
+        // The frames in the window of the analysis are only partially reconstructed.
+        self.skip = self.analysis().window_overlap();
+        self.bypass = false;
+    }
+}
+
+impl<S> iter::Iterator for PitchShift<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.counter == 0 {
+            let tempo = *self.tempo.lock().unwrap();
+            let pitch = *self.pitch.lock().unwrap();
+            if is_identity(tempo, pitch) {
+                if !self.bypass {
+                    self.enable_bypass();
+                }
+            } else {
+                if self.bypass {
+                    self.disable_bypass();
+                }
+                *self.ratio.lock().unwrap() = tempo / pitch;
+            }
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+
+        if self.bypass {
+            return match self.replay.pop_front() {
+                Some(frame) => Some(frame),
+                None => self.analysis().next_frame(),
+            };
+        }
+        while self.skip > 0 {
+            self.skip -= 1;
+            self.input.next()?;
+        }
+        self.input.next()
+    }
+}
+
+impl<S> audio::Source for PitchShift<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+}
+
+pub trait AdjustPitch: audio::Source + Sized
+where
+    Self::Item: sample::Frame,
+    <Self::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    /// Changes the pitch of the signal by the specified factor without affecting the tempo, which
+    /// is controlled separately. A pitch of 2.0 raises the signal by an octave.
+    ///
+    /// The window parameters are those of the STFT used for time stretching. An error is returned
+    /// if the signal can not be reconstructed with them.
+    fn adjust_pitch(
+        self,
+        window: stft::Window,
+        window_size: usize,
+        hop_size: usize,
+        tempo: sync::Arc<sync::Mutex<f64>>,
+        pitch: sync::Arc<sync::Mutex<f64>>,
+    ) -> Result<PitchShift<Self>, stft::Error> {
+        let initial_ratio = *tempo.lock().unwrap() / *pitch.lock().unwrap();
+        assert!(initial_ratio > 0.0);
+        let ratio = sync::Arc::new(sync::Mutex::new(initial_ratio));
+        let input = self
+            .stft(window, window_size, hop_size)?
+            .adjust_tempo(ratio.clone())
+            .inverse()?
+            .adjust_speed(pitch.clone());
+        let bypass = is_identity(*tempo.lock().unwrap(), *pitch.lock().unwrap());
+        Ok(PitchShift {
+            bypass,
+            tempo,
+            pitch,
+            ratio,
+            input,
+            replay: collections::VecDeque::new(),
+            skip: 0,
+            counter: 0,
+        })
+    }
+}
+
+impl<T> AdjustPitch for T
+where
+    T: audio::Source,
+    T::Item: sample::Frame,
+    <T::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+}
+
+/// Returns true if neither the tempo nor the pitch is altered, in which case the signal is passed
+/// through without delay.
+pub fn is_identity(tempo: f64, pitch: f64) -> bool {
+    (tempo - 1.0).abs() < 1e-9 && (pitch - 1.0).abs() < 1e-9
+}
+
+/// Converts a pitch shift in semitones to a frequency factor. Cents may be specified as the
+/// fractional part.
+pub fn semitones_to_factor(semitones: f64) -> f64 {
+    2.0f64.powf(semitones / 12.0)
+}
+
+/// Converts a frequency factor to a pitch shift in semitones.
+pub fn factor_to_semitones(factor: f64) -> f64 {
+    12.0 * factor.log2()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::audio::IntoSource;
+
+    fn zero_crossings(signal: &[[f64; 1]]) -> usize {
+        signal
+            .windows(2)
+            .filter(|w| (w[0][0] < 0.0) != (w[1][0] < 0.0))
+            .count()
+    }
+
+    #[test]
+    fn semitones() {
+        assert!((semitones_to_factor(12.0) - 2.0).abs() < 1e-9);
+        assert!((semitones_to_factor(-12.0) - 0.5).abs() < 1e-9);
+        assert!((factor_to_semitones(semitones_to_factor(3.5)) - 3.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn octave_up() {
+        let len = 44100;
+        let output: Vec<[f64; 1]> = Generator::<[f64; 1]>::new(Waveform::Sine(440.0), 44100, len)
+            .adjust_pitch(
+                stft::Window::Hann,
+                1024,
+                256,
+                sync::Arc::new(sync::Mutex::new(1.0)),
+                sync::Arc::new(sync::Mutex::new(2.0)),
+            )
+            .unwrap()
+            .collect();
+        assert!(
+            (output.len() as i64 - len as i64).abs() < 2048,
+            "{}",
+            output.len()
+        );
+
+        // A second worth of 880Hz crosses zero 1760 times.
+        let steady = &output[4096..output.len() - 4096];
+        let freq = zero_crossings(steady) as f64 / 2.0 * 44100.0 / steady.len() as f64;
+        assert!((freq - 880.0).abs() < 10.0, "{}", freq);
+    }
+
+    #[test]
+    fn tempo_only() {
+        let len = 44100;
+        let output: Vec<[f64; 1]> = Generator::<[f64; 1]>::new(Waveform::Sine(440.0), 44100, len)
+            .adjust_pitch(
+                stft::Window::Hann,
+                1024,
+                256,
+                sync::Arc::new(sync::Mutex::new(2.0)),
+                sync::Arc::new(sync::Mutex::new(1.0)),
+            )
+            .unwrap()
+            .collect();
+        assert!(
+            (output.len() as i64 - len as i64 / 2).abs() < 2048,
+            "{}",
+            output.len()
+        );
+
+        let steady = &output[2048..output.len() - 2048];
+        let freq = zero_crossings(steady) as f64 / 2.0 * 44100.0 / steady.len() as f64;
+        assert!((freq - 440.0).abs() < 10.0, "{}", freq);
+    }
+
+    #[test]
+    fn bypass() {
+        let input: Vec<[f64; 1]> = Generator::new(Waveform::Sine(440.0), 44100, 8192).collect();
+        let tempo = sync::Arc::new(sync::Mutex::new(1.0));
+        let pitch = sync::Arc::new(sync::Mutex::new(1.0));
+        let mut shift = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .adjust_pitch(stft::Window::Hann, 1024, 256, tempo.clone(), pitch.clone())
+            .unwrap();
+        // The input is passed through unaltered.
+        let head: Vec<_> = shift.by_ref().take(2048).collect();
+        assert_eq!(&input[..2048], &head[..]);
+
+        // Resuming the STFT and bypassing it again does not lose or repeat any frames.
+        *tempo.lock().unwrap() = 1.0 + 1e-6;
+        let middle: Vec<_> = shift.by_ref().take(2048).collect();
+        *tempo.lock().unwrap() = 1.0;
+        let tail: Vec<_> = shift.collect();
+        let total = head.len() + middle.len() + tail.len();
+        assert!((total as i64 - input.len() as i64).abs() <= 4, "{}", total);
+        for (a, b) in input[2048..].iter().zip(middle.iter().chain(tail.iter())) {
+            assert!((a[0] - b[0]).abs() < 1e-3);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod benchmarks {
+    extern crate test;
+    use super::*;
+    use crate::audio::IntoSource;
+
+    fn bench_pitch_shift(b: &mut test::Bencher, tempo: f64, pitch: f64) {
+        let input: Vec<[f64; 2]> = (0..44100)
+            .map(|i| [(i as f64 * 0.1).sin(), (i as f64 * 0.2).sin()])
+            .collect();
+        b.iter(|| {
+            input
+                .iter()
+                .cloned()
+                .source(44100)
+                .adjust_pitch(
+                    stft::Window::Hann,
+                    2048,
+                    512,
+                    sync::Arc::new(sync::Mutex::new(tempo)),
+                    sync::Arc::new(sync::Mutex::new(pitch)),
+                )
+                .unwrap()
+                .count()
+        });
+    }
+
+    #[bench]
+    fn identity(b: &mut test::Bencher) {
+        bench_pitch_shift(b, 1.0, 1.0);
+    }
+
+    #[bench]
+    fn tempo(b: &mut test::Bencher) {
+        bench_pitch_shift(b, 1.1, 1.0);
+    }
+
+    #[bench]
+    fn pitch(b: &mut test::Bencher) {
+        bench_pitch_shift(b, 1.0, 1.1);
+    }
+}
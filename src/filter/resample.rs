@@ -0,0 +1,194 @@
+use crate::audio;
+use crate::filter::PARAM_INTERVAL;
+use sample::{self, Frame, Sample};
+use std::*;
+
+/// Resample converts the sample rate of a source using cubic Hermite interpolation.
+///
+/// No low pass filtering is applied, so downsampling may introduce some aliasing.
+pub struct Resample<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+{
+    /// The playback speed. A speed of 2.0 reads the input twice as fast, raising the pitch by an
+    /// octave.
+    pub speed: sync::Arc<sync::Mutex<f64>>,
+
+    input: S,
+    sample_rate: u32,
+    /// The fractional position between `history[1]` and `history[2]`.
+    position: f64,
+    /// The four input frames surrounding the current position.
+    history: [S::Item; 4],
+    /// The number of frames that have been shifted into the history after the input ended.
+    padding: usize,
+    /// The speed as it was last read from `speed`.
+    current_speed: f64,
+    /// The number of frames since the speed was last checked.
+    counter: usize,
+}
+
+impl<S> Resample<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+{
+    pub fn get_ref(&self) -> &S {
+        &self.input
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.input
+    }
+
+    /// Returns the frames that have been read from the input but lie beyond the current position.
+    pub fn lookahead(&self) -> &[S::Item] {
+        let end = 4 - self.padding.min(4);
+        let start = 1 + self.position.ceil() as usize;
+        &self.history[start.min(end)..end]
+    }
+
+    /// Forgets the frames that have been read from the input so far. This should be called when
+    /// the continuity of the input is broken.
+    pub fn reset(&mut self) {
+        self.position = 3.0;
+        self.history = [S::Item::equilibrium(); 4];
+        self.padding = 0;
+    }
+}
+
+impl<S> iter::Iterator for Resample<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    type Item = S::Item;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position >= 1.0 {
+            let frame = match self.input.next() {
+                Some(frame) => frame,
+                None => {
+                    self.padding += 1;
+                    S::Item::equilibrium()
+                }
+            };
+            self.history = [self.history[1], self.history[2], self.history[3], frame];
+            self.position -= 1.0;
+        }
+        // The frame at the current position is only padding, so the input has been depleted.
+        if self.padding >= 3 {
+            return None;
+        }
+
+        let t = self.position;
+        let history = &self.history;
+        let frame = S::Item::from_fn(|ch| {
+            let x = |i: usize| -> f64 { history[i].channel(ch).unwrap().to_sample() };
+            let c0 = x(1);
+            let c1 = 0.5 * (x(2) - x(0));
+            let c2 = x(0) - 2.5 * x(1) + 2.0 * x(2) - 0.5 * x(3);
+            let c3 = 0.5 * (x(3) - x(0)) + 1.5 * (x(1) - x(2));
+            <S::Item as sample::Frame>::Sample::from_sample(((c3 * t + c2) * t + c1) * t + c0)
+        });
+
+        if self.counter == 0 {
+            self.current_speed = *self.speed.lock().unwrap();
+        }
+        self.counter = (self.counter + 1) % PARAM_INTERVAL;
+        let ratio = f64::from(self.input.sample_rate()) / f64::from(self.sample_rate);
+        self.position += self.current_speed * ratio;
+        Some(frame)
+    }
+}
+
+impl<S> audio::Source for Resample<S>
+where
+    S: audio::Source,
+    S::Item: sample::Frame,
+    <S::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+pub trait IntoResample: audio::Source + Sized
+where
+    Self::Item: sample::Frame,
+    <Self::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+    /// Converts the signal to the specified sample rate.
+    fn resample(self, sample_rate: u32) -> Resample<Self> {
+        assert_ne!(0, sample_rate);
+        Resample {
+            speed: sync::Arc::new(sync::Mutex::new(1.0)),
+            input: self,
+            sample_rate,
+            // Three frames should be read before the first input frame is at history[1].
+            position: 3.0,
+            history: [Self::Item::equilibrium(); 4],
+            padding: 0,
+            current_speed: 1.0,
+            counter: 0,
+        }
+    }
+
+    /// Changes the speed of the signal while retaining the sample rate. Both the tempo and pitch
+    /// are affected.
+    fn adjust_speed(self, speed: sync::Arc<sync::Mutex<f64>>) -> Resample<Self> {
+        assert!(*speed.lock().unwrap() > 0.0);
+        let sample_rate = self.sample_rate();
+        let mut resample = self.resample(sample_rate);
+        resample.speed = speed;
+        resample
+    }
+}
+
+impl<T> IntoResample for T
+where
+    T: audio::Source,
+    T::Item: sample::Frame,
+    <T::Item as sample::Frame>::Sample: sample::ToSample<f64> + sample::FromSample<f64>,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{IntoSource, Source};
+
+    #[test]
+    fn identity() {
+        let input: Vec<[f64; 1]> = (0..64).map(|i| [(i as f64 / 8.0).sin()]).collect();
+        let output: Vec<_> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .resample(44100)
+            .collect();
+        assert_eq!(input.len(), output.len());
+        for (a, b) in input.iter().zip(output.iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn length() {
+        let input = iter::repeat([0.5f64; 2]).take(44100).source(44100);
+        let output = input.resample(48000);
+        assert_eq!(48000, output.sample_rate());
+        let n = output.count() as i64;
+        assert!((n - 48000).abs() <= 2, "{}", n);
+    }
+
+    #[test]
+    fn speed() {
+        let input = iter::repeat([0.5f64; 1]).take(44100).source(44100);
+        let output = input.adjust_speed(sync::Arc::new(sync::Mutex::new(2.0)));
+        assert_eq!(44100, output.sample_rate());
+        let n = output.count() as i64;
+        assert!((n - 22050).abs() <= 2, "{}", n);
+    }
+}
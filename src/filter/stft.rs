@@ -4,39 +4,227 @@
 //!
 
 use crate::audio;
-use dft;
+use crate::filter::fft;
 use sample::{self, Frame, Sample};
 use std::*;
 
-pub trait Stft: iter::Iterator<Item = Vec<Vec<f64>>> + Sized {
+pub use rustfft::num_complex::Complex64;
+
+/// The window function that is applied to each block before it is transformed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Window {
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    /// The Kaiser window trades main lobe width for side lobe attenuation through `beta`.
+    Kaiser {
+        beta: f64,
+    },
+}
+
+impl Window {
+    /// Computes the periodic form of the window function for the specified size.
+    pub fn coefficients(&self, size: usize) -> Vec<f64> {
+        let tau = 2.0 * f64::consts::PI;
+        (0..size)
+            .map(|n| {
+                let x = n as f64 / size as f64;
+                match *self {
+                    Window::Hann => 0.5 - 0.5 * (tau * x).cos(),
+                    Window::Hamming => 0.54 - 0.46 * (tau * x).cos(),
+                    Window::BlackmanHarris => {
+                        0.35875 - 0.48829 * (tau * x).cos() + 0.14128 * (2.0 * tau * x).cos()
+                            - 0.01168 * (3.0 * tau * x).cos()
+                    }
+                    Window::Kaiser { beta } => {
+                        let r = 2.0 * x - 1.0;
+                        bessel_i0(beta * (1.0 - r * r).sqrt()) / bessel_i0(beta)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Computes the normalization factors for the weighted overlap-add of windows which are
+    /// applied for both analysis and synthesis. The factor for the frame at offset `n` of a hop
+    /// is at index `n`.
+    ///
+    /// Returns None if the signal can not be reconstructed because some frames are not covered by
+    /// any window.
+    fn overlap_add_norm(&self, window_size: usize, hop_size: usize) -> Option<Vec<f64>> {
+        let coefficients = self.coefficients(window_size);
+        let norm: Vec<f64> = (0..hop_size)
+            .map(|n| {
+                coefficients[n..]
+                    .iter()
+                    .step_by(hop_size)
+                    .map(|w| w * w)
+                    .sum()
+            })
+            .collect();
+        if norm.iter().any(|&f| f < 1e-6) {
+            return None;
+        }
+        Some(norm)
+    }
+}
+
+impl str::FromStr for Window {
+    type Err = ParseError;
+    /// Parses the name of a window, e.g. `hann` or `kaiser:8.6`.
+    fn from_str(s: &str) -> Result<Window, ParseError> {
+        match s {
+            "hann" => Ok(Window::Hann),
+            "hamming" => Ok(Window::Hamming),
+            "blackman-harris" => Ok(Window::BlackmanHarris),
+            _ if s.starts_with("kaiser:") => s["kaiser:".len()..]
+                .parse()
+                .map(|beta| Window::Kaiser { beta })
+                .map_err(|_| ParseError::Unmatched),
+            _ => Err(ParseError::Unmatched),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    /// The input text was not matched.
+    Unmatched,
+}
+
+/// The error returned when an STFT is set up with parameters it can not work with.
+#[derive(Debug)]
+pub enum Error {
+    /// The window size is not an even number of at least 2.
+    WindowSize(usize),
+    /// The hop size is zero or exceeds the window size.
+    HopSize { hop_size: usize, window_size: usize },
+    /// Some frames are not covered by any of the overlapping windows, so the signal can not be
+    /// reconstructed.
+    Reconstruction {
+        window: Window,
+        window_size: usize,
+        hop_size: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::WindowSize(size) => write!(
+                f,
+                "STFT window size must be an even number of at least 2: {}",
+                size
+            ),
+            Error::HopSize {
+                hop_size,
+                window_size,
+            } => write!(
+                f,
+                "STFT hop size must be between 1 and the window size ({}): {}",
+                window_size, hop_size
+            ),
+            Error::Reconstruction {
+                window,
+                window_size,
+                hop_size,
+            } => write!(
+                f,
+                "a {:?} window of {} frames with a hop size of {} can not be reconstructed",
+                window, window_size, hop_size
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Invalid STFT parameters"
+    }
+}
+
+/// Computes the zeroth order modified Bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+/// A block holds the complex bins of a single window for every channel.
+pub type Block = Vec<Vec<Complex64>>;
+
+/// An STFT yields the spectrum of consecutive, overlapping windows of a signal.
+///
+/// Because the input signal is real, only the non-negative frequencies are stored, so each block
+/// has `window_size / 2 + 1` bins per channel. Blocks are written into buffers provided by the
+/// caller so they can be reused.
+pub trait Stft: Sized {
     type NumChannels: sample::frame::NumChannels;
     fn sample_rate(&self) -> u32;
+    fn num_channels(&self) -> usize;
+    fn window(&self) -> Window;
     fn window_size(&self) -> usize;
-    fn window_overlap(&self) -> usize;
+    /// Returns the number of frames between the starts of two consecutive windows.
+    fn hop_size(&self) -> usize;
+
+    /// Computes the next block into `block`, which should have been allocated by `new_block`.
+    /// Returns false if the signal has ended.
+    fn next_block(&mut self, block: &mut Block) -> bool;
+
+    /// Discards all state which is derived from previously computed blocks. This should be
+    /// called when the continuity of the blocks is broken, e.g. because the input has been read
+    /// directly.
+    fn reset(&mut self);
+
+    fn window_overlap(&self) -> usize {
+        self.window_size() - self.hop_size()
+    }
+
+    /// Returns the number of bins in each block.
+    fn num_bins(&self) -> usize {
+        self.window_size() / 2 + 1
+    }
+
+    /// Allocates a block that can be passed to `next_block`.
+    fn new_block(&self) -> Block {
+        vec![vec![Complex64::new(0.0, 0.0); self.num_bins()]; self.num_channels()]
+    }
+
     /// Reconstructs a discrete signal from the STFT using an inverse Fourier transformation.
-    fn inverse<O>(self) -> Inverse<Self, O>
+    ///
+    /// The blocks are windowed again before they are overlapped and added, which suppresses
+    /// discontinuities introduced by modifications of the spectrum.
+    ///
+    /// An error is returned if the windows do not overlap enough to reconstruct every frame.
+    fn inverse<O>(self) -> Result<Inverse<Self, O>, Error>
     where
         O: sample::Frame<NumChannels = Self::NumChannels>,
-        O::Sample:
-            sample::FromSample<f64> + sample::FromSample<<O::Float as sample::Frame>::Sample>,
-        <<O::Float as sample::Frame>::Sample as sample::Sample>::Float: sample::ToSample<f64>,
+        O::Sample: sample::FromSample<f64>,
     {
-        let window_size = self.window_size();
-        let windows = vec![
-            collections::VecDeque::new(),
-            vec![O::Float::equilibrium(); window_size]
-                .into_iter()
-                .collect(),
-        ];
-        let window_scalars = (0..window_size)
-            .map(|n| f64::sin(f64::consts::PI / window_size as f64 * (n as f64 + 0.5)).powi(2))
-            .collect();
-        Inverse {
+        let (window, window_size, hop_size) = (self.window(), self.window_size(), self.hop_size());
+        let norm = window
+            .overlap_add_norm(window_size, hop_size)
+            .ok_or(Error::Reconstruction {
+                window,
+                window_size,
+                hop_size,
+            })?;
+        Ok(Inverse {
+            ifft: fft::RealIfft::new(window_size),
+            window: self.window().coefficients(window_size),
+            norm,
+            block: self.new_block(),
+            signal: vec![0.0; window_size],
+            accum: vec![vec![0.0; window_size]; O::n_channels()],
+            output: collections::VecDeque::with_capacity(self.hop_size()),
             stft: self,
-            fft_plan: dft::Plan::new(dft::Operation::Inverse, window_size),
-            window_scalars,
-            windows: windows.into_iter().collect(),
-        }
+        })
     }
 }
 
@@ -44,66 +232,91 @@ pub struct Inverse<T, O>
 where
     T: Stft + Sized,
     O: sample::Frame,
-    O::Sample: sample::FromSample<f64> + sample::FromSample<<O::Float as sample::Frame>::Sample>,
-    <<O::Float as sample::Frame>::Sample as sample::Sample>::Float: sample::ToSample<f64>,
+    O::Sample: sample::FromSample<f64>,
 {
     stft: T,
-    fft_plan: dft::Plan<f64>,
-    window_scalars: Vec<f64>,
-    windows: collections::VecDeque<collections::VecDeque<O::Float>>,
+    ifft: fft::RealIfft,
+    window: Vec<f64>,
+    /// The sum of the squared windows overlapping each frame of a hop.
+    norm: Vec<f64>,
+    block: Block,
+    /// Scratch space for the signal of a single channel.
+    signal: Vec<f64>,
+    /// The overlap-add accumulator of each channel.
+    accum: Vec<Vec<f64>>,
+    /// Frames that have been fully reconstructed.
+    output: collections::VecDeque<O>,
+}
+
+impl<T, O> Inverse<T, O>
+where
+    T: Stft + Sized,
+    O: sample::Frame,
+    O::Sample: sample::FromSample<f64>,
+{
+    pub fn get_ref(&self) -> &T {
+        &self.stft
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stft
+    }
+
+    /// Returns the number of frames that have been reconstructed but not yet yielded.
+    pub fn buffered(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Discards all frames that are being reconstructed and resets the STFT.
+    pub fn reset(&mut self) {
+        self.stft.reset();
+        self.output.clear();
+        for accum in self.accum.iter_mut() {
+            for s in accum.iter_mut() {
+                *s = 0.0;
+            }
+        }
+    }
 }
 
 impl<T, O> iter::Iterator for Inverse<T, O>
 where
     T: Stft<NumChannels = O::NumChannels> + Sized,
     O: sample::Frame,
-    O::Sample: sample::FromSample<f64> + sample::FromSample<<O::Float as sample::Frame>::Sample>,
-    <<O::Float as sample::Frame>::Sample as sample::Sample>::Float: sample::ToSample<f64>,
+    O::Sample: sample::FromSample<f64>,
 {
     type Item = O;
     fn next(&mut self) -> Option<Self::Item> {
-        assert_eq!(2, self.windows.len());
-        if self.windows.front().unwrap().is_empty() {
-            let mut blocks = self.stft.next()?;
-            for block in &mut blocks {
-                dft::transform(block, &self.fft_plan);
+        if self.output.is_empty() {
+            if !self.stft.next_block(&mut self.block) {
+                return None;
             }
-            assert_eq!(O::n_channels(), blocks.len());
-            assert!(blocks
-                .iter()
-                .all(|block| block.len() == self.stft.window_size()));
-
-            let next_window = (0..self.stft.window_size())
-                .map(|n| {
-                    O::Float::from_fn(|ch| {
-                        <O::Sample as sample::Sample>::Float::from_sample(blocks[ch][n])
-                    })
-                })
-                .collect();
-            self.windows.push_back(next_window);
-            self.windows.pop_front();
-            // Drop the overlapping part of the previous frame.
-            self.windows
-                .front_mut()
-                .unwrap()
-                .drain(0..self.stft.window_size() - self.stft.window_overlap());
-        }
+            let window_size = self.stft.window_size();
+            let hop_size = self.stft.hop_size();
 
-        let (front, back) = {
-            let mut w = self.windows.iter_mut();
-            (w.next().unwrap(), w.next().unwrap())
-        };
-        let n = self.stft.window_overlap() - front.len();
-        let sb = self.window_scalars[n];
-        let sa = self.window_scalars[n + self.stft.window_overlap()];
-        // The sum of sa and sb should be equal to 1.0.
-        assert!(1.0 - 10e-9 <= sa + sb && sa + sb <= 1.0 + 10e-9);
+            for (bins, accum) in self.block.iter().zip(self.accum.iter_mut()) {
+                self.ifft.process(bins, &mut self.signal);
+                for (a, (s, w)) in accum
+                    .iter_mut()
+                    .zip(self.signal.iter().zip(self.window.iter()))
+                {
+                    *a += s * w;
+                }
+            }
 
-        let fa = front.pop_front().unwrap();
-        let fb = back[n];
-        Some(fa.zip_map(fb, |a, b| {
-            O::Sample::from_sample(a * sa.to_sample() + b * sb.to_sample())
-        }))
+            // The first hop of the accumulator will not receive any more contributions.
+            for n in 0..hop_size {
+                let accum = &self.accum;
+                let norm = self.norm[n];
+                self.output
+                    .push_back(O::from_fn(|ch| O::Sample::from_sample(accum[ch][n] / norm)));
+            }
+            for accum in self.accum.iter_mut() {
+                accum.drain(0..hop_size);
+                accum.resize(window_size, 0.0);
+            }
+        }
+        self.output.pop_front()
     }
 }
 
@@ -111,8 +324,7 @@ impl<T, O> audio::Source for Inverse<T, O>
 where
     T: Stft<NumChannels = O::NumChannels> + Sized,
     O: sample::Frame,
-    O::Sample: sample::FromSample<f64> + sample::FromSample<<O::Float as sample::Frame>::Sample>,
-    <<O::Float as sample::Frame>::Sample as sample::Sample>::Float: sample::ToSample<f64>,
+    O::Sample: sample::FromSample<f64>,
 {
     fn sample_rate(&self) -> u32 {
         self.stft.sample_rate()
@@ -126,67 +338,99 @@ where
     <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
 {
     input: S,
+    window: Window,
     window_size: usize,
-    overlap: usize,
-    fft_plan: dft::Plan<f64>,
-    /// Stores the previous window.
-    window: collections::VecDeque<S::Item>,
+    hop_size: usize,
+    fft: fft::RealFft,
+    window_function: Vec<f64>,
+    /// Stores the frames of the previous window.
+    frames: collections::VecDeque<S::Item>,
+    /// Scratch space for the windowed signal of a single channel.
+    signal: Vec<f64>,
 }
 
-impl<S> Stft for FromSource<S>
+impl<S> FromSource<S>
 where
     S: audio::Source,
     S::Item: sample::Frame,
     <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
 {
-    type NumChannels = <S::Item as sample::Frame>::NumChannels;
-    fn sample_rate(&self) -> u32 {
-        self.input.sample_rate()
+    pub fn get_ref(&self) -> &S {
+        &self.input
     }
 
-    fn window_size(&self) -> usize {
-        self.window_size
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.input
     }
 
-    fn window_overlap(&self) -> usize {
-        self.overlap
+    /// Returns the frames of the most recent window.
+    pub fn frames(&self) -> &collections::VecDeque<S::Item> {
+        &self.frames
+    }
+
+    /// Reads the next frame from the input without computing any blocks. The frame is retained
+    /// in the window, so the transformation can be resumed after a `reset` without a gap.
+    pub fn next_frame(&mut self) -> Option<S::Item> {
+        let frame = self.input.next()?;
+        self.frames.pop_front();
+        self.frames.push_back(frame);
+        Some(frame)
     }
 }
 
-impl<S> iter::Iterator for FromSource<S>
+impl<S> Stft for FromSource<S>
 where
     S: audio::Source,
     S::Item: sample::Frame,
     <S::Item as sample::Frame>::Sample: sample::ToSample<f64>,
 {
-    type Item = Vec<Vec<f64>>;
-    fn next(&mut self) -> Option<Self::Item> {
-        assert_eq!(self.window_size, self.window.len());
-        for i in 0..(self.window_size - self.overlap) {
+    type NumChannels = <S::Item as sample::Frame>::NumChannels;
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn num_channels(&self) -> usize {
+        S::Item::n_channels()
+    }
+
+    fn window(&self) -> Window {
+        self.window
+    }
+
+    fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    fn hop_size(&self) -> usize {
+        self.hop_size
+    }
+
+    fn next_block(&mut self, block: &mut Block) -> bool {
+        assert_eq!(self.window_size, self.frames.len());
+        assert_eq!(S::Item::n_channels(), block.len());
+        for i in 0..self.hop_size {
             let frame = match self.input.next() {
                 Some(frame) => frame,
-                None if i == 0 => return None,
+                None if i == 0 => return false,
                 None => S::Item::equilibrium(),
             };
-            self.window.pop_front();
-            self.window.push_back(frame);
+            self.frames.pop_front();
+            self.frames.push_back(frame);
         }
-        assert_eq!(self.window_size, self.window.len());
 
-        let blocks: Vec<_> = (0..S::Item::n_channels())
-            .map(|ch| {
-                let mut block: Vec<_> = self
-                    .window
-                    .iter()
-                    .map(|frame| frame.channel(ch).unwrap().to_sample())
-                    .collect();
-                dft::transform(&mut block, &self.fft_plan);
-                block
-            })
-            .collect();
-        assert_eq!(S::Item::n_channels(), blocks.len());
-        Some(blocks)
+        for (ch, bins) in block.iter_mut().enumerate() {
+            let windowed = self.frames.iter().zip(self.window_function.iter());
+            for (s, (frame, w)) in self.signal.iter_mut().zip(windowed) {
+                let x: f64 = frame.channel(ch).unwrap().to_sample();
+                *s = x * w;
+            }
+            self.fft.process(&self.signal, bins);
+        }
+        true
     }
+
+    // The frames of the window are the input itself, so they are retained.
+    fn reset(&mut self) {}
 }
 
 pub trait IntoStft: audio::Source + Sized
@@ -195,20 +439,38 @@ where
     <Self::Item as sample::Frame>::Sample: sample::ToSample<f64>,
 {
     /// Computes the Short Time Fourier Transform of the signal over periods specified by the
-    /// number of samples. The window size should be a power of two.
+    /// number of samples. The window size should be a power of two. Consecutive windows start
+    /// `hop_size` frames apart.
     ///
-    /// The windows will overlap by 50%. The first and last windows contain a zero padding.
-    fn stft(self, window_size: usize) -> FromSource<Self> {
-        let window = iter::repeat(Self::Item::equilibrium())
+    /// The first and last windows contain a zero padding.
+    fn stft(
+        self,
+        window: Window,
+        window_size: usize,
+        hop_size: usize,
+    ) -> Result<FromSource<Self>, Error> {
+        if window_size < 2 || window_size % 2 != 0 {
+            return Err(Error::WindowSize(window_size));
+        }
+        if hop_size == 0 || hop_size > window_size {
+            return Err(Error::HopSize {
+                hop_size,
+                window_size,
+            });
+        }
+        let frames = iter::repeat(Self::Item::equilibrium())
             .take(window_size)
             .collect();
-        FromSource {
+        Ok(FromSource {
             input: self,
-            window_size,
-            overlap: window_size / 2, // TODO
-            fft_plan: dft::Plan::new(dft::Operation::Forward, window_size),
             window,
-        }
+            window_size,
+            hop_size,
+            fft: fft::RealFft::new(window_size),
+            window_function: window.coefficients(window_size),
+            frames,
+            signal: vec![0.0; window_size],
+        })
     }
 }
 
@@ -219,3 +481,134 @@ where
     <T::Item as sample::Frame>::Sample: sample::ToSample<f64>,
 {
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::IntoSource;
+
+    fn signal() -> Vec<[f64; 2]> {
+        (0..4096)
+            .map(|i| {
+                let t = i as f64 / 44100.0;
+                [
+                    (t * 440.0 * 2.0 * f64::consts::PI).sin(),
+                    (t * 1000.0 * 2.0 * f64::consts::PI).cos() * 0.5,
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reconstruct() {
+        let windows = [
+            Window::Hann,
+            Window::Hamming,
+            Window::BlackmanHarris,
+            Window::Kaiser { beta: 8.0 },
+        ];
+        for &window in windows.iter() {
+            for &(window_size, hop_size) in &[(256, 128), (256, 64), (512, 128), (512, 96)] {
+                let input = signal();
+                let output: Vec<[f64; 2]> = input
+                    .clone()
+                    .into_iter()
+                    .source(44100)
+                    .stft(window, window_size, hop_size)
+                    .unwrap()
+                    .inverse()
+                    .unwrap()
+                    .collect();
+                // The output is delayed by the overlap of the windows.
+                let delay = window_size - hop_size;
+                for (a, b) in input.iter().zip(output[delay..].iter()) {
+                    assert!((a[0] - b[0]).abs() < 1e-9, "{:?} {}", window, hop_size);
+                    assert!((a[1] - b[1]).abs() < 1e-9, "{:?} {}", window, hop_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_window() {
+        assert_eq!(Window::Hann, "hann".parse().unwrap());
+        assert_eq!(Window::BlackmanHarris, "blackman-harris".parse().unwrap());
+        assert_eq!(Window::Kaiser { beta: 8.6 }, "kaiser:8.6".parse().unwrap());
+        assert!("kaiser:".parse::<Window>().is_err());
+        assert!("rectangular".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn invalid_overlap() {
+        assert!(Window::Hann.overlap_add_norm(256, 256).is_none());
+        assert!(Window::Hann.overlap_add_norm(256, 64).is_some());
+
+        let stft = |window_size, hop_size| {
+            signal()
+                .into_iter()
+                .source(44100)
+                .stft(Window::Hann, window_size, hop_size)
+        };
+        assert!(match stft(255, 64) {
+            Err(Error::WindowSize(255)) => true,
+            _ => false,
+        });
+        assert!(match stft(256, 0) {
+            Err(Error::HopSize { .. }) => true,
+            _ => false,
+        });
+        assert!(match stft(256, 256).unwrap().inverse::<[f64; 2]>() {
+            Err(Error::Reconstruction { .. }) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn resume_after_direct_reads() {
+        let input = signal();
+        let (window_size, hop_size) = (256, 64);
+        let mut inverse = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .stft(Window::Hann, window_size, hop_size)
+            .unwrap()
+            .inverse::<[f64; 2]>()
+            .unwrap();
+        for _ in 0..1000 {
+            inverse.get_mut().next_frame().unwrap();
+        }
+        inverse.reset();
+        // The frames in the window at the moment of resuming are only partially reconstructed.
+        let output: Vec<_> = inverse.skip(window_size - hop_size).collect();
+        for (a, b) in input[1000..].iter().zip(output.iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-9);
+            assert!((a[1] - b[1]).abs() < 1e-9);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "unstable"))]
+mod benchmarks {
+    extern crate test;
+    use super::*;
+    use crate::audio::IntoSource;
+
+    #[bench]
+    fn analysis_synthesis(b: &mut test::Bencher) {
+        let input: Vec<[f64; 2]> = (0..44100)
+            .map(|i| [(i as f64 * 0.1).sin(), (i as f64 * 0.2).sin()])
+            .collect();
+        b.iter(|| {
+            input
+                .iter()
+                .cloned()
+                .source(44100)
+                .stft(Window::Hann, 2048, 512)
+                .unwrap()
+                .inverse::<[f64; 2]>()
+                .unwrap()
+                .count()
+        });
+    }
+}
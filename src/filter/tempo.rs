@@ -1,4 +1,15 @@
-use crate::filter::stft;
+//!
+//! This module implements time stretching using a phase vocoder. The output blocks are
+//! interpolated from the input blocks at a fractional position which advances by the tempo ratio.
+//! To keep the phases coherent, the phase of each bin is propagated by the instantaneous frequency
+//! measured between the surrounding input blocks.
+//!
+//! Optionally, identity phase locking may be enabled as described by Laroche and Dolson in
+//! "Improved Phase Vocoder Time-Scale Modification of Audio". This reduces phasiness by keeping
+//! the bins around a spectral peak in phase with that peak.
+//!
+
+use crate::filter::stft::{self, Complex64};
 use std::*;
 
 pub struct PhaseVocoder<S>
@@ -6,44 +17,62 @@ where
     S: stft::Stft,
 {
     pub ratio: sync::Arc<sync::Mutex<f64>>,
+    /// Whether the phases of the bins around a spectral peak should be locked to that peak.
+    pub phase_lock: bool,
 
     input: S,
-    // Stores how much of the accum[0] has been consumed in previous iterations.
-    consumption: f64,
-    accum: collections::VecDeque<Vec<Vec<f64>>>,
+    /// The fractional position between `blocks[0]` and `blocks[1]`.
+    position: f64,
+    /// The two input blocks surrounding the current position.
+    blocks: [stft::Block; 2],
+    /// The number of blocks in `blocks` that hold input.
+    num_blocks: usize,
+    /// The phase of each bin of each channel for the next output block.
+    phase: Vec<Vec<f64>>,
+    /// Set once the first input block has been read and the phases have been initialized.
+    started: bool,
+    /// Set after the input has been depleted and a silent block has been appended.
+    ended: bool,
+
+    magnitude: Vec<f64>,
+    out_phase: Vec<f64>,
+    peaks: Vec<usize>,
 }
 
-impl<S> iter::Iterator for PhaseVocoder<S>
+impl<S> PhaseVocoder<S>
 where
     S: stft::Stft,
 {
-    type Item = S::Item;
-    fn next(&mut self) -> Option<Self::Item> {
-        assert!(0.0 <= self.consumption && self.consumption < 1.0);
-
-        let ratio = self.ratio.lock().unwrap();
-        assert!(*ratio > 0.0);
-
-        // The ratio is also the number of blocks that we should use.
-        let next_consumption = self.consumption + *ratio;
+    pub fn get_ref(&self) -> &S {
+        &self.input
+    }
 
-        self.accum
-            .extend(self.input.by_ref().take(next_consumption.ceil() as usize));
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.input
+    }
 
-        let block_index = (next_consumption / 2.0).floor() as usize;
-        if block_index >= self.accum.len() {
-            assert_eq!(None, self.input.next());
-            return None;
+    /// Reads the next input block into the first free slot. After the input has ended, a single
+    /// silent block is appended so there is something to interpolate the last input block with.
+    fn push_block(&mut self) -> bool {
+        if self.ended {
+            return false;
         }
-        let output_block = self.accum[block_index].clone();
-
-        // Remove the blocks that have been fully consumed.
-        let num_blocks_available = self.accum.len();
-        self.accum
-            .drain(0..cmp::min(next_consumption.floor() as usize, num_blocks_available));
-        self.consumption = next_consumption % 1.0;
+        let block = &mut self.blocks[self.num_blocks];
+        if !self.input.next_block(block) {
+            self.ended = true;
+            for bins in block.iter_mut() {
+                for bin in bins.iter_mut() {
+                    *bin = Complex64::new(0.0, 0.0);
+                }
+            }
+        }
+        self.num_blocks += 1;
+        true
+    }
 
-        Some(output_block)
+    fn pop_block(&mut self) {
+        self.blocks.swap(0, 1);
+        self.num_blocks -= 1;
     }
 }
 
@@ -56,25 +85,228 @@ where
         self.input.sample_rate()
     }
 
+    fn num_channels(&self) -> usize {
+        self.input.num_channels()
+    }
+
+    fn window(&self) -> stft::Window {
+        self.input.window()
+    }
+
     fn window_size(&self) -> usize {
         self.input.window_size()
     }
 
-    fn window_overlap(&self) -> usize {
-        self.input.window_overlap()
+    fn hop_size(&self) -> usize {
+        self.input.hop_size()
+    }
+
+    fn next_block(&mut self, block: &mut stft::Block) -> bool {
+        let ratio = *self.ratio.lock().unwrap();
+        assert!(ratio > 0.0);
+
+        if !self.started {
+            if !self.input.next_block(&mut self.blocks[0]) {
+                return false;
+            }
+            for (phase, bins) in self.phase.iter_mut().zip(self.blocks[0].iter()) {
+                for (p, bin) in phase.iter_mut().zip(bins.iter()) {
+                    *p = bin.arg();
+                }
+            }
+            self.num_blocks = 1;
+            self.started = true;
+        }
+        while self.position >= 1.0 {
+            if self.num_blocks < 2 && !self.push_block() {
+                return false;
+            }
+            self.pop_block();
+            self.position -= 1.0;
+        }
+        while self.num_blocks < 2 {
+            if !self.push_block() {
+                return false;
+            }
+        }
+
+        let hop_size = self.input.hop_size() as f64;
+        let window_size = self.input.window_size() as f64;
+        let t = self.position;
+
+        for (ch, out) in block.iter_mut().enumerate() {
+            let a = &self.blocks[0][ch];
+            let b = &self.blocks[1][ch];
+            let phase = &mut self.phase[ch];
+
+            for (m, (a, b)) in self.magnitude.iter_mut().zip(a.iter().zip(b.iter())) {
+                *m = (1.0 - t) * a.norm() + t * b.norm();
+            }
+
+            if self.phase_lock {
+                locked_phase(
+                    &self.magnitude,
+                    phase,
+                    a,
+                    &mut self.peaks,
+                    &mut self.out_phase,
+                );
+            } else {
+                self.out_phase.copy_from_slice(phase);
+            }
+
+            // Advance the phase of each bin by its instantaneous frequency, which is the
+            // expected advance of the bin's center frequency plus the deviation measured
+            // between the input blocks.
+            for (k, p) in phase.iter_mut().enumerate() {
+                let omega = 2.0 * f64::consts::PI * k as f64 * hop_size / window_size;
+                let deviation = princarg(b[k].arg() - a[k].arg() - omega);
+                *p = princarg(*p + omega + deviation);
+            }
+
+            let polar = self.magnitude.iter().zip(self.out_phase.iter());
+            for (bin, (&m, &p)) in out.iter_mut().zip(polar) {
+                *bin = Complex64::new(m * p.cos(), m * p.sin());
+            }
+        }
+
+        self.position += ratio;
+        true
+    }
+
+    fn reset(&mut self) {
+        self.input.reset();
+        self.position = 0.0;
+        self.num_blocks = 0;
+        self.started = false;
+        self.ended = false;
     }
 }
 
+/// Computes the output phases using identity phase locking. Only the bins at spectral peaks use
+/// the propagated phase, all other bins keep their phase relative to the nearest peak.
+fn locked_phase(
+    magnitude: &[f64],
+    phase: &[f64],
+    analysis: &[Complex64],
+    peaks: &mut Vec<usize>,
+    out: &mut [f64],
+) {
+    peaks.clear();
+    peaks.extend((0..magnitude.len()).filter(|&k| {
+        let lo = k.saturating_sub(2);
+        let hi = cmp::min(k + 2, magnitude.len() - 1);
+        magnitude[k] > 0.0 && (lo..=hi).all(|j| j == k || magnitude[k] > magnitude[j])
+    }));
+    if peaks.is_empty() {
+        out.copy_from_slice(phase);
+        return;
+    }
+
+    let mut peak_iter = peaks.iter().peekable();
+    let mut peak = *peak_iter.next().unwrap();
+    for (k, out) in out.iter_mut().enumerate() {
+        // Regions of influence end halfway between two peaks.
+        while let Some(&&next) = peak_iter.peek() {
+            if k * 2 < peak + next {
+                break;
+            }
+            peak = next;
+            peak_iter.next();
+        }
+        *out = phase[peak] + analysis[k].arg() - analysis[peak].arg();
+    }
+}
+
+/// Wraps a phase to the range [-π, π].
+fn princarg(phase: f64) -> f64 {
+    let tau = 2.0 * f64::consts::PI;
+    phase - tau * (phase / tau).round()
+}
+
 pub trait AdjustTempo: stft::Stft + Sized {
+    /// Changes the tempo of the signal without affecting the pitch. A ratio of 2.0 plays twice
+    /// as fast.
     fn adjust_tempo(self, ratio: sync::Arc<sync::Mutex<f64>>) -> PhaseVocoder<Self> {
         assert!(*ratio.lock().unwrap() > 0.0);
+        let num_bins = self.num_bins();
         PhaseVocoder {
-            input: self,
             ratio,
-            consumption: 0.0,
-            accum: collections::VecDeque::new(),
+            phase_lock: true,
+            position: 0.0,
+            blocks: [self.new_block(), self.new_block()],
+            num_blocks: 0,
+            phase: vec![vec![0.0; num_bins]; self.num_channels()],
+            started: false,
+            ended: false,
+            magnitude: vec![0.0; num_bins],
+            out_phase: vec![0.0; num_bins],
+            peaks: Vec::with_capacity(num_bins),
+            input: self,
         }
     }
 }
 
 impl<T> AdjustTempo for T where T: stft::Stft {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::gen::{Generator, Waveform};
+    use crate::audio::IntoSource;
+    use crate::filter::stft::Stft;
+    use crate::filter::IntoStft;
+
+    #[test]
+    fn identity() {
+        let input: Vec<[f64; 1]> = Generator::new(Waveform::Sine(440.0), 44100, 8192).collect();
+        let output: Vec<[f64; 1]> = input
+            .clone()
+            .into_iter()
+            .source(44100)
+            .stft(stft::Window::Hann, 512, 128)
+            .unwrap()
+            .adjust_tempo(sync::Arc::new(sync::Mutex::new(1.0)))
+            .inverse()
+            .unwrap()
+            .collect();
+        for (a, b) in input.iter().zip(output[384..].iter()) {
+            assert!((a[0] - b[0]).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn length() {
+        for &ratio in &[0.5, 0.8, 1.25, 2.0] {
+            let len = 44100;
+            let n = Generator::<[f64; 1]>::new(Waveform::Sine(440.0), 44100, len)
+                .stft(stft::Window::Hann, 512, 128)
+                .unwrap()
+                .adjust_tempo(sync::Arc::new(sync::Mutex::new(ratio)))
+                .inverse::<[f64; 1]>()
+                .unwrap()
+                .count() as f64;
+            let expected = len as f64 / ratio;
+            assert!((n - expected).abs() < 1024.0, "{}: {}", ratio, n);
+        }
+    }
+
+    #[test]
+    fn phase_coherence() {
+        // A stretched sine should remain a sine of the same amplitude rather than being
+        // modulated by phase cancellation between overlapping blocks.
+        let output: Vec<[f64; 1]> =
+            Generator::<[f64; 1]>::new(Waveform::Sine(1000.0), 44100, 44100)
+                .stft(stft::Window::Hann, 1024, 256)
+                .unwrap()
+                .adjust_tempo(sync::Arc::new(sync::Mutex::new(0.7)))
+                .inverse()
+                .unwrap()
+                .collect();
+        let steady = &output[4096..output.len() - 4096];
+        for chunk in steady.chunks(441) {
+            let peak = chunk.iter().fold(0.0f64, |p, f| p.max(f[0].abs()));
+            assert!(peak > 0.8 && peak < 1.2, "{}", peak);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//!
+//! CUE sheets describe the tracks that are contained in one or more audio files.
+//!
+//! Positions in a CUE sheet are expressed in minutes, seconds and CD frames, of which there are 75
+//! per second.
+//!
+//! ```cue
+//! REM GENRE "Electronic"
+//! REM DATE 1984
+//! PERFORMER "Various Artists"
+//! TITLE "Dark Sine of the Moon"
+//! FILE "image.flac" WAVE
+//!   TRACK 01 AUDIO
+//!     TITLE "Lucy in the Cloud with Sine Waves"
+//!     PERFORMER "The B-Trees"
+//!     INDEX 01 00:00:00
+//! ```
+//!
+
+use std::*;
+
+/// The number of CD frames per second.
+const FRAMES_PER_SECOND: u64 = 75;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// The release date as stored in a `REM DATE` comment, usually just the year.
+    pub date: Option<String>,
+    /// The genre as stored in a `REM GENRE` comment.
+    pub genre: Option<String>,
+    pub files: Vec<File>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct File {
+    /// The path of the audio file, relative to the CUE sheet.
+    pub path: String,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    /// The number of the track starting at 1.
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// The position at which the track starts in its file.
+    pub start: time::Duration,
+}
+
+impl fmt::Display for Sheet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref genre) = self.genre {
+            writeln!(f, "REM GENRE {}", quote(genre))?;
+        }
+        if let Some(ref date) = self.date {
+            writeln!(f, "REM DATE {}", date)?;
+        }
+        if let Some(ref title) = self.title {
+            writeln!(f, "TITLE {}", quote(title))?;
+        }
+        if let Some(ref performer) = self.performer {
+            writeln!(f, "PERFORMER {}", quote(performer))?;
+        }
+        for file in &self.files {
+            writeln!(f, "FILE {} WAVE", quote(&file.path))?;
+            for track in &file.tracks {
+                writeln!(f, "  TRACK {:02} AUDIO", track.number)?;
+                if let Some(ref title) = track.title {
+                    writeln!(f, "    TITLE {}", quote(title))?;
+                }
+                if let Some(ref performer) = track.performer {
+                    writeln!(f, "    PERFORMER {}", quote(performer))?;
+                }
+                writeln!(f, "    INDEX 01 {}", format_time(track.start))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for Sheet {
+    type Err = Error;
+
+    /// Parses a CUE sheet. Only the first index of each track is used. Commands that do not
+    /// describe the audio or its tracks, such as `FLAGS` and `ISRC`, are ignored.
+    fn from_str(input: &str) -> Result<Sheet, Error> {
+        let mut sheet = Sheet::default();
+        // The track that is being described and the index of the file in which its first index
+        // lies. The first index of a track may follow a FILE command that starts the next file.
+        let mut current: Option<(Track, Option<usize>)> = None;
+        fn finish(sheet: &mut Sheet, current: Option<(Track, Option<usize>)>) -> Result<(), Error> {
+            match current {
+                Some((track, Some(file))) => sheet.files[file].tracks.push(track),
+                Some((track, None)) => return Err(Error::MissingIndex(track.number)),
+                None => (),
+            }
+            Ok(())
+        }
+
+        let input = input.trim_start_matches('\u{feff}');
+        for (i, line) in input.lines().enumerate() {
+            let syntax = || Error::Syntax(i + 1);
+            let (command, args) = split_word(line.trim());
+            match command {
+                "REM" => {
+                    let (key, value) = split_word(args);
+                    match key {
+                        "DATE" => sheet.date = Some(unquote(value).to_string()),
+                        "GENRE" => sheet.genre = Some(unquote(value).to_string()),
+                        _ => (),
+                    }
+                }
+                "TITLE" => match current {
+                    Some((ref mut track, _)) => track.title = Some(unquote(args).to_string()),
+                    None => sheet.title = Some(unquote(args).to_string()),
+                },
+                "PERFORMER" => match current {
+                    Some((ref mut track, _)) => track.performer = Some(unquote(args).to_string()),
+                    None => sheet.performer = Some(unquote(args).to_string()),
+                },
+                "FILE" => {
+                    let args = args.trim();
+                    // The last word is the type of the file.
+                    let path = args.rfind(' ').map(|i| &args[..i]).ok_or_else(syntax)?;
+                    sheet.files.push(File {
+                        path: unquote(path).to_string(),
+                        tracks: Vec::new(),
+                    });
+                }
+                "TRACK" => {
+                    if sheet.files.is_empty() {
+                        return Err(syntax());
+                    }
+                    finish(&mut sheet, current.take())?;
+                    let number = split_word(args).0.parse().map_err(|_| syntax())?;
+                    let track = Track {
+                        number,
+                        title: None,
+                        performer: None,
+                        start: time::Duration::from_secs(0),
+                    };
+                    current = Some((track, None));
+                }
+                "INDEX" => {
+                    let (number, time) = split_word(args);
+                    let (track, file) = current.as_mut().ok_or_else(syntax)?;
+                    if number == "01" {
+                        track.start = parse_time(time.trim()).ok_or_else(syntax)?;
+                        *file = Some(sheet.files.len() - 1);
+                    }
+                }
+                _ => (),
+            }
+        }
+        finish(&mut sheet, current)?;
+        Ok(sheet)
+    }
+}
+
+/// Splits the first word off a line.
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Removes the quotes around a string, if any.
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        &s[1..s.len() - 1]
+    } else {
+        s
+    }
+}
+
+/// Parses a time formatted as `mm:ss:ff`. The number of nanoseconds is rounded up so formatting
+/// the result yields the same time.
+fn parse_time(s: &str) -> Option<time::Duration> {
+    let parts: Vec<u64> = s
+        .split(':')
+        .map(|p| p.parse().ok())
+        .collect::<Option<_>>()?;
+    match parts.as_slice() {
+        &[min, sec, frames] if sec < 60 && frames < FRAMES_PER_SECOND => {
+            let nanos = (frames * 1_000_000_000 + FRAMES_PER_SECOND - 1) / FRAMES_PER_SECOND;
+            Some(time::Duration::new(min * 60 + sec, nanos as u32))
+        }
+        _ => None,
+    }
+}
+
+/// Quotes a string. CUE sheets have no means of escaping, so double quotes are replaced.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+/// Formats a duration as `mm:ss:ff`, rounded down to the preceding CD frame.
+fn format_time(t: time::Duration) -> String {
+    let frames = u64::from(t.subsec_nanos()) * FRAMES_PER_SECOND / 1_000_000_000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        t.as_secs() / 60,
+        t.as_secs() % 60,
+        frames
+    )
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// The line with the number, starting at 1, could not be parsed.
+    Syntax(usize),
+    /// The track with the number has no INDEX 01.
+    MissingIndex(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Syntax(line) => write!(f, "Syntax error on line {}", line),
+            Error::MissingIndex(track) => write!(f, "Track {} has no start index", track),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "CUE sheet error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write() {
+        let sheet = Sheet {
+            title: Some("Session".to_string()),
+            performer: None,
+            date: Some("2018".to_string()),
+            genre: None,
+            files: vec![File {
+                path: "session.flac".to_string(),
+                tracks: vec![
+                    Track {
+                        number: 1,
+                        title: Some("Lucy in the \"Cloud\"".to_string()),
+                        performer: Some("The B-Trees".to_string()),
+                        start: time::Duration::from_secs(0),
+                    },
+                    Track {
+                        number: 2,
+                        title: None,
+                        performer: None,
+                        start: time::Duration::from_millis(754_500),
+                    },
+                ],
+            }],
+        };
+        let expected = "REM DATE 2018\n\
+                        TITLE \"Session\"\n\
+                        FILE \"session.flac\" WAVE\n  \
+                        TRACK 01 AUDIO\n    \
+                        TITLE \"Lucy in the 'Cloud'\"\n    \
+                        PERFORMER \"The B-Trees\"\n    \
+                        INDEX 01 00:00:00\n  \
+                        TRACK 02 AUDIO\n    \
+                        INDEX 01 12:34:37\n";
+        assert_eq!(expected, sheet.to_string());
+    }
+
+    #[test]
+    fn parse() {
+        let input = "\u{feff}REM GENRE Electronic\r\n\
+                     REM DATE 1984\r\n\
+                     REM COMMENT \"ExactAudioCopy v1.0\"\r\n\
+                     PERFORMER \"Various Artists\"\r\n\
+                     TITLE \"Dark Sine of the Moon\"\r\n\
+                     FILE \"Dark Sine of the Moon (disc 1).flac\" WAVE\r\n  \
+                     TRACK 01 AUDIO\r\n    \
+                     TITLE \"Lucy in the Cloud with Sine Waves\"\r\n    \
+                     PERFORMER \"The B-Trees\"\r\n    \
+                     INDEX 01 00:00:00\r\n  \
+                     TRACK 02 AUDIO\r\n    \
+                     TITLE \"One or Zero\"\r\n    \
+                     FLAGS DCP\r\n    \
+                     INDEX 00 04:58:10\r\n\
+                     FILE \"Dark Sine of the Moon (disc 2).flac\" WAVE\r\n    \
+                     INDEX 01 00:00:01\r\n";
+        let sheet: Sheet = input.parse().unwrap();
+        assert_eq!(Some("Electronic"), sheet.genre.as_ref().map(|s| s.as_str()));
+        assert_eq!(Some("1984"), sheet.date.as_ref().map(|s| s.as_str()));
+        assert_eq!(
+            Some("Various Artists"),
+            sheet.performer.as_ref().map(|s| s.as_str())
+        );
+        assert_eq!(2, sheet.files.len());
+        assert_eq!("Dark Sine of the Moon (disc 1).flac", sheet.files[0].path);
+
+        let first = &sheet.files[0].tracks[0];
+        assert_eq!(1, first.number);
+        assert_eq!(
+            Some("The B-Trees"),
+            first.performer.as_ref().map(|s| s.as_str())
+        );
+        assert_eq!(time::Duration::from_secs(0), first.start);
+        // The first index of the second track lies in the second file.
+        let second = &sheet.files[1].tracks[0];
+        assert_eq!(
+            Some("One or Zero"),
+            second.title.as_ref().map(|s| s.as_str())
+        );
+        assert_eq!(None, second.performer);
+        assert_eq!("00:00:01", format_time(second.start));
+
+        // Writing and parsing the sheet again yields the same sheet.
+        assert_eq!(sheet, sheet.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let missing_index = "FILE \"a.wav\" WAVE\n  TRACK 01 AUDIO\n    TITLE \"A\"\n";
+        match missing_index.parse::<Sheet>() {
+            Err(Error::MissingIndex(1)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match "TRACK 01 AUDIO\n".parse::<Sheet>() {
+            Err(Error::Syntax(1)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        match "FILE \"a.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:61:00".parse::<Sheet>() {
+            Err(Error::Syntax(3)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
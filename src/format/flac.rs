@@ -455,6 +455,16 @@ unsafe extern "C" fn metadata_cb<R>(
                     "software" => ("TSSE", Content::Text(value.to_string())),
                     "title" => ("TIT2", Content::Text(value.to_string())),
                     "track" | "tracknumber" => ("TRCK", Content::Text(value.to_string())),
+                    "replaygaintrackgain"
+                    | "replaygaintrackpeak"
+                    | "replaygainalbumgain"
+                    | "replaygainalbumpeak" => (
+                        "TXXX",
+                        Content::ExtendedText(id3::frame::ExtendedText {
+                            key: key.to_uppercase(),
+                            value: value.to_string(),
+                        }),
+                    ),
                     "rating" => ("POPM", {
                         let rs = value
                             .parse()
@@ -571,6 +581,168 @@ unsafe fn entry_as_str(entry: &FLAC__StreamMetadata_VorbisComment_Entry) -> Opti
         .map(|cs| cs.to_string_lossy())
 }
 
+/// The number of frames that are buffered before they are passed to the encoder.
+const ENCODE_BLOCK_SIZE: usize = 4096;
+/// The compression level of the encoder, ranging from 0 (fastest) to 8 (smallest).
+const COMPRESSION_LEVEL: u32 = 5;
+
+/// Creates an encoder that writes a FLAC file to the specified path.
+pub fn encode_file<F>(path: &path::Path, sample_rate: u32) -> Result<Encoder<F>, Error>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+    use std::os::unix::ffi::OsStrExt;
+    let c_path = ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    unsafe {
+        let encoder = FLAC__stream_encoder_new();
+        if encoder.is_null() {
+            return Err(Error::ConstructionFailed);
+        }
+        FLAC__stream_encoder_set_channels(encoder, F::n_channels() as u32);
+        FLAC__stream_encoder_set_bits_per_sample(encoder, F::Sample::BITS);
+        FLAC__stream_encoder_set_sample_rate(encoder, sample_rate);
+        FLAC__stream_encoder_set_compression_level(encoder, COMPRESSION_LEVEL);
+        let init_status =
+            FLAC__stream_encoder_init_file(encoder, c_path.as_ptr(), None, ptr::null_mut());
+        if init_status != FLAC__StreamEncoderInitStatus::FLAC__STREAM_ENCODER_INIT_STATUS_OK {
+            FLAC__stream_encoder_delete(encoder);
+            return Err(Error::EncoderInitFailed(init_status));
+        }
+        Ok(Encoder {
+            encoder,
+            sample_rate,
+            buffer: Vec::with_capacity(ENCODE_BLOCK_SIZE * F::n_channels()),
+            finished: false,
+            _f: marker::PhantomData,
+        })
+    }
+}
+
+pub struct Encoder<F>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+    encoder: *mut FLAC__StreamEncoder,
+    sample_rate: u32,
+    /// Interleaved samples that have not yet been passed to the encoder.
+    buffer: Vec<FLAC__int32>,
+    finished: bool,
+    _f: marker::PhantomData<F>,
+}
+
+impl<F> Encoder<F>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+    /// Encodes the remaining audio and finalizes the file.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.finish_stream()
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let num_frames = (self.buffer.len() / F::n_channels()) as u32;
+        unsafe {
+            let ok = FLAC__stream_encoder_process_interleaved(
+                self.encoder,
+                self.buffer.as_ptr(),
+                num_frames,
+            );
+            self.buffer.clear();
+            if ok != 1 {
+                return Err(Error::EncoderBadState(FLAC__stream_encoder_get_state(
+                    self.encoder,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_stream(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.flush()?;
+        unsafe {
+            if FLAC__stream_encoder_finish(self.encoder) != 1 {
+                return Err(Error::EncoderBadState(FLAC__stream_encoder_get_state(
+                    self.encoder,
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F> Sink<F> for Encoder<F>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+    fn write_frame(&mut self, frame: F) -> Result<(), Box<error::Error + Send>> {
+        self.buffer.extend(frame.channels().map(|s| s.encode()));
+        if self.buffer.len() >= ENCODE_BLOCK_SIZE * F::n_channels() {
+            if let Err(err) = self.flush() {
+                return Err(Box::new(err));
+            }
+        }
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+unsafe impl<F> Send for Encoder<F>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+}
+
+impl<F> Drop for Encoder<F>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+{
+    fn drop(&mut self) {
+        if let Err(err) = self.finish_stream() {
+            error!("could not finish flac file: {}", err);
+        }
+        unsafe {
+            FLAC__stream_encoder_delete(self.encoder);
+        }
+    }
+}
+
+pub trait EncodeSample: sample::Sample {
+    /// The number of bits per sample.
+    const BITS: u32;
+    fn encode(self) -> i32;
+}
+
+impl EncodeSample for i16 {
+    const BITS: u32 = 16;
+    fn encode(self) -> i32 {
+        i32::from(self)
+    }
+}
+
+impl EncodeSample for I24 {
+    const BITS: u32 = 24;
+    fn encode(self) -> i32 {
+        self.inner()
+    }
+}
+
 pub trait SeekExt: io::Read + io::Seek {
     fn length(&mut self) -> Result<u64, ()>;
 
@@ -603,6 +775,8 @@ pub enum Error {
     ConstructionFailed,
     InitFailed(FLAC__StreamDecoderInitStatus),
     BadState(FLAC__StreamDecoderState),
+    EncoderInitFailed(FLAC__StreamEncoderInitStatus),
+    EncoderBadState(FLAC__StreamEncoderState),
     Unimplemented {
         known_length: bool,
         num_channels: u32,
@@ -629,6 +803,20 @@ impl fmt::Display for Error {
                 let errstr = ffi::CStr::from_ptr(*s);
                 write!(f, "Flac bad state: {}", errstr.to_str().unwrap())
             },
+            Error::EncoderInitFailed(status) => unsafe {
+                let s = FLAC__StreamEncoderInitStatusString
+                    .as_ptr()
+                    .offset(status as isize);
+                let errstr = ffi::CStr::from_ptr(*s);
+                write!(f, "Flac encoder init failed: {}", errstr.to_str().unwrap())
+            },
+            Error::EncoderBadState(state) => unsafe {
+                let s = FLAC__StreamEncoderStateString
+                    .as_ptr()
+                    .offset(state as isize);
+                let errstr = ffi::CStr::from_ptr(*s);
+                write!(f, "Flac encoder bad state: {}", errstr.to_str().unwrap())
+            },
             Error::Unimplemented {
                 known_length: kl,
                 num_channels: nc,
@@ -688,6 +876,31 @@ mod tests {
         assert_eq!(tag.date_released().unwrap(), "1984".parse().unwrap());
         assert_eq!(tag.album_artist().unwrap(), "Various Artists");
     }
+
+    #[test]
+    fn encode_roundtrip() {
+        let input: Vec<[i16; 2]> = (0..10_000)
+            .map(|i| {
+                let s = (f64::from(i) * 0.0627).sin() * 16000.0;
+                [s as i16, -s as i16]
+            })
+            .collect();
+        let path = env::temp_dir().join("audio-thing-flac-encode-test.flac");
+        let mut encoder = encode_file(&path, 44100).unwrap();
+        for frame in input.iter() {
+            encoder.write_frame(*frame).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let (audio, meta) = decode(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(Some(10_000), meta.num_samples);
+        let decoded: Vec<[i16; 2]> = match audio.into_seek() {
+            Some(dynam::Seek::StereoI16(s)) => s.collect(),
+            _ => panic!("unexpected format"),
+        };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(input, decoded);
+    }
 }
 
 #[cfg(all(test, feature = "unstable"))]
@@ -3,8 +3,10 @@ use id3;
 use std::io::{Read, Seek};
 use std::*;
 
+pub mod cue;
 pub mod flac;
 pub mod mp3;
+pub mod vorbis;
 pub mod wave;
 
 #[derive(Debug)]
@@ -168,6 +168,187 @@ where
     }
 }
 
+/// Decodes an MP3 stream that can not be seeked in, such as an internet radio stream.
+///
+/// Because no frame index can be built, the length of the stream is unknown. Garbage in the stream
+/// is skipped, so decoding continues after an interruption.
+pub fn decode_stream<R>(mut input: R) -> Result<dynam::Source, Error>
+where
+    R: io::Read + Send + 'static,
+{
+    // Skip the ID3 tag that some servers put in front of the stream, as LAME may mistake its
+    // contents for audio.
+    let mut read_buf = [0; MAX_FRAME_BYTES];
+    input.read_exact(&mut read_buf[..10])?;
+    let mut num_read = 10;
+    if &read_buf[..3] == b"ID3" {
+        let size = read_buf[6..10]
+            .iter()
+            .fold(0, |acc, b| (acc << 7) | u64::from(b & 0x7f));
+        io::copy(&mut input.by_ref().take(size), &mut io::sink())?;
+        num_read = 0;
+    }
+
+    unsafe {
+        let hip: hip_t = hip_decode_init();
+        if hip.is_null() {
+            return Err(Error::ConstructionFailed);
+        }
+        hip_set_debugf(hip, Some(debug_cb));
+        hip_set_msgf(hip, Some(msg_cb));
+        hip_set_errorf(hip, Some(error_cb));
+
+        let mut mp3_data: mp3data_struct = mem::zeroed();
+        let mut enc_delay = 0;
+        let mut enc_padding = 0;
+        let mut buffers = [[0; MAX_FRAME_SIZE]; 2];
+        let decode_count = loop {
+            let rs = hip_decode1_headersB(
+                hip,
+                read_buf.as_mut_ptr(),
+                num_read,
+                buffers[0].as_mut_ptr(),
+                buffers[1].as_mut_ptr(),
+                &mut mp3_data,
+                &mut enc_delay,
+                &mut enc_padding,
+            );
+            if rs < 0 {
+                hip_decode_exit(hip);
+                return Err(Error::Lame(rs));
+            }
+            if rs > 0 && mp3_data.header_parsed == 1 {
+                break rs as usize;
+            }
+            num_read = match input.read(&mut read_buf) {
+                Ok(0) => {
+                    hip_decode_exit(hip);
+                    return Err(Error::NoHeader);
+                }
+                Ok(nr) => nr,
+                Err(err) => {
+                    hip_decode_exit(hip);
+                    return Err(Error::IO(err));
+                }
+            };
+        };
+
+        macro_rules! dyn_type {
+            ($dyn:path) => {
+                $dyn(Box::from(StreamDecoder {
+                    input,
+                    input_buf: [0; MAX_FRAME_BYTES],
+                    hip,
+                    sample_rate: mp3_data.samplerate as u32,
+                    buffers,
+                    next_sample: 0,
+                    samples_available: decode_count,
+                    _f: marker::PhantomData,
+                }))
+            };
+        }
+        Ok(match mp3_data.stereo {
+            1 => dyn_type!(dynam::Source::MonoI16),
+            2 => dyn_type!(dynam::Source::StereoI16),
+            _ => unreachable!(), // LAME's interface does not allow this.
+        })
+    }
+}
+
+struct StreamDecoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    input: R,
+    input_buf: [u8; MAX_FRAME_BYTES],
+    hip: hip_t,
+    sample_rate: u32,
+
+    buffers: [[i16; MAX_FRAME_SIZE]; 2],
+    next_sample: usize,
+    samples_available: usize,
+
+    _f: marker::PhantomData<F>,
+}
+
+unsafe impl<F, R> Send for StreamDecoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+}
+
+impl<F, R> iter::Iterator for StreamDecoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    type Item = F;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut num_read = 0;
+        while self.next_sample >= self.samples_available {
+            unsafe {
+                let rs = hip_decode1(
+                    self.hip,
+                    self.input_buf.as_mut_ptr(),
+                    num_read,
+                    self.buffers[0].as_mut_ptr(),
+                    self.buffers[1].as_mut_ptr(),
+                );
+                match rs {
+                    // LAME needs more data before it can decode the next frame.
+                    0 => {
+                        num_read = match self.input.read(&mut self.input_buf) {
+                            Ok(nr) if nr == 0 => return None,
+                            Ok(nr) => nr,
+                            Err(err) => {
+                                error!("{}", err);
+                                return None;
+                            }
+                        };
+                    }
+                    code if code < 0 => {
+                        error!("Error decoding next frame: {}", Error::Lame(code));
+                        return None;
+                    }
+                    decode_count => {
+                        num_read = 0;
+                        self.next_sample = 0;
+                        self.samples_available = decode_count as usize;
+                    }
+                };
+            }
+        }
+
+        let frame = F::from_fn(|ch| self.buffers[ch][self.next_sample]);
+        self.next_sample += 1;
+        Some(frame)
+    }
+}
+
+impl<F, R> Source for StreamDecoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl<F, R> Drop for StreamDecoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    fn drop(&mut self) {
+        unsafe {
+            hip_decode_exit(self.hip);
+        }
+    }
+}
+
 struct Decoder<F, R>
 where
     F: sample::Frame<Sample = i16>,
@@ -0,0 +1,232 @@
+use crate::audio::*;
+use lewton::inside_ogg::OggStreamReader;
+use lewton::VorbisError;
+use log::*;
+use sample;
+use std::sync::Arc;
+use std::*;
+
+/// Decodes an Ogg Vorbis stream that can not be seeked in, such as an internet radio stream.
+///
+/// Internet radio typically starts a new logical stream for every track, each with its own
+/// comment header. The comments of every logical stream are passed to the specified callback.
+pub fn decode_stream<R>(
+    input: R,
+    on_comments: Arc<Fn(&[(String, String)]) + Send + Sync>,
+) -> Result<dynam::Source, Error>
+where
+    R: io::Read + Send + 'static,
+{
+    let reader = OggStreamReader::new(Unseekable(input))?;
+    let sample_rate = reader.ident_hdr.audio_sample_rate;
+    let num_channels = reader.ident_hdr.audio_channels;
+    on_comments(&reader.comment_hdr.comment_list);
+
+    macro_rules! dyn_type {
+        ($dyn:path) => {
+            $dyn(Box::from(Decoder {
+                comments: reader.comment_hdr.comment_list.clone(),
+                reader: Some(reader),
+                sample_rate,
+                buffer: Vec::new(),
+                next_sample: 0,
+                on_comments,
+                _f: marker::PhantomData,
+            }))
+        };
+    }
+    match num_channels {
+        1 => Ok(dyn_type!(dynam::Source::MonoI16)),
+        2 => Ok(dyn_type!(dynam::Source::StereoI16)),
+        n => Err(Error::Unimplemented { num_channels: n }),
+    }
+}
+
+struct Decoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    reader: Option<OggStreamReader<Unseekable<R>>>,
+    sample_rate: u32,
+    /// The comments of the current logical stream.
+    comments: Vec<(String, String)>,
+
+    /// Interleaved samples of the most recently decoded packet.
+    buffer: Vec<i16>,
+    next_sample: usize,
+
+    on_comments: Arc<Fn(&[(String, String)]) + Send + Sync>,
+    _f: marker::PhantomData<F>,
+}
+
+impl<F, R> Decoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    /// Reads the headers of a new logical stream after decoding the current one failed, which
+    /// happens when the stream was interrupted. Returns false if decoding can not continue.
+    fn restart(&mut self) -> bool {
+        let input = match self.reader.take() {
+            Some(reader) => reader.into_inner().into_inner(),
+            None => return false,
+        };
+        let reader = match OggStreamReader::new(input) {
+            Ok(reader) => reader,
+            Err(err) => {
+                error!("Could not restart Vorbis stream: {}", err);
+                return false;
+            }
+        };
+        let ident = &reader.ident_hdr;
+        if ident.audio_sample_rate != self.sample_rate
+            || usize::from(ident.audio_channels) != F::n_channels()
+        {
+            error!("The format of the Vorbis stream changed");
+            return false;
+        }
+        self.reader = Some(reader);
+        true
+    }
+}
+
+impl<F, R> iter::Iterator for Decoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    type Item = F;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next_sample + F::n_channels() > self.buffer.len() {
+            let packet = self.reader.as_mut()?.read_dec_packet_itl();
+            match packet {
+                Ok(Some(samples)) => {
+                    self.buffer = samples;
+                    self.next_sample = 0;
+                }
+                Ok(None) => return None,
+                Err(err) => {
+                    warn!("Error decoding Vorbis packet: {}", err);
+                    if !self.restart() {
+                        return None;
+                    }
+                }
+            }
+            let reader = self.reader.as_ref()?;
+            if reader.comment_hdr.comment_list != self.comments {
+                self.comments = reader.comment_hdr.comment_list.clone();
+                (self.on_comments)(&self.comments);
+            }
+        }
+
+        let buffer = &self.buffer[self.next_sample..];
+        let frame = F::from_fn(|ch| buffer[ch]);
+        self.next_sample += F::n_channels();
+        Some(frame)
+    }
+}
+
+impl<F, R> Source for Decoder<F, R>
+where
+    F: sample::Frame<Sample = i16>,
+    R: io::Read + Send + 'static,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// The Ogg reader requires its input to implement Seek, but only seeks when asked to. This
+/// adapter allows streams to be used as input.
+struct Unseekable<R>(R);
+
+impl<R> io::Read for Unseekable<R>
+where
+    R: io::Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R> io::Seek for Unseekable<R> {
+    fn seek(&mut self, _: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "the stream can not be seeked in",
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Vorbis(VorbisError),
+    Unimplemented { num_channels: u8 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Vorbis(ref err) => write!(f, "Vorbis: {}", err),
+            Error::Unimplemented { num_channels } => {
+                write!(f, "Unimplemented format: {} channels", num_channels)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Vorbis error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Vorbis(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<VorbisError> for Error {
+    fn from(err: VorbisError) -> Error {
+        Error::Vorbis(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn chained_stream() {
+        // Two logical streams of silence, like a radio stream with two tracks.
+        let file = fs::File::open("testdata/2x1s_silence_stereo_chained.ogg").unwrap();
+        let titles = Arc::new(Mutex::new(Vec::new()));
+        let titles_handler = titles.clone();
+        let source = decode_stream(
+            file,
+            Arc::new(move |comments: &[(String, String)]| {
+                let title = comments
+                    .iter()
+                    .find(|(key, _)| key == "TITLE")
+                    .map(|(_, value)| value.clone());
+                titles_handler.lock().unwrap().push(title.unwrap());
+            }),
+        )
+        .unwrap();
+        assert_eq!(44100, source.sample_rate());
+        let frames: Vec<[i16; 2]> = match source {
+            dynam::Source::StereoI16(s) => s.collect(),
+            _ => panic!("unexpected format"),
+        };
+        assert_eq!(88064, frames.len());
+        assert!(frames.iter().all(|f| *f == [0, 0]));
+        assert_eq!(
+            vec!["Lucy's Sine".to_string(), "Sine of the Times".to_string()],
+            *titles.lock().unwrap()
+        );
+    }
+}
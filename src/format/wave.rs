@@ -344,6 +344,166 @@ where
     }
 }
 
+/// Creates an encoder that writes little endian RIFF wave data to the output.
+///
+/// The header is written immediately with the sizes left empty. The sizes are filled in when the
+/// encoder is finished or dropped, so the output must be seekable.
+pub fn encode<F, W>(mut output: W, sample_rate: u32) -> Result<Encoder<F, W>, Error>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+    W: io::Write + io::Seek,
+{
+    let sample_size = F::Sample::SIZE as u16 * 8;
+    let block_align = F::n_channels() as u16 * F::Sample::SIZE as u16;
+    let mut header = [0; 44];
+    header[0..4].copy_from_slice(b"RIFF");
+    header[8..16].copy_from_slice(b"WAVEfmt ");
+    LittleEndian::write_u32(&mut header[16..20], 16);
+    LittleEndian::write_u16(&mut header[20..22], F::Sample::FORMAT);
+    LittleEndian::write_u16(&mut header[22..24], F::n_channels() as u16);
+    LittleEndian::write_u32(&mut header[24..28], sample_rate);
+    LittleEndian::write_u32(&mut header[28..32], sample_rate * u32::from(block_align));
+    LittleEndian::write_u16(&mut header[32..34], block_align);
+    LittleEndian::write_u16(&mut header[34..36], sample_size);
+    header[36..40].copy_from_slice(b"data");
+    output.write_all(&header)?;
+    Ok(Encoder {
+        output: Some(output),
+        sample_rate,
+        num_frames: 0,
+        buf: Vec::with_capacity(usize::from(block_align)),
+        _f: marker::PhantomData,
+    })
+}
+
+pub struct Encoder<F, W>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+    W: io::Write + io::Seek,
+{
+    /// Taken when the encoder is finished.
+    output: Option<W>,
+    sample_rate: u32,
+    num_frames: u64,
+    buf: Vec<u8>,
+    _f: marker::PhantomData<F>,
+}
+
+impl<F, W> Encoder<F, W>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+    W: io::Write + io::Seek,
+{
+    /// Writes the final sizes to the header and returns the output.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_sizes()?;
+        Ok(self.output.take().unwrap())
+    }
+
+    /// Returns the number of frames written so far.
+    pub fn num_frames(&self) -> u64 {
+        self.num_frames
+    }
+
+    fn write_sizes(&mut self) -> Result<(), Error> {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        let data_size = self.num_frames * (F::n_channels() * F::Sample::SIZE) as u64;
+        if data_size + 36 > u64::from(u32::max_value()) {
+            return Err(Error::TooLarge);
+        }
+        let mut buf = [0; 4];
+        LittleEndian::write_u32(&mut buf, data_size as u32 + 36);
+        output.seek(io::SeekFrom::Start(4))?;
+        output.write_all(&buf)?;
+        LittleEndian::write_u32(&mut buf, data_size as u32);
+        output.seek(io::SeekFrom::Start(40))?;
+        output.write_all(&buf)?;
+        output.seek(io::SeekFrom::End(0))?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+impl<F, W> Sink<F> for Encoder<F, W>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+    W: io::Write + io::Seek,
+{
+    fn write_frame(&mut self, frame: F) -> Result<(), Box<error::Error + Send>> {
+        self.buf.clear();
+        for sample in frame.channels() {
+            sample.encode(&mut self.buf);
+        }
+        match self.output.as_mut().unwrap().write_all(&self.buf) {
+            Ok(()) => (),
+            Err(ioerr) => return Err(Box::new(ioerr)),
+        };
+        self.num_frames += 1;
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl<F, W> Drop for Encoder<F, W>
+where
+    F: sample::Frame,
+    F::Sample: EncodeSample,
+    W: io::Write + io::Seek,
+{
+    fn drop(&mut self) {
+        if let Err(err) = self.write_sizes() {
+            error!("could not finish wave file: {}", err);
+        }
+    }
+}
+
+pub trait EncodeSample: sample::Sample {
+    /// The audio format code of the fmt chunk.
+    const FORMAT: u16;
+    /// The number of bytes per sample.
+    const SIZE: usize;
+    fn encode(self, buf: &mut Vec<u8>);
+}
+
+impl EncodeSample for i16 {
+    const FORMAT: u16 = 1;
+    const SIZE: usize = 2;
+    fn encode(self, buf: &mut Vec<u8>) {
+        let mut b = [0; 2];
+        LittleEndian::write_i16(&mut b, self);
+        buf.extend_from_slice(&b);
+    }
+}
+
+impl EncodeSample for I24 {
+    const FORMAT: u16 = 1;
+    const SIZE: usize = 3;
+    fn encode(self, buf: &mut Vec<u8>) {
+        let v = self.inner();
+        buf.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8]);
+    }
+}
+
+impl EncodeSample for f32 {
+    const FORMAT: u16 = 3;
+    const SIZE: usize = 4;
+    fn encode(self, buf: &mut Vec<u8>) {
+        let mut b = [0; 4];
+        LittleEndian::write_f32(&mut b, self);
+        buf.extend_from_slice(&b);
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
@@ -355,6 +515,8 @@ pub enum Error {
         sample_size: u16,
     },
     Unsupported,
+    /// The data does not fit in the 32 bit sizes of a wave file.
+    TooLarge,
 }
 
 impl fmt::Display for Error {
@@ -373,6 +535,7 @@ impl fmt::Display for Error {
                 num_channels, sample_size, endianness,
             ),
             Error::Unsupported => write!(f, "Non PCM formats are unsupported"),
+            Error::TooLarge => write!(f, "The audio is too large for a wave file"),
         }
     }
 }
@@ -416,3 +579,30 @@ mod benchmarks {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_roundtrip() {
+        let input: Vec<[f32; 2]> = (0..1000)
+            .map(|i| [i as f32 / 1000.0, -i as f32 / 1000.0])
+            .collect();
+        let mut encoder = encode(io::Cursor::new(Vec::new()), 44100).unwrap();
+        for frame in input.iter() {
+            encoder.write_frame(*frame).unwrap();
+        }
+        let mut output = encoder.finish().unwrap();
+        output.set_position(0);
+
+        let (audio, meta) = decode(output).unwrap();
+        assert_eq!(44100, meta.sample_rate);
+        assert_eq!(Some(1000), meta.num_samples);
+        let decoded: Vec<[f32; 2]> = match audio.into_seek() {
+            Some(dynam::Seek::StereoF32(s)) => s.collect(),
+            _ => panic!("unexpected format"),
+        };
+        assert_eq!(input, decoded);
+    }
+}
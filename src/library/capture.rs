@@ -0,0 +1,148 @@
+//!
+//! Live input from a PulseAudio recording source, such as a line-in or the monitor of a sink.
+//!
+//! A capture can be played like any other stream, so the input can be monitored through the
+//! effects of the player. It can also be recorded to disk independently of playback.
+//!
+
+use crate::audio::*;
+use crate::format::wave;
+use crate::library;
+use crate::pulse;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::*;
+
+/// The name of the library to which captures belong.
+pub const LIBRARY_NAME: &str = "capture";
+
+/// The name by which pulse refers to the monitor of the default sink.
+const DEFAULT_MONITOR: &str = "@DEFAULT_MONITOR@";
+
+/// The sample rate at which the source is recorded. Pulse resamples if the device runs at a
+/// different rate.
+const SAMPLE_RATE: u32 = 44100;
+
+/// The recording source to capture from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Device {
+    /// The default source as configured in pulse, usually a microphone or line-in.
+    Default,
+    /// The monitor of the default sink, which records what is being played.
+    Monitor,
+    /// A source by its pulse name, as listed by `pactl list sources short`.
+    Named(String),
+}
+
+impl Device {
+    /// Returns the name of the pulse source, or None for the default source.
+    pub fn source_name(&self) -> Option<&str> {
+        match *self {
+            Device::Default => None,
+            Device::Monitor => Some(DEFAULT_MONITOR),
+            Device::Named(ref name) => Some(name),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Device {
+    fn from(name: &'a str) -> Device {
+        match name {
+            "" | "default" => Device::Default,
+            "monitor" => Device::Monitor,
+            name => Device::Named(name.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Device::Default => write!(f, "default"),
+            Device::Monitor => write!(f, "monitor"),
+            Device::Named(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A live capture of a recording source.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    pub device: Device,
+    pub title: String,
+}
+
+impl Capture {
+    pub fn new(device: Device) -> Capture {
+        let title = match device {
+            Device::Default => "Line in".to_string(),
+            Device::Monitor => "Monitor".to_string(),
+            Device::Named(ref name) => name.clone(),
+        };
+        Capture { device, title }
+    }
+
+    fn source(&self) -> Result<pulse::Source<[f32; 2]>, Box<error::Error>> {
+        let app_name = format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        pulse::source(&app_name, self.device.source_name(), SAMPLE_RATE)
+    }
+
+    /// Starts recording the source to a wave file in the specified directory, named after the
+    /// time at which the recording was started. The recording opens its own connection to the
+    /// device, so it is not affected by the effects of the player.
+    pub fn record(&self, dir: &Path) -> Result<(PathBuf, record::Recording), Box<error::Error>> {
+        let path = record::timestamped_path(dir, LIBRARY_NAME, "wav");
+        let file = io::BufWriter::new(fs::File::create(&path)?);
+        let encoder = wave::encode(file, SAMPLE_RATE)?;
+        let recording = record::record(self.source()?, encoder);
+        Ok((path, recording))
+    }
+}
+
+impl library::Identity for Capture {
+    fn id(&self) -> (Cow<str>, Cow<str>) {
+        (
+            Cow::Borrowed(LIBRARY_NAME),
+            Cow::Owned(self.device.to_string()),
+        )
+    }
+}
+
+impl library::Stream for Capture {
+    fn title(&self) -> Cow<str> {
+        Cow::Borrowed(&self.title)
+    }
+
+    fn open(
+        &self,
+        _on_info: Arc<Fn(Option<Box<library::TrackInfo + Send>>) + Send + Sync>,
+    ) -> Result<dynam::Source, Box<error::Error>> {
+        Ok(dynam::Source::StereoF32(Box::new(self.source()?)))
+    }
+
+    fn buffer(&self) -> Option<buffer::Params> {
+        // The device delivers audio at a steady pace, so only a little buffering is needed. This
+        // keeps the delay low enough to monitor the input.
+        Some(buffer::Params {
+            capacity: time::Duration::from_millis(500),
+            prebuffer: time::Duration::from_millis(50),
+            watermark: time::Duration::from_millis(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device() {
+        assert_eq!(Device::Default, Device::from("default"));
+        assert_eq!(None, Device::from("").source_name());
+        assert_eq!(Some(DEFAULT_MONITOR), Device::from("monitor").source_name());
+        let name = "alsa_input.usb-Behringer_UCA222-00.analog-stereo";
+        assert_eq!(Some(name), Device::from(name).source_name());
+        assert_eq!(name, Device::from(name).to_string());
+    }
+}
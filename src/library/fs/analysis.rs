@@ -0,0 +1,262 @@
+use super::cuesheet;
+use super::track::replay_gain_from_tag;
+use super::Error;
+use crate::analysis::{self, bpm, key, loudness, waveform};
+use crate::audio::*;
+use crate::format;
+use crate::library;
+use log::*;
+use rusqlite as sqlite;
+use std::*;
+
+enum Gain {
+    /// The ReplayGain was read from the tags of the file.
+    Tag(library::ReplayGain),
+    Scan(loudness::Loudness),
+}
+
+struct Analysis {
+    gain: Gain,
+    bpm: Option<f64>,
+    beat_offset: Option<f64>,
+    key: Option<key::Key>,
+    waveform: waveform::Overview,
+}
+
+/// The number of points per second of the stored waveform overviews. Lower resolutions are
+/// derived from these.
+const WAVEFORM_RESOLUTION: u32 = 50;
+
+/// Analyses all indexed tracks that have not been analysed since they were last modified.
+///
+/// The database is only locked while it is being accessed, so the index remains usable while the
+/// audio is being decoded.
+pub fn analyse_outdated(db: &sync::Weak<sync::Mutex<sqlite::Connection>>) -> Result<(), Error> {
+    let outdated: Vec<(String, i64, Option<cuesheet::Image>)> = {
+        let arc = match db.upgrade() {
+            Some(arc) => arc,
+            None => return Ok(()),
+        };
+        let db = arc.lock().unwrap();
+        let mut stmt = db.prepare(
+            r#"
+            SELECT t."path", t."modified_at", t."image_path", t."image_start", t."image_end"
+            FROM "track" AS t
+            LEFT JOIN "track_analysis" AS a ON a."track_path" = t."path"
+            WHERE a."modified_at" IS NOT t."modified_at"
+        "#,
+        )?;
+        let rows = stmt.query_map(&[], |row| {
+            let image = row.get::<_, Option<String>>(2).map(|path| cuesheet::Image {
+                path,
+                start: row.get::<_, i64>(3) as u64,
+                end: row.get::<_, i64>(4) as u64,
+            });
+            (row.get(0), row.get(1), image)
+        })?;
+        rows.collect::<Result<_, _>>()?
+    };
+
+    for (path, modified_at, image) in outdated {
+        let result = match image {
+            Some(image) => analyse_image(&image),
+            None => analyse_file(path::Path::new(&path)),
+        };
+        let arc = match db.upgrade() {
+            Some(arc) => arc,
+            None => return Ok(()),
+        };
+        let mut db = arc.lock().unwrap();
+        match result {
+            Ok(analysis) => {
+                store(&mut db, &path, modified_at, &analysis)?;
+                debug!("analysed {}", path);
+            }
+            Err(err) => {
+                error!("could not analyse {}: {}", path, err);
+                store_failure(&db, &path, modified_at)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Estimates the tempo and key and computes the waveform of a file in a single pass over its
+/// audio. The ReplayGain is read from the tags of the file if present, and its loudness is
+/// measured otherwise.
+fn analyse_file(path: &path::Path) -> Result<Analysis, Error> {
+    let (audio, meta) = format::decode_file(path)?;
+    let tagged = meta.tag.as_ref().and_then(replay_gain_from_tag);
+    analyse(audio.into(), tagged)
+}
+
+/// Analyses a track of a CUE sheet. The tags of an image apply to the image as a whole, so the
+/// loudness is always measured.
+fn analyse_image(image: &cuesheet::Image) -> Result<Analysis, Error> {
+    analyse(image.audio()?.into(), None)
+}
+
+fn analyse(source: dynam::Source, tagged: Option<library::ReplayGain>) -> Result<Analysis, Error> {
+    let sample_rate = source.sample_rate();
+    let num_channels = source.num_channels() as usize;
+    let mut analysers = (
+        match tagged {
+            Some(_) => None,
+            None => Some(loudness::Meter::new(sample_rate, num_channels)),
+        },
+        bpm::analyser(sample_rate, num_channels),
+        key::analyser(sample_rate, num_channels),
+        waveform::Scanner::new(
+            sample_rate,
+            (sample_rate / WAVEFORM_RESOLUTION).max(1) as usize,
+            true,
+        ),
+    );
+    analysis::feed_dynam(source, &mut analysers);
+    let (meter, tempo, key, scanner) = analysers;
+
+    let gain = match (tagged, meter) {
+        (Some(rg), _) => Gain::Tag(rg),
+        (None, Some(meter)) => Gain::Scan(meter.loudness()),
+        (None, None) => unreachable!(),
+    };
+    let bpm = tempo.get_ref().bpm();
+    Ok(Analysis {
+        gain,
+        bpm,
+        beat_offset: bpm.map(|bpm| tempo.get_ref().beat_offset(bpm)),
+        key: key.get_ref().key(),
+        waveform: scanner.overview(),
+    })
+}
+
+fn store(
+    db: &mut sqlite::Connection,
+    path: &str,
+    modified_at: i64,
+    analysis: &Analysis,
+) -> Result<(), Error> {
+    match analysis.gain {
+        Gain::Tag(ref rg) => {
+            db.execute(
+                r#"
+                INSERT INTO "track_analysis"
+                ("track_path", "modified_at", "source", "track_gain", "track_peak", "album_gain", "album_peak", "bpm", "beat_offset", "key", "waveform")
+                VALUES (?1, ?2, 'tag', ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+            "#,
+                &[
+                    &path,
+                    &modified_at,
+                    &rg.track_gain,
+                    &rg.track_peak,
+                    &rg.album_gain,
+                    &rg.album_peak,
+                    &analysis.bpm,
+                    &analysis.beat_offset,
+                    &analysis.key,
+                    &analysis.waveform.to_bytes(),
+                ],
+            )?;
+        }
+        Gain::Scan(ref loudness) => {
+            db.execute(
+                r#"
+                INSERT INTO "track_analysis"
+                ("track_path", "modified_at", "source", "track_gain", "track_peak", "loudness_histogram", "bpm", "beat_offset", "key", "waveform")
+                VALUES (?1, ?2, 'scan', ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+                &[
+                    &path,
+                    &modified_at,
+                    &loudness.gain(),
+                    &loudness.peak,
+                    &loudness.histogram_bytes(),
+                    &analysis.bpm,
+                    &analysis.beat_offset,
+                    &analysis.key,
+                    &analysis.waveform.to_bytes(),
+                ],
+            )?;
+            album_update(db, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Records that the track could not be analysed, so it is not attempted again until the file is
+/// modified.
+fn store_failure(db: &sqlite::Connection, path: &str, modified_at: i64) -> Result<(), Error> {
+    db.execute(
+        r#"
+        INSERT INTO "track_analysis" ("track_path", "modified_at", "source")
+        VALUES (?1, ?2, 'error')
+    "#,
+        &[&path, &modified_at],
+    )?;
+    Ok(())
+}
+
+/// Computes the loudness of the album of a track from the measurements of all of its tracks.
+/// Tracks of which the ReplayGain was read from tags keep the album values of their tags.
+fn album_update(db: &mut sqlite::Connection, path: &str) -> Result<(), Error> {
+    let tx = db.transaction()?;
+    let title: Option<String> = tx.query_row(
+        r#"SELECT "album_title" FROM "track" WHERE "path" = ?1"#,
+        &[&path],
+        |row| row.get(0),
+    )?;
+    let title = match title {
+        Some(title) => title,
+        None => return Ok(()),
+    };
+    let artists = album_artists(&tx, path)?;
+
+    let measured: Vec<(String, Vec<u8>, f64)> = {
+        let mut stmt = tx.prepare(
+            r#"
+            SELECT a."track_path", a."loudness_histogram", a."track_peak"
+            FROM "track" AS t
+            JOIN "track_analysis" AS a ON a."track_path" = t."path"
+            WHERE t."album_title" = ?1 AND a."source" = 'scan'
+        "#,
+        )?;
+        let rows = stmt.query_map(&[&title], |row| (row.get(0), row.get(1), row.get(2)))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    let mut paths = Vec::new();
+    let mut measurements = Vec::new();
+    for (track_path, histogram, peak) in measured {
+        if album_artists(&tx, &track_path)? != artists {
+            continue;
+        }
+        if let Some(loudness) = loudness::Loudness::from_histogram_bytes(&histogram, peak) {
+            paths.push(track_path);
+            measurements.push(loudness);
+        }
+    }
+    let album = loudness::Loudness::merge(&measurements);
+    for track_path in paths {
+        tx.execute(
+            r#"
+            UPDATE "track_analysis"
+            SET "album_gain" = ?2, "album_peak" = ?3
+            WHERE "track_path" = ?1
+        "#,
+            &[&track_path, &album.gain(), &album.peak],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+fn album_artists(db: &sqlite::Connection, path: &str) -> Result<Vec<String>, Error> {
+    let mut stmt = db.prepare(
+        r#"
+        SELECT "name" FROM "track_artist"
+        WHERE "track_path" = ?1 AND "type" = 'album'
+        ORDER BY "name"
+    "#,
+    )?;
+    let rows = stmt.query_map(&[&path], |row| row.get(0))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
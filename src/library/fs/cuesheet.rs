@@ -0,0 +1,199 @@
+//!
+//! Tracks that are described by a CUE sheet, which is commonly used to split an album that has
+//! been ripped to a single audio file, the image, into its tracks.
+//!
+//! Each track of a sheet is indexed as a virtual track of which the path is the path of the sheet
+//! followed by `#` and the number of the track, e.g. `/music/album.cue#03`. The audio of a virtual
+//! track is a window over its image.
+//!
+
+use super::Error;
+use crate::audio::*;
+use crate::format::{self, cue};
+use crate::library;
+use log::*;
+use std::borrow::Cow;
+use std::*;
+
+/// The part of an image that makes up a track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    /// The absolute path of the audio file.
+    pub path: String,
+    /// The position of the first frame of the track.
+    pub start: u64,
+    /// The position after the last frame of the track.
+    pub end: u64,
+}
+
+impl Image {
+    pub fn audio(&self) -> Result<dynam::Seek, Error> {
+        let (audio, _) = format::decode_file(path::Path::new(&self.path))?;
+        let audio = audio.into_seek().ok_or(Error::NonSeek)?;
+        Ok(audio.segment(self.start, self.end)?)
+    }
+}
+
+/// A track as it is read from a CUE sheet.
+pub struct CueTrack {
+    /// The virtual path of the track.
+    pub path: String,
+    pub cue_path: String,
+    pub modified_at: time::SystemTime,
+    pub image: Image,
+    pub sample_rate: u32,
+
+    pub title: String,
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
+    pub album_title: Option<String>,
+    pub album_artists: Vec<String>,
+    pub album_track: Option<i32>,
+    pub release: Option<library::Release>,
+}
+
+impl library::Identity for CueTrack {
+    fn id(&self) -> (Cow<str>, Cow<str>) {
+        (Cow::Borrowed("fs"), Cow::Borrowed(&self.path))
+    }
+}
+
+impl library::TrackInfo for CueTrack {
+    fn title(&self) -> Cow<str> {
+        Cow::Borrowed(&self.title)
+    }
+
+    fn artists(&self) -> Cow<[String]> {
+        Cow::Borrowed(&self.artists)
+    }
+
+    fn remixers(&self) -> Cow<[String]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn genres(&self) -> Cow<[String]> {
+        Cow::Borrowed(&self.genres)
+    }
+
+    fn album_title(&self) -> Option<Cow<str>> {
+        self.album_title.as_ref().map(|s| Cow::Borrowed(s.as_str()))
+    }
+
+    fn album_artists(&self) -> Cow<[String]> {
+        Cow::Borrowed(&self.album_artists)
+    }
+
+    fn album_disc(&self) -> Option<i32> {
+        None
+    }
+
+    fn album_track(&self) -> Option<i32> {
+        self.album_track
+    }
+
+    fn rating(&self) -> Option<u8> {
+        None
+    }
+
+    fn release(&self) -> Option<library::Release> {
+        self.release.clone()
+    }
+}
+
+impl library::Track for CueTrack {
+    fn modified_at(&self) -> Option<time::SystemTime> {
+        Some(self.modified_at)
+    }
+
+    fn audio(&self) -> Result<dynam::Seek, Box<error::Error>> {
+        Ok(self.image.audio()?)
+    }
+
+    fn duration(&self) -> time::Duration {
+        duration_of(self.sample_rate, self.image.end - self.image.start)
+    }
+}
+
+/// Reads the tracks of the CUE sheet at the path. The images must exist and have a known length.
+pub fn read(path: &path::Path) -> Result<Vec<CueTrack>, Error> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+    // CUE sheets that are written by older rippers are often not UTF-8.
+    let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+    let sheet: cue::Sheet = text.parse()?;
+    let modified_at = fs::metadata(path)?.modified()?;
+    let dir = path.parent().unwrap_or_else(|| path::Path::new(""));
+
+    let mut tracks = Vec::new();
+    for file in &sheet.files {
+        let image_path = dir.join(&file.path).canonicalize()?;
+        let image_path = image_path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(image_path.clone()))?
+            .to_string();
+        let meta = format::decode_metadata_file(path::Path::new(&image_path))?;
+        let length = meta.num_samples.ok_or(Error::NonSeek)?;
+
+        for (i, track) in file.tracks.iter().enumerate() {
+            // A track ends where the next one in the same file starts, so any pregap of the next
+            // track is played as part of the current one.
+            let start = frames_of(meta.sample_rate, track.start);
+            let end = file
+                .tracks
+                .get(i + 1)
+                .map(|next| frames_of(meta.sample_rate, next.start))
+                .unwrap_or(length)
+                .min(length);
+            if start >= end {
+                warn!(
+                    "skipping track {} of {}: empty or out of range",
+                    track.number, path_str
+                );
+                continue;
+            }
+            let artists = track
+                .performer
+                .as_ref()
+                .or_else(|| sheet.performer.as_ref())
+                .map(|p| vec![p.clone()])
+                .unwrap_or_else(Vec::new);
+            tracks.push(CueTrack {
+                path: format!("{}#{:02}", path_str, track.number),
+                cue_path: path_str.to_string(),
+                modified_at,
+                image: Image {
+                    path: image_path.clone(),
+                    start,
+                    end,
+                },
+                sample_rate: meta.sample_rate,
+                title: track
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {:02}", track.number)),
+                artists,
+                genres: sheet
+                    .genre
+                    .as_ref()
+                    .map(|g| g.split(',').map(|g| g.trim().to_string()).collect())
+                    .unwrap_or_else(Vec::new),
+                album_title: sheet.title.clone(),
+                album_artists: sheet.performer.iter().cloned().collect(),
+                album_track: Some(track.number as i32),
+                release: sheet.date.as_ref().and_then(|d| d.parse().ok()),
+            });
+        }
+    }
+    Ok(tracks)
+}
+
+/// Splits a virtual path into the path of its CUE sheet and the number of the track.
+pub fn split_path(path: &str) -> Option<(&str, u32)> {
+    let i = path.rfind('#')?;
+    let (cue_path, number) = (&path[..i], &path[i + 1..]);
+    if !cue_path.to_lowercase().ends_with(".cue") {
+        return None;
+    }
+    number.parse().ok().map(|number| (cue_path, number))
+}
@@ -13,10 +13,21 @@ CREATE TABLE "track" (
     "album_disc" INTEGER,
     "album_track" INTEGER,
 
+    -- Tracks of a CUE sheet are a part of an audio file, the image. Their path is the path of the
+    -- sheet followed by '#' and the number of the track.
+    "cue_path" TEXT,
+    "image_path" TEXT,
+    -- The position of the first frame of the track in the image and the position after its last.
+    "image_start" INTEGER,
+    "image_end" INTEGER,
+
     PRIMARY KEY ("path")
         ON CONFLICT REPLACE
 );
 
+CREATE INDEX "track_cue_path" ON "track"("cue_path");
+CREATE INDEX "track_image_path" ON "track"("image_path");
+
 -- A track has zero or more artists.
 CREATE TABLE "track_artist" (
     "track_path" TEXT NOT NULL,
@@ -40,3 +51,52 @@ CREATE TABLE "track_genre" (
     FOREIGN KEY ("track_path") REFERENCES "track"("path")
         ON UPDATE CASCADE ON DELETE CASCADE
 );
+
+-- Named positions in a track. Unlike the rest of the index, cue points are set by the user, so they
+-- are kept when the track is updated.
+CREATE TABLE "track_cue" (
+    "track_path" TEXT NOT NULL,
+    "name" TEXT NOT NULL,
+    -- The position in frames at the sample rate of the track.
+    "position" INTEGER NOT NULL,
+
+    PRIMARY KEY ("track_path", "name")
+        ON CONFLICT REPLACE,
+    FOREIGN KEY ("track_path") REFERENCES "track"("path")
+        ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+-- The loudness, tempo, key and waveform of a track. The loudness is either read from its
+-- ReplayGain tags or measured. Tracks are analysed again when they have been modified since.
+CREATE TABLE "track_analysis" (
+    "track_path" TEXT NOT NULL,
+    "modified_at" INTEGER NOT NULL,
+    "source" TEXT NOT NULL,
+
+    -- The ReplayGain in dB and the linear true peak. The gain is NULL for silent tracks. All
+    -- results are NULL if the source is 'error', which marks a track that could not be analysed.
+    "track_gain" REAL,
+    "track_peak" REAL,
+    "album_gain" REAL,
+    "album_peak" REAL,
+    -- The histogram of the loudness of the measured gating blocks, from which the loudness of
+    -- an album is computed without analysing its tracks again.
+    "loudness_histogram" BLOB,
+    -- The tempo in beats per minute, the time in seconds of the first beat and the key like
+    -- "A minor", NULL if they could not be determined.
+    "bpm" REAL,
+    "beat_offset" REAL,
+    "key" TEXT,
+    -- The waveform overview with the level of the low, mid and high band, as encoded by
+    -- `analysis::waveform::Overview::to_bytes`. Levels are stored on a dB scale.
+    "waveform" BLOB,
+
+    PRIMARY KEY ("track_path")
+        ON CONFLICT REPLACE,
+    FOREIGN KEY ("track_path") REFERENCES "track"("path")
+        ON UPDATE CASCADE ON DELETE CASCADE,
+    CHECK ("source" IN ('tag', 'scan', 'error'))
+);
+
+CREATE INDEX "track_analysis_bpm" ON "track_analysis"("bpm");
+CREATE INDEX "track_analysis_key" ON "track_analysis"("key");
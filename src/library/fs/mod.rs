@@ -1,4 +1,7 @@
-use crate::format;
+use crate::analysis::key::Key;
+use crate::analysis::waveform;
+use crate::audio::{looper, SeekError};
+use crate::format::{self, cue};
 use crate::library::{self, Library, Track, TrackInfo};
 use log::*;
 use notify::{self, Watcher};
@@ -9,6 +12,8 @@ use std::sync::mpsc;
 use std::*;
 use xdg;
 
+mod analysis;
+mod cuesheet;
 mod playlist;
 mod track;
 use self::track::*;
@@ -75,6 +80,19 @@ impl Filesystem {
             db: sync::Arc::new(sync::Mutex::new(db)),
         };
 
+        // Analysis is done in a separate thread because it requires the audio of all tracks to be
+        // decoded. Any message on the channel signals that the index has been updated.
+        let (analysis_tx, analysis_rx) = mpsc::channel::<()>();
+        let db_weak = sync::Arc::downgrade(&fs.db);
+        thread::spawn(move || {
+            while analysis_rx.recv().is_ok() {
+                while analysis_rx.try_recv().is_ok() {}
+                if let Err(err) = analysis::analyse_outdated(&db_weak) {
+                    error!("error analysing tracks: {}", err);
+                }
+            }
+        });
+
         let root = fs.root.clone();
         let db_weak = sync::Arc::downgrade(&fs.db);
         thread::spawn(move || {
@@ -93,6 +111,7 @@ impl Filesystem {
                 }
                 debug!("done updating index in {:?}", update_start.elapsed());
             }
+            let _ = analysis_tx.send(());
 
             let (tx, rx) = mpsc::channel();
             let mut watcher: notify::RecommendedWatcher =
@@ -160,6 +179,7 @@ impl Filesystem {
                     notify::DebouncedEvent::Rescan => (),
                     notify::DebouncedEvent::Error(_, _) => (),
                 }
+                let _ = analysis_tx.send(());
             }
         });
 
@@ -171,45 +191,140 @@ impl Filesystem {
         &self,
         path: &path::Path,
     ) -> Result<Option<sync::Arc<Track>>, Box<error::Error>> {
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+        // Only the CUE sheet of a virtual track exists on disk.
+        let (path, number) = match cuesheet::split_path(path_str) {
+            Some((cue_path, number)) => (path::Path::new(cue_path), Some(number)),
+            None => (path, None),
+        };
         let path = if path.is_absolute() {
             path.canonicalize()?
         } else {
             self.root.join(path).canonicalize()?
         };
-        let id = path
+        let path_str = path
             .to_str()
             .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+        let id = match number {
+            Some(number) => format!("{}#{:02}", path_str, number),
+            None => path_str.to_string(),
+        };
         let track = self.tracks()?.find(|track| track.id().1 == id);
         Ok(track)
     }
-}
 
-impl library::Library for Filesystem {
-    fn name(&self) -> Cow<str> {
-        Cow::Owned(format!("file://{}", self.root.to_string_lossy()))
+    /// Returns the analysed tracks of which the tempo lies within the specified range of beats
+    /// per minute, ordered by tempo.
+    pub fn tracks_by_bpm(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        self.query_tracks(
+            r#"WHERE a."bpm" BETWEEN ?1 AND ?2 ORDER BY a."bpm""#,
+            &[&min, &max],
+        )
     }
 
-    fn find_by_id(
+    /// Returns the analysed tracks that are in any of the specified keys, ordered by tempo.
+    pub fn tracks_by_key(
         &self,
-        id: &library::Identity,
-    ) -> Result<Option<library::Audio>, Box<error::Error>> {
-        let (lib, id) = id.id();
-        if lib == self.name() {
-            unimplemented!();
-        }
-        let track = self.track_by_path(path::Path::new(id.as_ref()))?;
-        Ok(track.map(library::Audio::Track))
+        keys: &[Key],
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        let placeholders: Vec<_> = (1..=keys.len()).map(|i| format!("?{}", i)).collect();
+        let params: Vec<&sqlite::types::ToSql> =
+            keys.iter().map(|k| k as &sqlite::types::ToSql).collect();
+        self.query_tracks(
+            &format!(
+                r#"WHERE a."key" IN ({}) ORDER BY a."bpm""#,
+                placeholders.join(", ")
+            ),
+            &params,
+        )
     }
 
-    fn tracks(
+    /// Returns the waveform overview of the track at the path with at most the specified number
+    /// of points, or None if the track has not been analysed since it was last modified.
+    pub fn waveform(
         &self,
-    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        path: &path::Path,
+        num_points: usize,
+    ) -> Result<Option<waveform::Overview>, Error> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
         let db = self.db.lock().unwrap();
-        let mut stmt_tracks = db.prepare(
+        let mut stmt = db.prepare(
+            r#"
+            SELECT a."waveform"
+            FROM "track_analysis" AS a
+            JOIN "track" AS t ON t."path" = a."track_path" AND t."modified_at" = a."modified_at"
+            WHERE a."track_path" = ?1
+        "#,
+        )?;
+        let blob = match stmt
+            .query_map(&[&path], |row| row.get::<_, Option<Vec<u8>>>(0))?
+            .next()
+        {
+            Some(blob) => blob?,
+            None => return Ok(None),
+        };
+        Ok(blob
+            .and_then(|blob| waveform::Overview::from_bytes(&blob))
+            .map(|overview| overview.resample(num_points)))
+    }
+
+    /// Stores a cue point of the track at the path, replacing any cue point with the same name.
+    pub fn set_cue(&self, path: &path::Path, cue: &looper::Cue) -> Result<(), Error> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+        self.db.lock().unwrap().execute(
+            r#"
+            INSERT INTO "track_cue" ("track_path", "name", "position")
+            VALUES (?1, ?2, ?3)
+        "#,
+            &[&path, &cue.name, &(cue.position as i64)],
+        )?;
+        Ok(())
+    }
+
+    /// Removes the cue point with the specified name from the track at the path.
+    pub fn remove_cue(&self, path: &path::Path, name: &str) -> Result<(), Error> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+        self.db.lock().unwrap().execute(
             r#"
-           SELECT * FROM "track"
+            DELETE FROM "track_cue"
+            WHERE "track_path" = ?1 AND "name" = ?2
         "#,
+            &[&path, &name],
         )?;
+        Ok(())
+    }
+
+    /// Selects tracks together with their artists, genres and analysis. The condition is appended
+    /// to the query of the track table, which is joined with the analysis as `a`.
+    fn query_tracks(
+        &self,
+        condition: &str,
+        params: &[&sqlite::types::ToSql],
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        let db = self.db.lock().unwrap();
+        let mut stmt_tracks = db.prepare(&format!(
+            r#"
+           SELECT "track".*, a."track_gain", a."track_peak", a."album_gain", a."album_peak",
+                  a."bpm", a."beat_offset", a."key"
+           FROM "track"
+           LEFT JOIN "track_analysis" AS a
+           ON a."track_path" = "track"."path" AND a."modified_at" = "track"."modified_at"
+           {}
+        "#,
+            condition
+        ))?;
         let mut stmt_artists = db.prepare(
             r#"
            SELECT "name", "type" FROM "track_artist"
@@ -222,8 +337,15 @@ impl library::Library for Filesystem {
            WHERE "track_path" = ?1
         "#,
         )?;
+        let mut stmt_cues = db.prepare(
+            r#"
+           SELECT "name", "position" FROM "track_cue"
+           WHERE "track_path" = ?1
+           ORDER BY "position"
+        "#,
+        )?;
         let tracks: Result<Vec<_>, Box<error::Error>> = stmt_tracks
-            .query_and_then(&[], |row| {
+            .query_and_then(params, |row| {
                 let mut track = RawTrack {
                     path: row.get("path"),
                     modified_at: time::UNIX_EPOCH
@@ -239,6 +361,25 @@ impl library::Library for Filesystem {
                     album_track: row.get("album_track"),
                     rating: row.get("rating"),
                     release: row.get("release"),
+                    replay_gain: row.get::<_, Option<f64>>("track_gain").map(|track_gain| {
+                        library::ReplayGain {
+                            track_gain,
+                            track_peak: row.get("track_peak"),
+                            album_gain: row.get("album_gain"),
+                            album_peak: row.get("album_peak"),
+                        }
+                    }),
+                    bpm: row.get("bpm"),
+                    beat_offset: row.get("beat_offset"),
+                    key: row.get("key"),
+                    cues: vec![],
+                    image: row
+                        .get::<_, Option<String>>("image_path")
+                        .map(|path| cuesheet::Image {
+                            path,
+                            start: row.get::<_, i64>("image_start") as u64,
+                            end: row.get::<_, i64>("image_end") as u64,
+                        }),
                 };
                 let artists = stmt_artists
                     .query_map(&[&track.path], |row| (row.get("name"), row.get("type")))?;
@@ -254,6 +395,13 @@ impl library::Library for Filesystem {
                 for genre in stmt_genres.query_map(&[&track.path], |row| row.get("genre"))? {
                     track.genres.push(genre?);
                 }
+                let cues = stmt_cues.query_map(&[&track.path], |row| looper::Cue {
+                    name: row.get("name"),
+                    position: row.get::<_, i64>("position") as u64,
+                })?;
+                for cue in cues {
+                    track.cues.push(cue?);
+                }
                 Ok(track)
             })?
             .collect(); // TODO: Stream results instead of collecting.
@@ -263,6 +411,57 @@ impl library::Library for Filesystem {
     }
 }
 
+impl library::Library for Filesystem {
+    fn name(&self) -> Cow<str> {
+        Cow::Owned(format!("file://{}", self.root.to_string_lossy()))
+    }
+
+    fn find_by_id(
+        &self,
+        id: &library::Identity,
+    ) -> Result<Option<library::Audio>, Box<error::Error>> {
+        let (lib, id) = id.id();
+        if lib == self.name() {
+            unimplemented!();
+        }
+        let track = self.track_by_path(path::Path::new(id.as_ref()))?;
+        Ok(track.map(library::Audio::Track))
+    }
+
+    fn tracks(
+        &self,
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        self.query_tracks("", &[])
+    }
+
+    fn tracks_by_bpm(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        Filesystem::tracks_by_bpm(self, min, max)
+    }
+
+    fn tracks_by_key(
+        &self,
+        keys: &[Key],
+    ) -> Result<Box<iter::Iterator<Item = sync::Arc<library::Track>>>, Box<error::Error>> {
+        Filesystem::tracks_by_key(self, keys)
+    }
+
+    fn waveform(
+        &self,
+        id: &library::Identity,
+        num_points: usize,
+    ) -> Result<Option<waveform::Overview>, Box<error::Error>> {
+        Ok(Filesystem::waveform(
+            self,
+            path::Path::new(id.id().1.as_ref()),
+            num_points,
+        )?)
+    }
+}
+
 /// Creates an ad-hoc track from a path.
 pub fn track_from_path(path: &path::Path) -> Result<sync::Arc<Track>, Error> {
     let metadata = format::decode_metadata_file(path)?;
@@ -296,6 +495,9 @@ fn add_to_index(db: &mut sqlite::Connection, path: &path::Path) -> Result<(), Er
         metadata: &fs::Metadata,
     ) -> Result<(), Error> {
         assert!(metadata.is_file());
+        if is_cue_sheet(path) {
+            return cue_add(db, path, metadata);
+        }
 
         // Check whether the index is outdated by comparing the timestamp of the file with the one
         // in the database.
@@ -317,6 +519,23 @@ fn add_to_index(db: &mut sqlite::Connection, path: &path::Path) -> Result<(), Er
             debug!("skipping (up to date): {}", path.to_string_lossy());
             return Ok(());
         }
+        let is_image = db.query_row(
+            r#"
+            SELECT COUNT(*) AS "num" FROM "track"
+            WHERE "image_path" = ?1
+            LIMIT 1
+        "#,
+            &[&path_str],
+            |row| row.get::<_, bool>("num"),
+        )?;
+        if is_image {
+            // The tracks of the image are indexed through its CUE sheet.
+            debug!(
+                "skipping (image of a cue sheet): {}",
+                path.to_string_lossy()
+            );
+            return Ok(());
+        }
 
         let meta = match format::decode_metadata_file(path) {
             Ok(t) => t,
@@ -336,10 +555,75 @@ fn add_to_index(db: &mut sqlite::Connection, path: &path::Path) -> Result<(), Er
             return Ok(());
         }
         let track = MetadataTrack { path, meta };
-        track_upsert(db, &track)?;
+        track_upsert(db, path_str, &track, None)?;
         debug!("indexed {}", path.to_string_lossy());
         Ok(())
     }
+    fn cue_add(
+        db: &mut sqlite::Connection,
+        path: &path::Path,
+        metadata: &fs::Metadata,
+    ) -> Result<(), Error> {
+        let mtime = Timestamp(metadata.modified()?);
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| Error::BadPath(path.to_path_buf()))?;
+        let up_to_date = db.query_row(
+            r#"
+            SELECT COUNT(*) AS "num" FROM "track"
+            WHERE "cue_path" = ?1
+            AND "modified_at" = ?2
+            LIMIT 1
+        "#,
+            &[&path_str, &mtime],
+            |row| row.get::<_, bool>("num"),
+        )?;
+        if up_to_date {
+            debug!("skipping (up to date): {}", path.to_string_lossy());
+            return Ok(());
+        }
+
+        let tracks = match cuesheet::read(path) {
+            Ok(tracks) => tracks,
+            Err(err) => {
+                error!("skipping ({}): {}", err, path.to_string_lossy());
+                return Ok(());
+            }
+        };
+        // Tracks may have been removed from the sheet since it was last indexed. The others are
+        // updated in place, so their cue points and analysis are kept.
+        let stale: Vec<String> = {
+            let mut stmt = db.prepare(r#"SELECT "path" FROM "track" WHERE "cue_path" = ?1"#)?;
+            let paths = stmt.query_map(&[&path_str], |row| row.get::<_, String>("path"))?;
+            let mut stale = Vec::new();
+            for track_path in paths {
+                let track_path = track_path?;
+                if !tracks.iter().any(|track| track.path == track_path) {
+                    stale.push(track_path);
+                }
+            }
+            stale
+        };
+        for track_path in &stale {
+            track_remove(db, track_path)?;
+        }
+        for track in &tracks {
+            // The image may have been indexed as a regular track before its sheet was.
+            track_remove(db, &track.image.path)?;
+            track_upsert(
+                db,
+                &track.path,
+                track,
+                Some((track.cue_path.as_str(), &track.image)),
+            )?;
+        }
+        debug!(
+            "indexed {} tracks of {}",
+            tracks.len(),
+            path.to_string_lossy()
+        );
+        Ok(())
+    }
 
     let metadata = fs::metadata(path)?;
     if metadata.file_type().is_dir() {
@@ -351,20 +635,22 @@ fn add_to_index(db: &mut sqlite::Connection, path: &path::Path) -> Result<(), Er
     }
 }
 
-fn track_upsert<P>(db: &mut sqlite::Connection, track: &MetadataTrack<P>) -> Result<(), Error>
+/// Inserts or replaces a track. Tracks of a CUE sheet also store the path of the sheet and the
+/// part of the image they consist of.
+fn track_upsert<T>(
+    db: &mut sqlite::Connection,
+    path: &str,
+    track: &T,
+    cue: Option<(&str, &cuesheet::Image)>,
+) -> Result<(), Error>
 where
-    P: AsRef<path::Path> + Send + Sync,
+    T: library::Track + ?Sized,
 {
     let tx = db.transaction()?;
-    let path = track
-        .path
-        .as_ref()
-        .to_str()
-        .ok_or_else(|| Error::BadPath(track.path.as_ref().to_path_buf()))?;
     tx.execute(r#"
         INSERT INTO "track"
-        ("path", "modified_at", "duration", "title", "rating", "release", "album_title", "album_disc", "album_track")
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ("path", "modified_at", "duration", "title", "rating", "release", "album_title", "album_disc", "album_track", "cue_path", "image_path", "image_start", "image_end")
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
     "#, &[
         &path,
         &track.modified_at().map(Timestamp),
@@ -378,6 +664,10 @@ where
             .map(|s| s.as_ref()),
         &track.album_disc(),
         &track.album_track(),
+        &cue.map(|(cue_path, _)| cue_path),
+        &cue.map(|(_, image)| image.path.as_str()),
+        &cue.map(|(_, image)| image.start as i64),
+        &cue.map(|(_, image)| image.end as i64),
     ])?;
     tx.execute(
         r#"
@@ -419,6 +709,20 @@ where
     Ok(())
 }
 
+/// Removes a track along with its artists, genres, cue points and analysis.
+fn track_remove(db: &mut sqlite::Connection, path: &str) -> Result<(), Error> {
+    let tx = db.transaction()?;
+    for table in &["track_artist", "track_genre", "track_cue", "track_analysis"] {
+        tx.execute(
+            &format!(r#"DELETE FROM "{}" WHERE "track_path" = ?1"#, table),
+            &[&path],
+        )?;
+    }
+    tx.execute(r#"DELETE FROM "track" WHERE "path" = ?1"#, &[&path])?;
+    tx.commit()?;
+    Ok(())
+}
+
 fn track_clean_recursive(db: &sqlite::Connection, path: &path::Path) -> Result<(), Error> {
     let path_str = path
         .to_str()
@@ -427,13 +731,36 @@ fn track_clean_recursive(db: &sqlite::Connection, path: &path::Path) -> Result<(
     db.execute(
         r#"
         DELETE FROM "track"
-        WHERE "path" LIKE ?1 AND NOT file_exists("path")
+        WHERE "path" LIKE ?1 AND (
+            NOT file_exists(COALESCE("cue_path", "path"))
+            OR ("image_path" IS NOT NULL AND NOT file_exists("image_path"))
+        )
     "#,
         &[&(path_str + "%")],
     )?;
+    db.execute(
+        r#"
+        DELETE FROM "track_analysis"
+        WHERE "track_path" NOT IN (SELECT "path" FROM "track")
+    "#,
+        &[],
+    )?;
+    db.execute(
+        r#"
+        DELETE FROM "track_cue"
+        WHERE "track_path" NOT IN (SELECT "path" FROM "track")
+    "#,
+        &[],
+    )?;
     Ok(())
 }
 
+fn is_cue_sheet(path: &path::Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("cue"))
+        .unwrap_or(false)
+}
+
 fn init_db_functions(db: &mut sqlite::Connection) -> Result<(), Error> {
     db.create_scalar_function("file_exists", 1, false, |ctx| {
         let path = ctx.get::<String>(0)?;
@@ -465,6 +792,8 @@ impl sqlite::types::ToSql for Timestamp {
 #[derive(Debug)]
 pub enum Error {
     Format(format::Error),
+    Cue(cue::Error),
+    Seek(SeekError),
     IO(io::Error),
     Sqlite(sqlite::Error),
     Xdg(xdg::BaseDirectoriesError),
@@ -477,6 +806,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::Format(ref err) => write!(f, "Format: {}", err),
+            Error::Cue(ref err) => write!(f, "CUE sheet: {}", err),
+            Error::Seek(ref err) => write!(f, "Seek: {}", err),
             Error::IO(ref err) => write!(f, "IO: {}", err),
             Error::Sqlite(ref err) => write!(f, "Sqlite: {}", err),
             Error::Xdg(ref err) => write!(f, "Xdg: {}", err),
@@ -499,6 +830,8 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::Format(ref err) => Some(err),
+            Error::Cue(ref err) => Some(err),
+            Error::Seek(ref err) => Some(err),
             Error::IO(ref err) => Some(err),
             Error::Sqlite(ref err) => Some(err),
             Error::Xdg(ref err) => Some(err),
@@ -513,6 +846,18 @@ impl From<format::Error> for Error {
     }
 }
 
+impl From<cue::Error> for Error {
+    fn from(err: cue::Error) -> Error {
+        Error::Cue(err)
+    }
+}
+
+impl From<SeekError> for Error {
+    fn from(err: SeekError) -> Error {
+        Error::Seek(err)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::IO(err)
@@ -534,10 +879,13 @@ impl From<xdg::BaseDirectoriesError> for Error {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::analysis::key;
+    use crate::audio::{dynam, Seekable};
     use crate::library::{Library, Playlist};
     use std::sync::{Arc, Mutex};
 
     const ALBUM: &'static str = "testdata/Various Artists - Dark Sine of the Moon";
+    const CUE_ALBUM: &'static str = "testdata/cue";
 
     fn db() -> sqlite::Connection {
         let mut db = sqlite::Connection::open_in_memory().unwrap();
@@ -546,6 +894,15 @@ mod tests {
         db
     }
 
+    /// Creates a filesystem of which the index has been built before it is returned, so tests do
+    /// not have to wait for the initial scan.
+    fn indexed(root: &str) -> Filesystem {
+        let root = path::Path::new(root).canonicalize().unwrap();
+        let mut db = db();
+        add_to_index(&mut db, &root).unwrap();
+        Filesystem::with_db(db, &root).unwrap()
+    }
+
     #[test]
     fn db_schema() {
         let db = sqlite::Connection::open_in_memory().unwrap();
@@ -601,8 +958,7 @@ mod tests {
 
     #[test]
     fn track_index_conversion() {
-        let fs = Filesystem::with_db(db(), path::Path::new(ALBUM)).unwrap();
-        thread::sleep(time::Duration::from_secs(1)); // Await initial scan.
+        let fs = indexed(ALBUM);
         let file = "01 - The B-Trees - Lucy in the Cloud with Sine Waves.flac";
         let pt = track_from_path(
             &path::Path::new("testdata/Various Artists - Dark Sine of the Moon").join(file),
@@ -659,17 +1015,216 @@ mod tests {
         );
     }
 
+    #[test]
+    fn analyse_failure() {
+        let db = db();
+        db.execute(
+            r#"
+            INSERT INTO "track"
+            ("path", "modified_at", "duration", "title")
+            VALUES ('/home/user/non_existing.file', 1337, 42, 'Dummy')
+        "#,
+            &[],
+        )
+        .unwrap();
+        let db = Arc::new(Mutex::new(db));
+        analysis::analyse_outdated(&Arc::downgrade(&db)).unwrap();
+        let outdated = |db: &sqlite::Connection| -> i64 {
+            db.query_row(
+                r#"
+                SELECT COUNT(*) FROM "track" AS t
+                LEFT JOIN "track_analysis" AS a ON a."track_path" = t."path"
+                WHERE a."modified_at" IS NOT t."modified_at"
+            "#,
+                &[],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let source: String = db
+            .lock()
+            .unwrap()
+            .query_row(r#"SELECT "source" FROM "track_analysis""#, &[], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!("error", source);
+        assert_eq!(0, outdated(&db.lock().unwrap()));
+
+        // The track is analysed again once it has been modified.
+        db.lock()
+            .unwrap()
+            .execute(r#"UPDATE "track" SET "modified_at" = 1338"#, &[])
+            .unwrap();
+        assert_eq!(1, outdated(&db.lock().unwrap()));
+    }
+
+    #[test]
+    fn analyse_tracks() {
+        let fs = indexed(ALBUM);
+        analysis::analyse_outdated(&Arc::downgrade(&fs.db)).unwrap();
+        let num_analysed: i64 = fs
+            .db
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM \"track_analysis\"", &[], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(3, num_analysed);
+
+        let tracks: Vec<_> = fs.tracks().unwrap().collect();
+        for track in tracks {
+            let rg = track.replay_gain().unwrap();
+            assert!(rg.track_peak > 0.0 && rg.track_peak < 2.0);
+            assert!(rg.album_gain.is_some());
+        }
+
+        let track = fs.tracks().unwrap().next().unwrap();
+        let overview = fs
+            .waveform(path::Path::new(track.id().1.as_ref()), 100)
+            .unwrap()
+            .unwrap();
+        assert_eq!(100, overview.points.len());
+        assert_eq!(100, overview.bands.unwrap().len());
+        assert!(overview.points.iter().all(|p| p.min < 0.0 && p.max > 0.0));
+
+        fs.db
+            .lock()
+            .unwrap()
+            .execute(
+                r#"UPDATE "track_analysis" SET "bpm" = 100 + rowid, "key" = 'A minor'"#,
+                &[],
+            )
+            .unwrap();
+        let bpms: Vec<_> = fs
+            .tracks_by_bpm(101.5, 103.0)
+            .unwrap()
+            .map(|track| track.bpm().unwrap())
+            .collect();
+        assert_eq!(vec![102.0, 103.0], bpms);
+        let a_minor = Key::new(9, key::Mode::Minor);
+        let c_major = Key::new(0, key::Mode::Major);
+        assert!(fs.tracks().unwrap().all(|t| t.key() == Some(a_minor)));
+        assert_eq!(3, fs.tracks_by_key(&c_major.compatible()).unwrap().count());
+        // The search is available to users of the trait.
+        let lib: &Library = &fs;
+        assert_eq!(2, lib.tracks_by_bpm(101.5, 103.0).unwrap().count());
+        assert_eq!(3, lib.tracks_by_key(&[a_minor]).unwrap().count());
+        assert_eq!(0, fs.tracks_by_key(&[c_major]).unwrap().count());
+    }
+
     #[test]
     fn query_tracks() {
-        let fs = Filesystem::with_db(db(), path::Path::new(ALBUM)).unwrap();
-        thread::sleep(time::Duration::from_secs(1)); // Await initial scan.
+        let fs = indexed(ALBUM);
+        assert_eq!(3, fs.tracks().unwrap().count());
+    }
+
+    #[test]
+    fn cues() {
+        let fs = indexed(ALBUM);
+        let track = fs.tracks().unwrap().next().unwrap();
+        let path = path::PathBuf::from(library::Identity::id(&*track).1.as_ref());
+        let drop = looper::Cue {
+            name: "drop".to_string(),
+            position: 44100,
+        };
+        fs.set_cue(&path, &drop).unwrap();
+        fs.set_cue(
+            &path,
+            &looper::Cue {
+                name: "intro".to_string(),
+                position: 0,
+            },
+        )
+        .unwrap();
+        fs.remove_cue(&path, "intro").unwrap();
+
+        let track = fs.track_by_path(&path).unwrap().unwrap();
+        assert_eq!(vec![drop], track.cues());
+    }
+
+    #[test]
+    fn cue_sheet_tracks() {
+        let fs = indexed(CUE_ALBUM);
+        let mut tracks: Vec<_> = fs.tracks().unwrap().collect();
+        tracks.sort_by_key(|t| t.album_track());
+        // The image itself is not indexed as a track.
+        assert_eq!(3, tracks.len());
+        assert_eq!("Intro", tracks[0].title());
+        assert_eq!(vec!["Various Artists"], tracks[0].artists().into_owned());
+        assert_eq!(vec!["The B-Trees"], tracks[1].artists().into_owned());
+        assert_eq!(
+            Some("Sine Session"),
+            tracks[1].album_title().as_ref().map(|s| s.as_ref())
+        );
+        assert_eq!(vec!["Electronic"], tracks[1].genres().into_owned());
+        assert_eq!(Some(3), tracks[2].album_track());
+
+        // 00:03:00 to 00:07:30 is 4.5 seconds.
+        let track = &tracks[1];
+        assert_eq!(4, track.duration().as_secs());
+        let mut audio = match track.audio().unwrap() {
+            dynam::Seek::MonoI16(s) => s,
+            _ => panic!("unexpected format"),
+        };
+        assert_eq!(198_450, audio.length());
+        let mut image = match format::decode_file("testdata/cue/image.flac")
+            .unwrap()
+            .0
+            .into_seek()
+        {
+            Some(dynam::Seek::MonoI16(s)) => s,
+            _ => panic!("unexpected format"),
+        };
+        audio.seek(1000).unwrap();
+        image.seek(132_300 + 1000).unwrap();
+        assert_eq!(image.next(), audio.next());
+        assert_eq!(1001, audio.current_position());
+        assert_eq!(198_450 - 1001, audio.count());
+
+        let id = library::Identity::id(&**track).1.into_owned();
+        assert!(id.ends_with("album.cue#02"));
+        let found = fs.track_by_path(path::Path::new("album.cue#02")).unwrap();
+        assert_eq!(
+            Some(id),
+            found.map(|t| library::Identity::id(&*t).1.into_owned())
+        );
+
+        // Indexing the sheet again keeps the cue points of its tracks and removes the tracks that
+        // are no longer listed.
+        let drop = looper::Cue {
+            name: "drop".to_string(),
+            position: 44100,
+        };
+        fs.set_cue(path::Path::new(&id), &drop).unwrap();
+        let sheet = fs.root.join("album.cue");
+        {
+            let mut db = fs.db.lock().unwrap();
+            let sheet_str = sheet.to_str().unwrap();
+            db.execute(
+                r#"
+                INSERT INTO "track" ("path", "modified_at", "duration", "title", "cue_path")
+                VALUES (?1 || '#09', 0, 1, 'Removed', ?1)
+            "#,
+                &[&sheet_str],
+            )
+            .unwrap();
+            db.execute(
+                r#"UPDATE "track" SET "modified_at" = 0 WHERE "cue_path" = ?1"#,
+                &[&sheet_str],
+            )
+            .unwrap();
+            add_to_index(&mut db, &sheet).unwrap();
+        }
         assert_eq!(3, fs.tracks().unwrap().count());
+        let track = fs.track_by_path(path::Path::new(&id)).unwrap().unwrap();
+        assert_eq!(vec![drop], track.cues());
     }
 
     #[test]
     fn playlist_read() {
-        let fs = Filesystem::with_db(db(), path::Path::new(ALBUM)).unwrap();
-        thread::sleep(time::Duration::from_secs(1)); // Await initial scan.
+        let fs = indexed(ALBUM);
         let fs = Arc::new(Mutex::new(fs));
         let playlist = playlist::Playlist {
             file: "testdata/Various Artists - Dark Sine of the Moon/00 - playlist.m3u".to_string(),
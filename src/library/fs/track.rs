@@ -1,4 +1,6 @@
+use super::cuesheet;
 use super::Error;
+use crate::analysis::key::Key;
 use crate::audio::*;
 use crate::format;
 use crate::library;
@@ -22,6 +24,13 @@ pub struct RawTrack {
     pub album_track: Option<i32>,
     pub rating: Option<u8>,
     pub release: Option<library::Release>,
+    pub replay_gain: Option<library::ReplayGain>,
+    pub bpm: Option<f64>,
+    pub beat_offset: Option<f64>,
+    pub key: Option<Key>,
+    pub cues: Vec<looper::Cue>,
+    /// Set if the track is part of the image of a CUE sheet.
+    pub image: Option<cuesheet::Image>,
 }
 
 impl library::Identity for RawTrack {
@@ -70,6 +79,18 @@ impl library::TrackInfo for RawTrack {
     fn release(&self) -> Option<library::Release> {
         self.release.clone()
     }
+
+    fn bpm(&self) -> Option<f64> {
+        self.bpm
+    }
+
+    fn beat_offset(&self) -> Option<f64> {
+        self.beat_offset
+    }
+
+    fn key(&self) -> Option<Key> {
+        self.key
+    }
 }
 
 impl library::Track for RawTrack {
@@ -78,6 +99,9 @@ impl library::Track for RawTrack {
     }
 
     fn audio(&self) -> Result<dynam::Seek, Box<error::Error>> {
+        if let Some(ref image) = self.image {
+            return Ok(image.audio()?);
+        }
         let (decoder, _) = format::decode_file(path::Path::new(&self.path))?;
         decoder.into_seek().ok_or_else(|| Box::from(Error::NonSeek))
     }
@@ -85,6 +109,14 @@ impl library::Track for RawTrack {
     fn duration(&self) -> time::Duration {
         self.duration
     }
+
+    fn replay_gain(&self) -> Option<library::ReplayGain> {
+        self.replay_gain
+    }
+
+    fn cues(&self) -> Vec<looper::Cue> {
+        self.cues.clone()
+    }
 }
 
 pub struct MetadataTrack<P>
@@ -259,6 +291,38 @@ where
         let num_samples = self.meta.num_samples.expect("Unkown number of samples");
         duration_of(self.meta.sample_rate, num_samples)
     }
+
+    fn replay_gain(&self) -> Option<library::ReplayGain> {
+        self.meta.tag.as_ref().and_then(replay_gain_from_tag)
+    }
+}
+
+/// Reads the ReplayGain from the TXXX frames of a tag. The track gain must be present for the tag
+/// to be used. A missing peak is assumed to be at full scale.
+pub fn replay_gain_from_tag(tag: &id3::Tag) -> Option<library::ReplayGain> {
+    let value = |key: &str| -> Option<f64> {
+        tag.frames()
+            .filter(|frame| frame.id() == "TXXX")
+            .filter_map(|frame| frame.content().extended_text())
+            .find(|ext| ext.key.eq_ignore_ascii_case(key))
+            .and_then(|ext| {
+                let value = ext.value.trim();
+                let value = if value.to_lowercase().ends_with("db") {
+                    &value[..value.len() - 2]
+                } else {
+                    value
+                };
+                value.trim().parse().ok()
+            })
+    };
+    let track_gain = value("REPLAYGAIN_TRACK_GAIN")?;
+    let album_gain = value("REPLAYGAIN_ALBUM_GAIN");
+    Some(library::ReplayGain {
+        track_gain,
+        track_peak: value("REPLAYGAIN_TRACK_PEAK").unwrap_or(1.0),
+        album_gain,
+        album_peak: album_gain.map(|_| value("REPLAYGAIN_ALBUM_PEAK").unwrap_or(1.0)),
+    })
 }
 
 #[cfg(test)]
@@ -302,4 +366,27 @@ mod tests {
         assert_eq!(vec!["Darude"], track.artists().into_owned());
         assert_eq!(Some(1), track.album_track());
     }
+
+    #[test]
+    fn test_replay_gain() {
+        let mut tag = id3::Tag::new();
+        for (key, value) in &[
+            ("REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+            ("replaygain_track_peak", "0.988"),
+            ("REPLAYGAIN_ALBUM_GAIN", "-7.10dB"),
+        ] {
+            let content = id3::frame::Content::ExtendedText(id3::frame::ExtendedText {
+                key: key.to_string(),
+                value: value.to_string(),
+            });
+            tag.add_frame(id3::Frame::with_content("TXXX", content));
+        }
+        let rg = replay_gain_from_tag(&tag).unwrap();
+        assert_eq!(-6.54, rg.track_gain);
+        assert_eq!(0.988, rg.track_peak);
+        assert_eq!(Some(-7.10), rg.album_gain);
+        assert_eq!(Some(1.0), rg.album_peak);
+
+        assert_eq!(None, replay_gain_from_tag(&id3::Tag::new()));
+    }
 }
@@ -0,0 +1,688 @@
+//!
+//! Internet radio as served by Icecast and SHOUTcast servers.
+//!
+//! Streams are requested over plain HTTP with ICY metadata enabled, in which case the server
+//! interleaves the title of the song that is playing with the audio.
+//!
+
+use crate::audio::*;
+use crate::format::{mp3, vorbis};
+use crate::library;
+use log::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::*;
+
+/// The maximum number of redirects that are followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+/// The default number of consecutive attempts to reconnect after the connection dropped.
+const MAX_RECONNECTS: u32 = 5;
+/// The time to wait before reconnecting, which is multiplied by the number of the attempt.
+const RECONNECT_DELAY: time::Duration = time::Duration::from_secs(1);
+/// A server that does not send anything for this long is considered to be gone.
+const READ_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// The name of the library to which internet radio streams belong.
+pub const LIBRARY_NAME: &str = "radio";
+
+/// An internet radio stream.
+#[derive(Clone, Debug)]
+pub struct IcyStream {
+    pub url: String,
+    pub title: String,
+    /// The number of consecutive attempts to reconnect after the connection dropped.
+    pub max_reconnects: u32,
+}
+
+impl IcyStream {
+    pub fn new(url: impl Into<String>, title: impl Into<String>) -> IcyStream {
+        IcyStream {
+            url: url.into(),
+            title: title.into(),
+            max_reconnects: MAX_RECONNECTS,
+        }
+    }
+}
+
+impl library::Identity for IcyStream {
+    fn id(&self) -> (Cow<str>, Cow<str>) {
+        (Cow::Borrowed(LIBRARY_NAME), Cow::Borrowed(&self.url))
+    }
+}
+
+impl library::Stream for IcyStream {
+    fn title(&self) -> Cow<str> {
+        Cow::Borrowed(&self.title)
+    }
+
+    fn open(
+        &self,
+        on_info: Arc<Fn(Option<Box<library::TrackInfo + Send>>) + Send + Sync>,
+    ) -> Result<dynam::Source, Box<error::Error>> {
+        let response = connect(&self.url)?;
+        let content_type = response
+            .headers
+            .get("content-type")
+            .map(|t| t.split(';').next().unwrap().trim().to_lowercase())
+            .unwrap_or_else(|| "audio/mpeg".to_string());
+        let station = response
+            .headers
+            .get("icy-name")
+            .cloned()
+            .unwrap_or_else(|| self.title.clone());
+        let genres: Vec<String> = response
+            .headers
+            .get("icy-genre")
+            .map(|g| {
+                g.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let on_title: Arc<Fn(&str) + Send + Sync> = {
+            let on_info = on_info.clone();
+            let station = station.clone();
+            let genres = genres.clone();
+            Arc::new(move |title| {
+                let info = IcyInfo::from_stream_title(title, &station, &genres);
+                on_info(info.map(|i| Box::new(i) as Box<library::TrackInfo + Send>));
+            })
+        };
+        let metaint = response.metaint();
+        let conn = Connection {
+            url: self.url.clone(),
+            max_reconnects: self.max_reconnects,
+            reader: Some(IcyReader::new(response.reader, metaint, on_title.clone())),
+            on_title,
+        };
+
+        match content_type.as_str() {
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" => Ok(mp3::decode_stream(conn)?),
+            "application/ogg" | "audio/ogg" | "audio/vorbis" | "audio/x-vorbis+ogg" => {
+                let on_comments = Arc::new(move |comments: &[(String, String)]| {
+                    let info = IcyInfo::from_comments(comments, &station, &genres);
+                    on_info(info.map(|i| Box::new(i) as Box<library::TrackInfo + Send>));
+                });
+                Ok(vorbis::decode_stream(conn, on_comments)?)
+            }
+            _ => Err(Box::from(Error::UnsupportedContentType(content_type))),
+        }
+    }
+}
+
+/// Information about the song that is being played by a radio station.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IcyInfo {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
+    /// The name of the radio station.
+    pub station: String,
+}
+
+impl IcyInfo {
+    /// Parses a `StreamTitle`, which is conventionally formatted as "Artist - Title". Returns
+    /// None if the title is empty, which stations use to indicate that nothing is known.
+    fn from_stream_title(stream_title: &str, station: &str, genres: &[String]) -> Option<IcyInfo> {
+        let stream_title = stream_title.trim();
+        if stream_title.is_empty() {
+            return None;
+        }
+        let (artists, title) = match stream_title.find(" - ") {
+            Some(i) => (
+                vec![stream_title[..i].trim().to_string()],
+                stream_title[i + 3..].trim().to_string(),
+            ),
+            None => (Vec::new(), stream_title.to_string()),
+        };
+        Some(IcyInfo {
+            title,
+            artists,
+            genres: genres.to_vec(),
+            station: station.to_string(),
+        })
+    }
+
+    /// Reads the title and artists from Vorbis comments.
+    fn from_comments(
+        comments: &[(String, String)],
+        station: &str,
+        genres: &[String],
+    ) -> Option<IcyInfo> {
+        let values = |key: &str| -> Vec<String> {
+            comments
+                .iter()
+                .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, v)| v.clone())
+                .collect()
+        };
+        let title = values("TITLE").into_iter().next()?;
+        Some(IcyInfo {
+            title,
+            artists: values("ARTIST"),
+            genres: genres.to_vec(),
+            station: station.to_string(),
+        })
+    }
+}
+
+impl library::TrackInfo for IcyInfo {
+    fn title(&self) -> Cow<str> {
+        Cow::Borrowed(&self.title)
+    }
+
+    fn artists(&self) -> Cow<[String]> {
+        Cow::Borrowed(&self.artists)
+    }
+
+    fn remixers(&self) -> Cow<[String]> {
+        Cow::Owned(Vec::new())
+    }
+
+    fn genres(&self) -> Cow<[String]> {
+        Cow::Borrowed(&self.genres)
+    }
+
+    fn album_title(&self) -> Option<Cow<str>> {
+        None
+    }
+
+    fn album_artists(&self) -> Cow<[String]> {
+        Cow::Owned(Vec::new())
+    }
+
+    fn album_disc(&self) -> Option<i32> {
+        None
+    }
+
+    fn album_track(&self) -> Option<i32> {
+        None
+    }
+
+    fn rating(&self) -> Option<u8> {
+        None
+    }
+
+    fn release(&self) -> Option<library::Release> {
+        None
+    }
+}
+
+struct Response {
+    reader: io::BufReader<TcpStream>,
+    /// The headers of the response by lowercase name.
+    headers: BTreeMap<String, String>,
+}
+
+impl Response {
+    /// Returns the number of bytes of audio between metadata blocks, if the server sends
+    /// metadata.
+    fn metaint(&self) -> Option<usize> {
+        self.headers
+            .get("icy-metaint")
+            .and_then(|m| m.parse().ok())
+            .filter(|&m| m > 0)
+    }
+}
+
+/// Requests the stream at the specified URL, following redirects.
+fn connect(url: &str) -> Result<Response, Error> {
+    let mut url = Url::parse(url)?;
+    for _ in 0..MAX_REDIRECTS {
+        let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        write!(
+            &stream,
+            "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: audio-thing\r\nIcy-MetaData: 1\r\n\r\n",
+            url.path, url.host
+        )?;
+
+        let mut reader = io::BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line)?;
+        // SHOUTcast responds with "ICY 200 OK" instead of an HTTP status line.
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Error::BadResponse(status_line.trim().to_string()))?;
+
+        let mut headers = BTreeMap::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(i) = line.find(':') {
+                let name = line[..i].trim().to_lowercase();
+                headers.insert(name, line[i + 1..].trim().to_string());
+            }
+        }
+
+        match status {
+            200 => return Ok(Response { reader, headers }),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = headers
+                    .get("location")
+                    .ok_or_else(|| Error::BadResponse(status_line.trim().to_string()))?;
+                url = url.join(location)?;
+            }
+            _ => return Err(Error::Status(status)),
+        }
+    }
+    Err(Error::TooManyRedirects)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Url {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, Error> {
+        let rest = match url.find("://") {
+            Some(i) if url[..i].eq_ignore_ascii_case("http") => &url[i + 3..],
+            _ => return Err(Error::BadUrl(url.to_string())),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) => {
+                let port = authority[i + 1..]
+                    .parse()
+                    .map_err(|_| Error::BadUrl(url.to_string()))?;
+                (&authority[..i], port)
+            }
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(Error::BadUrl(url.to_string()));
+        }
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Resolves the location of a redirect, which may be relative to this URL.
+    fn join(&self, location: &str) -> Result<Url, Error> {
+        if location.starts_with('/') {
+            Ok(Url {
+                path: location.to_string(),
+                ..self.clone()
+            })
+        } else {
+            Url::parse(location)
+        }
+    }
+}
+
+/// IcyReader strips the metadata that is interleaved with the audio and passes the stream title
+/// in it to a callback.
+struct IcyReader<R> {
+    input: R,
+    metaint: Option<usize>,
+    /// The number of bytes of audio until the next metadata block.
+    until_meta: usize,
+    on_title: Arc<Fn(&str) + Send + Sync>,
+}
+
+impl<R> IcyReader<R>
+where
+    R: Read,
+{
+    fn new(input: R, metaint: Option<usize>, on_title: Arc<Fn(&str) + Send + Sync>) -> Self {
+        IcyReader {
+            input,
+            metaint,
+            until_meta: metaint.unwrap_or(0),
+            on_title,
+        }
+    }
+
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let mut len = [0; 1];
+        self.input.read_exact(&mut len)?;
+        if len[0] == 0 {
+            return Ok(());
+        }
+        let mut meta = vec![0; usize::from(len[0]) * 16];
+        self.input.read_exact(&mut meta)?;
+        if let Some(title) = parse_stream_title(&meta) {
+            (self.on_title)(&title);
+        }
+        Ok(())
+    }
+}
+
+impl<R> Read for IcyReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let metaint = match self.metaint {
+            Some(metaint) => metaint,
+            None => return self.input.read(buf),
+        };
+        if self.until_meta == 0 {
+            self.read_metadata()?;
+            self.until_meta = metaint;
+        }
+        let len = cmp::min(buf.len(), self.until_meta);
+        let nr = self.input.read(&mut buf[..len])?;
+        self.until_meta -= nr;
+        Ok(nr)
+    }
+}
+
+/// Extracts the value of `StreamTitle` from a metadata block such as
+/// `StreamTitle='Artist - Title';StreamUrl='';`.
+fn parse_stream_title(meta: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(meta);
+    let text = text.trim_end_matches('\0');
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    // Titles may contain quotes, so the value is terminated by the first quote that is followed
+    // by a semicolon.
+    let len = text[start..]
+        .find("';")
+        .or_else(|| text[start..].rfind('\''))?;
+    Some(text[start..start + len].to_string())
+}
+
+/// Connection reads the audio of a stream and transparently reconnects when the connection drops.
+struct Connection {
+    url: String,
+    max_reconnects: u32,
+    reader: Option<IcyReader<io::BufReader<TcpStream>>>,
+    on_title: Arc<Fn(&str) + Send + Sync>,
+}
+
+impl Connection {
+    fn reconnect(&mut self) {
+        self.reader = None;
+        for attempt in 1..=self.max_reconnects {
+            thread::sleep(RECONNECT_DELAY * attempt);
+            match connect(&self.url) {
+                Ok(response) => {
+                    let metaint = response.metaint();
+                    let on_title = self.on_title.clone();
+                    self.reader = Some(IcyReader::new(response.reader, metaint, on_title));
+                    return;
+                }
+                Err(err) => warn!("Reconnecting to {} failed: {}", self.url, err),
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.reader.as_mut().map(|r| r.read(buf)) {
+                Some(Ok(0)) => info!("Stream {} ended, reconnecting", self.url),
+                Some(Ok(nr)) => return Ok(nr),
+                Some(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Some(Err(err)) => warn!("Stream {} dropped, reconnecting: {}", self.url, err),
+                // All attempts to reconnect have failed.
+                None => return Ok(0),
+            }
+            self.reconnect();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(io::Error),
+    Mp3(mp3::Error),
+    Vorbis(vorbis::Error),
+    BadUrl(String),
+    BadResponse(String),
+    Status(u16),
+    TooManyRedirects,
+    UnsupportedContentType(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IO(ref err) => write!(f, "IO: {}", err),
+            Error::Mp3(ref err) => write!(f, "MP3: {}", err),
+            Error::Vorbis(ref err) => write!(f, "Vorbis: {}", err),
+            Error::BadUrl(ref url) => write!(f, "Unsupported URL: {}", url),
+            Error::BadResponse(ref line) => write!(f, "Bad response: {}", line),
+            Error::Status(status) => write!(f, "The server responded with status {}", status),
+            Error::TooManyRedirects => write!(f, "Too many redirects"),
+            Error::UnsupportedContentType(ref t) => write!(f, "Unsupported content type: {}", t),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Internet radio error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IO(ref err) => Some(err),
+            Error::Mp3(ref err) => Some(err),
+            Error::Vorbis(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IO(err)
+    }
+}
+
+impl From<mp3::Error> for Error {
+    fn from(err: mp3::Error) -> Error {
+        Error::Mp3(err)
+    }
+}
+
+impl From<vorbis::Error> for Error {
+    fn from(err: vorbis::Error) -> Error {
+        Error::Vorbis(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Stream, TrackInfo};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    const METAINT: usize = 8192;
+
+    fn testfile() -> Vec<u8> {
+        fs::read("testdata/10s_440hz_320cbr_stereo.mp3").unwrap()
+    }
+
+    /// Encodes a metadata block.
+    fn metadata(title: &str) -> Vec<u8> {
+        let mut meta = format!("StreamTitle='{}';StreamUrl='';", title).into_bytes();
+        let len = (meta.len() + 15) / 16;
+        meta.resize(len * 16, 0);
+        meta.insert(0, len as u8);
+        meta
+    }
+
+    /// Serves the data to each connection, with the title in the first metadata block. The
+    /// first connection is dropped after the specified number of bytes.
+    fn serve(data: Vec<u8>, title: &str, connections: usize, truncate: Option<usize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let meta = metadata(title);
+        thread::spawn(move || {
+            for i in 0..connections {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+                let mut request = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push(line);
+                }
+                assert!(request[0].starts_with("GET /stream "));
+                assert!(request.iter().any(|l| l.starts_with("Icy-MetaData: 1")));
+
+                let mut body = Vec::new();
+                for (j, chunk) in data.chunks(METAINT).enumerate() {
+                    body.extend_from_slice(chunk);
+                    if j == 0 {
+                        body.extend_from_slice(&meta);
+                    } else {
+                        body.push(0);
+                    }
+                }
+                if i == 0 {
+                    if let Some(n) = truncate {
+                        body.truncate(n);
+                    }
+                }
+                let mut stream = stream;
+                let _ = write!(
+                    stream,
+                    "ICY 200 OK\r\ncontent-type: audio/mpeg\r\nicy-name: Sine FM\r\nicy-genre: Test\r\nicy-metaint: {}\r\n\r\n",
+                    METAINT
+                );
+                let _ = stream.write_all(&body);
+            }
+        });
+        url
+    }
+
+    fn open(stream: &IcyStream) -> (dynam::Source, Arc<Mutex<Vec<IcyInfo>>>) {
+        let infos = Arc::new(Mutex::new(Vec::new()));
+        let infos_handler = infos.clone();
+        let source = stream
+            .open(Arc::new(move |info: Option<Box<TrackInfo + Send>>| {
+                let info = info.unwrap();
+                infos_handler.lock().unwrap().push(IcyInfo {
+                    title: info.title().into_owned(),
+                    artists: info.artists().into_owned(),
+                    genres: info.genres().into_owned(),
+                    station: String::new(),
+                });
+            }))
+            .unwrap();
+        (source, infos)
+    }
+
+    fn count_frames(source: dynam::Source) -> usize {
+        match source {
+            dynam::Source::StereoI16(s) => s.count(),
+            _ => panic!("unexpected format"),
+        }
+    }
+
+    #[test]
+    fn stream_title() {
+        let meta = metadata("The B-Trees - Lucy's Sine");
+        assert_eq!(
+            Some("The B-Trees - Lucy's Sine".to_string()),
+            parse_stream_title(&meta[1..])
+        );
+        assert_eq!(Some("".to_string()), parse_stream_title(b"StreamTitle='';"));
+        assert_eq!(None, parse_stream_title(b"StreamUrl='';"));
+
+        let info = IcyInfo::from_stream_title("The B-Trees - Lucy's Sine", "Sine FM", &[]).unwrap();
+        assert_eq!("Lucy's Sine", info.title);
+        assert_eq!(vec!["The B-Trees".to_string()], info.artists);
+        assert_eq!(None, IcyInfo::from_stream_title(" ", "Sine FM", &[]));
+    }
+
+    #[test]
+    fn url() {
+        let url = Url::parse("http://radio.example.com:8000/live.mp3").unwrap();
+        assert_eq!("radio.example.com", url.host);
+        assert_eq!(8000, url.port);
+        assert_eq!("/live.mp3", url.path);
+        let url = Url::parse("http://radio.example.com").unwrap();
+        assert_eq!(80, url.port);
+        assert_eq!("/", url.path);
+        assert_eq!("/other", url.join("/other").unwrap().path);
+        assert!(Url::parse("https://radio.example.com").is_err());
+    }
+
+    #[test]
+    fn icy_reader() {
+        let audio: Vec<u8> = (0..100).collect();
+        let mut data = Vec::new();
+        for (i, chunk) in audio.chunks(16).enumerate() {
+            data.extend_from_slice(chunk);
+            if i == 1 {
+                data.extend_from_slice(&metadata("Hello"));
+            } else {
+                data.push(0);
+            }
+        }
+        let titles = Arc::new(Mutex::new(Vec::new()));
+        let titles_handler = titles.clone();
+        let mut reader = IcyReader::new(
+            &data[..],
+            Some(16),
+            Arc::new(move |title: &str| titles_handler.lock().unwrap().push(title.to_string())),
+        );
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(audio, out);
+        assert_eq!(vec!["Hello".to_string()], *titles.lock().unwrap());
+    }
+
+    #[test]
+    fn decode_with_metadata() {
+        let url = serve(testfile(), "The B-Trees - Sine Waves", 1, None);
+        let mut stream = IcyStream::new(url, "Test");
+        stream.max_reconnects = 0;
+        let (source, infos) = open(&stream);
+        assert_eq!(44100, source.sample_rate());
+        let num_frames = count_frames(source);
+        assert!(num_frames > 44100 * 9, "{} frames", num_frames);
+        let infos = infos.lock().unwrap();
+        assert_eq!(1, infos.len());
+        assert_eq!("Sine Waves", infos[0].title);
+        assert_eq!(vec!["The B-Trees".to_string()], infos[0].artists);
+        assert_eq!(vec!["Test".to_string()], infos[0].genres);
+    }
+
+    #[test]
+    fn reconnect() {
+        let data = testfile();
+        let half = data.len() / 2;
+        let url = serve(data, "The B-Trees - Sine Waves", 2, Some(half));
+        let mut stream = IcyStream::new(url, "Test");
+        stream.max_reconnects = 1;
+        let (source, infos) = open(&stream);
+        let num_frames = count_frames(source);
+        // Both the truncated and the complete stream have been played.
+        assert!(num_frames > 44100 * 13, "{} frames", num_frames);
+        assert_eq!(2, infos.lock().unwrap().len());
+    }
+}
@@ -1,3 +1,5 @@
+use crate::analysis::key::Key;
+use crate::analysis::waveform;
 use crate::audio::*;
 use rand::{self, Rng};
 use std::borrow::Cow;
@@ -5,8 +7,12 @@ use std::collections::BTreeSet;
 use std::sync::Arc;
 use std::*;
 
+pub mod capture;
 pub mod fs;
+pub mod icy;
+pub mod radio;
 mod release;
+pub mod tone;
 pub use self::release::*;
 
 pub trait Library: Send + Sync {
@@ -15,8 +21,80 @@ pub trait Library: Send + Sync {
 
     fn find_by_id(&self, id: &Identity) -> Result<Option<Audio>, Box<error::Error>>;
 
-    // TODO: search
     fn tracks(&self) -> Result<Box<iter::Iterator<Item = Arc<Track>>>, Box<error::Error>>;
+
+    /// Returns the audio of which the title, artists, album or genres contain the query, ignoring
+    /// case.
+    fn search(&self, query: &str) -> Result<Box<iter::Iterator<Item = Audio>>, Box<error::Error>> {
+        let query = query.to_lowercase();
+        let tracks = self
+            .tracks()?
+            .filter(move |track| info_contains(&**track, &query))
+            .map(Audio::Track);
+        Ok(Box::new(tracks))
+    }
+
+    /// Returns the tracks of which the tempo lies within the specified range of beats per minute,
+    /// ordered by tempo.
+    fn tracks_by_bpm(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> Result<Box<iter::Iterator<Item = Arc<Track>>>, Box<error::Error>> {
+        let tracks = self
+            .tracks()?
+            .filter(|track| {
+                track
+                    .bpm()
+                    .map(|bpm| min <= bpm && bpm <= max)
+                    .unwrap_or(false)
+            })
+            .collect();
+        Ok(Box::new(sorted_by_bpm(tracks)))
+    }
+
+    /// Returns the tracks that are in any of the specified keys, ordered by tempo.
+    fn tracks_by_key(
+        &self,
+        keys: &[Key],
+    ) -> Result<Box<iter::Iterator<Item = Arc<Track>>>, Box<error::Error>> {
+        let tracks = self
+            .tracks()?
+            .filter(|track| track.key().map(|key| keys.contains(&key)).unwrap_or(false))
+            .collect();
+        Ok(Box::new(sorted_by_bpm(tracks)))
+    }
+
+    /// Returns the waveform overview of a track of this library with at most the specified
+    /// number of points, or None if it has not been computed.
+    fn waveform(
+        &self,
+        _id: &Identity,
+        _num_points: usize,
+    ) -> Result<Option<waveform::Overview>, Box<error::Error>> {
+        Ok(None)
+    }
+}
+
+fn sorted_by_bpm(mut tracks: Vec<Arc<Track>>) -> vec::IntoIter<Arc<Track>> {
+    tracks.sort_by(|a, b| {
+        a.bpm()
+            .partial_cmp(&b.bpm())
+            .unwrap_or(cmp::Ordering::Equal)
+    });
+    tracks.into_iter()
+}
+
+/// Returns true if any of the textual information contains the lowercase query.
+fn info_contains<T>(info: &T, query: &str) -> bool
+where
+    T: TrackInfo + ?Sized,
+{
+    let matches = |s: &str| s.to_lowercase().contains(query);
+    matches(&info.title())
+        || info.artists().iter().any(|a| matches(a))
+        || info.album_title().map(|t| matches(&t)).unwrap_or(false)
+        || info.genres().iter().any(|g| matches(g))
 }
 
 pub fn resolve_all<L>(libs: &[L], ids: &[&Identity]) -> Result<Vec<Audio>, Box<error::Error>>
@@ -98,6 +176,29 @@ pub trait TrackInfo {
     /// The rating (number of stars) ranging from 1 to 5 inclusive if known.
     fn rating(&self) -> Option<u8>;
     fn release(&self) -> Option<Release>;
+    /// The tempo in beats per minute if it has been analysed.
+    fn bpm(&self) -> Option<f64> {
+        None
+    }
+    /// The time in seconds of the first beat of the beat grid if it has been analysed.
+    fn beat_offset(&self) -> Option<f64> {
+        None
+    }
+    /// The musical key if it has been analysed.
+    fn key(&self) -> Option<Key> {
+        None
+    }
+}
+
+/// The ReplayGain of a track: the gain in dB that brings it to the reference loudness of -18 LUFS
+/// and its peak amplitude, so the gain can be limited to prevent clipping. The album values are
+/// set if the loudness of the album as a whole is known.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain: f64,
+    pub track_peak: f64,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
 }
 
 pub trait Track: TrackInfo + Identity {
@@ -107,6 +208,14 @@ pub trait Track: TrackInfo + Identity {
     fn audio(&self) -> Result<dynam::Seek, Box<error::Error>>;
     /// Returns the total duration of this track.
     fn duration(&self) -> time::Duration;
+    /// Returns the ReplayGain of this track if it has been analysed or tagged.
+    fn replay_gain(&self) -> Option<ReplayGain> {
+        None
+    }
+    /// Returns the named cue points of this track.
+    fn cues(&self) -> Vec<looper::Cue> {
+        Vec::new()
+    }
 }
 
 pub trait Stream: Identity {
@@ -115,8 +224,12 @@ pub trait Stream: Identity {
     /// it is applied to the specified callback.
     fn open(
         &self,
-        on_info: Arc<Fn(Option<Box<TrackInfo + Send>>)>,
+        on_info: Arc<Fn(Option<Box<TrackInfo + Send>>) + Send + Sync>,
     ) -> Result<dynam::Source, Box<error::Error>>;
+    /// Returns how the stream should be buffered if it differs from the defaults of the player.
+    fn buffer(&self) -> Option<buffer::Params> {
+        None
+    }
 }
 
 pub trait Playlist {
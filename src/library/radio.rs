@@ -0,0 +1,446 @@
+//!
+//! A library of internet radio stations.
+//!
+//! Stations are read from a directory that may be edited by the user. Each `.pls` or `.m3u` file
+//! in it describes a single station, possibly with multiple mirrors. Any `.toml` file may list
+//! multiple stations along with their genre and homepage:
+//!
+//! ```toml
+//! [[station]]
+//! title = "Sine FM"
+//! url = "http://radio.example.com/sine.mp3"
+//! genre = "Ambient, Drone"
+//! homepage = "http://sine.example.com"
+//! ```
+//!
+
+use crate::audio::*;
+use crate::library::icy::{self, IcyStream};
+use crate::library::{self, Identity};
+use log::*;
+use serde_derive::Deserialize;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::*;
+use toml;
+use xdg;
+
+/// A radio station.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Station {
+    pub title: String,
+    urls: Vec<String>,
+    pub genres: Vec<String>,
+    pub homepage: Option<String>,
+}
+
+impl Station {
+    /// Creates a station without genres or homepage. Empty URLs are ignored and `None` is
+    /// returned if no URL remains.
+    pub fn new(title: String, urls: Vec<String>) -> Option<Station> {
+        let urls: Vec<String> = urls.into_iter().filter(|url| !url.is_empty()).collect();
+        if urls.is_empty() {
+            return None;
+        }
+        Some(Station {
+            title,
+            urls,
+            genres: Vec::new(),
+            homepage: None,
+        })
+    }
+
+    /// The URLs of the stream of the station. The first URL is tried first and the others are
+    /// mirrors. There is always at least one URL.
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+}
+
+impl Identity for Station {
+    fn id(&self) -> (Cow<str>, Cow<str>) {
+        (
+            Cow::Borrowed(icy::LIBRARY_NAME),
+            Cow::Borrowed(&self.urls[0]),
+        )
+    }
+}
+
+impl library::Stream for Station {
+    fn title(&self) -> Cow<str> {
+        Cow::Borrowed(&self.title)
+    }
+
+    /// Opens the first URL that can be connected to.
+    fn open(
+        &self,
+        on_info: Arc<Fn(Option<Box<library::TrackInfo + Send>>) + Send + Sync>,
+    ) -> Result<dynam::Source, Box<error::Error>> {
+        let mut last_err = None;
+        for url in &self.urls {
+            match IcyStream::new(url.as_str(), self.title.as_str()).open(on_info.clone()) {
+                Ok(source) => return Ok(source),
+                Err(err) => {
+                    warn!("Could not open {}: {}", url, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.expect("a station has at least one url"))
+    }
+}
+
+/// Radio is a library of which the items are radio stations.
+pub struct Radio {
+    dir: path::PathBuf,
+}
+
+impl Radio {
+    /// Initializes a library of the stations in the specified directory. The directory is read
+    /// every time stations are looked up, so changes take effect immediately.
+    pub fn new(dir: &path::Path) -> Radio {
+        Radio {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Initializes a library of the stations in the `radio` directory in the user's
+    /// configuration directory, creating it if it does not exist.
+    pub fn from_config_dir() -> Result<Radio, Error> {
+        let dir = xdg::BaseDirectories::with_prefix(env!("CARGO_PKG_NAME"))?
+            .create_config_directory("radio")?;
+        Ok(Radio::new(&dir))
+    }
+
+    /// Reads all stations, ordered by title. Files that can not be parsed are skipped.
+    pub fn stations(&self) -> Result<Vec<Arc<Station>>, Error> {
+        let mut stations = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let ext = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let parse: fn(&str, &str) -> Result<Vec<Station>, Error> = match ext.as_str() {
+                "pls" => |text, name| Ok(parse_pls(text, name).into_iter().collect()),
+                "m3u" | "m3u8" => |text, name| Ok(parse_m3u(text, name).into_iter().collect()),
+                "toml" => |text, _| parse_toml(text),
+                _ => continue,
+            };
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            match fs::read_to_string(&path)
+                .map_err(Error::from)
+                .and_then(|text| parse(&text, &name))
+            {
+                Ok(s) => stations.extend(s.into_iter().map(Arc::new)),
+                Err(err) => warn!("Could not read stations from {}: {}", path.display(), err),
+            }
+        }
+        stations.sort_by(|a, b| a.title.cmp(&b.title));
+        Ok(stations)
+    }
+}
+
+impl library::Library for Radio {
+    fn name(&self) -> Cow<str> {
+        Cow::Borrowed(icy::LIBRARY_NAME)
+    }
+
+    /// Looks up a station by its URL. URLs of streams that are not listed are opened as is.
+    fn find_by_id(&self, id: &Identity) -> Result<Option<library::Audio>, Box<error::Error>> {
+        let (lib, url) = id.id();
+        if lib != self.name() {
+            return Ok(None);
+        }
+        let station = self
+            .stations()?
+            .into_iter()
+            .find(|station| station.urls.iter().any(|u| *u == url));
+        if let Some(station) = station {
+            return Ok(Some(library::Audio::Stream(station)));
+        }
+        if url.starts_with("http://") {
+            let stream = IcyStream::new(&*url, &*url);
+            return Ok(Some(library::Audio::Stream(Arc::new(stream))));
+        }
+        Ok(None)
+    }
+
+    fn tracks(&self) -> Result<Box<iter::Iterator<Item = Arc<library::Track>>>, Box<error::Error>> {
+        Ok(Box::new(iter::empty()))
+    }
+
+    /// Returns the stations of which the title or any of the genres contain the query.
+    fn search(
+        &self,
+        query: &str,
+    ) -> Result<Box<iter::Iterator<Item = library::Audio>>, Box<error::Error>> {
+        let query = query.to_lowercase();
+        let stations = self.stations()?.into_iter().filter(move |station| {
+            station.title.to_lowercase().contains(&query)
+                || station
+                    .genres
+                    .iter()
+                    .any(|genre| genre.to_lowercase().contains(&query))
+        });
+        Ok(Box::new(stations.map(|s| library::Audio::Stream(s))))
+    }
+}
+
+/// Parses a PLS playlist. The entries are taken to be mirrors of the same station. The title of
+/// the first entry that has one is used, falling back to the specified title.
+pub fn parse_pls(text: &str, default_title: &str) -> Option<Station> {
+    let mut entries = BTreeMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        let i = match line.find('=') {
+            Some(i) => i,
+            None => continue,
+        };
+        let (key, value) = (line[..i].trim().to_lowercase(), line[i + 1..].trim());
+        let (is_file, num) = if key.starts_with("file") {
+            (true, &key[4..])
+        } else if key.starts_with("title") {
+            (false, &key[5..])
+        } else {
+            continue;
+        };
+        let num: u32 = match num.parse() {
+            Ok(num) => num,
+            Err(_) => continue,
+        };
+        let entry = entries.entry(num).or_insert((None, None));
+        if is_file {
+            entry.0 = Some(value.to_string());
+        } else if !value.is_empty() {
+            entry.1 = Some(value.to_string());
+        }
+    }
+    let title = entries
+        .values()
+        .filter_map(|(_, title)| title.clone())
+        .next()
+        .unwrap_or_else(|| default_title.to_string());
+    let urls = entries
+        .into_iter()
+        .filter_map(|(_, (url, _))| url)
+        .collect();
+    Station::new(title, urls)
+}
+
+/// Parses an (extended) M3U playlist. The entries are taken to be mirrors of the same station. The
+/// title of the first `#EXTINF` is used, falling back to the specified title. Genres may be listed
+/// using `#EXTGENRE`.
+pub fn parse_m3u(text: &str, default_title: &str) -> Option<Station> {
+    let mut title = None;
+    let mut urls = Vec::new();
+    let mut genres = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("#EXTINF:") {
+            // The title follows the duration, separated by a comma.
+            if let Some(i) = line.find(',') {
+                let t = line[i + 1..].trim();
+                if title.is_none() && !t.is_empty() {
+                    title = Some(t.to_string());
+                }
+            }
+        } else if line.starts_with("#EXTGENRE:") {
+            genres.extend(
+                line["#EXTGENRE:".len()..]
+                    .split(',')
+                    .map(|g| g.trim().to_string())
+                    .filter(|g| !g.is_empty()),
+            );
+        } else if !line.is_empty() && !line.starts_with('#') {
+            urls.push(line.to_string());
+        }
+    }
+    let mut station = Station::new(title.unwrap_or_else(|| default_title.to_string()), urls)?;
+    station.genres = genres;
+    Some(station)
+}
+
+/// Parses a TOML list of stations. Stations without any non-empty URL are skipped.
+pub fn parse_toml(text: &str) -> Result<Vec<Station>, Error> {
+    #[derive(Deserialize)]
+    struct StationList {
+        #[serde(default)]
+        station: Vec<TomlStation>,
+    }
+    #[derive(Deserialize)]
+    struct TomlStation {
+        title: String,
+        url: String,
+        #[serde(default)]
+        mirrors: Vec<String>,
+        genre: Option<String>,
+        homepage: Option<String>,
+    }
+
+    let list: StationList = toml::from_str(text)?;
+    Ok(list
+        .station
+        .into_iter()
+        .filter_map(|s| {
+            let urls = iter::once(s.url).chain(s.mirrors).collect();
+            let mut station = match Station::new(s.title, urls) {
+                Some(station) => station,
+                None => {
+                    warn!("Skipping radio station without a URL");
+                    return None;
+                }
+            };
+            station.genres = s
+                .genre
+                .map(|g| {
+                    g.split(',')
+                        .map(|g| g.trim().to_string())
+                        .filter(|g| !g.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+            station.homepage = s.homepage;
+            Some(station)
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    IO(io::Error),
+    Toml(toml::de::Error),
+    Xdg(xdg::BaseDirectoriesError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IO(ref err) => write!(f, "IO: {}", err),
+            Error::Toml(ref err) => write!(f, "TOML: {}", err),
+            Error::Xdg(ref err) => write!(f, "Xdg: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        "Radio library error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::IO(ref err) => Some(err),
+            Error::Toml(ref err) => Some(err),
+            Error::Xdg(ref err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IO(err)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Error {
+        Error::Toml(err)
+    }
+}
+
+impl From<xdg::BaseDirectoriesError> for Error {
+    fn from(err: xdg::BaseDirectoriesError) -> Error {
+        Error::Xdg(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Library, Stream};
+
+    const DIR: &str = "testdata/radio";
+
+    #[test]
+    fn pls() {
+        let text = "[playlist]\nNumberOfEntries=2\nFile1=http://a.example.com/sine\nTitle1=Sine FM\nLength1=-1\nFile2=http://b.example.com/sine\nVersion=2\n";
+        let station = parse_pls(text, "sine").unwrap();
+        assert_eq!("Sine FM", station.title);
+        assert_eq!(
+            vec![
+                "http://a.example.com/sine".to_string(),
+                "http://b.example.com/sine".to_string(),
+            ],
+            station.urls()
+        );
+        assert_eq!(
+            "fallback",
+            parse_pls("File1=http://x\n", "fallback").unwrap().title
+        );
+        assert_eq!(None, parse_pls("[playlist]\n", "empty"));
+        assert_eq!(None, parse_pls("File1=\n", "empty"));
+    }
+
+    #[test]
+    fn m3u() {
+        let text = "#EXTM3U\n#EXTINF:-1,Square Radio\n#EXTGENRE:Chiptune, 8-bit\nhttp://square.example.com/live\n";
+        let station = parse_m3u(text, "square").unwrap();
+        assert_eq!("Square Radio", station.title);
+        assert_eq!(
+            vec!["http://square.example.com/live".to_string()],
+            station.urls()
+        );
+        assert_eq!(
+            vec!["Chiptune".to_string(), "8-bit".to_string()],
+            station.genres
+        );
+        assert_eq!("plain", parse_m3u("http://x\n", "plain").unwrap().title);
+    }
+
+    #[test]
+    fn toml_list() {
+        let text = "[[station]]\ntitle = \"Drone Zone\"\nurl = \"http://drone.example.com\"\nmirrors = [\"http://drone2.example.com\"]\ngenre = \"Ambient, Drone\"\nhomepage = \"http://example.com\"\n";
+        let stations = parse_toml(text).unwrap();
+        assert_eq!(1, stations.len());
+        assert_eq!(2, stations[0].urls().len());
+        assert_eq!(
+            vec!["Ambient".to_string(), "Drone".to_string()],
+            stations[0].genres
+        );
+        assert_eq!(Some("http://example.com".to_string()), stations[0].homepage);
+        assert!(parse_toml("[[station]]\ntitle = 1\n").is_err());
+        let empty = "[[station]]\ntitle = \"Silence\"\nurl = \"\"\n";
+        assert!(parse_toml(empty).unwrap().is_empty());
+        assert_eq!(None, Station::new("Silence".to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn library() {
+        let radio = Radio::new(path::Path::new(DIR));
+        let stations = radio.stations().unwrap();
+        let titles: Vec<_> = stations.iter().map(|s| s.title.as_str()).collect();
+        assert_eq!(
+            vec!["Drone Zone", "Sine FM", "Square Radio", "Triangle Talk"],
+            titles
+        );
+
+        let found = radio.find_by_id(&*stations[1]).unwrap().unwrap();
+        assert_eq!(stations[1].id(), found.id());
+        match found {
+            library::Audio::Stream(ref stream) => assert_eq!("Sine FM", stream.title()),
+            _ => panic!("expected a stream"),
+        }
+        let adhoc = IcyStream::new("http://unlisted.example.com", "Unlisted");
+        assert!(radio.find_by_id(&adhoc).unwrap().is_some());
+
+        let drone: Vec<_> = radio.search("drone").unwrap().collect();
+        assert_eq!(1, drone.len());
+        let ambient: Vec<_> = radio.search("AMBIENT").unwrap().collect();
+        assert_eq!(2, ambient.len());
+    }
+}
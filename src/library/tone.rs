@@ -0,0 +1,154 @@
+//!
+//! Generated test tones, which can be played to check the output and its levels without relying
+//! on any audio file.
+//!
+
+use crate::audio::gen::{self, Waveform};
+use crate::audio::*;
+use crate::library;
+use std::borrow::Cow;
+use std::*;
+
+/// The name of the library to which tones belong.
+pub const LIBRARY_NAME: &str = "tone";
+
+const SAMPLE_RATE: u32 = 44100;
+
+/// A generated signal that is played as a stereo track.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tone {
+    pub waveform: Waveform,
+    /// The peak amplitude, where 1.0 is full scale.
+    pub amplitude: f64,
+    pub duration: time::Duration,
+}
+
+impl Tone {
+    /// Parses the arguments of a test tone command: the name of a waveform followed by the
+    /// frequency in Hz and the duration in seconds, e.g. `sine 1000 10` or `sweep 20 20000 30`.
+    /// The waveform defaults to a 1kHz sine at -20dBFS, the standard alignment tone.
+    pub fn parse(args: &str) -> Option<Tone> {
+        let mut words = args.split_whitespace();
+        let name = words.next().unwrap_or("sine");
+        let numbers: Vec<f64> = words.map(|w| w.parse().ok()).collect::<Option<_>>()?;
+        let number = |i: usize, default: f64| numbers.get(i).cloned().unwrap_or(default);
+        let (waveform, num_args) = match name {
+            "sine" => (Waveform::Sine(number(0, 1000.0)), 1),
+            "square" => (Waveform::Square(number(0, 1000.0)), 1),
+            "saw" | "sawtooth" => (Waveform::Sawtooth(number(0, 1000.0)), 1),
+            "white" => (Waveform::WhiteNoise, 0),
+            "pink" => (Waveform::PinkNoise, 0),
+            "sweep" => (Waveform::Sweep(number(0, 20.0), number(1, 20_000.0)), 2),
+            "impulse" => (Waveform::Impulse, 0),
+            "silence" => (Waveform::Silence, 0),
+            _ => return None,
+        };
+        if !waveform.is_valid() || numbers.len() > num_args + 1 {
+            return None;
+        }
+        let secs = number(num_args, 10.0);
+        if !(secs > 0.0 && secs.is_finite()) {
+            return None;
+        }
+        Some(Tone {
+            waveform,
+            amplitude: 0.1,
+            duration: time::Duration::from_millis((secs * 1000.0) as u64),
+        })
+    }
+}
+
+impl library::Identity for Tone {
+    fn id(&self) -> (Cow<str>, Cow<str>) {
+        (
+            Cow::Borrowed(LIBRARY_NAME),
+            Cow::Owned(self.waveform.to_string()),
+        )
+    }
+}
+
+impl library::TrackInfo for Tone {
+    fn title(&self) -> Cow<str> {
+        Cow::Owned(self.waveform.to_string())
+    }
+
+    fn artists(&self) -> Cow<[String]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn remixers(&self) -> Cow<[String]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn genres(&self) -> Cow<[String]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn album_title(&self) -> Option<Cow<str>> {
+        None
+    }
+
+    fn album_artists(&self) -> Cow<[String]> {
+        Cow::Borrowed(&[])
+    }
+
+    fn album_disc(&self) -> Option<i32> {
+        None
+    }
+
+    fn album_track(&self) -> Option<i32> {
+        None
+    }
+
+    fn rating(&self) -> Option<u8> {
+        None
+    }
+
+    fn release(&self) -> Option<library::Release> {
+        None
+    }
+}
+
+impl library::Track for Tone {
+    fn modified_at(&self) -> Option<time::SystemTime> {
+        None
+    }
+
+    fn audio(&self) -> Result<dynam::Seek, Box<error::Error>> {
+        let length = frames_of(SAMPLE_RATE, self.duration);
+        let audio = gen::dynam_generator(self.waveform, self.amplitude, SAMPLE_RATE, length, 2)?;
+        Ok(audio)
+    }
+
+    fn duration(&self) -> time::Duration {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let tone = Tone::parse("").unwrap();
+        assert_eq!(Waveform::Sine(1000.0), tone.waveform);
+        assert_eq!(time::Duration::from_secs(10), tone.duration);
+        assert_eq!(
+            Waveform::Sweep(100.0, 200.0),
+            Tone::parse("sweep 100 200 5").unwrap().waveform
+        );
+        let pink = Tone::parse("pink 2.5").unwrap();
+        assert_eq!(
+            (Waveform::PinkNoise, time::Duration::from_millis(2500)),
+            (pink.waveform, pink.duration)
+        );
+        assert_eq!(None, Tone::parse("triangle"));
+        assert_eq!(None, Tone::parse("sine loud"));
+        assert_eq!(None, Tone::parse("white 10 10"));
+        assert_eq!(None, Tone::parse("sweep 0 1000"));
+        assert_eq!(None, Tone::parse("sine -440"));
+        assert_eq!(None, Tone::parse("sine inf"));
+        assert_eq!(None, Tone::parse("sine 1000 NaN"));
+    }
+}
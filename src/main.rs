@@ -8,6 +8,7 @@ use crate::library::Library;
 use std::io::{BufRead, Write};
 use std::*;
 
+mod analysis;
 mod audio;
 mod filter;
 mod format;
@@ -18,19 +19,35 @@ mod pulse;
 fn main() {
     env_logger::init();
 
+    if env::args().nth(1).as_ref().map(|s| s.as_str()) == Some("analyze") {
+        let args: Vec<String> = env::args().skip(2).collect();
+        if let Err(err) = analyze(&args) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
     let fs = sync::Arc::new(library::fs::Filesystem::new(path::Path::new("testdata")).unwrap());
-    let libs: Vec<sync::Arc<library::Library>> = vec![fs.clone()];
-    let player = player::Player::new(Box::new(player::output::pulse::Output {}), libs);
+    let radio = sync::Arc::new(library::radio::Radio::from_config_dir().unwrap());
+    let libs: Vec<sync::Arc<library::Library>> = vec![fs.clone(), radio];
+    let player = player::Player::new(Box::new(player::output::pulse::Output {}), libs).unwrap();
 
     let mut managed_id = env::args().nth(1).map(|filename| {
         let mut p = player.lock().unwrap();
-        let path = path::PathBuf::from(filename);
-        let track = library::fs::track_from_path(&path).unwrap();
-        p.queue.push(library::Audio::Track(track));
+        if filename.starts_with("http://") {
+            let stream = library::icy::IcyStream::new(filename.clone(), filename);
+            p.queue.push(library::Audio::Stream(sync::Arc::new(stream)));
+        } else {
+            let path = path::PathBuf::from(filename);
+            let track = library::fs::track_from_path(&path).unwrap();
+            p.queue.push(library::Audio::Track(track));
+        }
         let (id, _) = p.play_next_from_queue().unwrap().unwrap();
         id
     });
 
+    let mut recording: Option<(path::PathBuf, audio::record::Recording)> = None;
     let mut out = io::stderr();
     let stdin = io::stdin();
     let mut lines = stdin.lock().lines();
@@ -115,6 +132,25 @@ fn main() {
                             .unwrap_or_else(|| "-".to_string())
                     )
                     .unwrap();
+                    writeln!(
+                        out,
+                        "  bpm:     {}",
+                        info.bpm()
+                            .map(|bpm| format!("{:.1}", bpm))
+                            .unwrap_or_else(|| "-".to_string())
+                    )
+                    .unwrap();
+                    writeln!(
+                        out,
+                        "  key:     {}",
+                        info.key()
+                            .map(|key| {
+                                let (number, letter) = key.camelot();
+                                format!("{} ({}{})", key, number, letter)
+                            })
+                            .unwrap_or_else(|| "-".to_string())
+                    )
+                    .unwrap();
                 }
                 if let Some(&mut (ref audio, ref mut pb, ref info)) =
                     managed_id.as_ref().and_then(|id| p.playing.get_mut(id))
@@ -135,6 +171,15 @@ fn main() {
                     )
                     .unwrap();
                     writeln!(out, "tempo:    {}", pb.tempo()).unwrap();
+                    writeln!(out, "pitch:    {:+}", pb.pitch()).unwrap();
+                    writeln!(out, "gain:     {:+.2}dB", pb.gain()).unwrap();
+                    let meter = pb.meter();
+                    writeln!(
+                        out,
+                        "loudness: {:.1} LUFS (3s {:.1} LUFS)",
+                        meter.momentary, meter.short_term
+                    )
+                    .unwrap();
                     writeln!(
                         out,
                         "latency:  {}ns",
@@ -161,7 +206,9 @@ fn main() {
                             )
                             .unwrap();
                         }
-                        library::Audio::Stream(_) => unimplemented!(),
+                        library::Audio::Stream(ref stream) => {
+                            writeln!(out, "{}", stream.title()).unwrap();
+                        }
                     };
                 }
             }
@@ -180,6 +227,23 @@ fn main() {
                     }
                 }
             }
+            l if l.starts_with("tone") => {
+                // Plays a generated signal to check the output, see `library::tone::Tone::parse`.
+                let tone = match library::tone::Tone::parse(&l[4..]) {
+                    Some(tone) => tone,
+                    None => {
+                        writeln!(
+                            out,
+                            "usage: tone [sine|square|saw|white|pink|sweep|impulse|silence] [Hz..] [secs]"
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                };
+                p.queue.push(library::Audio::Track(sync::Arc::new(tone)));
+                let last = p.queue.len() - 1;
+                managed_id = p.play_from_queue(last).unwrap().map(|t| t.0);
+            }
             l if l.starts_with('t') => {
                 if let Some(&mut (_, ref mut pb, _)) =
                     managed_id.as_ref().and_then(|id| p.playing.get_mut(id))
@@ -189,11 +253,202 @@ fn main() {
                     }
                 }
             }
+            l if l.starts_with('p') => {
+                if let Some(&mut (_, ref mut pb, _)) =
+                    managed_id.as_ref().and_then(|id| p.playing.get_mut(id))
+                {
+                    if let Ok(s) = l[1..].parse() {
+                        pb.set_pitch(s);
+                    }
+                }
+            }
+            "ab" => {
+                // The first invocation marks the start, the second the end and the third stops
+                // repeating.
+                if let Some(&mut (_, ref mut pb, _)) =
+                    managed_id.as_ref().and_then(|id| p.playing.get_mut(id))
+                {
+                    if pb.region().is_some() {
+                        pb.set_region(None);
+                    } else if pb.repeat_start_marked() {
+                        pb.repeat_end();
+                    } else {
+                        pb.repeat_start();
+                    }
+                }
+            }
+            l if l.starts_with("loop ") => {
+                if let Some(&mut (_, ref mut pb, _)) =
+                    managed_id.as_ref().and_then(|id| p.playing.get_mut(id))
+                {
+                    let rate = f64::from(pb.sample_rate());
+                    let bounds: Vec<f64> = l[5..]
+                        .split_whitespace()
+                        .filter_map(|s| s.parse().ok())
+                        .collect();
+                    match bounds.as_slice() {
+                        [start, end] => pb.set_region(audio::looper::Region::new(
+                            (start * rate) as u64,
+                            (end * rate) as u64,
+                        )),
+                        _ => pb.set_region(None),
+                    }
+                }
+            }
+            l if l.starts_with("capture") => {
+                let device = library::capture::Device::from(l[7..].trim());
+                let capture = library::capture::Capture::new(device);
+                p.queue
+                    .push(library::Audio::Stream(sync::Arc::new(capture)));
+                let last = p.queue.len() - 1;
+                managed_id = p.play_from_queue(last).unwrap().map(|t| t.0);
+            }
+            l if l.starts_with("rec") => {
+                // Toggles recording the specified device to the working directory.
+                if let Some((path, rec)) = recording.take() {
+                    match rec.stop() {
+                        Ok(_) => writeln!(out, "recorded {}", path.display()).unwrap(),
+                        Err(err) => writeln!(out, "recording failed: {}", err).unwrap(),
+                    }
+                    continue;
+                }
+                let device = library::capture::Device::from(l[3..].trim());
+                match library::capture::Capture::new(device).record(path::Path::new(".")) {
+                    Ok((path, rec)) => {
+                        writeln!(out, "recording to {}", path.display()).unwrap();
+                        recording = Some((path, rec));
+                    }
+                    Err(err) => writeln!(out, "could not record: {}", err).unwrap(),
+                }
+            }
+            l if l.starts_with("session") => {
+                // Toggles recording the master output to the working directory.
+                let result = if p.session_path().is_some() {
+                    p.stop_session()
+                } else {
+                    let format = match l[7..].trim() {
+                        "flac" => player::session::Format::Flac,
+                        _ => player::session::Format::Wave,
+                    };
+                    p.start_session(path::Path::new("."), format).map(Some)
+                };
+                match result {
+                    Ok(Some(path)) => writeln!(out, "session: {}", path.display()).unwrap(),
+                    Ok(None) => (),
+                    Err(err) => writeln!(out, "session: {}", err).unwrap(),
+                }
+            }
+            l if l.starts_with("eq save ") => {
+                let name = l[8..].trim();
+                let bands = p.mixer().equalizer();
+                if let Err(err) = p.save_equalizer_preset(name, bands) {
+                    writeln!(out, "eq: {}", err).unwrap();
+                }
+            }
+            l if l.starts_with("eq ") => {
+                let name = l[3..].trim();
+                if let Some(bands) = p.equalizer_presets().get(name).cloned() {
+                    p.mixer_mut().set_equalizer(bands);
+                } else {
+                    writeln!(out, "no such preset: {}", name).unwrap();
+                }
+            }
+            l if l.starts_with("rg ") => {
+                let mut args = l[3..].split_whitespace();
+                let mode = match args.next() {
+                    Some("off") => player::ReplayGainMode::Off,
+                    Some("track") => player::ReplayGainMode::Track,
+                    Some("album") => player::ReplayGainMode::Album,
+                    _ => {
+                        writeln!(out, "usage: rg <off|track|album> [preamp]").unwrap();
+                        continue;
+                    }
+                };
+                p.set_replay_gain_mode(mode);
+                if let Some(Ok(preamp)) = args.next().map(|s| s.parse()) {
+                    p.set_replay_gain_preamp(preamp);
+                }
+            }
             ukn => writeln!(out, "wtf: {}", ukn).unwrap(),
         }
     }
 }
 
+const SPECTROGRAM_USAGE: &str = "usage: analyze spectrogram <input> <output.png|output.ppm>
+    [--window hann|hamming|blackman-harris|kaiser:<beta>] [--fft <size>] [--hop <size>]
+    [--range <min dB>:<max dB>] [--scale lin|log] [--min-freq <Hz>]
+    [--colormap magma|viridis|grey] [--size <width>x<height>]";
+
+fn analyze(args: &[String]) -> Result<(), Box<error::Error>> {
+    match args.get(0).map(|s| s.as_str()) {
+        Some("spectrogram") => spectrogram(&args[1..]),
+        _ => Err(Box::from(SPECTROGRAM_USAGE)),
+    }
+}
+
+fn spectrogram(args: &[String]) -> Result<(), Box<error::Error>> {
+    use crate::analysis::spectrogram;
+    let usage = || Box::<error::Error>::from(SPECTROGRAM_USAGE);
+    if args.len() < 2 || args.len() % 2 != 0 {
+        return Err(usage());
+    }
+    let mut params = spectrogram::Params::default();
+    let mut hop_size = None;
+    for opt in args[2..].chunks(2) {
+        let value = opt[1].as_str();
+        match opt[0].as_str() {
+            "--window" => params.window = value.parse().map_err(|_| usage())?,
+            "--fft" => params.fft_size = value.parse().map_err(|_| usage())?,
+            "--hop" => hop_size = Some(value.parse().map_err(|_| usage())?),
+            "--range" => {
+                let mut range = value.splitn(2, ':').map(|v| v.parse::<f64>());
+                match (range.next(), range.next()) {
+                    (Some(Ok(min)), Some(Ok(max))) if min < max => {
+                        params.min_db = min;
+                        params.max_db = max;
+                    }
+                    _ => return Err(usage()),
+                }
+            }
+            "--scale" => params.scale = value.parse().map_err(|_| usage())?,
+            "--min-freq" => params.min_freq = value.parse().map_err(|_| usage())?,
+            "--colormap" => params.colormap = value.parse().map_err(|_| usage())?,
+            "--size" => {
+                let mut size = value.splitn(2, 'x').map(|v| v.parse::<usize>());
+                match (size.next(), size.next()) {
+                    (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => {
+                        params.width = width;
+                        params.height = height;
+                    }
+                    _ => return Err(usage()),
+                }
+            }
+            _ => return Err(usage()),
+        }
+    }
+    params.hop_size = hop_size.unwrap_or(params.fft_size / 4);
+    if !params.fft_size.is_power_of_two()
+        || params.fft_size < 2
+        || params.hop_size == 0
+        || params.hop_size > params.fft_size
+    {
+        return Err(Box::from(
+            "the FFT size must be a power of two no smaller than the hop size",
+        ));
+    }
+
+    // Check the output before spending time on decoding and rendering.
+    let output = path::Path::new(&args[1]);
+    if spectrogram::ImageFormat::from_path(output).is_none() {
+        return Err(Box::new(spectrogram::Error::UnsupportedFormat));
+    }
+
+    let (audio, _) = format::decode_file(&args[0])?;
+    let image = spectrogram::render_dynam(audio.into(), &params)?;
+    image.save(output)?;
+    Ok(())
+}
+
 fn format_duraton(dur: &time::Duration) -> String {
     let secs = dur.as_secs();
     let nanos = dur.subsec_nanos();
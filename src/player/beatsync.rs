@@ -0,0 +1,156 @@
+//!
+//! Beat synchronisation locks the tempo and beats of a follower playback to those of a leader.
+//!
+//! The tempo of the follower is set so that its BPM matches that of the leader. Remaining
+//! differences in phase are corrected by briefly playing the follower slightly faster or slower,
+//! which is inaudible, unlike seeking.
+//!
+
+use std::*;
+
+/// The interval at which the phase of synchronised playbacks is checked.
+pub const INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+/// Phase offsets smaller than this number of beats are not corrected.
+const PHASE_TOLERANCE: f64 = 0.01;
+/// The time in seconds over which a phase offset is corrected.
+const CORRECTION_TIME: f64 = 2.0;
+/// The maximum relative deviation from the matched tempo while correcting the phase.
+const MAX_CORRECTION: f64 = 0.02;
+
+/// The beat grid of a track.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BeatGrid {
+    pub bpm: f64,
+    /// The time in seconds of the first beat.
+    pub offset: f64,
+}
+
+impl BeatGrid {
+    /// Returns the number of beats since the first beat at the specified time in seconds.
+    pub fn beats_at(&self, time: f64) -> f64 {
+        (time - self.offset) * self.bpm / 60.0
+    }
+
+    /// Returns the length of a beat in seconds.
+    pub fn beat_length(&self) -> f64 {
+        60.0 / self.bpm
+    }
+}
+
+/// Link describes how a follower is synchronised to its leader.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Link {
+    /// The ID of the leading playback.
+    pub leader: u64,
+    pub leader_grid: BeatGrid,
+    pub follower_grid: BeatGrid,
+}
+
+impl Link {
+    /// Returns the tempo at which the follower plays at the same BPM as the leader when the leader
+    /// plays at the specified tempo.
+    pub fn matched_tempo(&self, leader_tempo: f64) -> f64 {
+        leader_tempo * self.leader_grid.bpm / self.follower_grid.bpm
+    }
+
+    /// Returns the offset in beats of the follower relative to the leader, ranging from -0.5 to
+    /// 0.5. The offset is positive if the follower is ahead.
+    pub fn phase_offset(&self, leader_time: f64, follower_time: f64) -> f64 {
+        let beats =
+            self.follower_grid.beats_at(follower_time) - self.leader_grid.beats_at(leader_time);
+        beats - beats.round()
+    }
+
+    /// Returns the tempo at which the follower should play to gradually correct the phase offset.
+    pub fn corrected_tempo(&self, leader_tempo: f64, phase_offset: f64) -> f64 {
+        let matched = self.matched_tempo(leader_tempo);
+        if phase_offset.abs() < PHASE_TOLERANCE {
+            return matched;
+        }
+        let beats_per_sec = matched * self.follower_grid.bpm / 60.0;
+        let correction = -phase_offset / (CORRECTION_TIME * beats_per_sec);
+        matched * (1.0 + correction.max(-MAX_CORRECTION).min(MAX_CORRECTION))
+    }
+
+    /// Returns the time in seconds to which the follower should seek to immediately align its
+    /// beats with those of the leader.
+    pub fn aligned_time(&self, leader_time: f64, follower_time: f64) -> f64 {
+        let offset = self.phase_offset(leader_time, follower_time);
+        let time = follower_time - offset * self.follower_grid.beat_length();
+        if time < 0.0 {
+            time + self.follower_grid.beat_length()
+        } else {
+            time
+        }
+    }
+}
+
+/// Converts a duration to seconds.
+pub fn secs(duration: time::Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link() -> Link {
+        Link {
+            leader: 1,
+            leader_grid: BeatGrid {
+                bpm: 128.0,
+                offset: 0.1,
+            },
+            follower_grid: BeatGrid {
+                bpm: 120.0,
+                offset: 0.5,
+            },
+        }
+    }
+
+    #[test]
+    fn matched_tempo() {
+        let link = link();
+        assert!((link.matched_tempo(1.0) - 128.0 / 120.0).abs() < 1e-9);
+        assert!((link.matched_tempo(0.9) - 0.9 * 128.0 / 120.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn phase_offset() {
+        let link = link();
+        // Both are exactly on a beat.
+        let leader_time = 0.1 + 8.0 * 60.0 / 128.0;
+        let follower_time = 0.5 + 3.0 * 60.0 / 120.0;
+        assert!(link.phase_offset(leader_time, follower_time).abs() < 1e-9);
+        // The follower is a quarter beat ahead.
+        let offset = link.phase_offset(leader_time, follower_time + 0.125);
+        assert!((offset - 0.25).abs() < 1e-9);
+        // Three quarters ahead is a quarter behind.
+        let offset = link.phase_offset(leader_time, follower_time + 0.375);
+        assert!((offset + 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn corrected_tempo() {
+        let link = link();
+        let matched = link.matched_tempo(1.0);
+        assert_eq!(matched, link.corrected_tempo(1.0, 0.001));
+        assert!(link.corrected_tempo(1.0, 0.1) < matched);
+        assert!(link.corrected_tempo(1.0, -0.1) > matched);
+        let max = link.corrected_tempo(1.0, -0.5);
+        assert!((max - matched * (1.0 + MAX_CORRECTION)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn aligned_time() {
+        let link = link();
+        let leader_time = 0.1 + 8.0 * 60.0 / 128.0;
+        let time = link.aligned_time(leader_time, 10.2);
+        assert!(link.phase_offset(leader_time, time).abs() < 1e-9);
+        assert!((time - 10.2).abs() <= 0.25);
+        let time = link.aligned_time(leader_time, 0.0);
+        assert!(time >= 0.0);
+        assert!(link.phase_offset(leader_time, time).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,847 @@
+use crate::analysis::Analyser;
+use crate::analysis::{bands, meter};
+use crate::audio::*;
+use crate::filter::dynamics::Processor;
+use crate::filter::*;
+use crate::player::output;
+use log::*;
+use sample::{self, Sample};
+use std::sync::{mpsc, Arc, Mutex};
+use std::*;
+
+/// The number of frames that are mixed at once. Changes made to the inputs take effect at the
+/// start of the next block.
+const BLOCK_SIZE: usize = 256;
+/// The look-ahead of the limiter of the master output.
+const LIMITER_LOOKAHEAD: time::Duration = time::Duration::from_millis(5);
+/// The number of blocks that can be queued for the tap before blocks are dropped, about 6 seconds
+/// at 44.1kHz.
+const TAP_QUEUE: usize = 1024;
+
+/// The mixer sums the audio of any number of inputs into a single output stream.
+///
+/// All inputs are converted to stereo floating point samples at the rate of the mixer before they
+/// are summed. Because every input is read by the same thread, the latency between inputs is
+/// fixed, which makes it possible to precisely line up concurrently playing tracks.
+///
+/// The mixer acts as an `output::Output` itself, so it can be used in place of the output it
+/// feeds. That output is the final master stage.
+pub struct Mixer {
+    sample_rate: u32,
+    inputs: Arc<Mutex<Vec<Arc<Mutex<Input>>>>>,
+    master: Arc<Mutex<Control>>,
+    meter: meter::Snapshot,
+    spectrum: bands::Feed,
+    equalizer: Arc<Mutex<Vec<eq::Band>>>,
+    limiter: Arc<Mutex<dynamics::LimiterParams>>,
+    /// The number of frames by which the limiter delays the output.
+    limiter_latency: usize,
+    tap: Arc<Mutex<Option<TapSender>>>,
+    stream: Arc<Mutex<Box<output::Stream>>>,
+}
+
+impl Mixer {
+    /// Starts a new mixer that writes to the specified output. Initially, no inputs are present
+    /// and the mixer will output silence.
+    pub fn new(output: &output::Output, sample_rate: u32) -> Result<Mixer, Box<error::Error>> {
+        let inputs = Arc::new(Mutex::new(Vec::new()));
+        let master = Arc::new(Mutex::new(Control::default()));
+        let meter = meter::Snapshot::new(2);
+        let spectrum = bands::Feed::new(sample_rate, 2);
+        let equalizer = Arc::new(Mutex::new(Vec::new()));
+        let limiter_params = dynamics::LimiterParams::default();
+        let limiter = dynamics::Limiter::new(sample_rate, 2, LIMITER_LOOKAHEAD, &limiter_params);
+        let limiter_latency = limiter.latency();
+        let limiter_params = Arc::new(Mutex::new(limiter_params));
+        let tap = Arc::new(Mutex::new(None));
+        let bus = Bus {
+            sample_rate,
+            inputs: inputs.clone(),
+            mixing: Vec::new(),
+            removed: Vec::new(),
+            master: master.clone(),
+            meter: meter::LevelMeter::new(sample_rate, meter.clone()),
+            spectrum: spectrum.clone(),
+            equalizer: equalizer.clone(),
+            eq: eq::Chain::new(sample_rate, 2),
+            limiter_params: limiter_params.clone(),
+            limiter: Some(limiter),
+            tap: tap.clone(),
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+            cursor: 0,
+        };
+        let stream = output.consume(
+            dynam::Source::StereoF64(Box::from(bus)),
+            Arc::new(|event| {
+                if let output::Event::Error(err) = event {
+                    error!("master output: {}", err);
+                }
+            }),
+        )?;
+        Ok(Mixer {
+            sample_rate,
+            inputs,
+            master,
+            meter,
+            spectrum,
+            equalizer,
+            limiter: limiter_params,
+            limiter_latency,
+            tap,
+            stream: Arc::new(Mutex::new(stream)),
+        })
+    }
+
+    /// Returns the sample rate at which all inputs are mixed.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns the number of inputs that are currently being mixed.
+    pub fn num_inputs(&self) -> usize {
+        self.inputs.lock().unwrap().len()
+    }
+
+    /// Returns the gain applied to the sum of all inputs.
+    pub fn volume(&self) -> f64 {
+        self.master.lock().unwrap().gain
+    }
+
+    pub fn set_volume(&mut self, volume: f64) {
+        self.master.lock().unwrap().gain = volume;
+    }
+
+    pub fn mute(&self) -> bool {
+        self.master.lock().unwrap().mute
+    }
+
+    pub fn set_mute(&mut self, mute: bool) {
+        self.master.lock().unwrap().mute = mute;
+    }
+
+    /// Returns the peak levels of the left and right channel of the master output.
+    pub fn levels(&self) -> [f64; 2] {
+        self.master.lock().unwrap().levels
+    }
+
+    /// Returns the peak, RMS and loudness of the master output. Unlike `levels`, this is measured
+    /// over sliding windows, which makes it suitable for meters that are polled for display.
+    pub fn meter(&self) -> meter::Levels {
+        self.meter.levels()
+    }
+
+    /// Calls the handler with the levels of the frequency bands of the master output until the
+    /// returned subscription is dropped.
+    pub fn subscribe_spectrum(
+        &self,
+        params: &bands::Params,
+        handler: Arc<Fn(&[f64]) + Send + Sync>,
+    ) -> bands::Subscription {
+        self.spectrum.subscribe(params, handler)
+    }
+
+    /// Returns the bands of the equalizer that is applied to the sum of all inputs.
+    pub fn equalizer(&self) -> Vec<eq::Band> {
+        self.equalizer.lock().unwrap().clone()
+    }
+
+    pub fn set_equalizer(&mut self, bands: Vec<eq::Band>) {
+        *self.equalizer.lock().unwrap() = bands;
+    }
+
+    /// Returns the parameters of the limiter that prevents the master output from clipping.
+    pub fn limiter(&self) -> dynamics::LimiterParams {
+        *self.limiter.lock().unwrap()
+    }
+
+    pub fn set_limiter(&mut self, params: dynamics::LimiterParams) {
+        *self.limiter.lock().unwrap() = params;
+    }
+
+    /// Starts copying the master output to the returned source, replacing the previous tap.
+    ///
+    /// The copied audio is queued until it is read, so the master output is never held up by a
+    /// slow reader. The queue is bounded: blocks that do not fit are dropped and reported by the
+    /// tap. The tap ends once it is detached and the queue has been read.
+    pub fn tap(&mut self) -> Tap {
+        let (sender, tap) = tap_channel(self.sample_rate, TAP_QUEUE);
+        *self.tap.lock().unwrap() = Some(sender);
+        tap
+    }
+
+    /// Detaches the tap, if any.
+    pub fn untap(&mut self) {
+        *self.tap.lock().unwrap() = None;
+    }
+
+    /// Returns the number of frames that have been copied to the tap, if any.
+    pub fn tapped_frames(&self) -> Option<u64> {
+        self.tap.lock().unwrap().as_ref().map(|tap| tap.frames)
+    }
+
+    /// Returns the latency of the master output, including the buffering done by the mixer and
+    /// the look-ahead of the limiter.
+    pub fn latency(&self) -> Result<time::Duration, Box<error::Error>> {
+        let output = self.stream.lock().unwrap().latency()?;
+        let buffered = BLOCK_SIZE + self.limiter_latency;
+        Ok(output + duration_of(self.sample_rate, buffered as u64))
+    }
+}
+
+impl output::Output for Mixer {
+    /// Adds the source as an input of the mixer.
+    ///
+    /// The returned stream controls the gain, mute and levels of the input. Dropping the stream
+    /// removes the input from the mixer.
+    fn consume(
+        &self,
+        source: dynam::Source,
+        event_handler: Arc<Fn(output::Event) + Send + Sync>,
+    ) -> Result<Box<output::Stream>, Box<error::Error>> {
+        let source = into_stereo(source);
+        let source: Box<Source<Item = [f64; 2]> + Send> =
+            if source.sample_rate() == self.sample_rate {
+                source
+            } else {
+                debug!(
+                    "resampling mixer input from {}hz to {}hz",
+                    source.sample_rate(),
+                    self.sample_rate
+                );
+                Box::from(source.resample(self.sample_rate))
+            };
+        let control = Arc::new(Mutex::new(Control::default()));
+        self.inputs.lock().unwrap().push(Arc::new(Mutex::new(Input {
+            source,
+            control: control.clone(),
+            event_handler: event_handler.clone(),
+        })));
+        Ok(Box::new(Channel {
+            sample_rate: self.sample_rate,
+            limiter_latency: self.limiter_latency,
+            control,
+            stream: self.stream.clone(),
+            event_handler,
+        }))
+    }
+}
+
+/// The parameters of either a single input or the master output.
+#[derive(Clone, Debug)]
+struct Control {
+    gain: f64,
+    mute: bool,
+    /// The peak levels of the most recently mixed block.
+    levels: [f64; 2],
+    /// Set when the stream handle of an input is dropped.
+    closed: bool,
+}
+
+impl Default for Control {
+    fn default() -> Control {
+        Control {
+            gain: 1.0,
+            mute: false,
+            levels: [0.0; 2],
+            closed: false,
+        }
+    }
+}
+
+struct Input {
+    source: Box<Source<Item = [f64; 2]> + Send>,
+    control: Arc<Mutex<Control>>,
+    event_handler: Arc<Fn(output::Event) + Send + Sync>,
+}
+
+impl Input {
+    /// Adds the next block of audio to the buffer. Returns true if the input should be removed
+    /// from the mixer.
+    fn mix_into(&mut self, buffer: &mut [[f64; 2]]) -> bool {
+        let (gain, mute) = {
+            let control = self.control.lock().unwrap();
+            if control.closed {
+                return true;
+            }
+            (control.gain, control.mute)
+        };
+
+        let mut levels = [0.0; 2];
+        let mut ended = false;
+        for out in buffer.iter_mut() {
+            let frame = match self.source.next() {
+                Some(frame) => frame,
+                None => {
+                    ended = true;
+                    break;
+                }
+            };
+            for ch in 0..2 {
+                let s = frame[ch] * gain;
+                levels[ch] = f64::max(levels[ch], s.abs());
+                if !mute {
+                    out[ch] += s;
+                }
+            }
+        }
+        self.control.lock().unwrap().levels = levels;
+        ended
+    }
+}
+
+/// The bus is the source that is read by the master output.
+struct Bus {
+    sample_rate: u32,
+    /// The inputs are shared with the mixer, which adds them. Each input is locked separately, so
+    /// the list is only locked briefly and adding an input does not wait for a block to be mixed.
+    inputs: Arc<Mutex<Vec<Arc<Mutex<Input>>>>>,
+    /// The inputs that are mixed into the current block, kept to reuse its allocation.
+    mixing: Vec<Arc<Mutex<Input>>>,
+    /// The inputs that have ended during the current block, kept to reuse its allocation.
+    removed: Vec<Arc<Mutex<Input>>>,
+    master: Arc<Mutex<Control>>,
+    /// Measures the master output after the limiter.
+    meter: meter::LevelMeter,
+    /// Receives the master output as it is sent to the output.
+    spectrum: bands::Feed,
+    equalizer: Arc<Mutex<Vec<eq::Band>>>,
+    /// The equalizer is applied before the master gain.
+    eq: eq::Chain,
+    limiter_params: Arc<Mutex<dynamics::LimiterParams>>,
+    /// The limiter is applied after the master gain, where the signal would clip.
+    limiter: Option<dynamics::Limiter>,
+    tap: Arc<Mutex<Option<TapSender>>>,
+    buffer: Vec<[f64; 2]>,
+    /// The index of the next frame to read from the buffer.
+    cursor: usize,
+}
+
+impl Bus {
+    fn render(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(BLOCK_SIZE, [0.0; 2]);
+        self.cursor = 0;
+
+        self.mixing.clear();
+        self.mixing
+            .extend(self.inputs.lock().unwrap().iter().cloned());
+        for input in self.mixing.drain(..) {
+            if input.lock().unwrap().mix_into(&mut self.buffer) {
+                self.removed.push(input);
+            }
+        }
+        if !self.removed.is_empty() {
+            let removed = &self.removed;
+            self.inputs
+                .lock()
+                .unwrap()
+                .retain(|input| !removed.iter().any(|r| Arc::ptr_eq(r, input)));
+        }
+        // The removed inputs are dropped after the lock has been released, so adding new inputs
+        // is not blocked by the teardown of the old ones.
+        for input in self.removed.drain(..) {
+            let input = input.lock().unwrap();
+            if !input.control.lock().unwrap().closed {
+                (input.event_handler)(output::Event::End);
+            }
+        }
+
+        self.eq.set_bands(&self.equalizer.lock().unwrap());
+        if let Some(ref mut limiter) = self.limiter {
+            limiter.set_params(&self.limiter_params.lock().unwrap());
+        }
+
+        let mut master = self.master.lock().unwrap();
+        let mut levels = [0.0; 2];
+        for frame in self.buffer.iter_mut() {
+            self.eq.process(frame);
+            for s in frame.iter_mut() {
+                *s *= master.gain;
+            }
+            if let Some(ref mut limiter) = self.limiter {
+                limiter.process(frame);
+            }
+            self.meter.process(frame);
+            for ch in 0..2 {
+                levels[ch] = f64::max(levels[ch], frame[ch].abs());
+                if master.mute {
+                    frame[ch] = 0.0;
+                }
+            }
+        }
+        master.levels = levels;
+        self.spectrum.process(self.buffer.iter().map(|f| &f[..]));
+
+        let mut tap = self.tap.lock().unwrap();
+        let detached = match *tap {
+            Some(ref mut tap) => !tap.send(&self.buffer),
+            None => false,
+        };
+        if detached {
+            *tap = None;
+        }
+    }
+}
+
+impl iter::Iterator for Bus {
+    type Item = [f64; 2];
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor >= self.buffer.len() {
+            self.render();
+        }
+        let frame = self.buffer[self.cursor];
+        self.cursor += 1;
+        Some(frame)
+    }
+}
+
+impl Source for Bus {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// Creates the sending end of a tap along with the tap itself. At most `queue` blocks are
+/// buffered, for which the buffers are allocated up front.
+fn tap_channel(sample_rate: u32, queue: usize) -> (TapSender, Tap) {
+    let (sender, receiver) = mpsc::sync_channel(queue);
+    let pool: Vec<_> = (0..queue).map(|_| Vec::with_capacity(BLOCK_SIZE)).collect();
+    let pool = Arc::new(Mutex::new(pool));
+    let sender = TapSender {
+        sender,
+        pool: pool.clone(),
+        frames: 0,
+        dropped: 0,
+    };
+    let tap = Tap {
+        sample_rate,
+        receiver,
+        pool,
+        block: Vec::new(),
+        cursor: 0,
+        dropped: 0,
+        fill_gaps: false,
+        gap: 0,
+    };
+    (sender, tap)
+}
+
+struct TapBlock {
+    /// The number of frames that were dropped right before this block.
+    dropped: u64,
+    /// The frames of the block. Empty if the block itself was dropped as well.
+    frames: Vec<[f64; 2]>,
+}
+
+struct TapSender {
+    sender: mpsc::SyncSender<TapBlock>,
+    /// The buffers that are not in use by either end of the tap.
+    pool: Arc<Mutex<Vec<Vec<[f64; 2]>>>>,
+    /// The number of frames that have been copied, including those that were dropped.
+    frames: u64,
+    /// The number of frames that have been dropped since the last block was sent.
+    dropped: u64,
+}
+
+impl TapSender {
+    /// Copies the block into a free buffer and queues it without blocking. If no buffer is free
+    /// or the queue is full, the block is dropped. Returns false if the tap has been dropped.
+    fn send(&mut self, block: &[[f64; 2]]) -> bool {
+        self.frames += block.len() as u64;
+        let mut frames = match self.pool.lock().unwrap().pop() {
+            Some(mut frames) => {
+                frames.clear();
+                frames.extend_from_slice(block);
+                frames
+            }
+            None => {
+                // An empty block does not allocate and still carries the number of dropped
+                // frames.
+                self.dropped += block.len() as u64;
+                Vec::new()
+            }
+        };
+        let message = TapBlock {
+            dropped: self.dropped,
+            frames,
+        };
+        match self.sender.try_send(message) {
+            Ok(()) => {
+                self.dropped = 0;
+                true
+            }
+            Err(mpsc::TrySendError::Full(message)) => {
+                frames = message.frames;
+                if frames.capacity() > 0 {
+                    self.dropped += frames.len() as u64;
+                    self.pool.lock().unwrap().push(frames);
+                }
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// A copy of the master output, see `Mixer::tap`.
+pub struct Tap {
+    sample_rate: u32,
+    receiver: mpsc::Receiver<TapBlock>,
+    /// Shared with the sender, read buffers are returned here.
+    pool: Arc<Mutex<Vec<Vec<[f64; 2]>>>>,
+    /// The most recently received block.
+    block: Vec<[f64; 2]>,
+    /// The index of the next frame to read from the block.
+    cursor: usize,
+    /// The total number of frames that have been dropped.
+    dropped: u64,
+    /// Whether dropped frames are replaced by silence.
+    fill_gaps: bool,
+    /// The number of frames of silence to yield before the current block.
+    gap: u64,
+}
+
+impl Tap {
+    /// Replaces the frames that have been dropped with silence, so the number of frames that are
+    /// read matches the number of frames of the master output, see `Mixer::tapped_frames`.
+    pub fn fill_gaps(mut self) -> Tap {
+        self.fill_gaps = true;
+        self
+    }
+
+    /// Returns the number of frames of the master output that have been dropped because the tap
+    /// was not read fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl iter::Iterator for Tap {
+    type Item = [f64; 2];
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.gap > 0 {
+                self.gap -= 1;
+                return Some([0.0; 2]);
+            }
+            if self.cursor < self.block.len() {
+                let frame = self.block[self.cursor];
+                self.cursor += 1;
+                return Some(frame);
+            }
+            let block = self.receiver.recv().ok()?;
+            if block.dropped > 0 {
+                if self.fill_gaps {
+                    warn!(
+                        "tap: replacing {} dropped frames of the master output with silence",
+                        block.dropped
+                    );
+                    self.gap = block.dropped;
+                } else {
+                    warn!(
+                        "tap: dropped {} frames of the master output, the tap is not read fast enough",
+                        block.dropped
+                    );
+                }
+                self.dropped += block.dropped;
+            }
+            let read = mem::replace(&mut self.block, block.frames);
+            if read.capacity() > 0 {
+                self.pool.lock().unwrap().push(read);
+            }
+            self.cursor = 0;
+        }
+    }
+}
+
+impl Source for Tap {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// The stream handle of a single mixer input.
+struct Channel {
+    sample_rate: u32,
+    /// The number of frames by which the limiter of the master output delays the input.
+    limiter_latency: usize,
+    control: Arc<Mutex<Control>>,
+    stream: Arc<Mutex<Box<output::Stream>>>,
+    event_handler: Arc<Fn(output::Event) + Send + Sync>,
+}
+
+impl output::Stream for Channel {
+    fn volume(&self) -> Result<f64, Box<error::Error>> {
+        Ok(self.control.lock().unwrap().gain)
+    }
+
+    fn set_volume(&mut self, volume: f64) -> Result<(), Box<error::Error>> {
+        self.control.lock().unwrap().gain = volume;
+        (self.event_handler)(output::Event::Volume(volume));
+        Ok(())
+    }
+
+    fn mute(&self) -> Result<bool, Box<error::Error>> {
+        Ok(self.control.lock().unwrap().mute)
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(), Box<error::Error>> {
+        self.control.lock().unwrap().mute = mute;
+        Ok(())
+    }
+
+    fn levels(&self) -> Result<Vec<f64>, Box<error::Error>> {
+        Ok(self.control.lock().unwrap().levels.to_vec())
+    }
+
+    fn latency(&self) -> Result<time::Duration, Box<error::Error>> {
+        let output = self.stream.lock().unwrap().latency()?;
+        let buffered = BLOCK_SIZE + self.limiter_latency;
+        Ok(output + duration_of(self.sample_rate, buffered as u64))
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.control.lock().unwrap().closed = true;
+    }
+}
+
+/// Converts any source into a stereo floating point source. Mono sources are copied to both
+/// channels.
+fn into_stereo(source: dynam::Source) -> Box<Source<Item = [f64; 2]> + Send> {
+    fn mono<T>(source: Box<Source<Item = [T; 1]> + Send>) -> Box<Source<Item = [f64; 2]> + Send>
+    where
+        T: sample::Sample + sample::ToSample<f64> + 'static,
+    {
+        let sr = source.sample_rate();
+        Box::from(
+            source
+                .map(|f| -> [f64; 2] {
+                    let s: f64 = f[0].to_sample();
+                    [s, s]
+                })
+                .source(sr),
+        )
+    }
+    fn stereo<T>(source: Box<Source<Item = [T; 2]> + Send>) -> Box<Source<Item = [f64; 2]> + Send>
+    where
+        T: sample::Sample + sample::ToSample<f64> + 'static,
+    {
+        let sr = source.sample_rate();
+        Box::from(
+            source
+                .map(|f| -> [f64; 2] { [f[0].to_sample(), f[1].to_sample()] })
+                .source(sr),
+        )
+    }
+    match source {
+        dynam::Source::MonoI8(s) => mono(s),
+        dynam::Source::MonoU8(s) => mono(s),
+        dynam::Source::MonoI16(s) => mono(s),
+        dynam::Source::MonoU16(s) => mono(s),
+        dynam::Source::MonoI24(s) => mono(s),
+        dynam::Source::MonoU24(s) => mono(s),
+        dynam::Source::MonoI32(s) => mono(s),
+        dynam::Source::MonoU32(s) => mono(s),
+        dynam::Source::MonoI64(s) => mono(s),
+        dynam::Source::MonoU64(s) => mono(s),
+        dynam::Source::MonoF32(s) => mono(s),
+        dynam::Source::MonoF64(s) => mono(s),
+        dynam::Source::StereoI8(s) => stereo(s),
+        dynam::Source::StereoU8(s) => stereo(s),
+        dynam::Source::StereoI16(s) => stereo(s),
+        dynam::Source::StereoU16(s) => stereo(s),
+        dynam::Source::StereoI24(s) => stereo(s),
+        dynam::Source::StereoU24(s) => stereo(s),
+        dynam::Source::StereoI32(s) => stereo(s),
+        dynam::Source::StereoU32(s) => stereo(s),
+        dynam::Source::StereoI64(s) => stereo(s),
+        dynam::Source::StereoU64(s) => stereo(s),
+        dynam::Source::StereoF32(s) => stereo(s),
+        dynam::Source::StereoF64(s) => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus(inputs: Vec<Input>) -> Bus {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| Arc::new(Mutex::new(input)))
+            .collect();
+        Bus {
+            sample_rate: 44100,
+            inputs: Arc::new(Mutex::new(inputs)),
+            mixing: Vec::new(),
+            removed: Vec::new(),
+            master: Arc::new(Mutex::new(Control::default())),
+            meter: meter::LevelMeter::new(44100, meter::Snapshot::new(2)),
+            spectrum: bands::Feed::new(44100, 2),
+            equalizer: Arc::new(Mutex::new(Vec::new())),
+            eq: eq::Chain::new(44100, 2),
+            limiter_params: Arc::new(Mutex::new(dynamics::LimiterParams::default())),
+            limiter: None,
+            tap: Arc::new(Mutex::new(None)),
+            buffer: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    fn input(value: f64, len: usize) -> (Input, Arc<Mutex<Control>>) {
+        let control = Arc::new(Mutex::new(Control::default()));
+        let input = Input {
+            source: Box::from(iter::repeat([value; 2]).take(len).source(44100)),
+            control: control.clone(),
+            event_handler: Arc::new(|_| ()),
+        };
+        (input, control)
+    }
+
+    #[test]
+    fn sum() {
+        let (a, _) = input(0.25, 1000);
+        let (b, _) = input(0.5, 1000);
+        let frames: Vec<_> = bus(vec![a, b]).take(1000).collect();
+        assert!(frames.iter().all(|f| *f == [0.75; 2]));
+    }
+
+    #[test]
+    fn gain_and_mute() {
+        let (a, ca) = input(0.5, 1000);
+        let (b, cb) = input(0.5, 1000);
+        ca.lock().unwrap().gain = 0.5;
+        cb.lock().unwrap().mute = true;
+        let mut bus = bus(vec![a, b]);
+        let frames: Vec<_> = bus.by_ref().take(BLOCK_SIZE).collect();
+        assert!(frames.iter().all(|f| *f == [0.25; 2]));
+        assert_eq!([0.25; 2], ca.lock().unwrap().levels);
+        assert_eq!([0.5; 2], cb.lock().unwrap().levels);
+    }
+
+    #[test]
+    fn meter() {
+        let (a, _) = input(0.5, 44100);
+        let mut bus = bus(vec![a]);
+        let snapshot = meter::Snapshot::new(2);
+        bus.meter = meter::LevelMeter::new(44100, snapshot.clone());
+        bus.master.lock().unwrap().mute = true;
+        bus.take(44100).for_each(|_| ());
+        // The master output is measured before it is muted.
+        let levels = snapshot.levels();
+        let near = |v: &[f64]| v.iter().all(|&v| (v - 0.5).abs() < 1e-6);
+        assert!(near(&levels.peak) && near(&levels.rms));
+    }
+
+    #[test]
+    fn remove_ended() {
+        let (a, _) = input(1.0, 10);
+        let mut bus = bus(vec![a]);
+        let frames: Vec<_> = bus.by_ref().take(BLOCK_SIZE * 2).collect();
+        assert_eq!([1.0; 2], frames[9]);
+        assert_eq!([0.0; 2], frames[10]);
+        assert_eq!(0, bus.inputs.lock().unwrap().len());
+    }
+
+    #[test]
+    fn add_while_mixing() {
+        let mut bus = bus(Vec::new());
+        // The input checks that the list of inputs is not locked while it is being read.
+        let inputs = Arc::downgrade(&bus.inputs);
+        let source = (0..1000).map(move |_| {
+            let inputs = inputs.upgrade().unwrap();
+            assert!(inputs.try_lock().is_ok());
+            [0.5; 2]
+        });
+        let (mut a, _) = input(0.0, 0);
+        a.source = Box::from(source.source(44100));
+        bus.inputs.lock().unwrap().push(Arc::new(Mutex::new(a)));
+        let frames: Vec<_> = bus.take(1000).collect();
+        assert!(frames.iter().all(|f| *f == [0.5; 2]));
+    }
+
+    #[test]
+    fn limiter() {
+        let (a, _) = input(0.75, 10000);
+        let (b, _) = input(0.75, 10000);
+        let mut bus = bus(vec![a, b]);
+        let params = dynamics::LimiterParams::default();
+        bus.limiter = Some(dynamics::Limiter::new(44100, 2, LIMITER_LOOKAHEAD, &params));
+        let ceiling = gain::db_to_factor(params.ceiling);
+        let frames: Vec<_> = bus.take(10000).collect();
+        assert!(frames
+            .iter()
+            .all(|f| f[0].abs() <= ceiling && f[1].abs() <= ceiling));
+        assert!(frames[5000][0] > ceiling * 0.99);
+    }
+
+    #[test]
+    fn tap() {
+        let (a, _) = input(0.5, 10000);
+        let mut bus = bus(vec![a]);
+        let (sender, tap) = tap_channel(44100, TAP_QUEUE);
+        *bus.tap.lock().unwrap() = Some(sender);
+        let output: Vec<_> = bus.by_ref().take(BLOCK_SIZE * 4).collect();
+        assert_eq!(
+            Some(BLOCK_SIZE as u64 * 4),
+            bus.tap.lock().unwrap().as_ref().map(|t| t.frames)
+        );
+        *bus.tap.lock().unwrap() = None;
+        let tapped: Vec<_> = tap.collect();
+        assert_eq!(output, tapped);
+    }
+
+    #[test]
+    fn tap_overflow() {
+        let (mut a, _) = input(0.0, 0);
+        a.source = Box::from((0..10000).map(|i| [i as f64 / 10000.0; 2]).source(44100));
+        let mut bus = bus(vec![a]);
+        let (sender, mut tap) = tap_channel(44100, 2);
+        *bus.tap.lock().unwrap() = Some(sender);
+
+        // The third block does not fit.
+        let output: Vec<_> = bus.by_ref().take(BLOCK_SIZE * 3).collect();
+        let head: Vec<_> = tap.by_ref().take(BLOCK_SIZE * 2).collect();
+        assert_eq!(&output[..BLOCK_SIZE * 2], &head[..]);
+        assert_eq!(0, tap.dropped());
+
+        // Reading the tap has freed a buffer for the fourth block.
+        let output: Vec<_> = bus.by_ref().take(BLOCK_SIZE).collect();
+        *bus.tap.lock().unwrap() = None;
+        let tail: Vec<_> = tap.by_ref().collect();
+        assert_eq!(output, tail);
+        assert_eq!(BLOCK_SIZE as u64, tap.dropped());
+    }
+
+    #[test]
+    fn tap_fill_gaps() {
+        let (mut a, _) = input(0.0, 0);
+        a.source = Box::from((1..10000).map(|i| [i as f64 / 10000.0; 2]).source(44100));
+        let mut bus = bus(vec![a]);
+        let (sender, tap) = tap_channel(44100, 2);
+        let mut tap = tap.fill_gaps();
+        *bus.tap.lock().unwrap() = Some(sender);
+
+        let output: Vec<_> = bus.by_ref().take(BLOCK_SIZE * 3).collect();
+        let head: Vec<_> = tap.by_ref().take(BLOCK_SIZE * 2).collect();
+        let output2: Vec<_> = bus.by_ref().take(BLOCK_SIZE).collect();
+        *bus.tap.lock().unwrap() = None;
+        let tail: Vec<_> = tap.by_ref().collect();
+        assert_eq!(&output[..BLOCK_SIZE * 2], &head[..]);
+        assert!(tail[..BLOCK_SIZE].iter().all(|f| *f == [0.0; 2]));
+        assert_eq!(output2, &tail[BLOCK_SIZE..]);
+        assert_eq!(BLOCK_SIZE as u64, tap.dropped());
+    }
+
+    #[test]
+    fn mono_to_stereo() {
+        let source = dynam::Source::MonoI16(Box::from(
+            iter::repeat([i16::max_value()]).take(10).source(44100),
+        ));
+        let frames: Vec<_> = into_stereo(source).collect();
+        assert_eq!(10, frames.len());
+        assert!(frames.iter().all(|f| f[0] == f[1] && f[0] > 0.99));
+    }
+}
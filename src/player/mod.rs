@@ -1,15 +1,27 @@
+use crate::analysis::bands;
+use crate::audio::buffer;
 use crate::audio::*;
-use crate::library;
+use crate::filter;
+use crate::library::{self, Identity, TrackInfo};
 use log::*;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::sync::{Arc, Mutex, Weak};
+use std::sync::{mpsc, Arc, Mutex, Weak};
 use std::*;
+use xdg;
 
+pub mod beatsync;
+pub mod mixer;
 pub mod output;
 pub mod playback;
+pub mod session;
 pub use self::playback::*;
 
+/// The sample rate at which all playing audio is mixed.
+const SAMPLE_RATE: u32 = 44_100;
+/// The interval at which the position of playing audio is reported.
+const POSITION_INTERVAL: time::Duration = time::Duration::from_millis(200);
+
 /// A player manages the playback of audio from a list of audio. Multple tracks can be played at
 /// once to make mixing and crossfading possible.
 ///
@@ -31,7 +43,8 @@ pub struct Player {
     /// Used for generating the next playback key.
     gen_next_id: u64,
 
-    output: Box<output::Output + Send>,
+    /// All playbacks are inputs of the mixer, which writes to the output.
+    mixer: mixer::Mixer,
 
     pub queue: Vec<library::Audio>,
     pub queue_autofill: Box<iter::Iterator<Item = library::Audio> + Send>,
@@ -39,6 +52,28 @@ pub struct Player {
 
     pub libraries: Vec<Arc<library::Library>>,
 
+    /// Named equalizer settings that can be applied to playbacks or the master output.
+    equalizer_presets: BTreeMap<String, Vec<filter::eq::Band>>,
+    /// The file in which presets that are not builtin are stored, if it could be located.
+    equalizer_presets_file: Option<path::PathBuf>,
+
+    /// The buffering applied to streams, which are read in realtime.
+    pub stream_buffer: buffer::Params,
+
+    replay_gain_mode: ReplayGainMode,
+    /// The gain in dB that is added to the ReplayGain of every track.
+    replay_gain_preamp: f64,
+
+    /// Playbacks of which the tempo and beats are locked to another playback, by follower ID.
+    beat_sync: BTreeMap<u64, beatsync::Link>,
+    /// Whether the thread that keeps synchronised playbacks aligned is running.
+    beat_sync_running: bool,
+    /// Whether the thread that periodically reports the position of playbacks is running.
+    position_reporter_running: bool,
+
+    /// The recording of the master output, if any.
+    session: Option<session::Session>,
+
     /// A weak reference to this player to be used in event handlers.
     weak_self: Weak<Mutex<Player>>,
 }
@@ -47,27 +82,210 @@ impl Player {
     pub fn new(
         output: Box<output::Output + Send>,
         libraries: Vec<Arc<library::Library>>,
-    ) -> Arc<Mutex<Player>> {
+    ) -> Result<Arc<Mutex<Player>>, Error> {
         let p = Arc::new(Mutex::new(Player {
             playing: BTreeMap::new(),
             gen_next_id: 0,
-            output,
+            mixer: mixer::Mixer::new(&*output, SAMPLE_RATE)?,
             queue: Vec::new(),
             queue_cursor: None,
             queue_autofill: Box::from(iter::empty()),
             libraries,
+            equalizer_presets: filter::eq::presets(),
+            equalizer_presets_file: None,
+            stream_buffer: buffer::Params::default(),
+            replay_gain_mode: ReplayGainMode::Track,
+            replay_gain_preamp: 0.0,
+            beat_sync: BTreeMap::new(),
+            beat_sync_running: false,
+            position_reporter_running: false,
+            session: None,
             weak_self: Weak::new(),
         }));
-        p.lock().unwrap().weak_self = Arc::downgrade(&p);
-        p
+        {
+            let mut player = p.lock().unwrap();
+            player.weak_self = Arc::downgrade(&p);
+            player.load_equalizer_presets();
+        }
+        Ok(p)
+    }
+
+    /// Returns the mixer through which the master output can be controlled.
+    pub fn mixer(&self) -> &mixer::Mixer {
+        &self.mixer
+    }
+
+    pub fn mixer_mut(&mut self) -> &mut mixer::Mixer {
+        &mut self.mixer
+    }
+
+    /// Returns the builtin equalizer presets and those saved by the user.
+    pub fn equalizer_presets(&self) -> &BTreeMap<String, Vec<filter::eq::Band>> {
+        &self.equalizer_presets
+    }
+
+    /// Adds or replaces a preset. Presets are stored in `equalizer.toml` in the user's
+    /// configuration directory, so they are retained across restarts.
+    pub fn save_equalizer_preset(
+        &mut self,
+        name: &str,
+        bands: Vec<filter::eq::Band>,
+    ) -> Result<(), Error> {
+        self.equalizer_presets.insert(name.to_string(), bands);
+        self.store_equalizer_presets()
+    }
+
+    /// Removes a preset. Builtin presets are restored to their default.
+    pub fn remove_equalizer_preset(&mut self, name: &str) -> Result<(), Error> {
+        match filter::eq::presets().remove(name) {
+            Some(bands) => self.equalizer_presets.insert(name.to_string(), bands),
+            None => self.equalizer_presets.remove(name),
+        };
+        self.store_equalizer_presets()
+    }
+
+    fn load_equalizer_presets(&mut self) {
+        let file = xdg::BaseDirectories::with_prefix(env!("CARGO_PKG_NAME"))
+            .map_err(Box::<error::Error>::from)
+            .and_then(|dirs| Ok(dirs.place_config_file("equalizer.toml")?));
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("Could not locate the equalizer presets: {}", err);
+                return;
+            }
+        };
+        if file.exists() {
+            let presets = fs::read_to_string(&file)
+                .map_err(Box::<error::Error>::from)
+                .and_then(|text| Ok(filter::eq::parse_presets(&text)?));
+            match presets {
+                Ok(presets) => self.equalizer_presets.extend(presets),
+                Err(err) => warn!("Could not read {}: {}", file.display(), err),
+            }
+        }
+        self.equalizer_presets_file = Some(file);
+    }
+
+    /// Writes the presets that differ from the builtin ones to the presets file.
+    fn store_equalizer_presets(&self) -> Result<(), Error> {
+        let file = match self.equalizer_presets_file {
+            Some(ref file) => file,
+            None => return Ok(()),
+        };
+        let builtin = filter::eq::presets();
+        let custom = self
+            .equalizer_presets
+            .iter()
+            .filter(|&(name, bands)| builtin.get(name) != Some(bands))
+            .map(|(name, bands)| (name.clone(), bands.clone()))
+            .collect();
+        let text = filter::eq::format_presets(&custom).map_err(|err| Error::Other(err.into()))?;
+        fs::write(file, text).map_err(|err| Error::Other(err.into()))
+    }
+
+    /// Calls the handler with the levels of the frequency bands of a playback, or of the master
+    /// output if no ID is given, until the returned subscription is dropped. The handler is
+    /// called from an analysis thread at the rate set by the params.
+    pub fn subscribe_spectrum(
+        &self,
+        id: Option<u64>,
+        params: &bands::Params,
+        handler: Arc<Fn(&[f64]) + Send + Sync>,
+    ) -> Result<bands::Subscription, Error> {
+        match id {
+            Some(id) => {
+                let (_, pb, _) = self.playing.get(&id).ok_or(Error::NotPlaying(id))?;
+                Ok(pb.subscribe_spectrum(params, handler))
+            }
+            None => Ok(self.mixer.subscribe_spectrum(params, handler)),
+        }
+    }
+
+    pub fn replay_gain_mode(&self) -> ReplayGainMode {
+        self.replay_gain_mode
+    }
+
+    /// Sets the ReplayGain mode. The gain of the tracks that are playing is updated.
+    pub fn set_replay_gain_mode(&mut self, mode: ReplayGainMode) {
+        self.replay_gain_mode = mode;
+        self.update_replay_gain();
+    }
+
+    pub fn replay_gain_preamp(&self) -> f64 {
+        self.replay_gain_preamp
     }
 
-    /// Sets up playback for the specified track. The initial state is set to paused.
-    fn init_playback(&mut self, audio: &library::Audio) -> Result<(u64, &mut Playback), Error> {
+    /// Sets the gain in dB that is added to the ReplayGain of every track. The gain is still
+    /// limited to prevent clipping.
+    pub fn set_replay_gain_preamp(&mut self, preamp: f64) {
+        self.replay_gain_preamp = preamp;
+        self.update_replay_gain();
+    }
+
+    fn update_replay_gain(&mut self) {
+        let cursor = self
+            .queue_cursor
+            .and_then(|i| self.queue.get(i).map(|audio| (i, audio.id())));
+        let gains: Vec<(u64, f64)> = self
+            .playing
+            .iter()
+            .map(|(&id, &(ref audio, _, _))| {
+                // The playback of the track at the queue cursor is the one started from the
+                // queue.
+                let index = cursor
+                    .as_ref()
+                    .filter(|(_, cur_id)| *cur_id == audio.id())
+                    .map(|&(i, _)| i);
+                (id, self.replay_gain(audio, index))
+            })
+            .collect();
+        for (id, gain) in gains {
+            self.playing.get_mut(&id).unwrap().1.set_gain(gain);
+        }
+    }
+
+    /// Computes the gain in dB to apply to the audio according to the ReplayGain mode. The index
+    /// is the position of the audio in the queue, if it is played from the queue.
+    fn replay_gain(&self, audio: &library::Audio, index: Option<usize>) -> f64 {
+        let track = match audio.track() {
+            Some(track) => track,
+            None => return 0.0,
+        };
+        let rg = match track.replay_gain() {
+            Some(rg) => rg,
+            None => return 0.0,
+        };
+        let album = match self.replay_gain_mode {
+            ReplayGainMode::Off => return 0.0,
+            ReplayGainMode::Track => false,
+            ReplayGainMode::Album => index
+                .map(|i| {
+                    let neighbour = |j: Option<usize>| {
+                        j.and_then(|j| self.queue.get(j))
+                            .and_then(|a| a.track())
+                            .map(|other| same_album(track, other))
+                            .unwrap_or(false)
+                    };
+                    neighbour(i.checked_sub(1)) || neighbour(Some(i + 1))
+                })
+                .unwrap_or(false),
+        };
+        replay_gain_db(&rg, album, self.replay_gain_preamp)
+    }
+
+    /// Sets up playback for the specified track with the gain in dB. The initial state is set to
+    /// paused.
+    fn init_playback(
+        &mut self,
+        audio: &library::Audio,
+        gain: f64,
+    ) -> Result<(u64, &mut Playback), Error> {
         self.gen_next_id += 1;
         let id = self.gen_next_id;
 
         let weak = self.weak_self.clone();
+        let mut buffer = self.stream_buffer;
         let signal: dynam::Audio = match audio {
             library::Audio::Track(ref track) => track.audio()?.into(),
             library::Audio::Stream(ref stream) => {
@@ -83,46 +301,72 @@ impl Player {
                         }
                     });
                 });
+                buffer = stream.buffer().unwrap_or(buffer);
                 stream.open(on_info)?.into()
             }
         };
 
+        // Because mutations performed on the player may fire event that will in turn mutate the
+        // player, the events are handled asynchronously to prevent deadlocks. A single thread
+        // handles all events of the playback in order, it exits once the playback is dropped.
+        let (events, receiver) = mpsc::channel();
         let weak = self.weak_self.clone();
-        let playback = Playback::new(
-            signal,
-            &*self.output,
-            Arc::new(move |event| {
+        thread::spawn(move || {
+            for event in receiver {
                 let arc = match weak.upgrade() {
                     Some(arc) => arc,
                     None => return,
                 };
-                // Because mutations performed on the player may fire event that will in turn mutate
-                // the player, the handler is run asynchronously to prevent deadlocks.
-                thread::spawn(move || {
-                    let mut player = arc.lock().unwrap();
-                    match event {
-                        playback::Event::Output(output::Event::End) => {
-                            // Only advance the queue cursor if the track naturally ended.
-                            if let Err(err) = player.play_next_from_queue() {
-                                error!("{}", err);
-                                // TODO: (player.event_handers)(Event::Error(err));
-                            }
-                        }
-                        playback::Event::Output(output::Event::Error(err)) => {
+                let mut player = arc.lock().unwrap();
+                match event {
+                    playback::Event::Output(output::Event::End) => {
+                        // Only advance the queue cursor if the track naturally ended.
+                        if let Err(err) = player.play_next_from_queue() {
                             error!("{}", err);
+                            // TODO: (player.event_handers)(Event::Error(err));
+                        }
+                    }
+                    playback::Event::Output(output::Event::Error(err)) => {
+                        error!("{}", err);
+                    }
+                    playback::Event::State(state) => {
+                        // GC tracks that have been stopped.
+                        if state == State::Stopped {
+                            player.playing.remove(&id);
+                            player.beat_sync.remove(&id);
                         }
-                        playback::Event::State(state) => {
-                            // GC tracks that have been stopped.
-                            if state == State::Stopped {
-                                player.playing.remove(&id);
-                            }
+                        if state == State::Playing {
+                            player.mark_session(id);
                         }
-                        _ => (),
                     }
-                });
+                    playback::Event::Buffer(buffer::Event::Underrun) => {
+                        warn!("buffer of playback {} ran empty", id);
+                    }
+                    playback::Event::Tempo(_) => {
+                        // Keep followers locked when the tempo of their leader changes.
+                        if player.beat_sync.values().any(|link| link.leader == id) {
+                            player.update_beat_sync();
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        });
+        let events = Mutex::new(events);
+        let mut playback = Playback::new(
+            signal,
+            &self.mixer,
+            buffer,
+            gain,
+            Arc::new(move |event| {
+                let _ = events.lock().unwrap().send(event);
             }),
         );
+        if let Some(track) = audio.track() {
+            playback.set_cues(track.cues());
+        }
         self.playing.insert(id, (audio.clone(), playback, None));
+        self.start_position_reporter();
         Ok((id, &mut self.playing.get_mut(&id).unwrap().1))
     }
 
@@ -133,7 +377,8 @@ impl Player {
         };
         self.playing.clear();
         self.queue_cursor = Some(index);
-        let (id, pb) = self.init_playback(&audio)?;
+        let gain = self.replay_gain(&audio, Some(index));
+        let (id, pb) = self.init_playback(&audio, gain)?;
         pb.set_state(State::Playing);
         Ok(Some((id, pb)))
     }
@@ -158,6 +403,267 @@ impl Player {
         }
         self.play_from_queue(index)
     }
+
+    /// Starts recording the master output to a file in the specified directory, along with a CUE
+    /// sheet of the tracks that are played. A session that is already being recorded is finished
+    /// first. Returns the path of the file.
+    pub fn start_session(
+        &mut self,
+        dir: &path::Path,
+        format: session::Format,
+    ) -> Result<path::PathBuf, Error> {
+        self.stop_session()?;
+        self.session = Some(session::Session::start(&mut self.mixer, dir, format)?);
+        let playing: Vec<u64> = self
+            .playing
+            .iter()
+            .filter(|(_, (_, pb, _))| pb.state() == State::Playing)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in playing {
+            self.mark_session(id);
+        }
+        Ok(self.session.as_ref().unwrap().path().to_path_buf())
+    }
+
+    /// Finishes the recording of the session, if any. Returns the path of the recorded file.
+    pub fn stop_session(&mut self) -> Result<Option<path::PathBuf>, Error> {
+        match self.session.take() {
+            Some(session) => Ok(Some(session.finish(&mut self.mixer)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the path of the file the session is being recorded to, if any.
+    pub fn session_path(&self) -> Option<&path::Path> {
+        self.session.as_ref().map(|session| session.path())
+    }
+
+    /// Adds the playback to the CUE sheet of the session if one is being recorded.
+    fn mark_session(&mut self, id: u64) {
+        let session = match self.session {
+            Some(ref mut session) => session,
+            None => return,
+        };
+        let (title, performer) = match self.playing.get(&id) {
+            Some(&(library::Audio::Track(ref track), _, _)) => {
+                (track.title().into_owned(), track.artists().join(", "))
+            }
+            Some(&(library::Audio::Stream(ref stream), _, ref info)) => match *info {
+                Some(ref info) => (info.title().into_owned(), info.artists().join(", ")),
+                None => (stream.title().into_owned(), String::new()),
+            },
+            None => return,
+        };
+        let performer = Some(performer).filter(|p| !p.is_empty());
+        if let Err(err) = session.mark(&self.mixer, id, Some(title), performer) {
+            error!("could not update the session CUE sheet: {}", err);
+        }
+    }
+
+    /// Locks the tempo and beats of the follower to those of the leader, using the beat grids
+    /// that were analysed for their tracks.
+    pub fn sync(&mut self, leader: u64, follower: u64) -> Result<(), Error> {
+        let grid = |id: u64| -> Result<beatsync::BeatGrid, Error> {
+            let track = self
+                .playing
+                .get(&id)
+                .ok_or(Error::NotPlaying(id))?
+                .0
+                .track()
+                .ok_or(Error::NoBeatGrid(id))?;
+            let bpm = track.bpm().ok_or(Error::NoBeatGrid(id))?;
+            Ok(beatsync::BeatGrid {
+                bpm,
+                offset: track.beat_offset().unwrap_or(0.0),
+            })
+        };
+        let (leader_grid, follower_grid) = (grid(leader)?, grid(follower)?);
+        self.sync_with(leader, follower, leader_grid, follower_grid)
+    }
+
+    /// Like `sync`, but with explicitly specified beat grids.
+    ///
+    /// The follower seeks to the nearest position at which its beats align with those of the
+    /// leader. After that, the phase is kept aligned by adjusting the tempo of the follower.
+    pub fn sync_with(
+        &mut self,
+        leader: u64,
+        follower: u64,
+        leader_grid: beatsync::BeatGrid,
+        follower_grid: beatsync::BeatGrid,
+    ) -> Result<(), Error> {
+        for &id in &[leader, follower] {
+            if !self.playing.contains_key(&id) {
+                return Err(Error::NotPlaying(id));
+            }
+        }
+        // Following a playback that is directly or indirectly following the follower would
+        // make the tempo of both depend on each other.
+        let mut cur = Some(leader);
+        while let Some(id) = cur {
+            if id == follower {
+                return Err(Error::SyncCycle);
+            }
+            cur = self.beat_sync.get(&id).map(|link| link.leader);
+        }
+
+        let link = beatsync::Link {
+            leader,
+            leader_grid,
+            follower_grid,
+        };
+        let (leader_time, leader_tempo) = {
+            let pb = &self.playing[&leader].1;
+            (beatsync::secs(pb.position_time()), pb.tempo())
+        };
+        {
+            let pb = &mut self.playing.get_mut(&follower).unwrap().1;
+            let time = link.aligned_time(leader_time, beatsync::secs(pb.position_time()));
+            pb.set_tempo(link.matched_tempo(leader_tempo));
+            // The audio that was read before seeking is played first, so the seek target is
+            // advanced by the time it takes for the new position to become audible.
+            let position = (time * f64::from(pb.sample_rate())).round() as u64 + pb.delay();
+            pb.set_position(position);
+        }
+        self.beat_sync.insert(follower, link);
+        self.start_beat_sync();
+        Ok(())
+    }
+
+    /// Releases the follower from its leader. The follower keeps playing at its current tempo.
+    pub fn unsync(&mut self, follower: u64) {
+        self.beat_sync.remove(&follower);
+    }
+
+    /// Returns the ID of the playback the specified playback is synchronised to, if any.
+    pub fn sync_leader(&self, follower: u64) -> Option<u64> {
+        self.beat_sync.get(&follower).map(|link| link.leader)
+    }
+
+    /// Starts the thread that fires position events for playing audio if it is not already
+    /// running. The thread exits once nothing is playing anymore.
+    fn start_position_reporter(&mut self) {
+        if self.position_reporter_running {
+            return;
+        }
+        self.position_reporter_running = true;
+        let weak = self.weak_self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(POSITION_INTERVAL);
+            let arc = match weak.upgrade() {
+                Some(arc) => arc,
+                None => return,
+            };
+            let mut player = arc.lock().unwrap();
+            if player.playing.is_empty() {
+                player.position_reporter_running = false;
+                return;
+            }
+            for (_, pb, _) in player.playing.values_mut() {
+                if pb.state() == State::Playing {
+                    pb.report_position();
+                }
+            }
+        });
+    }
+
+    /// Periodically updates the synchronised playbacks until there are none left.
+    fn start_beat_sync(&mut self) {
+        if self.beat_sync_running {
+            return;
+        }
+        self.beat_sync_running = true;
+        let weak = self.weak_self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(beatsync::INTERVAL);
+            let arc = match weak.upgrade() {
+                Some(arc) => arc,
+                None => return,
+            };
+            let mut player = arc.lock().unwrap();
+            player.update_beat_sync();
+            if player.beat_sync.is_empty() {
+                player.beat_sync_running = false;
+                return;
+            }
+        });
+    }
+
+    /// Measures the phase offset of all followers and adjusts their tempo to match their leaders.
+    fn update_beat_sync(&mut self) {
+        let stale: Vec<u64> = self
+            .beat_sync
+            .iter()
+            .filter(|(follower, link)| {
+                !self.playing.contains_key(*follower) || !self.playing.contains_key(&link.leader)
+            })
+            .map(|(&follower, _)| follower)
+            .collect();
+        for follower in stale {
+            self.beat_sync.remove(&follower);
+        }
+        let updates: Vec<(u64, f64, f64)> = self
+            .beat_sync
+            .iter()
+            .map(|(&follower, link)| {
+                let leader_pb = &self.playing[&link.leader].1;
+                let follower_pb = &self.playing[&follower].1;
+                let offset = link.phase_offset(
+                    beatsync::secs(leader_pb.position_time()),
+                    beatsync::secs(follower_pb.position_time()),
+                );
+                (
+                    follower,
+                    offset,
+                    link.corrected_tempo(leader_pb.tempo(), offset),
+                )
+            })
+            .collect();
+        for (follower, offset, tempo) in updates {
+            let pb = &mut self.playing.get_mut(&follower).unwrap().1;
+            if pb.state() != State::Playing {
+                continue;
+            }
+            if pb.tempo() != tempo {
+                pb.set_tempo(tempo);
+            }
+            pb.report_phase(offset);
+        }
+    }
+}
+
+/// Determines which ReplayGain value is applied during playback.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    Off,
+    /// Every track is played at the same loudness.
+    Track,
+    /// Tracks played in the order of their album retain the loudness differences between them.
+    /// Other tracks are played using the track gain.
+    Album,
+}
+
+/// Selects the track or album gain, adds the pre-amp and limits the result so the peak does not
+/// exceed full scale.
+fn replay_gain_db(rg: &library::ReplayGain, album: bool, preamp: f64) -> f64 {
+    let (gain, peak) = match (album, rg.album_gain) {
+        (true, Some(gain)) => (gain, rg.album_peak.unwrap_or(rg.track_peak)),
+        _ => (rg.track_gain, rg.track_peak),
+    };
+    let headroom = if peak > 0.0 {
+        -filter::gain::factor_to_db(peak)
+    } else {
+        f64::INFINITY
+    };
+    f64::min(gain + preamp, headroom)
+}
+
+/// Whether two tracks belong to the same album.
+fn same_album(a: &library::Track, b: &library::Track) -> bool {
+    a.album_title().is_some()
+        && a.album_title() == b.album_title()
+        && a.album_artists() == b.album_artists()
 }
 
 impl library::Playlist for Player {
@@ -242,12 +748,21 @@ impl library::PlaylistMut for Player {
 
 #[derive(Debug)]
 pub enum Error {
+    /// There is no playback with the ID.
+    NotPlaying(u64),
+    /// The tempo of the track of the playback with the ID is unknown.
+    NoBeatGrid(u64),
+    /// The playbacks are already synchronised the other way around.
+    SyncCycle,
     Other(Box<error::Error>),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Error::NotPlaying(id) => write!(f, "No playback with ID {}", id),
+            Error::NoBeatGrid(id) => write!(f, "The tempo of playback {} is unknown", id),
+            Error::SyncCycle => write!(f, "Playbacks can not follow each other"),
             Error::Other(ref err) => err.fmt(f),
         }
     }
@@ -268,3 +783,23 @@ impl From<Box<error::Error>> for Error {
         Error::Other(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_gain_clipping() {
+        let rg = library::ReplayGain {
+            track_gain: 6.0,
+            track_peak: 0.5,
+            album_gain: Some(3.0),
+            album_peak: Some(0.9),
+        };
+        assert!((replay_gain_db(&rg, false, 0.0) - 6.0).abs() < 0.03);
+        // The peak of 0.5 leaves about 6dB of headroom.
+        assert!((replay_gain_db(&rg, false, 3.0) - 6.02).abs() < 0.01);
+        assert!((replay_gain_db(&rg, true, -6.0) + 3.0).abs() < 1e-9);
+        assert!((replay_gain_db(&rg, true, 0.0) - 0.915).abs() < 0.01);
+    }
+}
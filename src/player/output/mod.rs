@@ -33,6 +33,15 @@ pub trait Stream: Send {
     /// Values range from 0.0 to 1.0 inclusive.
     fn set_volume(&mut self, volume: f64) -> Result<(), Box<error::Error>>;
 
+    /// Returns the value set by `set_mute()`. Streams are initially not muted.
+    fn mute(&self) -> Result<bool, Box<error::Error>>;
+    /// Silences the stream without altering the volume.
+    fn set_mute(&mut self, mute: bool) -> Result<(), Box<error::Error>>;
+
+    /// Returns the peak level of each channel of the audio that was most recently played. A level
+    /// of 1.0 or higher indicates clipping.
+    fn levels(&self) -> Result<Vec<f64>, Box<error::Error>>;
+
     /// Returns the approximate latency of the audio output or 0 if it can not be reliably
     /// determined.
     fn latency(&self) -> Result<time::Duration, Box<error::Error>>;
@@ -125,18 +125,32 @@ impl super::Output for Output {
     }
 }
 
+/// The number of frames over which the levels of a stream are determined.
+const LEVEL_BLOCK_SIZE: usize = 1024;
+
 struct Stream<S>
 where
     S: Source,
     S::Item: sample::Frame,
 {
-    /// The sink to which the stream is being written to along with a bit to indicate whether the
-    /// stream should be closed.
-    sink: Arc<Mutex<(pulse::Sink<S::Item>, bool)>>,
+    /// The sink to which the stream is being written to along with the state that is shared with
+    /// the writing thread.
+    sink: Arc<Mutex<SinkState<S::Item>>>,
 
     event_handler: Arc<Fn(output::Event) + Send + Sync>,
 }
 
+struct SinkState<F>
+where
+    F: sample::Frame,
+{
+    sink: pulse::Sink<F>,
+    /// Indicates whether the stream should be closed.
+    closed: bool,
+    mute: bool,
+    levels: Vec<f64>,
+}
+
 impl<S> Stream<S>
 where
     S: Source,
@@ -149,22 +163,45 @@ where
     where
         S: Source + Send + 'static,
         S::Item: sample::Frame + Send,
-        <S::Item as sample::Frame>::Sample: Sample + pulse::AsSampleFormat,
+        <S::Item as sample::Frame>::Sample: Sample + sample::ToSample<f64> + pulse::AsSampleFormat,
     {
         let app_name = format!("{} v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
         let pulse_sink = pulse::sink(&app_name, "TODO", source.sample_rate())?;
-        let sink = Arc::new(Mutex::new((pulse_sink, false)));
+        let sink = Arc::new(Mutex::new(SinkState {
+            sink: pulse_sink,
+            closed: false,
+            mute: false,
+            levels: vec![0.0; S::Item::n_channels()],
+        }));
 
         let eh_sub = event_handler.clone();
         let sub_handler = Arc::new(Mutex::new(eh_sub));
         let sink_out = sink.clone();
         thread::spawn(move || {
+            let mut peaks = vec![0.0; S::Item::n_channels()];
+            let mut num_measured = 0;
             for frame in source {
                 let mut out_state = sink_out.lock().unwrap();
-                if out_state.1 {
+                if out_state.closed {
                     return;
                 }
-                if let Err(err) = out_state.0.write_frame(frame) {
+
+                for (peak, s) in peaks.iter_mut().zip(frame.channels()) {
+                    *peak = f64::max(*peak, s.to_sample::<f64>().abs());
+                }
+                num_measured += 1;
+                if num_measured == LEVEL_BLOCK_SIZE {
+                    let n = peaks.len();
+                    out_state.levels = mem::replace(&mut peaks, vec![0.0; n]);
+                    num_measured = 0;
+                }
+
+                let frame = if out_state.mute {
+                    S::Item::equilibrium()
+                } else {
+                    frame
+                };
+                if let Err(err) = out_state.sink.write_frame(frame) {
                     sub_handler.lock().unwrap()(output::Event::Error(err));
                     return;
                 }
@@ -192,9 +229,22 @@ where
         unimplemented!();
     }
 
+    fn mute(&self) -> Result<bool, Box<error::Error>> {
+        Ok(self.sink.lock().unwrap().mute)
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(), Box<error::Error>> {
+        self.sink.lock().unwrap().mute = mute;
+        Ok(())
+    }
+
+    fn levels(&self) -> Result<Vec<f64>, Box<error::Error>> {
+        Ok(self.sink.lock().unwrap().levels.clone())
+    }
+
     fn latency(&self) -> Result<time::Duration, Box<error::Error>> {
         let out_state = self.sink.lock().unwrap();
-        out_state.0.connection().latency().map_err(Box::from)
+        out_state.sink.connection().latency().map_err(Box::from)
     }
 }
 
@@ -205,6 +255,6 @@ where
 {
     fn drop(&mut self) {
         let mut out_state = self.sink.lock().unwrap();
-        (*out_state).1 = true;
+        out_state.closed = true;
     }
 }
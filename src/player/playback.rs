@@ -1,15 +1,36 @@
+use crate::analysis::bands::{self, IntoFeed};
+use crate::analysis::meter::{self, IntoMeter};
+use crate::audio::buffer::{self, IntoBuffer};
+use crate::audio::looper::{self, Cue, IntoLooper, Region};
 use crate::audio::*;
 use crate::filter::*;
 use crate::player::output;
 use sample;
-use std::sync::{Arc, Condvar, Mutex};
+use std::sync::{Arc, Mutex};
 use std::*;
 
 #[derive(Debug)]
 pub enum Event {
+    /// The audible position in frames.
     Position(u64),
     State(State),
     Tempo(f64),
+    /// The pitch shift in semitones.
+    Pitch(f64),
+    /// The gain in dB.
+    Gain(f64),
+    Equalizer(Vec<eq::Band>),
+    /// The offset in beats relative to the playback this playback is synchronised to, ranging
+    /// from -0.5 to 0.5. It is positive if this playback is ahead.
+    Phase(f64),
+    /// The loop or A-B repeat region was changed.
+    Region(Option<Region>),
+    /// The end of the region was reached and playback jumped back to its start.
+    Loop(Region),
+    /// The position of a cue point was passed.
+    Cue(Cue),
+    /// The fill level of the buffer of a live source changed notably.
+    Buffer(buffer::Event),
     Output(output::Event),
 }
 
@@ -24,11 +45,24 @@ pub struct Playback {
     pub stream: Box<output::Stream>,
 
     sample_rate: u32,
-    flow_state: Arc<(Condvar, Mutex<State>)>,
+    flow_state: Arc<Mutex<State>>,
     sample_counter: Arc<Mutex<u64>>,
+    /// The number of frames that are held back by the STFT while the tempo or pitch is altered.
+    stft_delay: u64,
+    /// The position that was most recently reported by `report_position`.
+    reported_position: Option<u64>,
 
     tempo: Option<Arc<Mutex<f64>>>,
+    pitch: Option<Arc<Mutex<f64>>>,
+    gain: Arc<Mutex<f64>>,
+    equalizer: Arc<Mutex<Vec<eq::Band>>>,
+    meter: meter::Snapshot,
+    spectrum: bands::Feed,
     seekable: Option<Arc<Mutex<Seekable + Send>>>,
+    region: Option<Arc<Mutex<Option<Region>>>>,
+    cues: Option<Arc<Mutex<Vec<Cue>>>>,
+    /// The start of an A-B repeat that has not yet been given an end.
+    repeat_start: Option<u64>,
 
     event_handler: Arc<Fn(Event) + Send + Sync>,
 }
@@ -36,108 +70,146 @@ pub struct Playback {
 impl Playback {
     /// Initializes a new Playback. Playback should be started manually by setting the playstate to
     /// Playing.
+    ///
+    /// Sources that can not be seeked in are assumed to be live and are read through a buffer
+    /// with the specified parameters.
+    ///
+    /// The gain in dB is applied from the first frame on, so a track does not start at unity gain
+    /// before it is moved to its ReplayGain.
     pub fn new(
         audio: dynam::Audio,
         output: &output::Output,
+        buffer: buffer::Params,
+        gain: f64,
         event_handler: Arc<Fn(Event) + Send + Sync>,
     ) -> Playback {
         match audio {
-            dynam::Audio::Source(source) => Playback::from_source(source, output, event_handler),
-            dynam::Audio::Seek(seek) => Playback::from_seek(seek, output, event_handler),
+            dynam::Audio::Source(source) => {
+                Playback::from_source(source, output, buffer, gain, event_handler)
+            }
+            dynam::Audio::Seek(seek) => Playback::from_seek(seek, output, gain, event_handler),
         }
     }
 
     fn from_source(
         source: dynam::Source,
         output: &output::Output,
+        buffer: buffer::Params,
+        gain: f64,
         event_handler: Arc<Fn(Event) + Send + Sync>,
     ) -> Playback {
-        let flow_state = Arc::new((Condvar::new(), Mutex::new(State::Paused)));
-        let sample_counter = Arc::new(Mutex::new(0));
+        let controls = Controls::new(source.sample_rate(), source.num_channels() as usize);
+        *controls.gain.lock().unwrap() = gain::db_to_factor(gain);
+        let (window_size, _) = stft_parameters(source.sample_rate());
+        let eh_buffer = event_handler.clone();
+        let buffer_events: Arc<Fn(buffer::Event) + Send + Sync> =
+            Arc::new(move |event| eh_buffer(Event::Buffer(event)));
 
         fn with_control<I>(
             source: I,
-            fs: &Arc<(Condvar, Mutex<State>)>,
-            sc: &Arc<Mutex<u64>>,
+            c: &Controls,
+            params: buffer::Params,
+            buffer_events: &Arc<Fn(buffer::Event) + Send + Sync>,
         ) -> Box<Source<Item = I::Item> + Send>
         where
             I: Source + Send + 'static,
-            I::Item: sample::Frame,
+            I::Item: sample::Frame + Send,
+            <I::Item as sample::Frame>::Sample:
+                sample::ToSample<f64> + sample::FromSample<f64> + Send + 'static,
         {
-            Box::from(source.flow_control(fs.clone()).count_samples(sc.clone()))
+            let (window_size, hop_size) = stft_parameters(source.sample_rate());
+            // Live sources can not be read ahead of time, so speeding them up eventually drains
+            // the buffer, after which playback resumes once it has been filled again.
+            let source_out = source
+                .buffer(params, buffer_events.clone())
+                .adjust_pitch(
+                    stft::Window::Hann,
+                    window_size,
+                    hop_size,
+                    c.tempo.clone(),
+                    c.pitch.clone(),
+                )
+                .expect("the STFT parameters for time stretching are invalid")
+                .adjust_gain(c.gain.clone())
+                .equalizer(c.equalizer.clone())
+                .count_samples(c.sample_counter.clone())
+                .flow_control(c.flow_state.clone())
+                .meter(c.meter.clone())
+                .feed(c.spectrum.clone());
+            Box::from(source_out)
         }
         let source_out = match source {
             dynam::Source::MonoI8(s) => {
-                dynam::Source::MonoI8(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoI8(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoU8(s) => {
-                dynam::Source::MonoU8(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoU8(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoI16(s) => {
-                dynam::Source::MonoI16(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoI16(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoU16(s) => {
-                dynam::Source::MonoU16(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoU16(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoI24(s) => {
-                dynam::Source::MonoI24(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoI24(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoU24(s) => {
-                dynam::Source::MonoU24(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoU24(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoI32(s) => {
-                dynam::Source::MonoI32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoI32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoU32(s) => {
-                dynam::Source::MonoU32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoU32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoI64(s) => {
-                dynam::Source::MonoI64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoI64(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoU64(s) => {
-                dynam::Source::MonoU64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoU64(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoF32(s) => {
-                dynam::Source::MonoF32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoF32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::MonoF64(s) => {
-                dynam::Source::MonoF64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::MonoF64(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoI8(s) => {
-                dynam::Source::StereoI8(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoI8(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoU8(s) => {
-                dynam::Source::StereoU8(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoU8(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoI16(s) => {
-                dynam::Source::StereoI16(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoI16(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoU16(s) => {
-                dynam::Source::StereoU16(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoU16(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoI24(s) => {
-                dynam::Source::StereoI24(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoI24(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoU24(s) => {
-                dynam::Source::StereoU24(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoU24(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoI32(s) => {
-                dynam::Source::StereoI32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoI32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoU32(s) => {
-                dynam::Source::StereoU32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoU32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoI64(s) => {
-                dynam::Source::StereoI64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoI64(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoU64(s) => {
-                dynam::Source::StereoU64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoU64(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoF32(s) => {
-                dynam::Source::StereoF32(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoF32(with_control(s, &controls, buffer, &buffer_events))
             }
             dynam::Source::StereoF64(s) => {
-                dynam::Source::StereoF64(with_control(s, &flow_state, &sample_counter))
+                dynam::Source::StereoF64(with_control(s, &controls, buffer, &buffer_events))
             }
         };
 
@@ -151,10 +223,20 @@ impl Playback {
         Playback {
             sample_rate: source_out.sample_rate(),
             stream: output.consume(source_out, sub_handler).unwrap(),
-            flow_state,
-            sample_counter,
-            tempo: None,
+            flow_state: controls.flow_state,
+            sample_counter: controls.sample_counter,
+            stft_delay: window_size as u64,
+            reported_position: None,
+            tempo: Some(controls.tempo),
+            pitch: Some(controls.pitch),
+            gain: controls.gain,
+            equalizer: controls.equalizer,
+            meter: controls.meter,
+            spectrum: controls.spectrum,
             seekable: None,
+            region: None,
+            cues: None,
+            repeat_start: None,
             event_handler,
         }
     }
@@ -162,17 +244,23 @@ impl Playback {
     fn from_seek(
         seek: dynam::Seek,
         output: &output::Output,
+        gain: f64,
         event_handler: Arc<Fn(Event) + Send + Sync>,
     ) -> Playback {
-        let flow_state = Arc::new((Condvar::new(), Mutex::new(State::Paused)));
-        let sample_counter = Arc::new(Mutex::new(0));
-        let tempo = Arc::new(Mutex::new(1.0));
+        let controls = Controls::new(seek.sample_rate(), seek.num_channels() as usize);
+        *controls.gain.lock().unwrap() = gain::db_to_factor(gain);
+        let (window_size, _) = stft_parameters(seek.sample_rate());
+        let eh_looper = event_handler.clone();
+        let looper_events: Arc<Fn(looper::Event) + Send + Sync> =
+            Arc::new(move |event| match event {
+                looper::Event::Loop(region) => eh_looper(Event::Loop(region)),
+                looper::Event::Cue(cue) => eh_looper(Event::Cue(cue)),
+            });
 
         fn with_control<I>(
             seek: I,
-            fs: &Arc<(Condvar, Mutex<State>)>,
-            sc: &Arc<Mutex<u64>>,
-            t: &Arc<Mutex<f64>>,
+            c: &Controls,
+            looper_events: &Arc<Fn(looper::Event) + Send + Sync>,
         ) -> (
             Box<Source<Item = I::Item> + Send>,
             Arc<Mutex<Seekable + Send>>,
@@ -180,119 +268,126 @@ impl Playback {
         where
             I: Seek + Send + 'static,
             I::Item: sample::Frame + Send,
-            <I::Item as sample::Frame>::Float: Send,
-            <I::Item as sample::Frame>::Sample: sample::ToSample<f64>
-                + sample::FromSample<f64>
-                + sample::FromSample<
-                    <<I::Item as sample::Frame>::Float as sample::Frame>::Sample,
-                > + Send
-                + 'static,
+            <I::Item as sample::Frame>::Sample:
+                sample::ToSample<f64> + sample::FromSample<f64> + Send + 'static,
         {
-            let shared_seek = seek.shared();
+            let (window_size, hop_size) = stft_parameters(seek.sample_rate());
+            let shared_seek = seek
+                .looper(c.region.clone(), c.cues.clone(), looper_events.clone())
+                .shared();
             let mut_seek = shared_seek.input.clone();
             let source_out = shared_seek
-                .stft(1024)
-                .adjust_tempo(t.clone())
-                .inverse()
-                .flow_control(fs.clone())
-                .count_samples(sc.clone());
+                .adjust_pitch(
+                    stft::Window::Hann,
+                    window_size,
+                    hop_size,
+                    c.tempo.clone(),
+                    c.pitch.clone(),
+                )
+                .expect("the STFT parameters for time stretching are invalid")
+                .adjust_gain(c.gain.clone())
+                .equalizer(c.equalizer.clone())
+                .count_samples(c.sample_counter.clone())
+                .flow_control(c.flow_state.clone())
+                .meter(c.meter.clone())
+                .feed(c.spectrum.clone());
             (Box::from(source_out), mut_seek)
         }
         let (source_out, mut_seek) = match seek {
             dynam::Seek::MonoI8(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoI8(o), m)
             }
             dynam::Seek::MonoU8(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoU8(o), m)
             }
             dynam::Seek::MonoI16(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoI16(o), m)
             }
             dynam::Seek::MonoU16(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoU16(o), m)
             }
             dynam::Seek::MonoI24(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoI24(o), m)
             }
             dynam::Seek::MonoU24(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoU24(o), m)
             }
             dynam::Seek::MonoI32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoI32(o), m)
             }
             dynam::Seek::MonoU32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoU32(o), m)
             }
             dynam::Seek::MonoI64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoI64(o), m)
             }
             dynam::Seek::MonoU64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoU64(o), m)
             }
             dynam::Seek::MonoF32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoF32(o), m)
             }
             dynam::Seek::MonoF64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::MonoF64(o), m)
             }
             dynam::Seek::StereoI8(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoI8(o), m)
             }
             dynam::Seek::StereoU8(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoU8(o), m)
             }
             dynam::Seek::StereoI16(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoI16(o), m)
             }
             dynam::Seek::StereoU16(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoU16(o), m)
             }
             dynam::Seek::StereoI24(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoI24(o), m)
             }
             dynam::Seek::StereoU24(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoU24(o), m)
             }
             dynam::Seek::StereoI32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoI32(o), m)
             }
             dynam::Seek::StereoU32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoU32(o), m)
             }
             dynam::Seek::StereoI64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoI64(o), m)
             }
             dynam::Seek::StereoU64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoU64(o), m)
             }
             dynam::Seek::StereoF32(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoF32(o), m)
             }
             dynam::Seek::StereoF64(s) => {
-                let (o, m) = with_control(s, &flow_state, &sample_counter, &tempo);
+                let (o, m) = with_control(s, &controls, &looper_events);
                 (dynam::Source::StereoF64(o), m)
             }
         };
@@ -307,14 +402,28 @@ impl Playback {
         Playback {
             sample_rate: source_out.sample_rate(),
             stream: output.consume(source_out, sub_handler).unwrap(),
-            flow_state,
-            sample_counter,
-            tempo: Some(tempo),
+            flow_state: controls.flow_state,
+            sample_counter: controls.sample_counter,
+            stft_delay: window_size as u64,
+            reported_position: None,
+            tempo: Some(controls.tempo),
+            pitch: Some(controls.pitch),
+            gain: controls.gain,
+            equalizer: controls.equalizer,
+            meter: controls.meter,
+            spectrum: controls.spectrum,
             seekable: Some(mut_seek),
+            region: Some(controls.region),
+            cues: Some(controls.cues),
+            repeat_start: None,
             event_handler,
         }
     }
 
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
     /// Returns the total number of samples in of the playing audio if known.
     pub fn duration(&self) -> Option<u64> {
         self.seekable
@@ -330,13 +439,60 @@ impl Playback {
 
     /// Returns the position of the sample that will be read next.
     /// If The audio is infinite, this will simply be the total number of samples played.
-    pub fn position(&self) -> u64 {
+    ///
+    /// This runs ahead of what can be heard by the delay of the signal pipeline and output, see
+    /// `position` for the audible position.
+    pub fn read_position(&self) -> u64 {
         self.seekable
             .as_ref()
             .map(|s| s.lock().unwrap().current_position())
             .unwrap_or_else(|| *self.sample_counter.lock().unwrap())
     }
 
+    /// Returns the position of the sample that is currently audible.
+    pub fn position(&self) -> u64 {
+        self.read_position().saturating_sub(self.delay())
+    }
+
+    /// Returns the number of frames of the audio that have been read but are not yet audible.
+    ///
+    /// Frames in the output buffers are played back at the current tempo, so more frames of the
+    /// audio fit in them when the tempo is increased.
+    pub fn delay(&self) -> u64 {
+        let output = self
+            .stream
+            .latency()
+            .map(|latency| {
+                let secs = latency.as_secs() as f64 + f64::from(latency.subsec_nanos()) * 1e-9;
+                secs * f64::from(self.sample_rate) * self.tempo()
+            })
+            .unwrap_or(0.0);
+        let pitch_factor = self
+            .pitch
+            .as_ref()
+            .map(|p| *p.lock().unwrap())
+            .unwrap_or(1.0);
+        let pipeline = if pitch::is_identity(self.tempo(), pitch_factor) {
+            0
+        } else {
+            self.stft_delay
+        };
+        output.round() as u64 + pipeline
+    }
+
+    /// Fires an `Event::Position` with the audible position if it has changed since this method
+    /// was last called.
+    ///
+    /// Reporting every change in position would flood the event handler, so this is intended to
+    /// be called periodically instead.
+    pub fn report_position(&mut self) {
+        let position = self.position();
+        if self.reported_position != Some(position) {
+            self.reported_position = Some(position);
+            (self.event_handler)(Event::Position(position));
+        }
+    }
+
     /// Seeks to the sample at the specified position. If seeking is not supported, this is a
     /// no-op.
     pub fn set_position(&mut self, position: u64) {
@@ -360,16 +516,16 @@ impl Playback {
     }
 
     pub fn state(&self) -> State {
-        *self.flow_state.1.lock().unwrap()
+        *self.flow_state.lock().unwrap()
     }
 
     pub fn set_state(&mut self, state: State) {
-        let &(ref cvar, ref lock) = &*self.flow_state;
-        let mut cur_state = lock.lock().unwrap();
-        if *cur_state != State::Stopped {
-            *cur_state = state;
+        {
+            let mut cur_state = self.flow_state.lock().unwrap();
+            if *cur_state != State::Stopped {
+                *cur_state = state;
+            }
         }
-        cvar.notify_all();
         (self.event_handler)(Event::State(state));
     }
 
@@ -391,17 +547,214 @@ impl Playback {
             }
         }
     }
+
+    /// Returns the pitch shift in semitones.
+    pub fn pitch(&self) -> f64 {
+        self.pitch
+            .as_ref()
+            .map(|p| pitch::factor_to_semitones(*p.lock().unwrap()))
+            .unwrap_or(0.0)
+    }
+
+    /// Shifts the pitch of the currently playing audio by the specified number of semitones
+    /// without affecting the tempo. Cents can be specified using the fractional part, so 1.5
+    /// shifts by a semitone and 50 cents.
+    /// This is a no-op if the pitch of the audio can not be altered.
+    pub fn set_pitch(&mut self, semitones: f64) {
+        if semitones.is_finite() {
+            if let Some(ref p) = self.pitch {
+                *p.lock().unwrap() = pitch::semitones_to_factor(semitones);
+                (self.event_handler)(Event::Pitch(semitones));
+            }
+        }
+    }
+
+    /// Returns the gain in dB that is applied to the audio before it is sent to the output.
+    pub fn gain(&self) -> f64 {
+        gain::factor_to_db(*self.gain.lock().unwrap())
+    }
+
+    /// Sets the gain in dB. Unlike the volume of the output stream, this is part of the signal
+    /// pipeline, so it is independent of the volume set by the user.
+    pub fn set_gain(&mut self, db: f64) {
+        if !db.is_finite() {
+            return;
+        }
+        *self.gain.lock().unwrap() = gain::db_to_factor(db);
+        (self.event_handler)(Event::Gain(db));
+    }
+
+    /// Returns the bands of the equalizer of this playback.
+    pub fn equalizer(&self) -> Vec<eq::Band> {
+        self.equalizer.lock().unwrap().clone()
+    }
+
+    /// Replaces the bands of the equalizer. The response of the equalizer changes smoothly, so
+    /// this can be done without interrupting playback.
+    pub fn set_equalizer(&mut self, bands: Vec<eq::Band>) {
+        *self.equalizer.lock().unwrap() = bands.clone();
+        (self.event_handler)(Event::Equalizer(bands));
+    }
+
+    /// Returns the levels of the audio as it is sent to the output. The meter is updated every
+    /// 10ms, so this is cheap enough to be polled for display.
+    pub fn meter(&self) -> meter::Levels {
+        self.meter.levels()
+    }
+
+    /// Calls the handler with the levels of the frequency bands of the audio as it is sent to the
+    /// output until the returned subscription is dropped.
+    pub fn subscribe_spectrum(
+        &self,
+        params: &bands::Params,
+        handler: Arc<Fn(&[f64]) + Send + Sync>,
+    ) -> bands::Subscription {
+        self.spectrum.subscribe(params, handler)
+    }
+
+    /// Returns the cue points of the playing audio.
+    pub fn cues(&self) -> Vec<Cue> {
+        self.cues
+            .as_ref()
+            .map(|c| c.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the cue points that are reported when they are passed.
+    /// This is a no-op if the audio is not seekable.
+    pub fn set_cues(&mut self, cues: Vec<Cue>) {
+        if let Some(ref c) = self.cues {
+            *c.lock().unwrap() = cues;
+        }
+    }
+
+    /// Adds a cue point, replacing any cue point with the same name.
+    pub fn set_cue(&mut self, cue: Cue) {
+        if let Some(ref c) = self.cues {
+            let mut cues = c.lock().unwrap();
+            cues.retain(|other| other.name != cue.name);
+            cues.push(cue);
+        }
+    }
+
+    /// Seeks to the cue point with the specified name. Returns false if there is no such cue.
+    pub fn jump_to_cue(&mut self, name: &str) -> bool {
+        let position = self
+            .cues()
+            .into_iter()
+            .find(|cue| cue.name == name)
+            .map(|cue| cue.position);
+        match position {
+            Some(position) => {
+                self.set_position(position);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the region that is being repeated, if any.
+    pub fn region(&self) -> Option<Region> {
+        self.region.as_ref().and_then(|r| *r.lock().unwrap())
+    }
+
+    /// Sets the region that is repeated. The jump back to the start of the region is sample
+    /// accurate, so it can be used for seamless loops.
+    /// This is a no-op if the audio is not seekable.
+    pub fn set_region(&mut self, region: Option<Region>) {
+        if let Some(ref r) = self.region {
+            *r.lock().unwrap() = region;
+            self.repeat_start = None;
+            (self.event_handler)(Event::Region(region));
+        }
+    }
+
+    /// Marks the current position as the start of an A-B repeat. The repeat starts once the end is
+    /// marked using `repeat_end`.
+    pub fn repeat_start(&mut self) {
+        if self.region.is_some() {
+            self.repeat_start = Some(self.position());
+        }
+    }
+
+    /// Returns true if the start of an A-B repeat has been marked but the end has not.
+    pub fn repeat_start_marked(&self) -> bool {
+        self.repeat_start.is_some()
+    }
+
+    /// Marks the current position as the end of an A-B repeat and starts repeating. This is a no-op
+    /// if no start has been marked or the end lies before the start.
+    ///
+    /// The audible position lags behind the position that is read, so the end has usually been
+    /// read already and playback jumps back to the start right away.
+    pub fn repeat_end(&mut self) {
+        let region = self
+            .repeat_start
+            .and_then(|start| Region::new(start, self.position()));
+        if region.is_some() {
+            self.set_region(region);
+        }
+    }
+
+    /// Reports the phase offset in beats relative to the playback this playback is synchronised
+    /// to.
+    pub fn report_phase(&self, offset: f64) {
+        (self.event_handler)(Event::Phase(offset));
+    }
+}
+
+/// The parameters that are shared between a Playback and its signal pipeline.
+struct Controls {
+    flow_state: Arc<Mutex<State>>,
+    sample_counter: Arc<Mutex<u64>>,
+    tempo: Arc<Mutex<f64>>,
+    pitch: Arc<Mutex<f64>>,
+    gain: Arc<Mutex<f64>>,
+    equalizer: Arc<Mutex<Vec<eq::Band>>>,
+    meter: meter::Snapshot,
+    spectrum: bands::Feed,
+    region: Arc<Mutex<Option<Region>>>,
+    cues: Arc<Mutex<Vec<Cue>>>,
+}
+
+impl Controls {
+    fn new(sample_rate: u32, num_channels: usize) -> Controls {
+        Controls {
+            flow_state: Arc::new(Mutex::new(State::Paused)),
+            sample_counter: Arc::new(Mutex::new(0)),
+            tempo: Arc::new(Mutex::new(1.0)),
+            pitch: Arc::new(Mutex::new(1.0)),
+            gain: Arc::new(Mutex::new(1.0)),
+            equalizer: Arc::new(Mutex::new(Vec::new())),
+            meter: meter::Snapshot::new(num_channels),
+            spectrum: bands::Feed::new(sample_rate, num_channels),
+            region: Arc::new(Mutex::new(None)),
+            cues: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+/// Picks the STFT window and hop size for time stretching a signal with the specified sample rate.
+///
+/// A window of about 46ms is long enough to resolve low frequencies while keeping transients
+/// reasonably sharp. The windows overlap by 75% to keep phase propagation accurate, which also
+/// allows any of the windows to be reconstructed.
+fn stft_parameters(sample_rate: u32) -> (usize, usize) {
+    let target = f64::from(sample_rate) * 0.046;
+    let window_size = 1 << (target.log2().round() as u32).max(8);
+    (window_size, window_size / 4)
 }
 
 /// FlowControl acts as a part of a signal pipeline allowing the flow to be paused and stopped.
-/// Because pausing works by blocking any calls to next, `FlowControl` provides its own concurrency
-/// method instead of recommending `audio::Shared`.
+///
+/// While paused, silence is produced without reading from the input. This way, a paused source
+/// never blocks the mixer it is feeding.
 struct FlowControl<S>
 where
     S: Source,
     S::Item: sample::Frame,
 {
-    pub state: Arc<(Condvar, Mutex<State>)>,
+    pub state: Arc<Mutex<State>>,
     input: S,
 }
 
@@ -412,15 +765,9 @@ where
 {
     type Item = S::Item;
     fn next(&mut self) -> Option<Self::Item> {
-        let &(ref cvar, ref lock) = &*self.state;
-
-        let mut state = lock.lock().unwrap();
-        while *state == State::Paused {
-            state = cvar.wait(state).unwrap();
-        }
-
+        let mut state = self.state.lock().unwrap();
         match *state {
-            State::Paused => unreachable!(),
+            State::Paused => Some(<S::Item as sample::Frame>::equilibrium()),
             State::Stopped => None,
             State::Playing => {
                 let f = self.input.next();
@@ -448,13 +795,10 @@ where
     S: Source,
     S::Item: sample::Frame,
 {
-    // If the state is set to paused, another thread attempting to read from the stream is blocked.
-    // Here, we set the state to stopped when this FlowControl is dropped, so that the reading
-    // thread will never deadlock.
+    // Mark the playback as stopped when this FlowControl is dropped, so the state is consistent
+    // with the stream having been torn down.
     fn drop(&mut self) {
-        let &(ref cvar, ref lock) = &*self.state;
-        *lock.lock().unwrap() = State::Stopped;
-        cvar.notify_all();
+        *self.state.lock().unwrap() = State::Stopped;
     }
 }
 
@@ -462,7 +806,7 @@ trait IntoFlowControl: Source + Sized
 where
     Self::Item: sample::Frame,
 {
-    fn flow_control(self, state: Arc<(Condvar, Mutex<State>)>) -> FlowControl<Self> {
+    fn flow_control(self, state: Arc<Mutex<State>>) -> FlowControl<Self> {
         FlowControl { state, input: self }
     }
 }
@@ -0,0 +1,151 @@
+//!
+//! A session records the master output of the player to a file, including all crossfades and
+//! effects, along with a CUE sheet that marks where each track started.
+//!
+//! The audio is copied from the mixer and encoded on a separate thread, so a slow disk can not
+//! cause the output to drop out.
+//!
+
+use crate::audio::*;
+use crate::format::{cue, flac, wave};
+use crate::player::mixer;
+use sample::{self, Sample, I24};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::*;
+
+/// The file format of a session recording.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// 32 bit floating point wave, which stores the output of the mixer without loss.
+    Wave,
+    /// 24 bit FLAC.
+    Flac,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Wave => "wav",
+            Format::Flac => "flac",
+        }
+    }
+}
+
+pub struct Session {
+    path: PathBuf,
+    sheet: cue::Sheet,
+    sample_rate: u32,
+    recording: record::Recording,
+    /// The playbacks of which the start has been added to the CUE sheet.
+    marked: BTreeSet<u64>,
+}
+
+impl Session {
+    /// Starts recording the output of the mixer to a file in the specified directory that is
+    /// named after the current time. The CUE sheet is written next to it.
+    pub fn start(
+        mixer: &mut mixer::Mixer,
+        dir: &Path,
+        format: Format,
+    ) -> Result<Session, Box<error::Error>> {
+        let path = record::timestamped_path(dir, "session", format.extension());
+        // Frames that could not be recorded in time are replaced by silence so the marks keep
+        // lining up with the recording.
+        let tap = mixer.tap().fill_gaps();
+        let sample_rate = tap.sample_rate();
+        let recording = match format {
+            Format::Wave => {
+                let file = io::BufWriter::new(fs::File::create(&path)?);
+                let encoder = wave::encode(file, sample_rate)?;
+                record::record(convert::<[f32; 2]>(tap), encoder)
+            }
+            Format::Flac => {
+                let encoder = flac::encode_file(&path, sample_rate)?;
+                record::record(convert::<[I24; 2]>(tap), encoder)
+            }
+        };
+        let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+        let session = Session {
+            sheet: cue::Sheet {
+                title: Some(filename.clone()),
+                performer: None,
+                date: None,
+                genre: None,
+                files: vec![cue::File {
+                    path: filename,
+                    tracks: Vec::new(),
+                }],
+            },
+            path,
+            sample_rate,
+            recording,
+            marked: BTreeSet::new(),
+        };
+        session.write_cue_sheet()?;
+        Ok(session)
+    }
+
+    /// Returns the path of the audio file that is being recorded.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path of the CUE sheet.
+    pub fn cue_path(&self) -> PathBuf {
+        self.path.with_extension("cue")
+    }
+
+    /// Adds a track to the CUE sheet at the current position of the recording. Nothing is done
+    /// if the playback with the ID has been marked before, such as when it is resumed after a
+    /// pause.
+    pub fn mark(
+        &mut self,
+        mixer: &mixer::Mixer,
+        id: u64,
+        title: Option<String>,
+        performer: Option<String>,
+    ) -> Result<(), Box<error::Error>> {
+        if !self.marked.insert(id) {
+            return Ok(());
+        }
+        let position = mixer.tapped_frames().unwrap_or(0);
+        let tracks = &mut self.sheet.files[0].tracks;
+        let number = tracks.len() as u32 + 1;
+        tracks.push(cue::Track {
+            number,
+            title,
+            performer,
+            start: duration_of(self.sample_rate, position),
+        });
+        self.write_cue_sheet()
+    }
+
+    /// Detaches the recording from the mixer and waits for the audio that is still queued to be
+    /// written.
+    pub fn finish(self, mixer: &mut mixer::Mixer) -> Result<PathBuf, Box<error::Error>> {
+        mixer.untap();
+        self.recording
+            .wait()
+            .map_err(|err| err as Box<error::Error>)?;
+        Ok(self.path)
+    }
+
+    /// The CUE sheet is rewritten every time a track is added, so it is complete even if the
+    /// session is not finished properly.
+    fn write_cue_sheet(&self) -> Result<(), Box<error::Error>> {
+        fs::write(self.cue_path(), self.sheet.to_string())?;
+        Ok(())
+    }
+}
+
+/// Converts the floating point output of the mixer to the format of an encoder.
+fn convert<F>(tap: mixer::Tap) -> impl Source<Item = F> + Send
+where
+    F: sample::Frame + Send + 'static,
+    F::Sample: sample::FromSample<f64>,
+{
+    let sample_rate = tap.sample_rate();
+    tap.map(|frame: [f64; 2]| F::from_fn(|ch| frame[ch].max(-1.0).min(1.0).to_sample()))
+        .source(sample_rate)
+}
@@ -50,7 +50,15 @@ where
     }
 }
 
-pub fn source<F, S>(app_name: &str, rate: u32) -> Result<Source<F>, Box<error::Error>>
+/// Opens a recording stream from the named source, or the default source if None.
+///
+/// The monitor of a sink, which records what is played through it, can be selected by the name
+/// of the sink followed by `.monitor`.
+pub fn source<F, S>(
+    app_name: &str,
+    device: Option<&str>,
+    rate: u32,
+) -> Result<Source<F>, Box<error::Error>>
 where
     F: sample::Frame<Sample = S>,
     S: sample::Sample + AsSampleFormat,
@@ -58,6 +66,7 @@ where
     Connection::new(
         app_name,
         "source",
+        device,
         rate,
         pa_stream_direction::PA_STREAM_RECORD,
     )
@@ -120,6 +129,7 @@ where
     Connection::new(
         app_name,
         stream_name,
+        None,
         rate,
         pa_stream_direction::PA_STREAM_PLAYBACK,
     )
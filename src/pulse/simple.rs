@@ -28,21 +28,27 @@ where
     F: sample::Frame,
     F::Sample: sample::Sample + AsSampleFormat,
 {
+    /// Connects to the named device, or the default device if None.
     pub fn new(
         app_name: &str,
         stream_name: &str,
+        device: Option<&str>,
         rate: u32,
         dir: pa_stream_direction,
     ) -> Result<Connection<F>, Box<error::Error>> {
         let s = unsafe {
             let c_app_name = ffi::CString::new(app_name)?;
             let c_stream_name = ffi::CString::new(stream_name)?;
+            let c_device = match device {
+                Some(device) => Some(ffi::CString::new(device)?),
+                None => None,
+            };
             let mut err_code = pa_error_code::PA_OK;
             let s = pa_simple_new(
                 ptr::null(), // Use the default server.
                 c_app_name.as_ptr(),
                 dir,
-                ptr::null(), // Use the default device.
+                c_device.as_ref().map(|d| d.as_ptr()).unwrap_or(ptr::null()),
                 c_stream_name.as_ptr(),
                 &sample_spec::<F>(rate),
                 ptr::null(), // Use default channel map
@@ -0,0 +1,17 @@
+REM GENRE "Electronic"
+REM DATE 1984
+PERFORMER "Various Artists"
+TITLE "Sine Session"
+FILE "image.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Intro"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Lucy in the Cloud with Sine Waves"
+    PERFORMER "The B-Trees"
+    INDEX 00 00:02:50
+    INDEX 01 00:03:00
+  TRACK 03 AUDIO
+    TITLE "One or Zero"
+    PERFORMER "Michael FLACson"
+    INDEX 01 00:07:30